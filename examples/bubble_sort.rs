@@ -13,7 +13,7 @@ fn main() {
     ];
 
     cpu.load_program(&program);
-    cpu.reset();
+    cpu.warm_reset();
 
     println!("Bubble sort requires:");
     println!("  - Load/Store instructions (LW, SW)");
@@ -1,6 +1,6 @@
 //! Factorial calculator in RISC-V
 
-use riscv32i_sim::{Cpu, InstructionEncoder};
+use riscv32i_sim::{Cpu, InstructionEncoder, RunConfig};
 
 fn main() {
     println!("=== Factorial Calculator Demo ===\n");
@@ -17,15 +17,18 @@ fn main() {
         // Loop: multiply result by n, decrement n
         // TODO: Need M extension for MUL instruction
         // For now, this is a stub showing the structure
+
+        // Halt: jal x0, 0
+        (8,  InstructionEncoder::j_type(0b1101111, 0, 0)),
     ];
 
     cpu.load_program(&program);
-    cpu.reset();
 
     println!("Calculating 5! ...\n");
     println!("Note: Requires M extension (multiplication) - stub only\n");
 
-    cpu.run_cycles(10);
+    let result = cpu.run(&RunConfig::new().with_max_cycles(10));
+    println!("Stopped: {:?} after {} cycles\n", result.reason, result.cycles);
 
     println!("Register state:");
     cpu.registers.dump_registers(0, 3);
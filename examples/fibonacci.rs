@@ -2,7 +2,7 @@
 //! 
 //! Calculates the first N Fibonacci numbers
 
-use riscv32i_sim::{Cpu, InstructionEncoder};
+use riscv32i_sim::{Cpu, InstructionEncoder, RunConfig};
 
 fn main() {
     println!("=== Fibonacci Sequence Demo ===\n");
@@ -23,20 +23,23 @@ fn main() {
         (24, InstructionEncoder::i_type(0b0010011, 3, 0b000, 3, -1)),           // x3 = x3 - 1
         
         // Branch if not zero
-        (28, InstructionEncoder::r_type(0b1100011, 0b001, 0, 3, 0, 0) | ((-16i32 as u32 & 0x1000) << 19) | ((-16i32 as u32 & 0x7E0) << 20) | ((-16i32 as u32 & 0x1E) << 7) | ((-16i32 as u32 & 0x800) >> 4)),
+        (28, InstructionEncoder::b_type(0b1100011, 0b001, 3, 0, -16)),         // bne x3, x0, loop
+
+        // Halt: jal x0, 0
+        (32, InstructionEncoder::j_type(0b1101111, 0, 0)),
     ];
 
     cpu.load_program(&program);
-    cpu.reset();
 
     println!("Calculating 10 Fibonacci numbers...\n");
     
-    // Run for enough cycles to complete
-    cpu.run_cycles(100);
+    // Run until the halt loop (with a safety budget)
+    let result = cpu.run(&RunConfig::new().with_max_cycles(1000));
 
     println!("Results:");
-    println!("  Cycles executed: {}", cpu.get_cycle_count());
-    println!("  Final result in x2: {}\n", cpu.registers.get_read_data_a());
+    println!("  Stopped: {:?}", result.reason);
+    println!("  Cycles executed: {}", result.cycles);
+    println!("  Final result in x2: {}\n", cpu.registers.peek(2));
 
     println!("Register state:");
     cpu.registers.dump_registers(0, 5);
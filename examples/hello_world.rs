@@ -1,14 +1,27 @@
-//! Hello World using system calls (future feature)
+//! Hello World using system calls
+//!
+//! Prints a string with the RARS/SPIM print_string service (a7 = 4)
+
+use riscv32i_sim::{Cpu, InstructionEncoder, RarsSyscalls, RunConfig};
 
 fn main() {
     println!("=== Hello World Demo ===\n");
-    println!("Hello World requires:");
-    println!("  - ECALL instruction implementation");
-    println!("  - System call interface");
-    println!("  - String storage in memory");
-    println!("\nStub - to be implemented\n");
-    println!("Expected RISC-V code:");
-    println!("  la   a0, hello_str  # Load address of string");
-    println!("  li   a7, 4          # System call 4: print string");
-    println!("  ecall               # Make system call");
+
+    let mut cpu = Cpu::new();
+    cpu.set_syscall_handler(Box::new(RarsSyscalls::stdio()));
+
+    let program = vec![
+        (0,  InstructionEncoder::i_type(0b0010011, 10, 0b000, 0, 0x100)), // la   a0, hello_str
+        (4,  InstructionEncoder::i_type(0b0010011, 17, 0b000, 0, 4)),     // li   a7, 4 (print string)
+        (8,  0x0000_0073),                                                // ecall
+        (12, InstructionEncoder::i_type(0b0010011, 17, 0b000, 0, 10)),    // li   a7, 10 (exit)
+        (16, 0x0000_0073),                                                // ecall
+    ];
+
+    cpu.load_program(&program);
+    cpu.memory.write_bytes(0x100, b"Hello, World!\n\0");  // hello_str
+
+    let result = cpu.run(&RunConfig::new().with_max_cycles(100));
+
+    println!("\nStopped: {:?} (exit code {:?}) after {} cycles", result.reason, result.exit_code, result.cycles);
 }
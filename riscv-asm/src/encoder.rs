@@ -1,27 +1,499 @@
 //! Instruction encoder - converts parsed instructions to machine code
+//!
+//! RV32I, Zicsr and Zifencei plus the common pseudo-instructions. `li`
+//! and `la` expand to two words when the value does not fit in 12 bits.
 
 use riscv32i_sim::{Word, InstructionEncoder};
 use std::collections::HashMap;
 use crate::{AsmError, Result};
 
+const OP: u8 = 0b0110011;
+const OP_IMM: u8 = 0b0010011;
+const LOAD: u8 = 0b0000011;
+const STORE: u8 = 0b0100011;
+const BRANCH: u8 = 0b1100011;
+const LUI: u8 = 0b0110111;
+const AUIPC: u8 = 0b0010111;
+const JAL: u8 = 0b1101111;
+const JALR: u8 = 0b1100111;
+const MISC_MEM: u8 = 0b0001111;
+const SYSTEM: u8 = 0b1110011;
+
+/// Register names by number
+pub const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+const CSR_NAMES: [(&str, u16); 20] = [
+    ("mstatus", 0x300), ("misa", 0x301), ("mie", 0x304), ("mtvec", 0x305),
+    ("mscratch", 0x340), ("mepc", 0x341), ("mcause", 0x342), ("mtval", 0x343),
+    ("mip", 0x344), ("mcycle", 0xB00), ("minstret", 0xB02), ("mcycleh", 0xB80),
+    ("minstreth", 0xB82), ("cycle", 0xC00), ("time", 0xC01), ("instret", 0xC02),
+    ("cycleh", 0xC80), ("timeh", 0xC81), ("instreth", 0xC82), ("mhartid", 0xF14),
+];
+
+/// Register number from "x5" or an ABI name ("t0", "fp")
+pub fn parse_register(name: &str) -> Result<u8> {
+    let name = name.trim().to_lowercase();
+    if let Some(number) = name.strip_prefix('x').and_then(|n| n.parse::<u8>().ok()) {
+        if number < 32 {
+            return Ok(number);
+        }
+    }
+    if name == "fp" {
+        return Ok(8);
+    }
+    ABI_NAMES
+        .iter()
+        .position(|&abi| abi == name)
+        .map(|i| i as u8)
+        .ok_or(AsmError::InvalidRegister(name))
+}
+
+/// Decimal, hex (0x) or binary (0b) integer, optionally negative
+pub fn parse_immediate(text: &str) -> Result<i64> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        digits.parse()
+    };
+    value
+        .map(|v| if negative { -v } else { v })
+        .map_err(|_| AsmError::InvalidImmediate(text.to_string()))
+}
+
+/// Bytes a source line assembles to (0 for labels, comments and blanks)
+pub fn instruction_size(line: &str) -> u32 {
+    let line = strip_comment(line).trim();
+    if line.is_empty() || line.starts_with('.') && !line.starts_with(".word") {
+        return 0;
+    }
+    let mut parts = line.splitn(2, char::is_whitespace);
+    let mnemonic = parts.next().unwrap_or("").to_lowercase();
+    let operands = split_operands(parts.next().unwrap_or(""));
+    match (mnemonic.as_str(), operands.as_slice()) {
+        ("la", _) => 8,
+        ("li", [_, value]) if parse_immediate(value).map_or(true, |v| !fits(v, 12)) => 8,
+        _ => 4,
+    }
+}
+
+/// Line without its `#` (or `//`) comment
+pub fn strip_comment(line: &str) -> &str {
+    let end = [line.find('#'), line.find("//")].into_iter().flatten().min().unwrap_or(line.len());
+    &line[..end]
+}
+
+/// Encode one instruction at `address`, with labels resolved from `labels`
 pub fn encode_instruction(
     line: &str,
-    _address: u32,
-    _labels: &HashMap<String, u32>,
-) -> Result<Word> {
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if parts.is_empty() {
+    address: u32,
+    labels: &HashMap<String, u32>,
+) -> Result<Vec<Word>> {
+    let line = strip_comment(line).trim();
+    let mut parts = line.splitn(2, char::is_whitespace);
+    let mnemonic = parts.next().unwrap_or("").to_lowercase();
+    if mnemonic.is_empty() {
         return Err(AsmError::ParseError("Empty instruction".to_string()));
     }
+    let operands = split_operands(parts.next().unwrap_or(""));
+    let ops = Operands { list: &operands, line, address, labels };
+
+    let word = match mnemonic.as_str() {
+        ".word" => ops.value(0)? as Word,
+
+        // R-type
+        "add" => ops.r(0b000, 0)?,
+        "sub" => ops.r(0b000, 0b0100000)?,
+        "sll" => ops.r(0b001, 0)?,
+        "slt" => ops.r(0b010, 0)?,
+        "sltu" => ops.r(0b011, 0)?,
+        "xor" => ops.r(0b100, 0)?,
+        "srl" => ops.r(0b101, 0)?,
+        "sra" => ops.r(0b101, 0b0100000)?,
+        "or" => ops.r(0b110, 0)?,
+        "and" => ops.r(0b111, 0)?,
+
+        // Register-immediate
+        "addi" => ops.i(0b000)?,
+        "slti" => ops.i(0b010)?,
+        "sltiu" => ops.i(0b011)?,
+        "xori" => ops.i(0b100)?,
+        "ori" => ops.i(0b110)?,
+        "andi" => ops.i(0b111)?,
+        "slli" => ops.shift(0b001, 0)?,
+        "srli" => ops.shift(0b101, 0)?,
+        "srai" => ops.shift(0b101, 0b0100000)?,
+
+        // Loads and stores: op reg, offset(base)
+        "lb" => ops.load(0b000)?,
+        "lh" => ops.load(0b001)?,
+        "lw" => ops.load(0b010)?,
+        "lbu" => ops.load(0b100)?,
+        "lhu" => ops.load(0b101)?,
+        "sb" => ops.store(0b000)?,
+        "sh" => ops.store(0b001)?,
+        "sw" => ops.store(0b010)?,
+
+        // Branches
+        "beq" => ops.branch(0b000, 0, 1)?,
+        "bne" => ops.branch(0b001, 0, 1)?,
+        "blt" => ops.branch(0b100, 0, 1)?,
+        "bge" => ops.branch(0b101, 0, 1)?,
+        "bltu" => ops.branch(0b110, 0, 1)?,
+        "bgeu" => ops.branch(0b111, 0, 1)?,
+        "bgt" => ops.branch(0b100, 1, 0)?,
+        "ble" => ops.branch(0b101, 1, 0)?,
+        "bgtu" => ops.branch(0b110, 1, 0)?,
+        "bleu" => ops.branch(0b111, 1, 0)?,
+        "beqz" => ops.branch_zero(0b000, false)?,
+        "bnez" => ops.branch_zero(0b001, false)?,
+        "bltz" => ops.branch_zero(0b100, false)?,
+        "bgez" => ops.branch_zero(0b101, false)?,
+        "bgtz" => ops.branch_zero(0b100, true)?,
+        "blez" => ops.branch_zero(0b101, true)?,
+
+        // Upper immediates (the 20-bit field, as written in assembly)
+        "lui" => InstructionEncoder::u_type(LUI, ops.reg(0)?, (ops.value(1)? << 12) as i32),
+        "auipc" => InstructionEncoder::u_type(AUIPC, ops.reg(0)?, (ops.value(1)? << 12) as i32),
+
+        // Jumps
+        "jal" if operands.len() == 1 => InstructionEncoder::j_type(JAL, 1, ops.jump_offset(0)?),
+        "jal" => InstructionEncoder::j_type(JAL, ops.reg(0)?, ops.jump_offset(1)?),
+        "j" | "tail" => InstructionEncoder::j_type(JAL, 0, ops.jump_offset(0)?),
+        "call" => InstructionEncoder::j_type(JAL, 1, ops.jump_offset(0)?),
+        "jalr" => ops.jalr()?,
+        "jr" => InstructionEncoder::i_type(JALR, 0, 0b000, ops.reg(0)?, 0),
+        "ret" => InstructionEncoder::i_type(JALR, 0, 0b000, 1, 0),
+
+        // Other pseudo-instructions
+        "nop" => InstructionEncoder::i_type(OP_IMM, 0, 0b000, 0, 0),
+        "mv" => InstructionEncoder::i_type(OP_IMM, ops.reg(0)?, 0b000, ops.reg(1)?, 0),
+        "not" => InstructionEncoder::i_type(OP_IMM, ops.reg(0)?, 0b100, ops.reg(1)?, -1),
+        "neg" => InstructionEncoder::r_type(OP, ops.reg(0)?, 0b000, 0, ops.reg(1)?, 0b0100000),
+        "seqz" => InstructionEncoder::i_type(OP_IMM, ops.reg(0)?, 0b011, ops.reg(1)?, 1),
+        "snez" => InstructionEncoder::r_type(OP, ops.reg(0)?, 0b011, 0, ops.reg(1)?, 0),
+        "li" => return ops.load_immediate(),
+        "la" => return ops.load_address(),
+
+        // System
+        "ecall" => 0x0000_0073,
+        "ebreak" => 0x0010_0073,
+        "mret" => 0x3020_0073,
+        "wfi" => 0x1050_0073,
+        "fence" => 0x0FF0_000F,
+        "fence.i" => InstructionEncoder::i_type(MISC_MEM, 0, 0b001, 0, 0),
+        "csrrw" => ops.csr(0b001, false)?,
+        "csrrs" => ops.csr(0b010, false)?,
+        "csrrc" => ops.csr(0b011, false)?,
+        "csrrwi" => ops.csr(0b101, true)?,
+        "csrrsi" => ops.csr(0b110, true)?,
+        "csrrci" => ops.csr(0b111, true)?,
+        "csrr" => csr_word(ops.reg(0)?, 0b010, 0, parse_csr(ops.get(1)?)?),
+        "csrw" => csr_word(0, 0b001, ops.reg(1)?, parse_csr(ops.get(0)?)?),
+        "csrs" => csr_word(0, 0b010, ops.reg(1)?, parse_csr(ops.get(0)?)?),
+        "csrc" => csr_word(0, 0b011, ops.reg(1)?, parse_csr(ops.get(0)?)?),
+
+        _ => return Err(AsmError::UnknownInstruction(mnemonic)),
+    };
+    Ok(vec![word])
+}
+
+fn split_operands(text: &str) -> Vec<String> {
+    text.split(',').map(|op| op.trim().to_string()).filter(|op| !op.is_empty()).collect()
+}
+
+fn fits(value: i64, bits: u32) -> bool {
+    let limit = 1i64 << (bits - 1);
+    (-limit..limit).contains(&value)
+}
+
+/// CSR number from its name or a number
+fn parse_csr(text: &str) -> Result<u16> {
+    let name = text.trim().to_lowercase();
+    if let Some(&(_, number)) = CSR_NAMES.iter().find(|(csr, _)| *csr == name) {
+        return Ok(number);
+    }
+    match parse_immediate(&name) {
+        Ok(number) if (0..0x1000).contains(&number) => Ok(number as u16),
+        _ => Err(AsmError::ParseError(format!("Unknown CSR: {}", text))),
+    }
+}
+
+fn csr_word(rd: u8, funct3: u8, rs1: u8, csr: u16) -> Word {
+    (csr as Word) << 20 | (rs1 as Word) << 15 | (funct3 as Word) << 12 | (rd as Word) << 7 | SYSTEM as Word
+}
+
+/// One instruction's operands with what's needed to resolve them
+struct Operands<'a> {
+    list: &'a [String],
+    line: &'a str,
+    address: u32,
+    labels: &'a HashMap<String, u32>,
+}
+
+impl Operands<'_> {
+    fn get(&self, index: usize) -> Result<&str> {
+        self.list
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| AsmError::ParseError(format!("Missing operand in '{}'", self.line)))
+    }
+
+    fn reg(&self, index: usize) -> Result<u8> {
+        parse_register(self.get(index)?)
+    }
+
+    /// Immediate or label address
+    fn value(&self, index: usize) -> Result<i64> {
+        let text = self.get(index)?;
+        match self.labels.get(text) {
+            Some(&addr) => Ok(addr as i64),
+            None => parse_immediate(text),
+        }
+    }
+
+    /// 12-bit signed immediate
+    fn imm12(&self, index: usize) -> Result<i16> {
+        let value = self.value(index)?;
+        if fits(value, 12) {
+            Ok(value as i16)
+        } else {
+            Err(AsmError::InvalidImmediate(self.get(index)?.to_string()))
+        }
+    }
+
+    /// PC-relative offset to a label, or a literal offset
+    fn offset(&self, index: usize, bits: u32) -> Result<i64> {
+        let text = self.get(index)?;
+        let offset = match self.labels.get(text) {
+            Some(&target) => target.wrapping_sub(self.address) as i32 as i64,
+            None => parse_immediate(text).map_err(|_| AsmError::UndefinedLabel(text.to_string()))?,
+        };
+        if fits(offset, bits) && offset % 2 == 0 {
+            Ok(offset)
+        } else {
+            Err(AsmError::InvalidImmediate(text.to_string()))
+        }
+    }
+
+    fn jump_offset(&self, index: usize) -> Result<i32> {
+        self.offset(index, 21).map(|offset| offset as i32)
+    }
+
+    /// "offset(base)" - a bare "(base)" means offset 0
+    fn memory(&self, index: usize) -> Result<(i16, u8)> {
+        let text = self.get(index)?;
+        let (offset, base) = text
+            .strip_suffix(')')
+            .and_then(|t| t.split_once('('))
+            .ok_or_else(|| AsmError::ParseError(format!("Expected offset(register), got '{}'", text)))?;
+        let offset = if offset.trim().is_empty() { 0 } else { parse_immediate(offset)? };
+        if !fits(offset, 12) {
+            return Err(AsmError::InvalidImmediate(offset.to_string()));
+        }
+        Ok((offset as i16, parse_register(base)?))
+    }
+
+    fn r(&self, funct3: u8, funct7: u8) -> Result<Word> {
+        Ok(InstructionEncoder::r_type(OP, self.reg(0)?, funct3, self.reg(1)?, self.reg(2)?, funct7))
+    }
+
+    fn i(&self, funct3: u8) -> Result<Word> {
+        Ok(InstructionEncoder::i_type(OP_IMM, self.reg(0)?, funct3, self.reg(1)?, self.imm12(2)?))
+    }
+
+    fn shift(&self, funct3: u8, funct7: u8) -> Result<Word> {
+        let shamt = self.value(2)?;
+        if !(0..32).contains(&shamt) {
+            return Err(AsmError::InvalidImmediate(shamt.to_string()));
+        }
+        let imm = (funct7 as i16) << 5 | shamt as i16;
+        Ok(InstructionEncoder::i_type(OP_IMM, self.reg(0)?, funct3, self.reg(1)?, imm))
+    }
 
-    let mnemonic = parts[0].to_lowercase();
-    
-    // Stub implementation - will be expanded
-    match mnemonic.as_str() {
-        "addi" => {
-            // Parse: addi rd, rs1, imm
-            Ok(InstructionEncoder::i_type(0b0010011, 1, 0b000, 0, 42))
+    fn load(&self, funct3: u8) -> Result<Word> {
+        let (offset, base) = self.memory(1)?;
+        Ok(InstructionEncoder::i_type(LOAD, self.reg(0)?, funct3, base, offset))
+    }
+
+    fn store(&self, funct3: u8) -> Result<Word> {
+        let (offset, base) = self.memory(1)?;
+        Ok(InstructionEncoder::s_type(STORE, funct3, base, self.reg(0)?, offset))
+    }
+
+    /// Branch on operands `a` and `b` (swapped for bgt/ble)
+    fn branch(&self, funct3: u8, a: usize, b: usize) -> Result<Word> {
+        let offset = self.offset(2, 13)? as i16;
+        Ok(InstructionEncoder::b_type(BRANCH, funct3, self.reg(a)?, self.reg(b)?, offset))
+    }
+
+    /// Branch comparing with zero - `swap` puts x0 first (bgtz, blez)
+    fn branch_zero(&self, funct3: u8, swap: bool) -> Result<Word> {
+        let offset = self.offset(1, 13)? as i16;
+        let (rs1, rs2) = if swap { (0, self.reg(0)?) } else { (self.reg(0)?, 0) };
+        Ok(InstructionEncoder::b_type(BRANCH, funct3, rs1, rs2, offset))
+    }
+
+    /// jalr rs1 | jalr rd, offset(rs1) | jalr rd, rs1, offset
+    fn jalr(&self) -> Result<Word> {
+        match self.list.len() {
+            1 => Ok(InstructionEncoder::i_type(JALR, 1, 0b000, self.reg(0)?, 0)),
+            2 => {
+                let (offset, base) = self.memory(1)?;
+                Ok(InstructionEncoder::i_type(JALR, self.reg(0)?, 0b000, base, offset))
+            }
+            _ => Ok(InstructionEncoder::i_type(JALR, self.reg(0)?, 0b000, self.reg(1)?, self.imm12(2)?)),
+        }
+    }
+
+    fn csr(&self, funct3: u8, immediate: bool) -> Result<Word> {
+        let source = if immediate {
+            let value = self.value(2)?;
+            if !(0..32).contains(&value) {
+                return Err(AsmError::InvalidImmediate(value.to_string()));
+            }
+            value as u8
+        } else {
+            self.reg(2)?
+        };
+        Ok(csr_word(self.reg(0)?, funct3, source, parse_csr(self.get(1)?)?))
+    }
+
+    /// lui + addi, with the lui rounded so the sign-extended addi lands exactly
+    fn upper_lower(value: i64) -> (i32, i16) {
+        let value = value as u32;
+        let lower = ((value & 0xFFF) as i16) << 4 >> 4;
+        let upper = value.wrapping_sub(lower as i32 as u32) as i32;
+        (upper, lower)
+    }
+
+    fn load_immediate(&self) -> Result<Vec<Word>> {
+        let rd = self.reg(0)?;
+        let value = self.value(1)?;
+        if !(i32::MIN as i64..=u32::MAX as i64).contains(&value) {
+            return Err(AsmError::InvalidImmediate(value.to_string()));
+        }
+        // Sized as in `instruction_size`, before labels are known
+        if fits(value, 12) && parse_immediate(self.get(1)?).is_ok() {
+            return Ok(vec![InstructionEncoder::i_type(OP_IMM, rd, 0b000, 0, value as i16)]);
+        }
+        let (upper, lower) = Self::upper_lower(value);
+        Ok(vec![
+            InstructionEncoder::u_type(LUI, rd, upper),
+            InstructionEncoder::i_type(OP_IMM, rd, 0b000, rd, lower),
+        ])
+    }
+
+    /// auipc + addi, PC-relative
+    fn load_address(&self) -> Result<Vec<Word>> {
+        let rd = self.reg(0)?;
+        let text = self.get(1)?;
+        let target = *self.labels.get(text).ok_or_else(|| AsmError::UndefinedLabel(text.to_string()))?;
+        let (upper, lower) = Self::upper_lower(target.wrapping_sub(self.address) as i64);
+        Ok(vec![
+            InstructionEncoder::u_type(AUIPC, rd, upper),
+            InstructionEncoder::i_type(OP_IMM, rd, 0b000, rd, lower),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_at(line: &str, address: u32, labels: &[(&str, u32)]) -> Result<Vec<Word>> {
+        let labels = labels.iter().map(|&(name, addr)| (name.to_string(), addr)).collect();
+        encode_instruction(line, address, &labels)
+    }
+
+    fn encode(line: &str) -> Result<Vec<Word>> {
+        encode_at(line, 0, &[])
+    }
+
+    /// Words for `line`, checking `instruction_size` agrees
+    fn words(line: &str) -> Vec<Word> {
+        let words = encode(line).unwrap();
+        assert_eq!(instruction_size(line), 4 * words.len() as u32, "{}", line);
+        words
+    }
+
+    #[test]
+    fn test_known_encodings() {
+        for (line, word) in [
+            ("add x1, x2, x3", 0x003100B3),                 // R
+            ("addi x1, x2, -1", 0xFFF10093),                // I
+            ("srai x1, x2, 3", 0x40315093),
+            ("lw a0, 8(sp)", 0x00812503),
+            ("sw ra, 12(sp)", 0x00112623),                  // S
+            ("beq x1, x2, 8", 0x00208463),                  // B
+            ("lui a0, 0x12345", 0x12345537),                // U
+            ("jal ra, 16", 0x010000EF),                     // J
+            ("csrrw t0, mscratch, t1", 0x340312F3),
+            ("ecall", 0x00000073),
+        ] {
+            assert_eq!(words(line), [word], "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_branch_and_jump_ranges() {
+        assert_eq!(words("beq x0, x0, 4094"), [0x7E000FE3]);
+        assert_eq!(words("beq x0, x0, -4096"), [0x80000063]);
+        assert_eq!(words("jal x0, 1048574"), [0x7FFFF06F]);
+        assert_eq!(words("jal x0, -1048576"), [0x8000006F]);
+        for line in ["beq x0, x0, 4096", "bnez a0, -4098", "jal x0, 1048576", "j -1048578"] {
+            assert!(matches!(encode(line), Err(AsmError::InvalidImmediate(_))), "{}", line);
+        }
+        for line in ["beq x0, x0, 3", "jal x0, -5"] {
+            assert!(matches!(encode(line), Err(AsmError::InvalidImmediate(_))), "{}", line);
+        }
+
+        // Label offsets are checked the same way
+        assert_eq!(encode_at("bne a0, a1, far", 4, &[("far", 4098)]).unwrap(), encode("bne a0, a1, 4094").unwrap());
+        assert!(encode_at("bne a0, a1, far", 4, &[("far", 4100)]).is_err());
+        assert!(matches!(encode("j nowhere"), Err(AsmError::UndefinedLabel(_))));
+    }
+
+    #[test]
+    fn test_li_and_la_splitting() {
+        assert_eq!(words("li a0, 0x7FF"), [0x7FF00513]);
+        assert_eq!(words("li a0, -2048"), [0x80000513]);
+        assert_eq!(words("li a0, 0x800"), [0x00001537, 0x80050513]);           // lui 1; addi -2048
+        assert_eq!(words("li a0, 0xFFFFF800"), [0x00000537, 0x80050513]);      // lui 0; addi -2048
+        assert_eq!(words("li a0, -2049"), [0xFFFFF537, 0x7FF50513]);           // lui 0xfffff; addi 2047
+        assert!(matches!(encode("li a0, 0x100000000"), Err(AsmError::InvalidImmediate(_))));
+
+        let la = |target| encode_at("la a0, data", 0x1000, &[("data", target)]).unwrap();
+        assert_eq!(la(0x2800), [0x00002517, 0x80050513]);                      // +0x1800 rounds the auipc up
+        assert_eq!(la(0x27FC), [0x00001517, 0x7FC50513]);
+        assert_eq!(la(0x0000), [0xFFFFF517, 0x00050513]);                      // -0x1000
+    }
+
+    #[test]
+    fn test_csr_names_and_register_aliases() {
+        assert_eq!(words("csrr t0, mhartid"), [0xF14022F3]);
+        assert_eq!(words("csrw mtvec, a0"), [0x30551073]);
+        assert_eq!(words("csrrsi zero, 0x340, 1"), words("csrrsi x0, mscratch, 1"));
+        assert!(matches!(encode("csrr t0, satp"), Err(AsmError::ParseError(_))));
+        assert!(matches!(encode("csrrwi t0, mscratch, 32"), Err(AsmError::InvalidImmediate(_))));
+
+        for (name, number) in [("zero", 0), ("ra", 1), ("fp", 8), ("s0", 8), ("x8", 8), ("S11", 27), ("t6", 31)] {
+            assert_eq!(parse_register(name).unwrap(), number, "{}", name);
+        }
+        for name in ["x32", "a8", "f0"] {
+            assert!(matches!(parse_register(name), Err(AsmError::InvalidRegister(_))), "{}", name);
         }
-        _ => Err(AsmError::UnknownInstruction(mnemonic)),
+        assert_eq!(words("addi fp, sp, 16"), words("addi s0, x2, 16"));
     }
 }
@@ -15,14 +15,14 @@
 //! ```
 
 use std::collections::HashMap;
-use riscv32i_sim::{Word, InstructionEncoder};
+use riscv32i_sim::Word;
 
 pub mod parser;
 pub mod encoder;
 pub mod labels;
 
 pub use parser::parse_assembly;
-pub use encoder::encode_instruction;
+pub use encoder::{encode_instruction, ABI_NAMES, instruction_size, parse_immediate, parse_register, strip_comment};
 
 #[derive(Debug, thiserror::Error)]
 pub enum AsmError {
@@ -65,21 +65,21 @@ impl Assembler {
         self.generate_code(source)
     }
 
+    /// Label addresses from the last `assemble`
+    pub fn labels(&self) -> &HashMap<String, u32> {
+        &self.labels
+    }
+
     fn collect_labels(&mut self, source: &str) -> Result<()> {
         let mut address = 0u32;
-        
+        self.labels.clear();
+
         for line in source.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
-            
-            if line.ends_with(':') {
-                let label = line.trim_end_matches(':');
+            let (label, rest) = split_label(line);
+            if let Some(label) = label {
                 self.labels.insert(label.to_string(), address);
-            } else {
-                address += 4;
             }
+            address += instruction_size(rest);
         }
         
         Ok(())
@@ -90,20 +90,34 @@ impl Assembler {
         let mut address = 0u32;
         
         for line in source.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') || line.ends_with(':') {
+            let (_, rest) = split_label(line);
+            if instruction_size(rest) == 0 {
                 continue;
             }
             
-            let instruction = encode_instruction(line, address, &self.labels)?;
-            program.push((address, instruction));
-            address += 4;
+            for instruction in encode_instruction(rest, address, &self.labels)? {
+                program.push((address, instruction));
+                address += 4;
+            }
         }
         
         Ok(program)
     }
 }
 
+/// "label: rest" - the label (if any) and the rest of the line
+fn split_label(line: &str) -> (Option<&str>, &str) {
+    let code = strip_comment(line);
+    match code.split_once(':') {
+        Some((label, rest)) if is_label(label.trim()) => (Some(label.trim()), rest),
+        _ => (None, code),
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+}
+
 impl Default for Assembler {
     fn default() -> Self {
         Self::new()
@@ -113,11 +127,44 @@ impl Default for Assembler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use riscv32i_sim::InstructionEncoder;
 
     #[test]
     fn test_simple_assembly() {
         let mut asm = Assembler::new();
         let result = asm.assemble("addi x1, x0, 42");
         assert!(result.is_ok());
+        assert_eq!(result.unwrap(), [(0, InstructionEncoder::i_type(0b0010011, 1, 0b000, 0, 42))]);
+    }
+
+    #[test]
+    fn test_labels_and_pseudo_instructions() {
+        let source = "
+            # sum 1..10 into a0, then store it at `result`
+            _start: li t0, 10
+                    li a0, 0
+            loop:   add a0, a0, t0
+                    addi t0, t0, -1
+                    bnez t0, loop
+                    la t1, result
+                    sw a0, 0(t1)
+                    li t2, 0x12345FFF
+            done:   j done
+            result: .word 0
+        ";
+        let mut asm = Assembler::new();
+        let program = asm.assemble(source).unwrap();
+        assert_eq!(asm.labels()["loop"], 8);
+        assert_eq!(asm.labels()["result"], 44);
+        assert_eq!(program.len(), 12);
+
+        let mut cpu = riscv32i_sim::Cpu::new();
+        cpu.load_program(&program);
+        cpu.run_cycles(100);
+        assert_eq!(cpu.registers.peek(10), 55);
+        assert_eq!(cpu.registers.peek(7), 0x12345FFF);
+        assert_eq!(cpu.memory.load(44, 0b010), 55);
+        assert!(matches!(asm.assemble("addi x1, x0, 5000"), Err(AsmError::InvalidImmediate(_))));
+        assert!(matches!(asm.assemble("j nowhere"), Err(AsmError::UndefinedLabel(_))));
     }
 }
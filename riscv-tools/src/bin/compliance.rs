@@ -0,0 +1,114 @@
+//! riscv-compliance: Run riscv-arch-test style signature tests
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::Parser;
+use riscv32i_sim::Addr;
+use riscv_tools::compliance::{self, ComplianceConfig, ComplianceReport, ComplianceTest};
+
+#[derive(Parser)]
+#[command(author, version, about = "Run RISC-V compliance tests and check their signatures", long_about = None)]
+struct Args {
+    /// Test ELFs, or directories of them (references found as <name>.reference_output)
+    #[arg(required = true)]
+    tests: Vec<PathBuf>,
+
+    /// Reference signature (only with a single test ELF)
+    #[arg(short, long)]
+    reference: Option<PathBuf>,
+
+    /// Write each test's signature to <DIR>/<name>.signature
+    #[arg(short, long)]
+    signature_dir: Option<PathBuf>,
+
+    /// Memory base address
+    #[arg(long, default_value = "0x80000000", value_parser = parse_addr)]
+    memory_base: Addr,
+
+    /// Memory size in KiB
+    #[arg(long, default_value = "4096")]
+    memory_kib: usize,
+
+    /// Maximum cycles per test
+    #[arg(short = 'c', long, default_value = "10000000")]
+    max_cycles: u64,
+
+    /// Wall-clock limit per test in milliseconds
+    #[arg(long, default_value = "10000")]
+    timeout_ms: u64,
+}
+
+fn parse_addr(text: &str) -> Result<Addr, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => Addr::from_str_radix(&hex.replace('_', ""), 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|e| format!("invalid address '{}': {}", text, e))
+}
+
+fn collect_tests(args: &Args) -> Result<Vec<ComplianceTest>, String> {
+    if args.reference.is_some() && (args.tests.len() != 1 || args.tests[0].is_dir()) {
+        return Err("--reference needs exactly one test ELF".to_string());
+    }
+
+    let mut tests = Vec::new();
+    for path in &args.tests {
+        if path.is_dir() {
+            tests.extend(compliance::discover(path).map_err(|e| format!("{}: {}", path.display(), e))?);
+            continue;
+        }
+
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("test").to_string();
+        let reference = args.reference.clone().or_else(|| {
+            let candidate = path.with_file_name(format!("{}.reference_output", name));
+            candidate.is_file().then_some(candidate)
+        });
+        tests.push(ComplianceTest { name, elf: path.clone(), reference });
+    }
+    Ok(tests)
+}
+
+fn write_signature(dir: &Path, name: &str, words: &[u32]) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(format!("{}.signature", name)), compliance::format_signature(words))
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let tests = match collect_tests(&args) {
+        Ok(tests) => tests,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let config = ComplianceConfig {
+        memory_base: args.memory_base,
+        memory_size: args.memory_kib * 1024,
+        max_cycles: args.max_cycles,
+        timeout: Duration::from_millis(args.timeout_ms),
+    };
+
+    let mut report = ComplianceReport::default();
+    for test in &tests {
+        let result = compliance::run_test(test, &config);
+        if let Some(dir) = &args.signature_dir {
+            if let Err(e) = write_signature(dir, &result.name, &result.signature) {
+                eprintln!("{}: failed to write signature: {}", result.name, e);
+            }
+        }
+        report.tests.push(result);
+    }
+
+    println!("{}", report);
+
+    if report.all_passed() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -1,24 +1,675 @@
 //! riscv-debug: Interactive debugger
+//!
+//! A gdb-style shell over `riscv_tools::Debugger`. Pressing Enter on an
+//! empty line repeats the last stepping or display command; `help` lists
+//! them all. Ctrl-C stops a running `continue`, `next` or `finish` at the
+//! current PC.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use clap::Parser;
+use colored::Colorize;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use riscv32i_sim::linux::setup_process_stack;
+use riscv32i_sim::{Addr, Cpu, ElfImage, Htif, LinuxSyscalls, Memory, RunResult, StopReason, Symbol, SymbolKind, SymbolTable, Word};
+use riscv_asm::{parse_immediate, parse_register, Assembler, ABI_NAMES};
+use riscv_tools::Debugger;
 
 #[derive(Parser)]
 #[command(author, version, about = "Interactive RISC-V debugger", long_about = None)]
 struct Args {
-    /// Program to debug
-    file: String,
+    /// Program to debug: RV32 ELF executable, assembly source (.s, .S,
+    /// .asm) or a memory image
+    file: Option<String>,
+
+    /// Load address (and start PC) for assembly and memory images
+    #[arg(long, default_value = "0", value_parser = parse_addr)]
+    base: Addr,
+
+    /// Memory size in KiB
+    #[arg(long, default_value = "16384")]
+    memory_kib: usize,
+}
+
+fn parse_addr(text: &str) -> Result<Addr, String> {
+    parse_immediate(text).map(|value| value as Addr).map_err(|e| e.to_string())
+}
+
+const HELP: &str = "\
+load FILE [BASE]               load an ELF, assembly source (.s/.asm) or memory image
+step [N]           (s)         execute N instructions
+next [N]           (n)         step over calls
+finish                         run until the current function returns
+continue           (c)         run until a breakpoint or the program stops
+break LOC          (b)         set a breakpoint
+delete [LOC]       (d)         delete a breakpoint, or all of them
+breakpoints        (info b)    list breakpoints
+registers [FMT]    (r)         show registers: hex (default), dec or signed
+print REG          (p)         show a register (or pc) in every format
+x/NFU LOC                      examine N units of memory: format x d u c s i, unit b h w
+disassemble [LOC] [N]  (disas) disassemble around the PC, or N instructions at LOC
+set REG VALUE                  write a register (or pc)
+set byte|half|word ADDR VALUE  write memory
+history                        list previous commands
+quit               (q)
+
+LOC is an address, symbol, register or pc, optionally + or - an offset.
+Enter on an empty line repeats the last command.";
+
+/// Instructions shown before and after the PC by `disassemble`
+const CONTEXT_BEFORE: u32 = 4;
+const CONTEXT_AFTER: u32 = 6;
+
+/// What the shell does after a command
+enum Flow {
+    Continue,
+    Quit,
+}
+
+struct Session {
+    debugger: Debugger,
+    base: Addr,
+    memory_kib: usize,
+    /// Raised by Ctrl-C
+    interrupt: Arc<AtomicBool>,
+}
+
+impl Session {
+    fn new(args: &Args) -> Self {
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.set_interrupt(interrupt.clone());
+        Self {
+            debugger,
+            base: args.base,
+            memory_kib: args.memory_kib,
+            interrupt,
+        }
+    }
+
+    fn cpu(&self) -> &Cpu {
+        self.debugger.get_cpu()
+    }
+
+    fn cpu_mut(&mut self) -> &mut Cpu {
+        self.debugger.get_cpu_mut()
+    }
+
+    fn execute(&mut self, line: &str) -> Result<Flow, String> {
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let args: Vec<&str> = rest.split_whitespace().collect();
+
+        match command {
+            "load" | "file" => {
+                let file = args.first().ok_or("usage: load FILE [BASE]")?;
+                let base = args.get(1).map(|b| self.resolve(b)).transpose()?.unwrap_or(self.base);
+                self.load(file, base)?;
+                self.show_location();
+            }
+            "step" | "s" | "stepi" | "si" => self.repeat(&args, Debugger::step)?,
+            "next" | "n" | "nexti" | "ni" => self.repeat(&args, Debugger::step_over)?,
+            "finish" | "fin" => {
+                let result = self.debugger.finish();
+                self.report(&result);
+            }
+            "continue" | "c" | "run" => {
+                let result = self.debugger.run_until_breakpoint();
+                self.report(&result);
+            }
+            "break" | "b" => {
+                let loc = args.first().map_or(Ok(self.cpu().control.get_pc()), |loc| self.resolve(loc))?;
+                self.debugger.add_breakpoint(loc);
+                println!("Breakpoint at {}", self.describe_addr(loc));
+            }
+            "delete" | "d" => match args.first() {
+                None => {
+                    self.debugger.clear_breakpoints();
+                    println!("All breakpoints deleted");
+                }
+                Some(loc) => {
+                    let addr = self.resolve(loc)?;
+                    if !self.debugger.remove_breakpoint(addr) {
+                        return Err(format!("no breakpoint at {}", self.describe_addr(addr)));
+                    }
+                }
+            },
+            "breakpoints" => self.list_breakpoints(),
+            "info" => match args.first().copied() {
+                Some("b" | "break" | "breakpoints") => self.list_breakpoints(),
+                Some("r" | "reg" | "registers") => self.show_registers(args.get(1).copied())?,
+                _ => return Err("usage: info breakpoints | info registers [FMT]".to_string()),
+            },
+            "registers" | "regs" | "r" => self.show_registers(args.first().copied())?,
+            "print" | "p" => self.print(args.first().ok_or("usage: print REG")?)?,
+            "disassemble" | "disas" => {
+                let count = args.get(1).map(|n| n.parse::<u32>().map_err(|e| e.to_string())).transpose()?;
+                match args.first() {
+                    Some(loc) => self.disassemble(self.resolve(loc)?, count.unwrap_or(CONTEXT_BEFORE + CONTEXT_AFTER)),
+                    None => self.disassemble_around_pc(),
+                }
+            }
+            "set" => self.set(&args)?,
+            "quit" | "q" | "exit" => return Ok(Flow::Quit),
+            "help" | "h" | "?" => println!("{}", HELP),
+            _ if command == "x" || command.starts_with("x/") => self.examine(command.strip_prefix("x").unwrap_or(""), rest)?,
+            _ => return Err(format!("unknown command '{}' (try 'help')", command)),
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Load a program into a fresh CPU, keeping the breakpoints
+    fn load(&mut self, file: &str, base: Addr) -> Result<(), String> {
+        let bytes = std::fs::read(file).map_err(|e| format!("cannot read {}: {}", file, e))?;
+        let mut cpu = Cpu::new();
+        let memory_size = self.memory_kib * 1024;
+        let symbols = if bytes.starts_with(b"\x7fELF") {
+            load_elf(&mut cpu, file, &bytes, memory_size)?
+        } else if is_assembly(file) {
+            let source = String::from_utf8(bytes).map_err(|e| format!("{}: {}", file, e))?;
+            load_assembly(&mut cpu, &source, base, memory_size).map_err(|e| format!("{}: {}", file, e))?
+        } else {
+            cpu.memory = Memory::with_base(base & !0xFFF, memory_size);
+            let start = cpu.memory.load_image_file(file, base).map_err(|e| e.to_string())?;
+            cpu.control.set_pc(start.unwrap_or(base));
+            SymbolTable::new()
+        };
+
+        self.install(cpu, symbols);
+        println!("{}", format!("Loaded {} ({} symbols)", file, self.debugger.get_symbols().len()).yellow());
+        Ok(())
+    }
+
+    /// Debug `cpu` from now on, keeping the breakpoints
+    fn install(&mut self, cpu: Cpu, symbols: SymbolTable) {
+        let breakpoints = self.debugger.breakpoints().to_vec();
+        self.debugger = Debugger::new(cpu);
+        self.debugger.set_symbols(symbols);
+        self.debugger.set_interrupt(self.interrupt.clone());
+        for addr in breakpoints {
+            self.debugger.add_breakpoint(addr);
+        }
+    }
+
+    /// Run a stepping command N times, stopping early if the program does
+    fn repeat(&mut self, args: &[&str], mut command: impl FnMut(&mut Debugger) -> RunResult) -> Result<(), String> {
+        let count = args.first().map_or(Ok(1), |n| n.parse::<u32>().map_err(|e| e.to_string()))?;
+        let mut result = None;
+        for _ in 0..count {
+            let run = command(&mut self.debugger);
+            result = Some(run);
+            if !self.completed(&run) {
+                break;
+            }
+        }
+        if let Some(result) = result {
+            self.report(&result);
+        }
+        Ok(())
+    }
+
+    /// A step/next/finish that stopped where it meant to
+    fn completed(&self, result: &RunResult) -> bool {
+        match result.reason {
+            StopReason::InstructionLimit => true,
+            StopReason::Breakpoint(pc) => !self.debugger.breakpoints().contains(&pc),
+            _ => false,
+        }
+    }
+
+    /// Why execution stopped (if noteworthy), then where
+    fn report(&self, result: &RunResult) {
+        if !self.completed(result) {
+            let message = match result.reason {
+                StopReason::Exit => format!("Program exited with code {}", result.exit_code.unwrap_or(0)),
+                StopReason::Ebreak => "Stopped at ebreak".to_string(),
+                StopReason::Breakpoint(pc) => format!("Breakpoint at {}", self.describe_addr(pc)),
+                StopReason::IllegalInstruction { pc, word } => format!("Illegal instruction 0x{:08x} at {}", word, self.describe_addr(pc)),
+                StopReason::SelfLoop(pc) => format!("Halted in self-loop at {}", self.describe_addr(pc)),
+                StopReason::Interrupted => "Interrupted".to_string(),
+                StopReason::CycleLimit | StopReason::InstructionLimit | StopReason::Timeout => "Stopped".to_string(),
+            };
+            println!("{}", message.yellow());
+        }
+        self.show_location();
+    }
+
+    fn show_location(&self) {
+        let pc = self.cpu().control.get_pc();
+        println!("{}", self.format_instruction(pc));
+    }
+
+    /// "0x00000010 <main+4>:" - the symbol part only if one covers `addr`
+    fn describe_addr(&self, addr: Addr) -> String {
+        match self.debugger.get_symbols().symbolize(addr) {
+            Some(_) => format!("0x{:08x} <{}>", addr, self.debugger.get_symbols().format(addr)),
+            None => format!("0x{:08x}", addr),
+        }
+    }
+
+    /// One disassembly line, marked with => at the PC and * at breakpoints
+    fn format_instruction(&self, addr: Addr) -> String {
+        let word = self.cpu().memory.fetch(addr);
+        let text = riscv_disasm::disassemble(word).unwrap_or_else(|_| format!(".word 0x{:08x}", word));
+        let marker = if addr == self.cpu().control.get_pc() { "=>" } else { "  " };
+        let breakpoint = if self.debugger.breakpoints().contains(&addr) { "*" } else { " " };
+        format!(
+            "{}{} {}: {:08x}  {}",
+            marker.green().bold(),
+            breakpoint.red().bold(),
+            self.describe_addr(addr),
+            word,
+            riscv_tools::formatter::colorize_instruction(&text)
+        )
+    }
+
+    /// Context before the PC only where it is in RAM
+    fn disassemble_around_pc(&self) {
+        let pc = self.cpu().control.get_pc();
+        let before = pc.checked_sub(self.cpu().memory.base()).map_or(0, |offset| (offset / 4).min(CONTEXT_BEFORE));
+        self.disassemble(pc - 4 * before, before + CONTEXT_AFTER);
+    }
+
+    fn disassemble(&self, start: Addr, count: u32) {
+        for i in 0..count {
+            let addr = start.wrapping_add(4 * i);
+            if let Some((symbol, 0)) = self.debugger.get_symbols().symbolize(addr) {
+                println!("{}", format!("{}:", symbol.name).cyan());
+            }
+            println!("{}", self.format_instruction(addr));
+        }
+    }
+
+    fn list_breakpoints(&self) {
+        if self.debugger.breakpoints().is_empty() {
+            println!("No breakpoints");
+        }
+        for (i, &addr) in self.debugger.breakpoints().iter().enumerate() {
+            println!("{:>3}  {}", i + 1, self.describe_addr(addr));
+        }
+    }
+
+    fn show_registers(&self, format: Option<&str>) -> Result<(), String> {
+        let format = match format.unwrap_or("hex") {
+            "hex" | "x" => 'x',
+            "dec" | "u" => 'u',
+            "signed" | "d" => 'd',
+            other => return Err(format!("unknown format '{}' (hex, dec or signed)", other)),
+        };
+        println!("{:>4}       {}", "pc", self.describe_addr(self.cpu().control.get_pc()));
+        for row in 0..8 {
+            let line: Vec<String> = (0..4)
+                .map(|col| {
+                    let reg = col * 8 + row;
+                    let value = self.cpu().registers.peek(reg as u8);
+                    let text = format_value(value, format);
+                    let text = if value == 0 { text.dimmed().to_string() } else { text };
+                    format!("{:>4} {:<4} {:>12}", ABI_NAMES[reg], format!("x{}", reg), text)
+                })
+                .collect();
+            println!("{}", line.join("   "));
+        }
+        Ok(())
+    }
+
+    fn print(&self, name: &str) -> Result<(), String> {
+        let name = name.trim_start_matches('$');
+        let (label, value) = match name {
+            "pc" => ("pc".to_string(), self.cpu().control.get_pc()),
+            _ => {
+                let reg = parse_register(name).map_err(|e| e.to_string())?;
+                (format!("{} (x{})", ABI_NAMES[reg as usize], reg), self.cpu().registers.peek(reg))
+            }
+        };
+        println!("{} = 0x{:08x}  {}  {}", label, value, value, value as i32);
+        Ok(())
+    }
+
+    /// x/NFU LOC
+    fn examine(&mut self, spec: &str, loc: &str) -> Result<(), String> {
+        let (count, format, unit) = parse_examine(spec)?;
+        let mut addr = self.resolve(if loc.is_empty() { "pc" } else { loc })?;
+
+        match format {
+            'i' => self.disassemble(addr, count),
+            's' => {
+                for _ in 0..count {
+                    let bytes = self.read_string(addr);
+                    println!("{}:  {:?}", self.describe_addr(addr), String::from_utf8_lossy(&bytes));
+                    addr = addr.wrapping_add(bytes.len() as Addr + 1);
+                }
+            }
+            _ => {
+                let unit = unit.unwrap_or(if format == 'c' { 1 } else { 4 });
+                let per_line = 16 / unit as u32;
+                for line in 0..count.div_ceil(per_line) {
+                    let line_addr = addr.wrapping_add(line * 16);
+                    let values: Vec<String> = (0..per_line.min(count - line * per_line))
+                        .map(|i| {
+                            let bytes = self.cpu().memory.read_bytes(line_addr.wrapping_add(i * unit as u32), unit);
+                            let value = bytes.iter().rev().fold(0, |value, &b| value << 8 | b as Word);
+                            format_unit(value, unit, format)
+                        })
+                        .collect();
+                    println!("{}:  {}", self.describe_addr(line_addr), values.join(" "));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// NUL-terminated string at `addr` (at most 256 bytes)
+    fn read_string(&self, addr: Addr) -> Vec<u8> {
+        self.cpu().memory.read_bytes(addr, 256).into_iter().take_while(|&b| b != 0).collect()
+    }
+
+    fn set(&mut self, args: &[&str]) -> Result<(), String> {
+        let args: Vec<&str> = args.iter().copied().filter(|&a| a != "=").collect();
+        match args.as_slice() {
+            [size @ ("byte" | "half" | "word"), addr, value] => {
+                let addr = self.resolve(addr)?;
+                let value = self.resolve(value)?;
+                let len = match *size {
+                    "byte" => 1,
+                    "half" => 2,
+                    _ => 4,
+                };
+                if !self.cpu().memory.contains(addr, len) {
+                    return Err(format!("0x{:08x} is outside memory", addr));
+                }
+                self.cpu_mut().memory.write_bytes(addr, &value.to_le_bytes()[..len]);
+            }
+            [reg, value] => {
+                let value = self.resolve(value)?;
+                match reg.trim_start_matches('$') {
+                    "pc" => self.cpu_mut().control.set_pc(value),
+                    name => {
+                        let reg = parse_register(name).map_err(|e| e.to_string())?;
+                        self.cpu_mut().registers.poke(reg, value);
+                    }
+                }
+            }
+            _ => return Err("usage: set REG VALUE | set byte|half|word ADDR VALUE".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Address or value from a number, symbol, register or pc, optionally
+    /// followed by +/- an offset
+    fn resolve(&self, text: &str) -> Result<Addr, String> {
+        if let Some(split) = text.get(1..).unwrap_or("").rfind(['+', '-']).map(|i| i + 1) {
+            let base = self.resolve(&text[..split])?;
+            let offset = parse_immediate(&text[split + 1..]).map_err(|e| e.to_string())? as Addr;
+            return Ok(if &text[split..=split] == "+" { base.wrapping_add(offset) } else { base.wrapping_sub(offset) });
+        }
+        if let Ok(value) = parse_immediate(text) {
+            return Ok(value as Addr);
+        }
+        if let Some(addr) = self.debugger.get_symbols().lookup(text) {
+            return Ok(addr);
+        }
+        let name = text.trim_start_matches('$');
+        if name == "pc" {
+            return Ok(self.cpu().control.get_pc());
+        }
+        match parse_register(name) {
+            Ok(reg) => Ok(self.cpu().registers.peek(reg)),
+            Err(_) => Err(format!("no symbol, register or number '{}'", text)),
+        }
+    }
+}
+
+/// "/NFU" of an x command: count, format letter and unit size (if given)
+fn parse_examine(spec: &str) -> Result<(u32, char, Option<usize>), String> {
+    let spec = spec.trim_start_matches('/');
+    let digits: String = spec.chars().take_while(char::is_ascii_digit).collect();
+    let count = if digits.is_empty() { 1 } else { digits.parse::<u32>().map_err(|e| e.to_string())? };
+    let mut format = 'x';
+    let mut unit = None;
+    for c in spec[digits.len()..].chars() {
+        match c {
+            'x' | 'd' | 'u' | 'c' | 's' | 'i' => format = c,
+            'b' => unit = Some(1),
+            'h' => unit = Some(2),
+            'w' => unit = Some(4),
+            _ => return Err(format!("unknown format letter '{}'", c)),
+        }
+    }
+    Ok((count, format, unit))
+}
+
+fn format_value(value: Word, format: char) -> String {
+    match format {
+        'u' => value.to_string(),
+        'd' => (value as i32).to_string(),
+        _ => format!("0x{:08x}", value),
+    }
+}
+
+/// One memory unit of `size` bytes in an x/NFU format
+fn format_unit(value: Word, size: usize, format: char) -> String {
+    let signed = match size {
+        1 => value as u8 as i8 as i32,
+        2 => value as u16 as i16 as i32,
+        _ => value as i32,
+    };
+    match format {
+        'd' => format!("{:>11}", signed),
+        'u' => format!("{:>10}", value),
+        'c' => format!("{:>4}", format!("{:?}", value as u8 as char).trim_matches('\'')),
+        _ => format!("0x{:0width$x}", value, width = size * 2),
+    }
+}
+
+fn is_assembly(file: &str) -> bool {
+    [".s", ".S", ".asm"].iter().any(|ext| file.ends_with(ext))
+}
+
+/// User-mode program, set up as riscv-run does: Linux syscalls on host
+/// stdio and argv on the stack
+fn load_elf(cpu: &mut Cpu, file: &str, bytes: &[u8], memory_size: usize) -> Result<SymbolTable, String> {
+    let image = ElfImage::parse(bytes).map_err(|e| format!("{}: {}", file, e))?;
+    cpu.memory = Memory::with_base(image.base() & !0xFFF, memory_size);
+    image.load_into(cpu).map_err(|e| format!("{}: {}", file, e))?;
+    if let Some(htif) = Htif::from_elf(&image) {
+        cpu.set_htif(htif);
+    }
+    let syscalls = LinuxSyscalls::stdio().with_program_break(image.end().next_multiple_of(16) as u32);
+    cpu.set_syscall_handler(Box::new(syscalls));
+    setup_process_stack(cpu, &image, &[file], &[]);
+    Ok(image.symbols.clone())
+}
+
+/// Assemble at `base`, with sp at the top of memory and the labels as symbols
+fn load_assembly(cpu: &mut Cpu, source: &str, base: Addr, memory_size: usize) -> Result<SymbolTable, String> {
+    let mut assembler = Assembler::new();
+    let program = assembler.assemble(source).map_err(|e| e.to_string())?;
+    let program: Vec<(Addr, Word)> = program.into_iter().map(|(addr, word)| (base.wrapping_add(addr), word)).collect();
+
+    cpu.memory = Memory::with_base(base & !0xFFF, memory_size);
+    cpu.load_program(&program);
+    cpu.control.set_pc(base);
+    cpu.registers.poke(2, (cpu.memory.end() as Addr) & !0xF);
+
+    // Each label runs to the next one (or the end of the program)
+    let end = base.wrapping_add(4 * program.len() as Addr);
+    let mut labels: Vec<(&String, Addr)> = assembler.labels().iter().map(|(name, &addr)| (name, base.wrapping_add(addr))).collect();
+    labels.sort_by_key(|&(_, addr)| addr);
+    let symbols = labels.iter().enumerate().map(|(i, &(name, addr))| {
+        let next = labels[i + 1..].iter().map(|&(_, a)| a).find(|&a| a > addr).unwrap_or(end);
+        Symbol { name: name.clone(), addr, size: next.saturating_sub(addr), kind: SymbolKind::Function }
+    });
+    Ok(symbols.collect())
+}
+
+/// Commands that Enter on an empty line repeats
+fn repeatable(line: &str) -> bool {
+    let command = line.split_whitespace().next().unwrap_or("");
+    matches!(
+        command,
+        "step" | "s" | "stepi" | "si" | "next" | "n" | "nexti" | "ni" | "finish" | "fin" | "continue" | "c"
+            | "registers" | "regs" | "r" | "print" | "p" | "disassemble" | "disas"
+    ) || command == "x" || command.starts_with("x/")
+}
+
+/// The command an input line runs: Enter on an empty line repeats the
+/// last repeatable command
+fn command_for(line: &str, last: &mut String) -> Option<String> {
+    let line = line.trim();
+    let command = if !line.is_empty() {
+        line.to_string()
+    } else if !last.is_empty() {
+        last.clone()
+    } else {
+        return None;
+    };
+    *last = if repeatable(&command) { command.clone() } else { String::new() };
+    Some(command)
 }
 
 fn main() {
-    let _args = Args::parse();
-
-    println!("RISC-V Interactive Debugger");
-    println!("Stub - to be implemented");
-    println!("\nPlanned commands:");
-    println!("  step (s)      - Execute one instruction");
-    println!("  continue (c)  - Run until breakpoint");
-    println!("  break (b)     - Set breakpoint");
-    println!("  registers (r) - Show register state");
-    println!("  memory (m)    - Show memory");
-    println!("  quit (q)      - Exit debugger");
+    let args = Args::parse();
+
+    println!("{}", "RISC-V Interactive Debugger".green().bold());
+    println!("Type 'help' for commands");
+
+    let mut session = Session::new(&args);
+    let interrupt = session.interrupt.clone();
+    if let Err(e) = ctrlc::set_handler(move || interrupt.store(true, Ordering::Relaxed)) {
+        eprintln!("{}", format!("Warning: Ctrl-C will not stop execution: {}", e).yellow());
+    }
+    if let Some(file) = &args.file {
+        match session.load(file, args.base) {
+            Ok(()) => session.show_location(),
+            Err(e) => eprintln!("{}", format!("Error: {}", e).red()),
+        }
+    }
+
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("{}", format!("Error: cannot start line editor: {}", e).red());
+            return;
+        }
+    };
+    let mut last = String::new();
+    loop {
+        let line = match editor.readline("(rvdb) ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", format!("Error: {}", e).red());
+                break;
+            }
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.trim());
+        }
+        let Some(command) = command_for(&line, &mut last) else {
+            continue;
+        };
+
+        if command == "history" {
+            for (i, entry) in editor.history().iter().enumerate() {
+                println!("{:>4}  {}", i + 1, entry);
+            }
+            continue;
+        }
+        // A Ctrl-C that arrived after the last run finished doesn't count
+        session.interrupt.store(false, Ordering::Relaxed);
+        match session.execute(&command) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => break,
+            Err(e) => eprintln!("{}", format!("Error: {}", e).red()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "
+        main:  li t0, 5
+               addi t1, t0, 1
+        loop:  j loop
+        data:  .word 0
+    ";
+
+    /// Session debugging PROGRAM at 0x1000, with sp at 0x3000
+    fn session() -> Session {
+        let mut session = Session::new(&Args::parse_from(["riscv-debug"]));
+        let mut cpu = Cpu::new();
+        let symbols = load_assembly(&mut cpu, PROGRAM, 0x1000, 0x4000).unwrap();
+        cpu.registers.poke(2, 0x3000);
+        session.install(cpu, symbols);
+        session
+    }
+
+    #[test]
+    fn test_resolve() {
+        let session = session();
+        assert_eq!(session.resolve("0x20"), Ok(0x20));
+        assert_eq!(session.resolve("-4"), Ok(-4i32 as Addr));
+        assert_eq!(session.resolve("loop"), Ok(0x1008));
+        assert_eq!(session.resolve("main+8"), Ok(0x1008));
+        assert_eq!(session.resolve("data-0x4"), Ok(0x1008));
+        assert_eq!(session.resolve("sp-16"), Ok(0x2FF0));
+        assert_eq!(session.resolve("$pc+4"), Ok(0x1004));
+        assert!(session.resolve("nowhere+4").is_err());
+    }
+
+    #[test]
+    fn test_parse_examine() {
+        assert_eq!(parse_examine(""), Ok((1, 'x', None)));
+        assert_eq!(parse_examine("/4xw"), Ok((4, 'x', Some(4))));
+        assert_eq!(parse_examine("/16ub"), Ok((16, 'u', Some(1))));
+        assert_eq!(parse_examine("/hd"), Ok((1, 'd', Some(2))));
+        assert_eq!(parse_examine("/3i"), Ok((3, 'i', None)));
+        assert!(parse_examine("/4q").is_err());
+    }
+
+    #[test]
+    fn test_set_memory_and_registers() {
+        let mut session = session();
+        session.execute("set word data 0x11223344").unwrap();
+        session.execute("set byte data+1 0xAB").unwrap();
+        session.execute("set half data+2 = 0xBEEF").unwrap();
+        assert_eq!(session.cpu().memory.read_bytes(0x100C, 4), [0x44, 0xAB, 0xEF, 0xBE]);
+        assert!(session.execute("set word 0x10000 1").is_err());
+
+        session.execute("set a0 = data").unwrap();
+        session.execute("set pc loop").unwrap();
+        assert_eq!(session.cpu().registers.peek(10), 0x100C);
+        assert_eq!(session.cpu().control.get_pc(), 0x1008);
+    }
+
+    #[test]
+    fn test_enter_repeats_stepping_commands() {
+        let mut session = session();
+        let mut last = String::new();
+        assert_eq!(command_for("", &mut last), None);
+
+        for line in ["step", ""] {
+            let command = command_for(line, &mut last).unwrap();
+            assert_eq!(command, "step");
+            session.execute(&command).unwrap();
+        }
+        assert_eq!(session.cpu().control.get_pc(), 0x1008);
+        assert_eq!(session.cpu().registers.peek(6), 6);
+
+        // Breakpoints aren't repeated
+        assert_eq!(command_for("break loop", &mut last).as_deref(), Some("break loop"));
+        assert_eq!(command_for("  ", &mut last), None);
+    }
+
+    #[test]
+    fn test_disassemble_outside_memory() {
+        let mut session = session();
+        session.execute("set pc 0x10").unwrap();
+        session.execute("disassemble").unwrap();
+    }
 }
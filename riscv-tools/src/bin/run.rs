@@ -1,16 +1,146 @@
 //! riscv-run: Execute RISC-V programs
 
-use clap::Parser;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
 use colored::Colorize;
-use riscv32i_sim::Cpu;
+use riscv32i_sim::linux::setup_process_stack;
+use riscv32i_sim::block::BLK_SIZE;
+use riscv32i_sim::dma::DMA_SIZE;
+use riscv32i_sim::framebuffer::SnapshotFormat;
+use riscv32i_sim::gpio::GPIO_SIZE;
+use riscv32i_sim::panel::PANEL_SIZE;
+use riscv32i_sim::uart::UART_SIZE;
+use riscv32i_sim::{Addr, BlockDevice, Cpu, DmaController, ElfImage, ExitAbi, FastInterpreter, Framebuffer, Gpio, Htif, ImageFormat, InstructionEncoder, LinuxSyscalls, Lockstep, Machine, MachineDescription, Memory, OutputLog, Panel, PixelFormat, RarsSyscalls, RunConfig, RunResult, Schedule, StopReason, TestFinisher, Timeline, Uart16550};
+use riscv_tools::commit_log;
 
 #[derive(Parser)]
 #[command(author, version, about = "Execute RISC-V programs", long_about = None)]
 struct Args {
-    /// Assembly file to run
+    /// Program to run: RV32 ELF executable, or a memory image
+    /// (.bin, .hex/.ihex, .srec, .mem/.memh $readmemh, .memb $readmemb)
     #[arg(short, long)]
     file: Option<String>,
 
+    /// Load address (and start PC) for memory images
+    #[arg(long, default_value = "0", value_parser = parse_addr)]
+    base: Addr,
+
+    /// Write memory to this file after the run (format from its extension)
+    #[arg(long)]
+    dump: Option<String>,
+
+    /// Region to dump as START:LEN (hex or decimal), default all of memory
+    #[arg(long, value_parser = parse_range)]
+    dump_range: Option<(Addr, usize)>,
+
+    /// Machine description (TOML or JSON) giving the memory map, devices and
+    /// reset vector; programs load into its memory and run bare-metal, with
+    /// a0 = hart ID and a1 = device tree, and an explicit reset vector
+    /// overrides the program's entry point; multiple harts run round-robin,
+    /// one instruction each, on the CPU engine
+    #[arg(long, value_parser = parse_machine)]
+    machine: Option<(String, MachineDescription)>,
+
+    /// Kernel command line for the machine's device tree
+    #[arg(long, requires = "machine")]
+    bootargs: Option<String>,
+
+    /// Write the machine's device tree blob to this file
+    #[arg(long, requires = "machine")]
+    dtb: Option<String>,
+
+    /// Memory size in KiB for loaded programs (without --machine)
+    #[arg(long, default_value = "16384")]
+    memory_kib: usize,
+
+    /// Syscall ABI for programs run without --machine (RARS also serves
+    /// memory images); unserviced ECALLs exit by its convention
+    #[arg(long, value_enum, default_value = "linux")]
+    syscalls: Abi,
+
+    /// Host directory the program may open files under
+    #[arg(long)]
+    root: Option<String>,
+
+    /// Arguments passed to the program
+    #[arg(last = true)]
+    program_args: Vec<String>,
+
+    /// Map a SiFive test finisher at this address (e.g. 0x100000)
+    #[arg(long, value_parser = parse_addr)]
+    test_finisher: Option<Addr>,
+
+    /// Map a 16550 UART console: `stdio`, `pty`, or a file to write TX to
+    #[arg(long)]
+    uart: Option<String>,
+
+    /// UART base address (QEMU virt's UART0 by default)
+    #[arg(long, default_value = "0x10000000", value_parser = parse_addr)]
+    uart_base: Addr,
+
+    /// Map a framebuffer as WIDTHxHEIGHT[:FORMAT] (gray8, rgb565, xrgb8888)
+    #[arg(long, value_parser = parse_framebuffer)]
+    framebuffer: Option<(u32, u32, PixelFormat)>,
+
+    /// Framebuffer base address (registers, then VRAM at +0x1000)
+    #[arg(long, default_value = "0x30000000", value_parser = parse_addr)]
+    fb_base: Addr,
+
+    /// Save the final frame to this file (.ppm or .png)
+    #[arg(long)]
+    fb_save: Option<String>,
+
+    /// Save frames into this directory on every PRESENT
+    #[arg(long)]
+    fb_dir: Option<String>,
+
+    /// Also save a frame into --fb-dir every N cycles
+    #[arg(long, requires = "fb_dir")]
+    fb_every: Option<u64>,
+
+    /// Print the final frame as ANSI art
+    #[arg(long)]
+    fb_preview: bool,
+
+    /// Map a GPIO block at this address
+    #[arg(long, value_parser = parse_addr)]
+    gpio: Option<Addr>,
+
+    /// Timeline file driving GPIO inputs (pinN, pins)
+    #[arg(long, requires = "gpio")]
+    gpio_inputs: Option<String>,
+
+    /// Map an LED/7-segment/switch panel at this address
+    #[arg(long, value_parser = parse_addr)]
+    panel: Option<Addr>,
+
+    /// Timeline file driving panel inputs (swN, switches, btnN, buttons)
+    #[arg(long, requires = "panel")]
+    panel_inputs: Option<String>,
+
+    /// Log GPIO and panel output changes with cycle timestamps to this file
+    #[arg(long)]
+    io_log: Option<String>,
+
+    /// Map a block device backed by this disk image
+    #[arg(long)]
+    disk: Option<String>,
+
+    /// Block device base address
+    #[arg(long, default_value = "0x10001000", value_parser = parse_addr)]
+    disk_base: Addr,
+
+    /// Keep disk writes in memory, leaving the image file unchanged
+    #[arg(long, requires = "disk")]
+    disk_cow: bool,
+
+    /// Map a 4-channel DMA controller at this address
+    #[arg(long, value_parser = parse_addr)]
+    dma: Option<Addr>,
+
     /// Show register state after execution
     #[arg(short, long)]
     registers: bool,
@@ -19,36 +149,491 @@ struct Args {
     #[arg(short, long)]
     trace: bool,
 
-    /// Maximum cycles to execute
-    #[arg(short = 'c', long, default_value = "1000")]
-    max_cycles: usize,
+    /// Check execution against a Spike `--log-commits` log instead of running freely
+    #[arg(long)]
+    commit_log: Option<String>,
+
+    /// Check every instruction against the golden reference model (syscalls are not run)
+    #[arg(long, conflicts_with = "commit_log")]
+    lockstep: bool,
+
+    /// Maximum cycles to execute (unlimited by default)
+    #[arg(short = 'c', long)]
+    max_cycles: Option<u64>,
+
+    /// Maximum instructions to retire
+    #[arg(short = 'i', long)]
+    max_instructions: Option<u64>,
+
+    /// Wall-clock limit in milliseconds
+    #[arg(long)]
+    timeout_ms: Option<u64>,
+
+    /// Execution engine (all give the same results)
+    #[arg(long, value_enum, default_value = "cpu")]
+    engine: Engine,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Abi {
+    /// Linux user-mode syscalls (exit is a7 = 93/94)
+    Linux,
+    /// RARS/SPIM services (exit is a7 = 10/17)
+    Rars,
+}
+
+impl Abi {
+    fn exit_abi(self) -> ExitAbi {
+        match self {
+            Abi::Linux => ExitAbi::Linux,
+            Abi::Rars => ExitAbi::Rars,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Engine {
+    /// Clock the datapath model one instruction at a time
+    Cpu,
+    /// Cached pre-decoded blocks
+    Fast,
+    /// Fast, plus hot blocks translated to native code (x86-64 Linux)
+    Jit,
+}
+
+fn parse_addr(text: &str) -> Result<Addr, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => Addr::from_str_radix(&hex.replace('_', ""), 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|e| format!("invalid address '{}': {}", text, e))
+}
+
+fn parse_framebuffer(text: &str) -> Result<(u32, u32, PixelFormat), String> {
+    let (size, format) = text.split_once(':').unwrap_or((text, "xrgb8888"));
+    let (width, height) = size.split_once('x').ok_or("expected WIDTHxHEIGHT")?;
+    let format = PixelFormat::from_name(format).ok_or_else(|| format!("unknown pixel format '{}'", format))?;
+    let dimension = |text: &str| text.parse::<u32>().map_err(|e| format!("invalid size '{}': {}", text, e));
+    Ok((dimension(width)?, dimension(height)?, format))
 }
 
-fn main() {
+/// Read a --machine description
+fn parse_machine(path: &str) -> Result<(String, MachineDescription), String> {
+    let description = MachineDescription::from_file(path).map_err(|e| e.to_string())?;
+    Ok((path.to_string(), description))
+}
+
+fn parse_range(text: &str) -> Result<(Addr, usize), String> {
+    let (start, len) = text.split_once(':').ok_or("expected START:LEN")?;
+    Ok((parse_addr(start)?, parse_addr(len)? as usize))
+}
+
+/// Process exit status for a finished run
+/// Follows shell conventions: 124 for budgets (as timeout(1)), 128 + signal for faults
+fn exit_status(result: &RunResult) -> u8 {
+    match result.reason {
+        StopReason::Exit => result.exit_code.unwrap_or(0) as u8,
+        StopReason::Ebreak | StopReason::SelfLoop(_) | StopReason::Breakpoint(_) => 0,
+        StopReason::IllegalInstruction { .. } => 128 + 4,  // SIGILL
+        StopReason::CycleLimit | StopReason::InstructionLimit | StopReason::Timeout => 124,
+        StopReason::Interrupted => 128 + 2, // SIGINT
+    }
+}
+
+fn describe(reason: &StopReason, exit_code: Option<i32>) -> String {
+    match reason {
+        StopReason::Exit => format!("exited with code {}", exit_code.unwrap_or(0)),
+        StopReason::Ebreak => "stopped at ebreak".to_string(),
+        StopReason::Breakpoint(pc) => format!("breakpoint at 0x{:08x}", pc),
+        StopReason::IllegalInstruction { pc, word } => format!("illegal instruction 0x{:08x} at 0x{:08x}", word, pc),
+        StopReason::CycleLimit => "cycle limit reached".to_string(),
+        StopReason::InstructionLimit => "instruction limit reached".to_string(),
+        StopReason::Timeout => "timed out".to_string(),
+        StopReason::Interrupted => "interrupted".to_string(),
+        StopReason::SelfLoop(pc) => format!("halted in self-loop at 0x{:08x}", pc),
+    }
+}
+
+/// Without --file: a few ALU instructions from address 0, then EBREAK
+fn load_demo(cpu: &mut Cpu) {
+    cpu.load_words(0, &[
+        InstructionEncoder::addi(1, 0, 42),                                // addi x1, x0, 42
+        InstructionEncoder::addi(2, 0, 8),                                 // addi x2, x0, 8
+        InstructionEncoder::r_type(0b0110011, 3, 0b000, 1, 2, 0b0000000),  // add x3, x1, x2
+        InstructionEncoder::r_type(0b0110011, 4, 0b000, 1, 2, 0b0100000),  // sub x4, x1, x2
+        InstructionEncoder::r_type(0b0110011, 5, 0b111, 1, 2, 0b0000000),  // and x5, x1, x2
+        0x0010_0073,                                                       // ebreak
+    ]);
+}
+
+/// Load a memory image at --base and start there
+fn load_image(cpu: &mut Cpu, file: &str, args: &Args) -> Result<(), riscv32i_sim::ImageError> {
+    if args.machine.is_none() {
+        cpu.memory = Memory::with_base(args.base & !0xFFF, args.memory_kib * 1024);
+        if let Abi::Rars = args.syscalls {
+            cpu.set_syscall_handler(Box::new(RarsSyscalls::stdio()));
+        }
+    }
+    let start = cpu.memory.load_image_file(file, args.base)?;
+    cpu.control.set_pc(start.unwrap_or(args.base));
+    Ok(())
+}
+
+fn dump_memory(cpu: &Cpu, path: &str, range: Option<(Addr, usize)>) -> std::io::Result<()> {
+    let (start, len) = range.unwrap_or((cpu.memory.base(), cpu.memory.size()));
+    let format = ImageFormat::detect(std::path::Path::new(path), b"");
+    std::fs::write(path, cpu.memory.dump_image(format, start, len))
+}
+
+/// Load an ELF as a user-mode process: memory from its lowest segment,
+/// --syscalls services on host stdio, argv/envp on the stack
+fn load_elf(cpu: &mut Cpu, file: &str, args: &Args) -> Result<(), riscv32i_sim::LoadError> {
+    let image = ElfImage::from_file(file)?;
+    cpu.memory = Memory::with_base(image.base() & !0xFFF, args.memory_kib * 1024);
+    image.load_into(cpu)?;
+
+    // riscv-tests style programs report through tohost
+    if let Some(htif) = Htif::from_elf(&image) {
+        cpu.set_htif(htif);
+    }
+
+    let program_break = image.end().next_multiple_of(16) as Addr;
+    match args.syscalls {
+        Abi::Linux => {
+            let mut syscalls = LinuxSyscalls::stdio().with_program_break(program_break);
+            if let Some(root) = &args.root {
+                syscalls = syscalls.with_root(root);
+            }
+            cpu.set_syscall_handler(Box::new(syscalls));
+        }
+        Abi::Rars => cpu.set_syscall_handler(Box::new(RarsSyscalls::stdio().with_heap_base(program_break))),
+    }
+
+    let argv: Vec<&str> = std::iter::once(file).chain(args.program_args.iter().map(String::as_str)).collect();
+    setup_process_stack(cpu, &image, &argv, &[]);
+    Ok(())
+}
+
+/// Load a bare-metal ELF into the machine's memory, leaving a0/a1 set up
+fn load_firmware(cpu: &mut Cpu, file: &str) -> Result<(), riscv32i_sim::LoadError> {
+    let image = ElfImage::from_file(file)?;
+    image.load_into(cpu)?;
+    if let Some(htif) = Htif::from_elf(&image) {
+        cpu.set_htif(htif);
+    }
+    Ok(())
+}
+
+/// The CPU, and the --machine it was taken out of (its own `cpu` is left
+/// fresh; the program is loaded into the returned one)
+type Platform = (Cpu, Option<Machine>);
+
+/// Build the platform from --machine, or a bare CPU
+fn build_cpu(args: &Args) -> Result<Platform, String> {
+    let Some((path, description)) = &args.machine else {
+        let mut cpu = Cpu::new();
+        cpu.reset();
+        return Ok((cpu, None));
+    };
+    let mut description = description.clone();
+    if args.bootargs.is_some() {
+        description.bootargs = args.bootargs.clone();
+    }
+    let mut machine = description.build().map_err(|e| format!("{}: {}", path, e))?;
+    println!("{}", format!("Machine {} ({}, {} devices)", description.name, description.isa, machine.devices.len()).yellow());
+    for device in &machine.devices {
+        if let Some(pty) = &device.pty {
+            println!("{}", format!("{} on {}", device.name, pty.display()).cyan());
+        }
+    }
+    if let Some(dtb) = &args.dtb {
+        std::fs::write(dtb, &machine.dtb).map_err(|e| format!("cannot write {}: {}", dtb, e))?;
+    }
+    let cpu = std::mem::replace(&mut machine.cpu, Cpu::new());
+    Ok((cpu, Some(machine)))
+}
+
+/// Map the UART console selected by --uart
+fn map_uart(cpu: &mut Cpu, spec: &str, base: Addr) -> Result<(), String> {
+    let uart = match spec {
+        "stdio" => Uart16550::stdio(),
+        #[cfg(unix)]
+        "pty" => {
+            let (uart, path) = Uart16550::pty().map_err(|e| format!("cannot open pty: {}", e))?;
+            println!("{}", format!("UART on {}", path.display()).cyan());
+            uart
+        }
+        path => {
+            let file = std::fs::File::create(path).map_err(|e| format!("cannot create {}: {}", path, e))?;
+            Uart16550::new().with_output(file)
+        }
+    };
+    cpu.memory.map_device("uart0", base, UART_SIZE, uart).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Map the framebuffer selected by --framebuffer
+fn map_framebuffer(cpu: &mut Cpu, args: &Args) -> Result<Option<Arc<Mutex<Framebuffer>>>, String> {
+    let Some((width, height, format)) = args.framebuffer else {
+        return Ok(None);
+    };
+    let mut fb = Framebuffer::new(width, height, format);
+    if let Some(dir) = &args.fb_dir {
+        std::fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir, e))?;
+        fb = fb.with_snapshots(dir, SnapshotFormat::Png, args.fb_every);
+    }
+    let size = fb.window_size();
+    cpu.memory.map_device("fb", args.fb_base, size, fb).map(Some).map_err(|e| e.to_string())
+}
+
+/// Map the GPIO block and panel selected by --gpio and --panel
+fn map_io(cpu: &mut Cpu, args: &Args) -> Result<Option<Arc<Mutex<Panel>>>, String> {
+    // Both devices append to one file
+    let sink = match &args.io_log {
+        Some(path) => Some(std::fs::File::create(path).map_err(|e| format!("cannot create {}: {}", path, e))?),
+        None => None,
+    };
+    let log = || -> Result<OutputLog, String> {
+        match &sink {
+            Some(file) => Ok(OutputLog::default().with_sink(file.try_clone().map_err(|e| e.to_string())?)),
+            None => Ok(OutputLog::default()),
+        }
+    };
+    let timeline = |path: &Option<String>| match path {
+        Some(path) => Timeline::from_file(path).map(Some).map_err(|e| format!("{}: {}", path, e)),
+        None => Ok(None),
+    };
+
+    if let Some(base) = args.gpio {
+        let mut gpio = Gpio::new().with_log(log()?);
+        if let Some(timeline) = timeline(&args.gpio_inputs)? {
+            gpio = gpio.with_timeline(timeline).map_err(|e| e.to_string())?;
+        }
+        cpu.memory.map_device("gpio", base, GPIO_SIZE, gpio).map_err(|e| e.to_string())?;
+    }
+    let Some(base) = args.panel else {
+        return Ok(None);
+    };
+    let mut panel = Panel::new().with_log(log()?);
+    if let Some(timeline) = timeline(&args.panel_inputs)? {
+        panel = panel.with_timeline(timeline).map_err(|e| e.to_string())?;
+    }
+    cpu.memory.map_device("panel", base, PANEL_SIZE, panel).map(Some).map_err(|e| e.to_string())
+}
+
+/// Map the DMA controller and block device selected by --dma and --disk
+fn map_bus_masters(cpu: &mut Cpu, args: &Args) -> Result<(), String> {
+    if let Some(base) = args.dma {
+        cpu.memory.map_device("dma", base, DMA_SIZE, DmaController::new(4)).map_err(|e| e.to_string())?;
+    }
+    let Some(path) = &args.disk else {
+        return Ok(());
+    };
+    let disk = if args.disk_cow { BlockDevice::open_cow(path) } else { BlockDevice::open(path) };
+    let disk = disk.map_err(|e| format!("cannot open {}: {}", path, e))?;
+    cpu.memory.map_device("disk", args.disk_base, BLK_SIZE, disk).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Run every hart of a multi-hart machine from where hart 0 would start;
+/// the hart that stopped the run (holding the memory) is reported
+fn run_harts(mut machine: Machine, cpu: Cpu, config: &RunConfig) -> (Cpu, RunResult) {
+    machine.description.reset_vector = Some(cpu.control.get_pc());
+    machine.cpu = cpu;
+    let mut system = machine.into_multi_hart(Schedule::RoundRobin { quantum: 1 });
+    let (hart, result) = system.run(config);
+    println!("{}", format!("Hart {} of {} stopped the run", hart, system.hart_count()).yellow());
+    let mut cpu = system.harts.swap_remove(hart);
+    cpu.memory = system.memory;
+    (cpu, result)
+}
+
+/// Check every instruction against the golden model, reporting the first divergence
+fn run_lockstep(mut cpu: Cpu, config: &RunConfig) -> Result<(Cpu, RunResult), ExitCode> {
+    if cpu.take_syscall_handler().is_some() {
+        println!("{}", "Lockstep: the golden model has no syscalls, ECALL traps instead".yellow());
+    }
+    let mut lockstep = Lockstep::new(cpu);
+    match lockstep.run_with(config) {
+        Ok(result) => {
+            println!("{}", format!("Lockstep: no divergence in {} instructions", result.instructions).green());
+            Ok((lockstep.cpu, result))
+        }
+        Err(divergence) => {
+            println!("{}", divergence.to_string().red());
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Replay against a commit log and report the first mismatch
+fn compare_commit_log(cpu: &mut Cpu, path: &str) -> ExitCode {
+    let records = match commit_log::parse_file(path) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("{}", format!("Error reading {}: {}", path, e).red());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match commit_log::replay(cpu, &records) {
+        Ok(count) => {
+            println!("{}", format!("{} commits match {}", count, path).green());
+            ExitCode::SUCCESS
+        }
+        Err(mismatch) => {
+            println!("{}", format!("Mismatch at {}", mismatch).red());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
 
     println!("{}", "RISC-V Simulator".green().bold());
     println!("{}", "=".repeat(50));
 
-    let mut cpu = Cpu::new();
-    cpu.reset();
-
-    if let Some(_file) = args.file {
-        println!("{}", "Loading program...".yellow());
-        // TODO: Load from file
-        println!("{}", "File loading not yet implemented".red());
-        return;
+    let (mut cpu, machine) = match build_cpu(&args) {
+        Ok(built) => built,
+        Err(e) => {
+            eprintln!("{}", format!("Error: {}", e).red());
+            return ExitCode::FAILURE;
+        }
+    };
+    let harts = machine.as_ref().map_or(1, |machine| machine.description.harts);
+    if harts > 1 && (args.lockstep || args.commit_log.is_some() || !matches!(args.engine, Engine::Cpu)) {
+        eprintln!("{}", format!("Error: --lockstep, --commit-log and --engine fast/jit run one hart, the machine has {}", harts).red());
+        return ExitCode::FAILURE;
     }
 
-    println!("{}", "Running demo program...".cyan());
+    if let Some(file) = &args.file {
+        println!("{}", format!("Loading {}...", file).yellow());
+        let is_elf = std::fs::read(file).map(|bytes| bytes.starts_with(b"\x7fELF")).unwrap_or(false);
+        let loaded = if is_elf && args.machine.is_some() {
+            load_firmware(&mut cpu, file).map_err(|e| e.to_string())
+        } else if is_elf {
+            load_elf(&mut cpu, file, &args).map_err(|e| e.to_string())
+        } else {
+            load_image(&mut cpu, file, &args).map_err(|e| e.to_string())
+        };
+        if let Err(e) = loaded {
+            eprintln!("{}", format!("Error: {}", e).red());
+            return ExitCode::FAILURE;
+        }
+        if let Some(rom) = machine.as_ref().and_then(|machine| machine.boot_rom.as_ref()) {
+            // Boot through the ROM into the program
+            rom.lock().unwrap().set_entry(cpu.control.get_pc());
+            cpu.warm_reset();
+        } else if let Some(vector) = machine.as_ref().and_then(|machine| machine.description.reset_vector) {
+            cpu.control.set_pc(vector);
+        }
+    } else if args.machine.is_none() {
+        println!("{}", "Running demo program...".cyan());
+        load_demo(&mut cpu);
+    }
+    if let Some(addr) = args.test_finisher {
+        cpu.set_test_finisher(TestFinisher::new(addr));
+    }
+    if let Some(spec) = &args.uart {
+        if let Err(e) = map_uart(&mut cpu, spec, args.uart_base) {
+            eprintln!("{}", format!("Error: {}", e).red());
+            return ExitCode::FAILURE;
+        }
+    }
+    let devices = map_bus_masters(&mut cpu, &args)
+        .and_then(|_| map_framebuffer(&mut cpu, &args))
+        .and_then(|fb| Ok((fb, map_io(&mut cpu, &args)?)));
+    let (fb, panel) = match devices {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("{}", format!("Error: {}", e).red());
+            return ExitCode::FAILURE;
+        }
+    };
     println!("{}", "=".repeat(50));
 
-    cpu.run_cycles(args.max_cycles);
+    if let Some(path) = &args.commit_log {
+        return compare_commit_log(&mut cpu, path);
+    }
+
+    let mut config = RunConfig::new();
+    config.max_cycles = args.max_cycles;
+    config.max_instructions = args.max_instructions;
+    config.exit_abi = Some(args.syscalls.exit_abi());
+    config.timeout = args.timeout_ms.map(Duration::from_millis);
 
-    println!("\n{}", format!("Executed {} cycles", cpu.get_cycle_count()).green());
+    let result = if let Some(machine) = machine.filter(|machine| machine.description.harts > 1) {
+        let (checked, result) = run_harts(machine, cpu, &config);
+        cpu = checked;
+        result
+    } else if args.lockstep {
+        match run_lockstep(cpu, &config) {
+            Ok((checked, result)) => {
+                cpu = checked;
+                result
+            }
+            Err(code) => return code,
+        }
+    } else {
+        match args.engine {
+            Engine::Cpu => cpu.run(&config),
+            Engine::Fast => FastInterpreter::new().run(&mut cpu, &config),
+            Engine::Jit => {
+                let mut engine = FastInterpreter::new().with_jit(true);
+                if !engine.jit_enabled() {
+                    println!("{}", "JIT not available on this host, interpreting".yellow());
+                }
+                engine.run(&mut cpu, &config)
+            }
+        }
+    };
+
+    let mut summary = format!(
+        "{} after {} cycles, {} instructions",
+        describe(&result.reason, result.exit_code),
+        result.cycles,
+        result.instructions
+    );
+    if cpu.bus_stall_cycles() > 0 {
+        summary += &format!(" ({} cycles waiting for DMA)", cpu.bus_stall_cycles());
+    }
+    match result.reason {
+        StopReason::Exit | StopReason::Ebreak | StopReason::SelfLoop(_) => println!("\n{}", summary.green()),
+        _ => println!("\n{}", summary.red()),
+    }
 
     if args.registers {
         println!("\n{}", "Register State:".blue().bold());
         cpu.registers.dump_registers(0, 32);
     }
+
+    if let Some(panel) = &panel {
+        println!("\n{}", panel.lock().unwrap().render());
+    }
+
+    if let Some(fb) = &fb {
+        let fb = fb.lock().unwrap();
+        if let Some(e) = fb.last_error() {
+            eprintln!("{}", format!("Error saving frame {}", e).red());
+        }
+        if args.fb_preview {
+            println!("\n{}", fb.ansi_preview(80));
+        }
+        if let Some(path) = &args.fb_save {
+            if let Err(e) = fb.save(path) {
+                eprintln!("{}", format!("Error writing {}: {}", path, e).red());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(path) = &args.dump {
+        if let Err(e) = dump_memory(&cpu, path, args.dump_range) {
+            eprintln!("{}", format!("Error writing {}: {}", path, e).red());
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::from(exit_status(&result))
 }
@@ -0,0 +1,359 @@
+//! Spike commit-log co-simulation
+//!
+//! Parses the output of `spike --log-commits` and replays a `Cpu` against
+//! it, one retired instruction per log line:
+//!
+//! ```text
+//! core   0: 3 0x80000008 (0x1062a023) mem 0x80000100 0x0000002a
+//! core   0: 3 0x8000000c (0x1002c383) x7  0x0000002a mem 0x80000100
+//! ```
+//!
+//! i.e. `core HART: [PRIV] PC (INSN)` followed by register writes
+//! (`xN VALUE`), CSR writes (`cNNN_name VALUE`, decimal CSR number) and
+//! memory accesses (`mem ADDR` for loads, `mem ADDR VALUE` for stores, the
+//! value's digit count giving the access width). Other lines - exceptions,
+//! `tval`, `-l` disassembly traces - are skipped.
+
+use std::fmt;
+use std::path::Path;
+
+use riscv32i_sim::csr::{MCAUSE, MEPC};
+use riscv32i_sim::{Addr, Cpu, Word};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CommitLogError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Line {line}: {message}")]
+    Parse { line: usize, message: String },
+}
+
+/// Store recorded in the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemWrite {
+    pub addr: Addr,
+    pub value: Word,
+    /// Access width in bytes
+    pub size: u8,
+}
+
+/// One retired instruction
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CommitRecord {
+    /// 1-based line in the log
+    pub line: usize,
+    pub hart: u32,
+    pub privilege: Option<u8>,
+    pub pc: Addr,
+    pub instruction: Word,
+    pub reg_writes: Vec<(u8, Word)>,
+    pub csr_writes: Vec<(u16, Word)>,
+    pub mem_reads: Vec<Addr>,
+    pub mem_writes: Vec<MemWrite>,
+}
+
+/// `mem` or a register write like `x5`, `f0`, `c773_mtvec` (not a mnemonic)
+fn is_commit_token(token: &str) -> bool {
+    let mut chars = token.chars();
+    token == "mem"
+        || (matches!(chars.next(), Some('x' | 'f' | 'c' | 'v')) && chars.next().is_some_and(|c| c.is_ascii_digit()))
+}
+
+fn parse_hex(token: &str) -> Option<u64> {
+    let digits = token.strip_prefix("0x")?;
+    u64::from_str_radix(digits, 16).ok()
+}
+
+/// Parse one log line - None for lines that are not commits
+pub fn parse_line(line: &str, number: usize) -> Result<Option<CommitRecord>, CommitLogError> {
+    let error = |message: String| CommitLogError::Parse { line: number, message };
+
+    let Some(rest) = line.trim().strip_prefix("core") else {
+        return Ok(None);
+    };
+    let Some((hart, rest)) = rest.split_once(':') else {
+        return Ok(None);
+    };
+    let Ok(hart) = hart.trim().parse() else {
+        return Ok(None);
+    };
+
+    let mut tokens = rest.split_whitespace().peekable();
+    let privilege = match tokens.peek() {
+        Some(token) if !token.starts_with("0x") => match token.parse() {
+            Ok(privilege) => {
+                tokens.next();
+                Some(privilege)
+            }
+            Err(_) => return Ok(None),  // "exception ...", "tval ..."
+        },
+        _ => None,
+    };
+
+    let Some(pc) = tokens.next().and_then(parse_hex) else {
+        return Ok(None);
+    };
+    let instruction = tokens
+        .next()
+        .and_then(|token| token.strip_prefix('(')?.strip_suffix(')').map(str::to_string))
+        .and_then(|token| parse_hex(&token))
+        .ok_or_else(|| error("expected (0xINSN) after the pc".to_string()))?;
+
+    // `-l` instruction trace lines carry disassembly instead of writes
+    if tokens.peek().is_some_and(|token| !is_commit_token(token)) {
+        return Ok(None);
+    }
+
+    let mut record = CommitRecord {
+        line: number,
+        hart,
+        privilege,
+        pc: pc as Addr,
+        instruction: instruction as Word,
+        ..CommitRecord::default()
+    };
+
+    while let Some(token) = tokens.next() {
+        if token == "mem" {
+            let addr = tokens.next().and_then(parse_hex).ok_or_else(|| error("expected address after mem".to_string()))?;
+            match tokens.peek().filter(|value| value.starts_with("0x")) {
+                Some(&value) => {
+                    tokens.next();
+                    let size = (value.len() - 2).div_ceil(2) as u8;
+                    let value = parse_hex(value).ok_or_else(|| error(format!("invalid value '{}'", value)))?;
+                    record.mem_writes.push(MemWrite { addr: addr as Addr, value: value as Word, size });
+                }
+                None => record.mem_reads.push(addr as Addr),
+            }
+            continue;
+        }
+
+        let value = tokens.next().and_then(parse_hex);
+        if let Some(reg) = token.strip_prefix('x').and_then(|reg| reg.parse::<u8>().ok()) {
+            let value = value.ok_or_else(|| error(format!("expected value after {}", token)))?;
+            record.reg_writes.push((reg, value as Word));
+        } else if let Some(csr) = token.strip_prefix('c').and_then(|csr| csr.split('_').next()?.parse::<u16>().ok()) {
+            let value = value.ok_or_else(|| error(format!("expected value after {}", token)))?;
+            record.csr_writes.push((csr, value as Word));
+        }
+        // Floating-point and vector writes are not modelled
+    }
+
+    Ok(Some(record))
+}
+
+pub fn parse(text: &str) -> Result<Vec<CommitRecord>, CommitLogError> {
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        records.extend(parse_line(line, i + 1)?);
+    }
+    Ok(records)
+}
+
+pub fn parse_file(path: impl AsRef<Path>) -> Result<Vec<CommitRecord>, CommitLogError> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+/// How our commit differed from the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MismatchKind {
+    /// Our CPU never reached the logged PC
+    Pc { actual: Addr },
+    Instruction { actual: Word },
+    /// Register value after the instruction (expected None = not written in the log)
+    Register { reg: u8, expected: Option<Word>, actual: Word },
+    MemoryWrite { expected: Option<MemWrite>, actual: Option<(Addr, Word)> },
+    /// CSR value after a logged CSR write (actual None = CSR not implemented)
+    Csr { csr: u16, expected: Word, actual: Option<Word> },
+}
+
+/// First commit that did not match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitMismatch {
+    /// Index among the compared commits
+    pub index: usize,
+    pub expected: CommitRecord,
+    pub kind: MismatchKind,
+}
+
+impl fmt::Display for CommitMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "commit {} (log line {}) pc 0x{:08x} insn 0x{:08x}: ",
+            self.index, self.expected.line, self.expected.pc, self.expected.instruction
+        )?;
+        let word = |value: Option<Word>| value.map_or("(no write)".to_string(), |v| format!("0x{:08x}", v));
+        match &self.kind {
+            MismatchKind::Pc { actual } => write!(f, "our pc is 0x{:08x}", actual),
+            MismatchKind::Instruction { actual } => write!(f, "our memory holds 0x{:08x}", actual),
+            MismatchKind::Register { reg, expected, actual } => {
+                write!(f, "x{} expected {}, got 0x{:08x}", reg, word(*expected), actual)
+            }
+            MismatchKind::MemoryWrite { expected, actual } => {
+                let expected = expected.map_or("no store".to_string(), |w| format!("0x{:08x} <- 0x{:x}", w.addr, w.value));
+                let actual = actual.map_or("no store".to_string(), |(addr, value)| format!("0x{:08x} <- 0x{:x}", addr, value));
+                write!(f, "expected {}, got {}", expected, actual)
+            }
+            MismatchKind::Csr { csr, expected, actual } => {
+                let actual = actual.map_or("no such CSR".to_string(), |v| format!("0x{:08x}", v));
+                write!(f, "csr 0x{:03x} expected 0x{:08x}, got {}", csr, expected, actual)
+            }
+        }
+    }
+}
+
+/// Clocks that retire nothing the log would show (traps, WFI stalls)
+/// allowed between two logged commits
+const MAX_UNLOGGED_CLOCKS: usize = 10_000;
+
+/// Replay `cpu` from its current state against `records`
+///
+/// Commits before the first one at the CPU's PC (e.g. Spike's boot ROM at
+/// 0x1000) are skipped, as are commits for other harts. Returns the number
+/// of commits compared.
+pub fn replay(cpu: &mut Cpu, records: &[CommitRecord]) -> Result<usize, Box<CommitMismatch>> {
+    let hart = cpu.csrs.hart_id();
+    let start_pc = cpu.control.get_pc();
+    let records: Vec<&CommitRecord> = records
+        .iter()
+        .filter(|record| record.hart == hart)
+        .skip_while(|record| record.pc != start_pc)
+        .collect();
+
+    for (index, &expected) in records.iter().enumerate() {
+        let mismatch = |kind| Box::new(CommitMismatch { index, expected: expected.clone(), kind });
+
+        // Let traps and stalls that Spike doesn't log go by
+        let mut unlogged = 0;
+        while cpu.control.get_pc() != expected.pc && unlogged < MAX_UNLOGGED_CLOCKS {
+            let pc = cpu.control.get_pc();
+            if !clock_unlogged(cpu) {
+                return Err(mismatch(MismatchKind::Pc { actual: pc }));
+            }
+            unlogged += 1;
+        }
+        let pc = cpu.control.get_pc();
+        if pc != expected.pc {
+            return Err(mismatch(MismatchKind::Pc { actual: pc }));
+        }
+        let instruction = cpu.memory.fetch(pc);
+        if instruction != expected.instruction {
+            return Err(mismatch(MismatchKind::Instruction { actual: instruction }));
+        }
+
+        let before: Vec<Word> = (0..32).map(|reg| cpu.registers.peek(reg)).collect();
+        cpu.clock();
+
+        for reg in 1..32u8 {
+            let actual = cpu.registers.peek(reg);
+            let logged = expected.reg_writes.iter().rev().find(|(r, _)| *r == reg).map(|&(_, value)| value);
+            let matches = match logged {
+                Some(value) => value == actual,
+                None => actual == before[reg as usize],
+            };
+            if !matches {
+                return Err(mismatch(MismatchKind::Register { reg, expected: logged, actual }));
+            }
+        }
+
+        let actual = cpu.last_memory_access().filter(|access| access.is_write()).map(|access| (access.addr, access.value));
+        let logged = expected.mem_writes.last().copied();
+        let matches = match (logged, actual) {
+            (None, None) => true,
+            (Some(write), Some((addr, value))) => {
+                let mask = if write.size >= 4 { Word::MAX } else { (1 << (8 * write.size)) - 1 };
+                write.addr == addr && write.value == value & mask
+            }
+            _ => false,
+        };
+        if !matches {
+            return Err(mismatch(MismatchKind::MemoryWrite { expected: logged, actual }));
+        }
+
+        for &(csr, expected) in &expected.csr_writes {
+            let actual = cpu.csrs.read(csr);
+            if actual != Some(expected) {
+                return Err(mismatch(MismatchKind::Csr { csr, expected, actual }));
+            }
+        }
+    }
+
+    Ok(records.len())
+}
+
+/// Clock once, returning whether that was an unlogged step: nothing
+/// retired, or an exception was taken (mepc/mcause changed)
+fn clock_unlogged(cpu: &mut Cpu) -> bool {
+    let trap_state = |cpu: &Cpu| (cpu.csrs.read(MEPC), cpu.csrs.read(MCAUSE));
+    let (before, instret) = (trap_state(cpu), cpu.csrs.get_instret());
+    cpu.clock();
+    cpu.csrs.get_instret() == instret || trap_state(cpu) != before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use riscv32i_sim::Memory;
+
+    const LOG: &str = include_str!("../fixtures/spike_commits.log");
+
+    /// The program the fixture was logged from, at 0x80000000
+    fn cpu() -> Cpu {
+        cpu_with(&[0x00000297, 0x02a00313, 0x1062a023, 0x1002c383, 0xfff00e13, 0x11c28223, 0x00730eb3, 0x0000006f])
+    }
+
+    /// `program` at 0x80000000
+    fn cpu_with(program: &[Word]) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.memory = Memory::with_base(0x8000_0000, 64 * 1024);
+        cpu.load_words(0x8000_0000, program);
+        cpu.control.set_pc(0x8000_0000);
+        cpu
+    }
+
+    #[test]
+    fn test_parse_commit_lines() {
+        let records = parse(LOG).unwrap();
+        assert_eq!(records.len(), 13);
+
+        let load = &records[8];
+        assert_eq!((load.line, load.pc, load.instruction, load.privilege), (9, 0x8000_000c, 0x1002c383, Some(3)));
+        assert_eq!(load.reg_writes, vec![(7, 0x2a)]);
+        assert_eq!(load.mem_reads, vec![0x8000_0100]);
+        assert_eq!(records[10].mem_writes, vec![MemWrite { addr: 0x8000_0104, value: 0xff, size: 1 }]);
+
+        let csr = parse_line("core   0: 3 0x80000040 (0x30529073) c773_mtvec 0x80000080", 1).unwrap().unwrap();
+        assert_eq!(csr.csr_writes, vec![(0x305, 0x8000_0080)]);
+        assert_eq!(parse_line("core   0: exception trap_illegal_instruction, epc 0x80000000", 2).unwrap(), None);
+        assert_eq!(parse_line("core   0: 0x80000000 (0x00000297) auipc   t0, 0x0", 2).unwrap(), None);
+        assert!(parse_line("core   0: 3 0x80000000 x5", 3).is_err());
+    }
+
+    #[test]
+    fn test_replay_against_fixture() {
+        let records = parse(LOG).unwrap();
+        assert_eq!(replay(&mut cpu(), &records), Ok(8));
+
+        // A wrong logged store value is reported at its commit
+        let mut records = records;
+        records[7].mem_writes[0].value = 0x2b;
+        let mismatch = replay(&mut cpu(), &records).unwrap_err();
+        assert_eq!(mismatch.index, 2);
+        assert_eq!(mismatch.expected.line, 8);
+        assert!(matches!(mismatch.kind, MismatchKind::MemoryWrite { actual: Some((0x8000_0100, 0x2a)), .. }));
+    }
+
+    #[test]
+    fn test_replay_checks_csr_writes() {
+        let log = "core   0: 3 0x80000000 (0x02a00293) x5  0x0000002a\n\
+                   core   0: 3 0x80000004 (0x34029073) c832_mscratch 0x0000002a\n";
+        let mut records = parse(log).unwrap();
+        assert_eq!(replay(&mut cpu_with(&[0x02a00293, 0x34029073]), &records), Ok(2));
+
+        records[1].csr_writes[0].1 = 0x2b;
+        let mismatch = replay(&mut cpu_with(&[0x02a00293, 0x34029073]), &records).unwrap_err();
+        assert_eq!(mismatch.kind, MismatchKind::Csr { csr: 0x340, expected: 0x2b, actual: Some(0x2a) });
+    }
+}
@@ -0,0 +1,330 @@
+//! Architectural compliance harness (riscv-arch-test style)
+//!
+//! Each test ELF is loaded into memory at the link address, run until it
+//! halts (HTIF tohost, ECALL exit, or a self-loop), and the words between
+//! `begin_signature` and `end_signature` are compared with the reference
+//! signature: one 32-bit word per line, 8 lowercase hex digits.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use colored::Colorize;
+use prettytable::{format, Cell, Row, Table};
+use riscv32i_sim::{Addr, Cpu, ElfImage, Htif, Memory, RunConfig, StopReason, Word};
+
+/// Memory given to each test (arch tests link at 0x8000_0000)
+#[derive(Debug, Clone)]
+pub struct ComplianceConfig {
+    pub memory_base: Addr,
+    pub memory_size: usize,
+    pub max_cycles: u64,
+    pub timeout: Duration,
+}
+
+impl Default for ComplianceConfig {
+    fn default() -> Self {
+        Self {
+            memory_base: 0x8000_0000,
+            memory_size: 4 * 1024 * 1024,
+            max_cycles: 10_000_000,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A test ELF and its reference signature file
+#[derive(Debug, Clone)]
+pub struct ComplianceTest {
+    pub name: String,
+    pub elf: PathBuf,
+    pub reference: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// Signature differs - first differing word index and values
+    Mismatch { index: usize, expected: Option<Word>, actual: Option<Word> },
+    /// Ran to completion but there is no reference to compare against
+    NoReference,
+    /// Test did not halt normally
+    DidNotComplete(StopReason),
+    /// begin_signature/end_signature symbols missing
+    NoSignature,
+    /// ELF or reference could not be loaded
+    Error(String),
+}
+
+impl Verdict {
+    pub fn is_pass(&self) -> bool {
+        *self == Verdict::Pass
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let word = |w: &Option<Word>| w.map_or("(none)".to_string(), |w| format!("{:08x}", w));
+        match self {
+            Verdict::Pass => write!(f, "pass"),
+            Verdict::Mismatch { index, expected, actual } => {
+                write!(f, "signature word {}: expected {}, got {}", index, word(expected), word(actual))
+            }
+            Verdict::NoReference => write!(f, "no reference signature"),
+            Verdict::DidNotComplete(reason) => write!(f, "did not complete: {:?}", reason),
+            Verdict::NoSignature => write!(f, "no begin_signature/end_signature symbols"),
+            Verdict::Error(message) => write!(f, "error: {}", message),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestReport {
+    pub name: String,
+    pub verdict: Verdict,
+    pub signature: Vec<Word>,
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+/// Signature file text for a list of words
+pub fn format_signature(words: &[Word]) -> String {
+    words.iter().map(|w| format!("{:08x}\n", w)).collect()
+}
+
+/// Parse a signature/reference file (blank lines ignored)
+pub fn parse_signature(text: &str) -> Result<Vec<Word>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| Word::from_str_radix(line, 16).map_err(|_| format!("invalid signature word '{}'", line)))
+        .collect()
+}
+
+/// Words in [begin, end) of memory
+pub fn read_signature(memory: &Memory, begin: Addr, end: Addr) -> Vec<Word> {
+    (begin..end).step_by(4).map(|addr| memory.fetch(addr)).collect()
+}
+
+/// Compare against the reference - the first differing word, if any
+pub fn compare_signature(actual: &[Word], expected: &[Word]) -> Verdict {
+    let len = actual.len().max(expected.len());
+    match (0..len).find(|&i| actual.get(i) != expected.get(i)) {
+        Some(index) => Verdict::Mismatch {
+            index,
+            expected: expected.get(index).copied(),
+            actual: actual.get(index).copied(),
+        },
+        None => Verdict::Pass,
+    }
+}
+
+/// Run one already-parsed test image
+pub fn run_image(name: &str, image: &ElfImage, reference: Option<&[Word]>, config: &ComplianceConfig) -> TestReport {
+    let mut report = TestReport {
+        name: name.to_string(),
+        verdict: Verdict::NoSignature,
+        signature: Vec::new(),
+        cycles: 0,
+        instructions: 0,
+    };
+
+    let mut cpu = Cpu::new();
+    cpu.memory = Memory::with_base(config.memory_base, config.memory_size);
+    if let Err(e) = image.load_into(&mut cpu) {
+        report.verdict = Verdict::Error(e.to_string());
+        return report;
+    }
+    if let Some(htif) = Htif::from_elf(image) {
+        cpu.set_htif(htif);
+    }
+
+    let run = RunConfig::new().with_max_cycles(config.max_cycles).with_timeout(config.timeout);
+    let result = cpu.run(&run);
+    report.cycles = result.cycles;
+    report.instructions = result.instructions;
+
+    if !matches!(result.reason, StopReason::Exit | StopReason::SelfLoop(_) | StopReason::Ebreak) {
+        report.verdict = Verdict::DidNotComplete(result.reason);
+        return report;
+    }
+
+    let (Some(begin), Some(end)) = (image.symbols.lookup("begin_signature"), image.symbols.lookup("end_signature")) else {
+        return report;
+    };
+    report.signature = read_signature(&cpu.memory, begin, end);
+    report.verdict = match reference {
+        Some(expected) => compare_signature(&report.signature, expected),
+        None => Verdict::NoReference,
+    };
+    report
+}
+
+/// Load and run one test from disk
+pub fn run_test(test: &ComplianceTest, config: &ComplianceConfig) -> TestReport {
+    let failed = |message: String| TestReport {
+        name: test.name.clone(),
+        verdict: Verdict::Error(message),
+        signature: Vec::new(),
+        cycles: 0,
+        instructions: 0,
+    };
+
+    let image = match ElfImage::from_file(&test.elf) {
+        Ok(image) => image,
+        Err(e) => return failed(e.to_string()),
+    };
+    let reference = match &test.reference {
+        Some(path) => match std::fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|t| parse_signature(&t)) {
+            Ok(words) => Some(words),
+            Err(e) => return failed(format!("{}: {}", path.display(), e)),
+        },
+        None => None,
+    };
+
+    run_image(&test.name, &image, reference.as_deref(), config)
+}
+
+/// Find tests in a directory: every `*.elf` (or extension-less ELF), with the
+/// reference at `<name>.reference_output` or `references/<name>.reference_output`
+pub fn discover(dir: &Path) -> std::io::Result<Vec<ComplianceTest>> {
+    let mut tests = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_elf = std::fs::read(&path).map(|bytes| bytes.starts_with(b"\x7fELF")).unwrap_or(false);
+        if !path.is_file() || !is_elf {
+            continue;
+        }
+
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("test").to_string();
+        let reference_name = format!("{}.reference_output", name);
+        let reference = [dir.join(&reference_name), dir.join("references").join(&reference_name)]
+            .into_iter()
+            .find(|candidate| candidate.is_file());
+
+        tests.push(ComplianceTest { name, elf: path, reference });
+    }
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(tests)
+}
+
+/// Results of a whole suite
+#[derive(Debug, Clone, Default)]
+pub struct ComplianceReport {
+    pub tests: Vec<TestReport>,
+}
+
+impl ComplianceReport {
+    pub fn passed(&self) -> usize {
+        self.tests.iter().filter(|t| t.verdict.is_pass()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.tests.len() - self.passed()
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.failed() == 0
+    }
+
+    pub fn to_table(&self) -> Table {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_BOX_CHARS);
+
+        table.add_row(Row::new(vec![
+            Cell::new("Test").style_spec("Fb"),
+            Cell::new("Result").style_spec("Fb"),
+            Cell::new("Instructions").style_spec("Fb"),
+            Cell::new("Details").style_spec("Fb"),
+        ]));
+
+        for test in &self.tests {
+            let (result, style) = if test.verdict.is_pass() { ("PASS", "Fg") } else { ("FAIL", "Fr") };
+            let details = if test.verdict.is_pass() { String::new() } else { test.verdict.to_string() };
+            table.add_row(Row::new(vec![
+                Cell::new(&test.name),
+                Cell::new(result).style_spec(style),
+                Cell::new(&test.instructions.to_string()),
+                Cell::new(&details),
+            ]));
+        }
+
+        table
+    }
+}
+
+impl fmt::Display for ComplianceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let summary = format!("{} passed, {} failed", self.passed(), self.failed());
+        let summary = if self.all_passed() { summary.green() } else { summary.red() };
+        write!(f, "{}{}", self.to_table(), summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use riscv32i_sim::elf::Segment;
+    use riscv32i_sim::{InstructionEncoder, Symbol, SymbolKind, SymbolTable};
+
+    const BASE: Addr = 0x8000_0000;
+
+    fn symbol(name: &str, addr: Addr) -> Symbol {
+        Symbol { name: name.to_string(), addr, size: 0, kind: SymbolKind::Other }
+    }
+
+    /// Stores 0x2a and 0x7 into a two-word signature, then `j .`
+    fn signature_image() -> ElfImage {
+        let program = [
+            InstructionEncoder::u_type(0b0010111, 5, 0),                  // auipc t0, 0
+            InstructionEncoder::i_type(0b0010011, 6, 0b000, 0, 0x2a),
+            InstructionEncoder::s_type(0b0100011, 0b010, 5, 6, 0x100),
+            InstructionEncoder::i_type(0b0010011, 6, 0b000, 0, 7),
+            InstructionEncoder::s_type(0b0100011, 0b010, 5, 6, 0x104),
+            InstructionEncoder::j_type(0b1101111, 0, 0),
+        ];
+        let mut symbols = SymbolTable::new();
+        symbols.insert(symbol("begin_signature", BASE + 0x100));
+        symbols.insert(symbol("end_signature", BASE + 0x108));
+
+        ElfImage {
+            entry: BASE,
+            segments: vec![Segment {
+                addr: BASE,
+                data: program.iter().flat_map(|w| w.to_le_bytes()).collect(),
+                mem_size: 0x200,
+            }],
+            symbols,
+            global_pointer: None,
+            tohost: None,
+            fromhost: None,
+            program_headers: None,
+            program_header_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_signature_format_round_trip() {
+        let words = [0xdead_beef, 0x0000_0001];
+        let text = format_signature(&words);
+        assert_eq!(text, "deadbeef\n00000001\n");
+        assert_eq!(parse_signature(&text).unwrap(), words);
+        assert!(parse_signature("xyz\n").is_err());
+    }
+
+    #[test]
+    fn test_run_image_against_reference() {
+        let image = signature_image();
+        let config = ComplianceConfig { memory_size: 64 * 1024, ..ComplianceConfig::default() };
+
+        let report = run_image("sig", &image, Some(&[0x2a, 0x7]), &config);
+        assert_eq!(report.verdict, Verdict::Pass);
+        assert_eq!(report.signature, vec![0x2a, 0x7]);
+
+        let report = run_image("sig", &image, Some(&[0x2a, 0x8]), &config);
+        assert_eq!(report.verdict, Verdict::Mismatch { index: 1, expected: Some(0x8), actual: Some(0x7) });
+
+        let report = run_image("sig", &image, None, &config);
+        assert_eq!(report.verdict, Verdict::NoReference);
+    }
+}
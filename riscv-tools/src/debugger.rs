@@ -1,10 +1,35 @@
 //! Interactive debugger for RISC-V programs
 
-use riscv32i_sim::Cpu;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use riscv32i_sim::{Addr, Cpu, Instruction, RunConfig, RunResult, StopReason, SymbolTable};
 
 pub struct Debugger {
     cpu: Cpu,
-    breakpoints: Vec<u32>,
+    breakpoints: Vec<Addr>,
+    symbols: SymbolTable,
+    interrupt: Option<Arc<AtomicBool>>,
+}
+
+/// How an instruction moves between functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transfer {
+    /// jal/jalr linking through ra or t0
+    Call,
+    /// jalr x0, 0(ra) or 0(t0)
+    Return,
+    Other,
+}
+
+fn transfer(word: u32) -> Transfer {
+    let inst = Instruction::new(word);
+    let link = |reg: u8| reg == 1 || reg == 5;
+    match inst.opcode() {
+        0b1101111 | 0b1100111 if link(inst.rd()) => Transfer::Call,
+        0b1100111 if inst.rd() == 0 && link(inst.rs1()) && inst.imm_i() == 0 => Transfer::Return,
+        _ => Transfer::Other,
+    }
 }
 
 impl Debugger {
@@ -12,24 +37,140 @@ impl Debugger {
         Self {
             cpu,
             breakpoints: Vec::new(),
+            symbols: SymbolTable::new(),
+            interrupt: None,
         }
     }
 
+    /// Flag that stops any run at the current PC with
+    /// `StopReason::Interrupted` (e.g. raised by a Ctrl-C handler)
+    pub fn set_interrupt(&mut self, interrupt: Arc<AtomicBool>) {
+        self.interrupt = Some(interrupt);
+    }
+
+    /// Symbols of the loaded program (e.g. `ElfImage::symbols`)
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn get_symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Break at a symbol - returns its address, None if unknown
+    pub fn add_breakpoint_at_symbol(&mut self, name: &str) -> Option<Addr> {
+        let addr = self.symbols.lookup(name)?;
+        self.add_breakpoint(addr);
+        Some(addr)
+    }
+
+    /// Current PC as "symbol+offset"
+    pub fn location(&self) -> String {
+        self.symbols.format(self.cpu.control.get_pc())
+    }
+
     pub fn add_breakpoint(&mut self, address: u32) {
-        self.breakpoints.push(address);
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    /// Returns false if there was no breakpoint at `address`
+    pub fn remove_breakpoint(&mut self, address: u32) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|&addr| addr != address);
+        self.breakpoints.len() != before
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Breakpoints in the order they were set
+    pub fn breakpoints(&self) -> &[Addr] {
+        &self.breakpoints
+    }
+
+    /// Execute one instruction - stops (`StopReason::InstructionLimit`)
+    /// unless the program exits, traps to the debugger or loops forever
+    pub fn step(&mut self) -> RunResult {
+        let config = self.config().with_max_instructions(1);
+        self.cpu.run(&config)
     }
 
-    pub fn step(&mut self) {
-        self.cpu.clock();
+    /// Step, running a call to completion (recursive calls included)
+    pub fn step_over(&mut self) -> RunResult {
+        let pc = self.cpu.control.get_pc();
+        if transfer(self.cpu.memory.fetch(pc)) != Transfer::Call {
+            return self.step();
+        }
+        let return_addr = pc.wrapping_add(4);
+        let sp = self.cpu.registers.peek(2);
+        let config = RunConfig {
+            breakpoints: self.breakpoints.iter().copied().chain([return_addr]).collect(),
+            ..self.config()
+        };
+        self.run_while(&config, |cpu| cpu.control.get_pc() == return_addr && cpu.registers.peek(2) < sp)
     }
 
-    pub fn run_until_breakpoint(&mut self) -> u32 {
+    /// Run until the current function returns
+    pub fn finish(&mut self) -> RunResult {
+        let mut depth = 0usize;
+        let mut total = self.nothing_run();
         loop {
+            let pc = self.cpu.control.get_pc();
+            let kind = transfer(self.cpu.memory.fetch(pc));
+            let result = self.step();
+            total = accumulate(total, result);
+            if result.reason != StopReason::InstructionLimit {
+                return total;
+            }
+            match kind {
+                Transfer::Call => depth += 1,
+                Transfer::Return if depth == 0 => return total,
+                Transfer::Return => depth -= 1,
+                Transfer::Other => {}
+            }
             let pc = self.cpu.control.get_pc();
             if self.breakpoints.contains(&pc) {
-                return pc;
+                return RunResult { reason: StopReason::Breakpoint(pc), ..total };
             }
-            self.cpu.clock();
+        }
+    }
+
+    /// Run until a breakpoint or any other stop condition (exit, ebreak,
+    /// illegal instruction, self-loop)
+    pub fn run_until_breakpoint(&mut self) -> RunResult {
+        let config = RunConfig {
+            breakpoints: self.breakpoints.clone(),
+            ..self.config()
+        };
+        self.cpu.run(&config)
+    }
+
+    /// Default stop conditions, plus the interrupt flag
+    fn config(&self) -> RunConfig {
+        RunConfig { interrupt: self.interrupt.clone(), ..RunConfig::default() }
+    }
+
+    /// `Cpu::run`, resumed for as long as `resume` says a breakpoint stop
+    /// doesn't count
+    fn run_while(&mut self, config: &RunConfig, resume: impl Fn(&Cpu) -> bool) -> RunResult {
+        let mut total = self.cpu.run(config);
+        while matches!(total.reason, StopReason::Breakpoint(_)) && resume(&self.cpu) {
+            total = accumulate(total, self.cpu.run(config));
+        }
+        total
+    }
+
+    /// Zero-length run at the current PC, to accumulate into
+    fn nothing_run(&self) -> RunResult {
+        RunResult {
+            reason: StopReason::InstructionLimit,
+            exit_code: None,
+            pc: self.cpu.control.get_pc(),
+            cycles: 0,
+            instructions: 0,
         }
     }
 
@@ -41,3 +182,104 @@ impl Debugger {
         &mut self.cpu
     }
 }
+
+/// `later`'s outcome, with the cycles and instructions of both runs
+fn accumulate(earlier: RunResult, later: RunResult) -> RunResult {
+    RunResult {
+        cycles: earlier.cycles + later.cycles,
+        instructions: earlier.instructions + later.instructions,
+        ..later
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use riscv_asm::Assembler;
+
+    /// main calls twice(), which calls inc() twice
+    const PROGRAM: &str = "
+        main:  li sp, 0x7f0
+               li a0, 1
+               call twice
+               addi a0, a0, 100
+        done:  j done
+        twice: addi sp, sp, -4
+               sw ra, 0(sp)
+               call inc
+               call inc
+               lw ra, 0(sp)
+               addi sp, sp, 4
+               ret
+        inc:   addi a0, a0, 1
+               ret
+    ";
+
+    fn debugger() -> Debugger {
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(PROGRAM).unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_program(&program);
+        let mut symbols = SymbolTable::new();
+        for (name, &addr) in assembler.labels() {
+            symbols.insert(riscv32i_sim::Symbol { name: name.clone(), addr, size: 0x100, kind: riscv32i_sim::SymbolKind::Function });
+        }
+        let mut debugger = Debugger::new(cpu);
+        debugger.set_symbols(symbols);
+        debugger
+    }
+
+    #[test]
+    fn test_breakpoints_next_and_finish() {
+        let mut debugger = debugger();
+        let inc = debugger.add_breakpoint_at_symbol("inc").unwrap();
+        debugger.add_breakpoint(inc);
+        assert_eq!(debugger.breakpoints(), [inc]);
+
+        let result = debugger.run_until_breakpoint();
+        assert_eq!(result.reason, StopReason::Breakpoint(inc));
+        assert_eq!(debugger.get_cpu().registers.peek(10), 1);
+
+        // Back in twice() just after the first call
+        let result = debugger.finish();
+        assert_eq!(result.reason, StopReason::InstructionLimit);
+        assert_eq!(debugger.location(), "twice+0xc");
+        assert_eq!(debugger.get_cpu().registers.peek(10), 2);
+
+        // Stepping over the second call still stops at the breakpoint inside it
+        assert_eq!(debugger.step_over().reason, StopReason::Breakpoint(inc));
+        assert!(debugger.remove_breakpoint(inc));
+        assert!(!debugger.remove_breakpoint(inc));
+        debugger.finish();
+        debugger.finish();
+        assert_eq!(debugger.location(), "main+0xc");
+        assert_eq!(debugger.get_cpu().registers.peek(10), 3);
+
+        // Over a whole call from main
+        let mut debugger = self::debugger();
+        for _ in 0..2 {
+            debugger.step();
+        }
+        let result = debugger.step_over();
+        assert_eq!(debugger.location(), "main+0xc");
+        assert_eq!(result.instructions, 12);
+        assert_eq!(debugger.step().reason, StopReason::InstructionLimit);
+        assert_eq!(debugger.get_cpu().registers.peek(10), 103);
+        assert!(matches!(debugger.step().reason, StopReason::SelfLoop(_)));
+    }
+
+    #[test]
+    fn test_interrupt_stops_runs() {
+        let mut debugger = debugger();
+        let interrupt = Arc::new(AtomicBool::new(true));
+        debugger.set_interrupt(interrupt.clone());
+        let result = debugger.run_until_breakpoint();
+        assert_eq!((result.reason, result.instructions), (StopReason::Interrupted, 0));
+
+        // Raised again in the middle of finish()
+        debugger.step();
+        interrupt.store(true, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(debugger.finish().reason, StopReason::Interrupted);
+        assert_eq!(debugger.location(), "main+0x4");
+    }
+}
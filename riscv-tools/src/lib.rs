@@ -1,8 +1,11 @@
 //! RISC-V Tools library
 
+pub mod commit_log;
+pub mod compliance;
 pub mod debugger;
 pub mod formatter;
 pub mod trace;
 
+pub use compliance::{ComplianceConfig, ComplianceReport, ComplianceTest, Verdict};
 pub use debugger::Debugger;
 pub use trace::ExecutionTrace;
@@ -1,6 +1,8 @@
 //! Execution tracing
 
-use riscv32i_sim::{Word, Addr};
+use std::collections::HashMap;
+
+use riscv32i_sim::{Word, Addr, SymbolTable};
 
 #[derive(Debug, Clone)]
 pub struct TraceEntry {
@@ -13,6 +15,9 @@ pub struct TraceEntry {
 pub struct ExecutionTrace {
     entries: Vec<TraceEntry>,
     max_entries: usize,
+    symbols: SymbolTable,
+    /// Executions per PC over the whole run, not just the kept entries
+    hits: HashMap<Addr, u64>,
 }
 
 impl ExecutionTrace {
@@ -20,10 +25,19 @@ impl ExecutionTrace {
         Self {
             entries: Vec::new(),
             max_entries,
+            symbols: SymbolTable::new(),
+            hits: HashMap::new(),
         }
     }
 
+    /// Symbols of the traced program (e.g. `ElfImage::symbols`)
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
     pub fn record(&mut self, entry: TraceEntry) {
+        *self.hits.entry(entry.pc).or_default() += 1;
         self.entries.push(entry);
         if self.entries.len() > self.max_entries {
             self.entries.remove(0);
@@ -34,7 +48,69 @@ impl ExecutionTrace {
         &self.entries
     }
 
+    /// One trace line: cycle, PC with its symbol, raw word and disassembly
+    pub fn format_entry(&self, entry: &TraceEntry) -> String {
+        let location = match self.symbols.symbolize(entry.pc) {
+            Some(_) => format!("0x{:08x} <{}>", entry.pc, self.symbols.format(entry.pc)),
+            None => format!("0x{:08x}", entry.pc),
+        };
+        format!("{:>8}  {}  {:08x}  {}", entry.cycle, location, entry.instruction, entry.disassembly)
+    }
+
+    /// Instructions executed per symbol (bare address outside any symbol), busiest first
+    pub fn profile(&self) -> Vec<(String, u64)> {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for (&pc, &hits) in &self.hits {
+            let name = match self.symbols.symbolize(pc) {
+                Some((symbol, _)) => symbol.name.clone(),
+                None => format!("0x{:08x}", pc),
+            };
+            *counts.entry(name).or_default() += hits;
+        }
+        let mut profile: Vec<_> = counts.into_iter().collect();
+        profile.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        profile
+    }
+
     pub fn clear(&mut self) {
         self.entries.clear();
+        self.hits.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use riscv32i_sim::{Symbol, SymbolKind};
+
+    fn entry(cycle: u64, pc: Addr) -> TraceEntry {
+        TraceEntry { cycle, pc, instruction: 0x0000_0013, disassembly: "nop".to_string() }
+    }
+
+    fn trace() -> ExecutionTrace {
+        let symbols = [("main", 0x100), ("loop", 0x200)]
+            .into_iter()
+            .map(|(name, addr)| Symbol { name: name.to_string(), addr, size: 0x20, kind: SymbolKind::Function })
+            .collect();
+        ExecutionTrace::new(2).with_symbols(symbols)
+    }
+
+    #[test]
+    fn test_entries_show_symbols() {
+        let trace = trace();
+
+        assert_eq!(trace.format_entry(&entry(7, 0x108)), "       7  0x00000108 <main+0x8>  00000013  nop");
+        assert_eq!(trace.format_entry(&entry(8, 0x400)), "       8  0x00000400  00000013  nop");
+    }
+
+    #[test]
+    fn test_profile_groups_by_symbol() {
+        let mut trace = trace();
+        for (cycle, pc) in [0x100, 0x200, 0x204, 0x200, 0x204, 0x400].into_iter().enumerate() {
+            trace.record(entry(cycle as u64, pc));
+        }
+
+        assert_eq!(trace.get_entries().len(), 2);
+        assert_eq!(trace.profile(), [("loop".to_string(), 4), ("0x00000400".to_string(), 1), ("main".to_string(), 1)]);
     }
 }
@@ -0,0 +1,71 @@
+//! Instruction throughput: `Cpu::run` vs `FastInterpreter` (with and without the JIT)
+//!
+//! Run with `cargo bench -p riscv32i-sim --bench throughput`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use riscv32i_sim::{Cpu, FastInterpreter, InstructionEncoder, RunConfig, Word};
+
+const ITERATIONS: i16 = 2000;
+
+/// Checksum loop over a 256-byte buffer: ALU ops, byte stores and loads,
+/// one branch per 7 instructions, then exit(a0)
+fn workload() -> Vec<Word> {
+    let addi = InstructionEncoder::addi;
+    vec![
+        addi(5, 0, ITERATIONS),                                          // t0 = iterations
+        InstructionEncoder::r_type(0b0110011, 10, 0b000, 10, 5, 0),      // loop: a0 += t0
+        InstructionEncoder::i_type(0b0010011, 6, 0b111, 5, 0xFF),        // t1 = t0 & 0xff
+        InstructionEncoder::s_type(0b0100011, 0b000, 6, 10, 0x400),      // sb a0, 0x400(t1)
+        InstructionEncoder::i_type(0b0000011, 7, 0b100, 6, 0x400),       // lbu t2, 0x400(t1)
+        InstructionEncoder::r_type(0b0110011, 10, 0b100, 10, 7, 0),      // a0 ^= t2
+        addi(5, 5, -1),                                                  // t0 -= 1
+        InstructionEncoder::b_type(0b1100011, 0b001, 5, 0, -24),         // bnez t0, loop
+        addi(17, 0, 93),                                                 // exit
+        0x0000_0073,
+    ]
+}
+
+fn bench_throughput(c: &mut Criterion) {
+    let program = workload();
+    let config = RunConfig::new();
+
+    let mut cpu = Cpu::new();
+    cpu.load_words(0, &program);
+    let instructions = cpu.run(&config).instructions;
+
+    let mut group = c.benchmark_group("throughput");
+    group.throughput(Throughput::Elements(instructions));
+
+    group.bench_function("cpu_run", |b| {
+        b.iter(|| {
+            let mut cpu = Cpu::new();
+            cpu.load_words(0, &program);
+            black_box(cpu.run(&config))
+        })
+    });
+
+    let mut fast = FastInterpreter::new();
+    group.bench_function("fast_interpreter", |b| {
+        b.iter(|| {
+            let mut cpu = Cpu::new();
+            cpu.load_words(0, &program);
+            black_box(fast.run(&mut cpu, &config))
+        })
+    });
+
+    let mut jit = FastInterpreter::new().with_jit(true);
+    if jit.jit_enabled() {
+        group.bench_function("jit", |b| {
+            b.iter(|| {
+                let mut cpu = Cpu::new();
+                cpu.load_words(0, &program);
+                black_box(jit.run(&mut cpu, &config))
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_throughput);
+criterion_main!(benches);
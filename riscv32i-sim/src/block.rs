@@ -0,0 +1,374 @@
+//! File-backed block storage device
+//!
+//! 512-byte sectors moved by DMA between the disk and a buffer in RAM:
+//!
+//! | offset | register    |                                              |
+//! |--------|-------------|----------------------------------------------|
+//! | 0x00   | CAPACITY    | read-only, sectors                           |
+//! | 0x04   | SECTOR      | first sector of the transfer                 |
+//! | 0x08   | BUFFER      | RAM address of the transfer                  |
+//! | 0x0C   | COUNT       | sectors to transfer                          |
+//! | 0x10   | COMMAND     | write: 1 read, 2 write, 3 flush              |
+//! | 0x14   | STATUS      | busy / done / error, write 1 to clear        |
+//! | 0x18   | IRQ_ENABLE  | bit 0: interrupt while done or error is set  |
+//! | 0x1C   | SECTOR_SIZE | read-only, 512                               |
+//!
+//! A command takes `latency` cycles per sector, then the whole transfer
+//! happens in one DMA turn and DONE (or ERROR, for a range past the end, a
+//! buffer outside RAM or a host I/O failure) is set. Commands written while busy are ignored.
+//!
+//! The disk is a host image file, opened read-write or copy-on-write (writes
+//! kept in memory, the file never changes), or an in-memory image.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::types::*;
+use crate::bus::{BusMaster, Device};
+
+pub const SECTOR_SIZE: usize = 512;
+
+pub const BLK_CAPACITY: Addr = 0x00;
+pub const BLK_SECTOR: Addr = 0x04;
+pub const BLK_BUFFER: Addr = 0x08;
+pub const BLK_COUNT: Addr = 0x0C;
+pub const BLK_COMMAND: Addr = 0x10;
+pub const BLK_STATUS: Addr = 0x14;
+pub const BLK_IRQ_ENABLE: Addr = 0x18;
+pub const BLK_SECTOR_SIZE: Addr = 0x1C;
+/// Size of the register window to map
+pub const BLK_SIZE: Addr = 0x100;
+
+pub const BLK_CMD_READ: Word = 1;
+pub const BLK_CMD_WRITE: Word = 2;
+pub const BLK_CMD_FLUSH: Word = 3;
+
+pub const BLK_STATUS_BUSY: Word = 1 << 0;
+pub const BLK_STATUS_DONE: Word = 1 << 1;
+pub const BLK_STATUS_ERROR: Word = 1 << 2;
+
+/// Cycles per sector unless set with `with_latency`
+const DEFAULT_LATENCY: u32 = 64;
+
+type Sector = Box<[u8; SECTOR_SIZE]>;
+
+enum Backing {
+    Memory(Vec<u8>),
+    File {
+        file: File,
+        len: u64,
+        /// Copy-on-write: sectors written since opening
+        overlay: Option<HashMap<u64, Sector>>,
+    },
+}
+
+/// Block device
+pub struct BlockDevice {
+    backing: Backing,
+    capacity: u64,
+    latency: u32,
+    sector: Word,
+    buffer: Addr,
+    count: Word,
+    status: Word,
+    irq_enable: Word,
+    /// Command in flight and the cycles until it completes
+    pending: Option<(Word, u64)>,
+}
+
+impl BlockDevice {
+    /// Disk held in memory (rounded up to whole sectors)
+    pub fn from_bytes(mut bytes: Vec<u8>) -> Self {
+        bytes.resize(bytes.len().next_multiple_of(SECTOR_SIZE), 0);
+        let capacity = (bytes.len() / SECTOR_SIZE) as u64;
+        Self::with_backing(Backing::Memory(bytes), capacity)
+    }
+
+    /// Image file, writes go to the file
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::options().read(true).write(true).open(path)?;
+        Self::from_file(file, false)
+    }
+
+    /// Image file, writes kept in memory so the file is never modified
+    pub fn open_cow(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_file(File::open(path)?, true)
+    }
+
+    fn from_file(file: File, cow: bool) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        let overlay = cow.then(HashMap::new);
+        Ok(Self::with_backing(Backing::File { file, len, overlay }, len.div_ceil(SECTOR_SIZE as u64)))
+    }
+
+    fn with_backing(backing: Backing, capacity: u64) -> Self {
+        Self {
+            backing,
+            capacity,
+            latency: DEFAULT_LATENCY,
+            sector: 0,
+            buffer: 0,
+            count: 0,
+            status: 0,
+            irq_enable: 0,
+            pending: None,
+        }
+    }
+
+    /// Cycles per sector transferred
+    pub fn with_latency(mut self, cycles: u32) -> Self {
+        self.latency = cycles;
+        self
+    }
+
+    /// Size in sectors
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn status(&self) -> Word {
+        self.status
+    }
+
+    /// Sectors past the end are an error for every backing
+    fn check_sector(&self, sector: u64) -> io::Result<()> {
+        if sector < self.capacity {
+            return Ok(());
+        }
+        let message = format!("sector {} past the end of the disk ({} sectors)", sector, self.capacity);
+        Err(io::Error::new(io::ErrorKind::InvalidInput, message))
+    }
+
+    /// Host-side read of one sector
+    pub fn read_sector(&mut self, sector: u64) -> io::Result<[u8; SECTOR_SIZE]> {
+        self.check_sector(sector)?;
+        let mut data = [0; SECTOR_SIZE];
+        match &mut self.backing {
+            Backing::Memory(bytes) => {
+                let start = sector as usize * SECTOR_SIZE;
+                data.copy_from_slice(&bytes[start..start + SECTOR_SIZE]);
+            }
+            Backing::File { file, len, overlay } => {
+                if let Some(copy) = overlay.as_ref().and_then(|o| o.get(&sector)) {
+                    return Ok(**copy);
+                }
+                // A short last sector reads as zero-padded
+                let start = sector * SECTOR_SIZE as u64;
+                let available = len.saturating_sub(start).min(SECTOR_SIZE as u64) as usize;
+                file.seek(SeekFrom::Start(start))?;
+                file.read_exact(&mut data[..available])?;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Host-side write of one sector
+    pub fn write_sector(&mut self, sector: u64, data: &[u8; SECTOR_SIZE]) -> io::Result<()> {
+        self.check_sector(sector)?;
+        match &mut self.backing {
+            Backing::Memory(bytes) => {
+                let start = sector as usize * SECTOR_SIZE;
+                bytes[start..start + SECTOR_SIZE].copy_from_slice(data);
+            }
+            Backing::File { overlay: Some(overlay), .. } => {
+                overlay.insert(sector, Box::new(*data));
+            }
+            Backing::File { file, len, overlay: None } => {
+                file.seek(SeekFrom::Start(sector * SECTOR_SIZE as u64))?;
+                file.write_all(data)?;
+                *len = (*len).max((sector + 1) * SECTOR_SIZE as u64);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.backing {
+            Backing::File { file, overlay: None, .. } => file.sync_data(),
+            _ => Ok(()),
+        }
+    }
+
+    fn start(&mut self, command: Word) {
+        if self.status & BLK_STATUS_BUSY != 0 {
+            return;
+        }
+        let in_range = self.count > 0 && self.sector as u64 + self.count as u64 <= self.capacity;
+        let cycles = match command {
+            BLK_CMD_READ | BLK_CMD_WRITE if in_range => self.latency as u64 * self.count as u64,
+            BLK_CMD_FLUSH => self.latency as u64,
+            _ => {
+                self.status |= BLK_STATUS_DONE | BLK_STATUS_ERROR;
+                return;
+            }
+        };
+        self.status = BLK_STATUS_BUSY;
+        self.pending = Some((command, cycles));
+    }
+
+    /// Move the data for `command` between disk and RAM
+    fn transfer(&mut self, command: Word, master: &mut BusMaster) -> io::Result<()> {
+        if command == BLK_CMD_FLUSH {
+            return self.flush();
+        }
+        let mut data = [0; SECTOR_SIZE];
+        for i in 0..self.count {
+            let sector = self.sector as u64 + i as u64;
+            let addr = self.buffer.wrapping_add(i * SECTOR_SIZE as Word);
+            if command == BLK_CMD_READ {
+                master.write_bytes(addr, &self.read_sector(sector)?).map_err(io::Error::other)?;
+            } else {
+                master.read_bytes(addr, &mut data).map_err(io::Error::other)?;
+                self.write_sector(sector, &data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Device for BlockDevice {
+    fn read(&mut self, offset: Addr, _size: usize) -> Word {
+        let value = match offset & !3 {
+            BLK_CAPACITY => self.capacity.min(Word::MAX as u64) as Word,
+            BLK_SECTOR => self.sector,
+            BLK_BUFFER => self.buffer,
+            BLK_COUNT => self.count,
+            BLK_STATUS => self.status,
+            BLK_IRQ_ENABLE => self.irq_enable,
+            BLK_SECTOR_SIZE => SECTOR_SIZE as Word,
+            _ => 0,
+        };
+        value >> (8 * (offset & 3))
+    }
+
+    fn write(&mut self, offset: Addr, value: Word, _size: usize) {
+        match offset & !3 {
+            BLK_SECTOR => self.sector = value,
+            BLK_BUFFER => self.buffer = value,
+            BLK_COUNT => self.count = value,
+            BLK_COMMAND => self.start(value),
+            BLK_STATUS => self.status &= !(value & (BLK_STATUS_DONE | BLK_STATUS_ERROR)),
+            BLK_IRQ_ENABLE => self.irq_enable = value & 1,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        if let Some((_, cycles)) = &mut self.pending {
+            *cycles = cycles.saturating_sub(1);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_enable != 0 && self.status & (BLK_STATUS_DONE | BLK_STATUS_ERROR) != 0
+    }
+
+    fn reset(&mut self) {
+        self.sector = 0;
+        self.buffer = 0;
+        self.count = 0;
+        self.status = 0;
+        self.irq_enable = 0;
+        self.pending = None;
+    }
+
+    fn is_bus_master(&self) -> bool {
+        true
+    }
+
+    fn dma(&mut self, master: &mut BusMaster) {
+        let Some((command, 0)) = self.pending else {
+            return;
+        };
+        self.pending = None;
+        self.status = match self.transfer(command, master) {
+            Ok(()) => BLK_STATUS_DONE,
+            Err(_) => BLK_STATUS_DONE | BLK_STATUS_ERROR,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::csr::{self, MIP_MEIP};
+    use crate::test_util::cpu_with;
+
+    const BASE: Addr = 0xF00;
+
+    /// Idle CPU with the disk mapped at BASE
+    fn disk_cpu(disk: BlockDevice) -> (Cpu, std::sync::Arc<std::sync::Mutex<BlockDevice>>) {
+        let mut cpu = cpu_with(&[InstructionEncoder::j_type(0b1101111, 0, 0)]);
+        let disk = cpu.memory.map_device("disk", BASE, BLK_SIZE, disk).unwrap();
+        (cpu, disk)
+    }
+
+    fn command(cpu: &mut Cpu, sector: Word, buffer: Addr, count: Word, command: Word) {
+        for (reg, value) in [(BLK_SECTOR, sector), (BLK_BUFFER, buffer), (BLK_COUNT, count), (BLK_COMMAND, command)] {
+            cpu.memory.store(BASE + reg, value, 0b010);
+        }
+    }
+
+    #[test]
+    fn test_read_dma_and_completion_irq() {
+        let image: Vec<u8> = (0..4 * SECTOR_SIZE).map(|i| (i / SECTOR_SIZE) as u8 + 1).collect();
+        let (mut cpu, _disk) = disk_cpu(BlockDevice::from_bytes(image).with_latency(10));
+        assert_eq!(cpu.memory.load(BASE + BLK_CAPACITY, 0b010), 4);
+        cpu.memory.store(BASE + BLK_IRQ_ENABLE, 1, 0b010);
+
+        command(&mut cpu, 2, 0x400, 2, BLK_CMD_READ);
+        cpu.run_cycles(19);
+        assert_eq!(cpu.memory.load(BASE + BLK_STATUS, 0b010), BLK_STATUS_BUSY);
+        assert_eq!(cpu.memory.read_byte(0x400), 0);
+        cpu.run_cycles(1);
+        assert_eq!(cpu.memory.load(BASE + BLK_STATUS, 0b010), BLK_STATUS_DONE);
+        assert_eq!(cpu.memory.read_bytes(0x400, 2 * SECTOR_SIZE)[..], [[3; SECTOR_SIZE], [4; SECTOR_SIZE]].concat());
+        assert!(cpu.csrs.read(csr::MIP).unwrap() & MIP_MEIP != 0);
+
+        cpu.memory.store(BASE + BLK_STATUS, BLK_STATUS_DONE, 0b010);
+        command(&mut cpu, 3, 0x400, 2, BLK_CMD_READ);      // past the end
+        assert_eq!(cpu.memory.load(BASE + BLK_STATUS, 0b010), BLK_STATUS_DONE | BLK_STATUS_ERROR);
+    }
+
+    #[test]
+    fn test_copy_on_write_leaves_image_untouched() {
+        let path = std::env::temp_dir().join(format!("blk-test-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0xAA; SECTOR_SIZE + 100]).unwrap();
+
+        let (mut cpu, disk) = disk_cpu(BlockDevice::open_cow(&path).unwrap().with_latency(1));
+        assert_eq!(disk.lock().unwrap().capacity(), 2);
+        cpu.memory.write_bytes(0x200, &[0x55; SECTOR_SIZE]);
+        command(&mut cpu, 1, 0x200, 1, BLK_CMD_WRITE);
+        cpu.run_cycles(2);
+        command(&mut cpu, 0, 0x400, 2, BLK_CMD_READ);
+        cpu.run_cycles(200);                                // after the write's 128-cycle burst
+
+        assert_eq!(cpu.memory.read_bytes(0x400, SECTOR_SIZE), vec![0xAA; SECTOR_SIZE]);
+        assert_eq!(cpu.memory.read_bytes(0x600, SECTOR_SIZE), vec![0x55; SECTOR_SIZE]);
+        assert_eq!(std::fs::read(&path).unwrap(), vec![0xAA; SECTOR_SIZE + 100]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_host_access_past_the_end_fails() {
+        let path = std::env::temp_dir().join(format!("blk-bounds-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0xAA; SECTOR_SIZE]).unwrap();
+        let disks = [
+            BlockDevice::from_bytes(vec![0xAA; SECTOR_SIZE]),
+            BlockDevice::open(&path).unwrap(),
+            BlockDevice::open_cow(&path).unwrap(),
+        ];
+
+        for mut disk in disks {
+            assert_eq!(disk.read_sector(0).unwrap(), [0xAA; SECTOR_SIZE]);
+            assert_eq!(disk.read_sector(1).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(disk.write_sector(1, &[0; SECTOR_SIZE]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(disk.capacity(), 1);
+        }
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), SECTOR_SIZE as u64);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,389 @@
+//! Memory-mapped peripherals
+//!
+//! A `Device` claims an address range on the `MmioBus` owned by `Memory`.
+//! Sized loads and stores (and AMOs) in that range go to the device instead
+//! of RAM, with the access width the instruction used. Instruction fetch
+//! from a device range reads 0, except from devices that implement `fetch`
+//! (ROM and extra RAM regions).
+//!
+//! Devices tick once per `Cpu` clock. Any device with its IRQ output high
+//! raises the machine external interrupt (mip.MEIP) of the hart that clocks,
+//! unless its line is routed to an interrupt controller (`route_irq`).
+//!
+//! Devices are shared (`Arc<Mutex<_>>`), so the embedder can keep a handle
+//! to inspect or drive a device while the program runs, and a cloned
+//! `Memory` sees the same devices.
+//!
+//! Bus masters (DMA-capable devices) get a `BusMaster` after every tick to
+//! read and write RAM and other devices directly; accesses that hit neither
+//! fail with `BusError::Unmapped`.
+
+use std::sync::{Arc, Mutex};
+
+use crate::types::*;
+use crate::memory::Memory;
+
+/// Peripheral register block
+pub trait Device: Send {
+    /// Read `size` bytes (1, 2 or 4) at `offset` from the device base
+    fn read(&mut self, offset: Addr, size: usize) -> Word;
+
+    /// Write the low `size` bytes of `value` at `offset`
+    fn write(&mut self, offset: Addr, value: Word, size: usize);
+
+    /// Instruction word at `offset`, None if the device is not executable
+    fn fetch(&mut self, _offset: Addr) -> Option<Word> {
+        None
+    }
+
+    /// Advance one clock cycle
+    fn tick(&mut self) {}
+
+    /// Interrupt output level
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Interrupt output to `hart` - the same line for every hart, unless the
+    /// device is an interrupt controller with an output per hart
+    fn hart_irq(&self, _hart: usize) -> bool {
+        self.irq()
+    }
+
+    /// Return to the power-on state
+    fn reset(&mut self) {}
+
+    /// Wants a `dma` turn each cycle (checked when mapped)
+    fn is_bus_master(&self) -> bool {
+        false
+    }
+
+    /// Bus-master accesses for this cycle, after `tick`
+    fn dma(&mut self, _master: &mut BusMaster) {}
+}
+
+pub type SharedDevice = Arc<Mutex<dyn Device>>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BusError {
+    #[error("Device '{name}' at 0x{base:08x}..+0x{size:x} overlaps '{other}'")]
+    Overlap { name: String, base: Addr, size: Addr, other: String },
+
+    #[error("Device '{name}' has an empty or wrapping range")]
+    BadRange { name: String },
+
+    #[error("Bus master access to unmapped address 0x{addr:08x}")]
+    Unmapped { addr: Addr },
+}
+
+/// Address range claimed by a device
+#[derive(Clone)]
+pub struct Mapping {
+    pub name: String,
+    pub base: Addr,
+    pub size: Addr,
+    device: SharedDevice,
+    master: bool,
+    /// IRQ goes to an interrupt controller, not straight to MEIP
+    routed: bool,
+}
+
+impl Mapping {
+    pub fn contains(&self, addr: Addr) -> bool {
+        addr.wrapping_sub(self.base) < self.size
+    }
+
+    pub fn device(&self) -> &SharedDevice {
+        &self.device
+    }
+}
+
+/// Routes device address ranges (everything else is RAM)
+#[derive(Clone, Default)]
+pub struct MmioBus {
+    mappings: Vec<Mapping>,
+}
+
+impl MmioBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Claim [base, base + size) for `device`
+    pub fn map(&mut self, name: &str, base: Addr, size: Addr, device: SharedDevice) -> Result<(), BusError> {
+        if size == 0 || base.checked_add(size - 1).is_none() {
+            return Err(BusError::BadRange { name: name.to_string() });
+        }
+        let last = base + (size - 1);
+        if let Some(other) = self.mappings.iter().find(|m| m.contains(base) || m.contains(last) || (base..=last).contains(&m.base)) {
+            return Err(BusError::Overlap { name: name.to_string(), base, size, other: other.name.clone() });
+        }
+        let master = lock(&device).is_bus_master();
+        self.mappings.push(Mapping { name: name.to_string(), base, size, device, master, routed: false });
+        Ok(())
+    }
+
+    /// Remove a device by name - its mapping, if it was mapped
+    pub fn unmap(&mut self, name: &str) -> Option<Mapping> {
+        let index = self.mappings.iter().position(|m| m.name == name)?;
+        Some(self.mappings.remove(index))
+    }
+
+    /// Take a device's IRQ line off MEIP and return the device, for an
+    /// interrupt controller to sample instead
+    pub fn route_irq(&mut self, name: &str) -> Option<SharedDevice> {
+        let mapping = self.mappings.iter_mut().find(|m| m.name == name)?;
+        mapping.routed = true;
+        Some(mapping.device.clone())
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Device mapped at `addr`
+    pub fn find(&self, addr: Addr) -> Option<&Mapping> {
+        self.mappings.iter().find(|m| m.contains(addr))
+    }
+
+    pub fn is_mmio(&self, addr: Addr) -> bool {
+        !self.mappings.is_empty() && self.find(addr).is_some()
+    }
+
+    /// Device read, None if `addr` is not MMIO
+    pub fn read(&self, addr: Addr, size: usize) -> Option<Word> {
+        let mapping = self.find(addr)?;
+        let value = lock(&mapping.device).read(addr - mapping.base, size);
+        Some(value & size_mask(size))
+    }
+
+    /// Device write - false if `addr` is not MMIO
+    pub fn write(&self, addr: Addr, value: Word, size: usize) -> bool {
+        let Some(mapping) = self.find(addr) else {
+            return false;
+        };
+        lock(&mapping.device).write(addr - mapping.base, value & size_mask(size), size);
+        true
+    }
+
+    /// Instruction fetch, None if `addr` is not MMIO or not executable
+    pub fn fetch(&self, addr: Addr) -> Option<Word> {
+        let mapping = self.find(addr)?;
+        lock(&mapping.device).fetch(addr - mapping.base)
+    }
+
+    /// Clock every device once
+    pub fn tick(&self) {
+        for mapping in &self.mappings {
+            lock(&mapping.device).tick();
+        }
+    }
+
+    /// Some device wired to MEIP has its IRQ output to `hart` high
+    pub fn irq_pending(&self, hart: usize) -> bool {
+        self.mappings.iter().any(|m| !m.routed && lock(&m.device).hart_irq(hart))
+    }
+
+    pub fn reset(&self) {
+        for mapping in &self.mappings {
+            lock(&mapping.device).reset();
+        }
+    }
+
+    /// Some device is a bus master
+    pub fn has_masters(&self) -> bool {
+        self.mappings.iter().any(|m| m.master)
+    }
+
+    /// Give each bus master its DMA turn - `memory` is the RAM behind this
+    /// bus, taken out of it for the duration
+    pub(crate) fn run_masters(&self, memory: &mut Memory) {
+        for (index, mapping) in self.mappings.iter().enumerate().filter(|(_, m)| m.master) {
+            let mut master = BusMaster { memory, bus: self, current: index, accesses: 0 };
+            lock(&mapping.device).dma(&mut master);
+            memory.dma_accesses += master.accesses;
+        }
+    }
+}
+
+/// A bus master's view of the system: RAM and every other device
+pub struct BusMaster<'a> {
+    memory: &'a mut Memory,
+    bus: &'a MmioBus,
+    /// Mapping of the device doing the accesses (locked, so not reachable)
+    current: usize,
+    accesses: u64,
+}
+
+impl BusMaster<'_> {
+    /// Another device at `addr`
+    fn device_at(&self, addr: Addr) -> Option<&Mapping> {
+        let (index, mapping) = self.bus.mappings.iter().enumerate().find(|(_, m)| m.contains(addr))?;
+        (index != self.current).then_some(mapping)
+    }
+
+    /// `len` bytes at `addr` lie in RAM, without wrapping
+    fn check_ram(&self, addr: Addr, len: usize) -> Result<(), BusError> {
+        match self.memory.contains(addr, len) {
+            true => Ok(()),
+            false => Err(BusError::Unmapped { addr }),
+        }
+    }
+
+    /// Sized read (1, 2 or 4 bytes, zero-extended)
+    pub fn read(&mut self, addr: Addr, size: usize) -> Result<Word, BusError> {
+        self.accesses += 1;
+        match self.device_at(addr) {
+            Some(mapping) => Ok(lock(&mapping.device).read(addr - mapping.base, size) & size_mask(size)),
+            None if self.bus.find(addr).is_some() => Ok(0),
+            None => {
+                self.check_ram(addr, size)?;
+                Ok((0..size).fold(0, |value, i| value | (self.memory.read_byte(addr + i as Addr) as Word) << (8 * i)))
+            }
+        }
+    }
+
+    /// Sized write of the low `size` bytes
+    pub fn write(&mut self, addr: Addr, value: Word, size: usize) -> Result<(), BusError> {
+        self.accesses += 1;
+        match self.device_at(addr) {
+            Some(mapping) => lock(&mapping.device).write(addr - mapping.base, value & size_mask(size), size),
+            None if self.bus.find(addr).is_some() => {}
+            None => {
+                self.check_ram(addr, size)?;
+                self.memory.write_bytes(addr, &value.to_le_bytes()[..size.min(4)]);
+                self.record_write(addr, size);
+            }
+        }
+        Ok(())
+    }
+
+    /// Block read from RAM, one bus access per word
+    pub fn read_bytes(&mut self, addr: Addr, buf: &mut [u8]) -> Result<(), BusError> {
+        self.accesses += buf.len().div_ceil(4) as u64;
+        self.check_ram(addr, buf.len())?;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = self.memory.read_byte(addr + i as Addr);
+        }
+        Ok(())
+    }
+
+    /// Block write to RAM, one bus access per word
+    pub fn write_bytes(&mut self, addr: Addr, data: &[u8]) -> Result<(), BusError> {
+        self.accesses += data.len().div_ceil(4) as u64;
+        self.check_ram(addr, data.len())?;
+        self.memory.write_bytes(addr, data);
+        self.record_write(addr, data.len());
+        Ok(())
+    }
+
+    fn record_write(&mut self, addr: Addr, len: usize) {
+        if self.memory.track_dma_writes {
+            self.memory.dma_writes.push((addr, len));
+        }
+    }
+
+    /// Bus accesses made so far this turn
+    pub fn accesses(&self) -> u64 {
+        self.accesses
+    }
+}
+
+/// A device that panicked mid-access is still usable
+pub(crate) fn lock(device: &SharedDevice) -> std::sync::MutexGuard<'_, dyn Device + 'static> {
+    device.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn size_mask(size: usize) -> Word {
+    Word::MAX >> (32 - 8 * size.min(4) as u32)
+}
+
+/// `register` after a `size`-byte write of `value` at `offset`: only the
+/// byte lanes the write covers change
+pub(crate) fn merge_lanes(register: Word, offset: Addr, value: Word, size: usize) -> Word {
+    let shift = 8 * (offset & 3);
+    let mask = size_mask(size) << shift;
+    (register & !mask) | ((value << shift) & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::csr::{self, MIP_MEIP};
+    use crate::memory::Memory;
+
+    /// Scratch register at 0, access log, IRQ after `fire_at` ticks
+    #[derive(Default)]
+    struct TestDevice {
+        scratch: Word,
+        log: Vec<(bool, Addr, Word, usize)>,
+        ticks: u64,
+        fire_at: Option<u64>,
+    }
+
+    impl Device for TestDevice {
+        fn read(&mut self, offset: Addr, size: usize) -> Word {
+            self.log.push((false, offset, self.scratch, size));
+            self.scratch >> (8 * (offset & 3))
+        }
+
+        fn write(&mut self, offset: Addr, value: Word, size: usize) {
+            self.log.push((true, offset, value, size));
+            self.scratch = value;
+        }
+
+        fn tick(&mut self) {
+            self.ticks += 1;
+        }
+
+        fn irq(&self) -> bool {
+            self.fire_at.is_some_and(|at| self.ticks >= at)
+        }
+    }
+
+    #[test]
+    fn test_sized_accesses_route_to_device() {
+        let mut memory = Memory::new();
+        let device = memory.map_device("test", 0x800, 0x10, TestDevice::default()).unwrap();
+        assert!(memory.map_device("clash", 0x80C, 0x10, TestDevice::default()).is_err());
+
+        memory.store(0x800, 0xAABB_CCDD, 0b010);
+        memory.store(0x805, 0x1234, 0b000);
+        assert_eq!(memory.load(0x800, 0b000), 0x34);
+        assert_eq!(memory.load(0x7FC, 0b010), 0);    // RAM below is untouched
+        assert_eq!(memory.fetch(0x800), 0);         // not executable
+
+        let log = &device.lock().unwrap().log;
+        assert_eq!(log[0], (true, 0, 0xAABB_CCDD, 4));
+        assert_eq!(log[1], (true, 5, 0x34, 1));
+        assert_eq!(log[2], (false, 0, 0x34, 1));
+    }
+
+    #[test]
+    fn test_device_irq_raises_external_interrupt() {
+        let mut cpu = Cpu::new();
+        let device = TestDevice { fire_at: Some(5), ..Default::default() };
+        let device = cpu.memory.map_device("timer", 0xF00, 0x10, device).unwrap();
+        cpu.csrs.write(csr::MTVEC, 0x100);
+        cpu.csrs.write(csr::MIE, MIP_MEIP);
+        cpu.csrs.write(csr::MSTATUS, csr::MSTATUS_MIE);
+        // addi x1, x1, 1 forever
+        let program: Vec<(Addr, Word)> = (0..16)
+            .map(|i| (i * 4, InstructionEncoder::i_type(0b0010011, 1, 0b000, 1, 1)))
+            .collect();
+        cpu.load_program(&program);
+
+        // Four instructions, then the interrupt is taken on the fifth clock
+        cpu.run_cycles(5);
+
+        assert_eq!(device.lock().unwrap().ticks, 5);
+        assert_eq!(cpu.csrs.read(csr::MCAUSE), Some(csr::MCAUSE_INTERRUPT | 11));
+        assert_eq!(cpu.control.get_pc(), 0x100);
+        assert_eq!(cpu.registers.peek(1), 4);
+    }
+}
@@ -0,0 +1,465 @@
+//! Snooping cache coherence between harts
+//!
+//! Every hart gets a private data cache in front of the shared `Memory`.
+//! The caches keep per-line coherence state with MESI (or MOESI) on a
+//! shared snooping bus. Data always comes from the shared memory - the
+//! caches are coherent, so they only track state and statistics:
+//! bus transactions, invalidations and false sharing per cache line.
+//!
+//! False sharing is counted when a write invalidates another hart's copy
+//! of a line although that hart never touched the written word.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::types::*;
+use crate::memory::MemoryAccess;
+use crate::multi_hart::{MultiHart, HartStep};
+
+/// Coherence protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Mesi,
+    Moesi,
+}
+
+/// Coherence state of a cache line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineState {
+    Modified,
+    Owned,
+    Exclusive,
+    Shared,
+    Invalid,
+}
+
+/// Private cache geometry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub sets: usize,
+    pub ways: usize,
+    pub line_size: usize,  // Bytes, power of two, at most 64
+}
+
+impl CacheConfig {
+    /// 1 KB, 2-way, 32-byte lines
+    pub fn small() -> Self {
+        Self { sets: 16, ways: 2, line_size: 32 }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CacheLine {
+    line_addr: Addr,
+    state: LineState,
+    last_use: u64,
+    words_touched: u64,  // Words accessed by this hart since the line was filled
+}
+
+/// Private per-hart cache (tags and coherence state only)
+#[derive(Debug, Clone)]
+pub struct PrivateCache {
+    config: CacheConfig,
+    sets: Vec<Vec<CacheLine>>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl PrivateCache {
+    pub fn new(config: CacheConfig) -> Self {
+        assert!(config.line_size.is_power_of_two() && (4..=64).contains(&config.line_size),
+            "line size must be a power of two between 4 and 64 bytes");
+        assert!(config.sets > 0 && config.ways > 0, "cache needs at least one set and way");
+
+        Self {
+            config,
+            sets: vec![Vec::with_capacity(config.ways); config.sets],
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Coherence state of the line holding `addr`
+    pub fn state(&self, addr: Addr) -> LineState {
+        let line_addr = self.line_addr(addr);
+        self.find(line_addr).map_or(LineState::Invalid, |line| line.state)
+    }
+
+    fn line_addr(&self, addr: Addr) -> Addr {
+        addr & !(self.config.line_size as Addr - 1)
+    }
+
+    fn set_index(&self, line_addr: Addr) -> usize {
+        (line_addr as usize / self.config.line_size) % self.config.sets
+    }
+
+    fn find(&self, line_addr: Addr) -> Option<&CacheLine> {
+        self.sets[self.set_index(line_addr)].iter()
+            .find(|line| line.line_addr == line_addr && line.state != LineState::Invalid)
+    }
+
+    fn find_mut(&mut self, line_addr: Addr) -> Option<&mut CacheLine> {
+        let set = self.set_index(line_addr);
+        self.sets[set].iter_mut()
+            .find(|line| line.line_addr == line_addr && line.state != LineState::Invalid)
+    }
+
+    /// Install a line, returning the evicted victim (if valid)
+    fn fill(&mut self, line_addr: Addr, state: LineState, now: u64) -> Option<CacheLine> {
+        let ways = self.config.ways;
+        let set = self.set_index(line_addr);
+        let lines = &mut self.sets[set];
+        lines.retain(|line| line.state != LineState::Invalid);
+
+        // LRU replacement
+        let victim = if lines.len() >= ways {
+            lines.iter().enumerate()
+                .min_by_key(|(_, line)| line.last_use)
+                .map(|(index, _)| index)
+                .map(|index| lines.remove(index))
+        } else {
+            None
+        };
+
+        lines.push(CacheLine { line_addr, state, last_use: now, words_touched: 0 });
+        victim
+    }
+}
+
+/// Snooping bus transactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusTransaction {
+    BusRd,    // Read miss
+    BusRdX,   // Write miss (read for ownership)
+    BusUpgr,  // Write hit on a shared line
+    Flush,    // Dirty data written back / supplied by the owner
+}
+
+/// Per cache line coherence statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineStats {
+    pub invalidations: u64,
+    pub false_sharing: u64,
+}
+
+/// Bus-wide statistics
+#[derive(Debug, Clone, Default)]
+pub struct CoherenceStats {
+    pub bus_rd: u64,
+    pub bus_rdx: u64,
+    pub bus_upgr: u64,
+    pub flushes: u64,
+    pub cache_to_cache: u64,  // Misses served by another cache
+    pub invalidations: u64,
+    pub per_line: BTreeMap<Addr, LineStats>,
+}
+
+impl CoherenceStats {
+    pub fn bus_transactions(&self) -> u64 {
+        self.bus_rd + self.bus_rdx + self.bus_upgr + self.flushes
+    }
+
+    pub fn false_sharing(&self) -> u64 {
+        self.per_line.values().map(|line| line.false_sharing).sum()
+    }
+
+    fn record(&mut self, transaction: BusTransaction) {
+        match transaction {
+            BusTransaction::BusRd => self.bus_rd += 1,
+            BusTransaction::BusRdX => self.bus_rdx += 1,
+            BusTransaction::BusUpgr => self.bus_upgr += 1,
+            BusTransaction::Flush => self.flushes += 1,
+        }
+    }
+}
+
+impl fmt::Display for CoherenceStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Bus transactions: {}", self.bus_transactions())?;
+        writeln!(f, "  BusRd:   {}", self.bus_rd)?;
+        writeln!(f, "  BusRdX:  {}", self.bus_rdx)?;
+        writeln!(f, "  BusUpgr: {}", self.bus_upgr)?;
+        writeln!(f, "  Flush:   {}", self.flushes)?;
+        writeln!(f, "Cache-to-cache transfers: {}", self.cache_to_cache)?;
+        writeln!(f, "Invalidations: {} ({} false sharing)", self.invalidations, self.false_sharing())?;
+        for (line_addr, line) in &self.per_line {
+            writeln!(f, "  line 0x{:08X}: {} invalidations, {} false sharing",
+                line_addr, line.invalidations, line.false_sharing)?;
+        }
+        Ok(())
+    }
+}
+
+/// Private caches kept coherent on a snooping bus
+pub struct SnoopBus {
+    protocol: Protocol,
+    caches: Vec<PrivateCache>,
+    stats: CoherenceStats,
+    now: u64,
+}
+
+impl SnoopBus {
+    pub fn new(protocol: Protocol, harts: usize, config: CacheConfig) -> Self {
+        Self {
+            protocol,
+            caches: (0..harts).map(|_| PrivateCache::new(config)).collect(),
+            stats: CoherenceStats::default(),
+            now: 0,
+        }
+    }
+
+    pub fn cache(&self, hart: usize) -> &PrivateCache {
+        &self.caches[hart]
+    }
+
+    pub fn get_stats(&self) -> &CoherenceStats {
+        &self.stats
+    }
+
+    /// Run one data access from `hart` through its cache and the bus
+    pub fn access(&mut self, hart: usize, access: &MemoryAccess) {
+        self.now += 1;
+        let line_addr = self.caches[hart].line_addr(access.addr);
+        let word_bit = 1u64 << ((access.addr - line_addr) / 4);
+
+        let state = self.caches[hart].state(access.addr);
+        let hit = if access.is_write() {
+            self.write(hart, line_addr, state, word_bit)
+        } else {
+            self.read(hart, line_addr, state)
+        };
+
+        let cache = &mut self.caches[hart];
+        if hit {
+            cache.hits += 1;
+        } else {
+            cache.misses += 1;
+        }
+        if let Some(line) = cache.find_mut(line_addr) {
+            line.last_use = self.now;
+            line.words_touched |= word_bit;
+        }
+    }
+
+    /// Processor read - returns true on a hit
+    fn read(&mut self, hart: usize, line_addr: Addr, state: LineState) -> bool {
+        if state != LineState::Invalid {
+            return true;
+        }
+
+        // BusRd: other copies downgrade, a dirty owner supplies the data
+        self.stats.record(BusTransaction::BusRd);
+        let mut shared = false;
+        for other in self.others(hart) {
+            let protocol = self.protocol;
+            let Some(line) = self.caches[other].find_mut(line_addr) else {
+                continue;
+            };
+            shared = true;
+            let (next, flush) = match (line.state, protocol) {
+                (LineState::Modified, Protocol::Mesi) => (LineState::Shared, true),
+                (LineState::Modified, Protocol::Moesi) => (LineState::Owned, false),
+                (LineState::Owned, _) => (LineState::Owned, false),
+                (_, _) => (LineState::Shared, false),
+            };
+            let supplies = matches!(line.state, LineState::Modified | LineState::Owned);
+            line.state = next;
+            if flush {
+                self.stats.record(BusTransaction::Flush);
+            }
+            if supplies {
+                self.stats.cache_to_cache += 1;
+            }
+        }
+
+        let state = if shared { LineState::Shared } else { LineState::Exclusive };
+        self.install(hart, line_addr, state);
+        false
+    }
+
+    /// Processor write - returns true on a hit
+    fn write(&mut self, hart: usize, line_addr: Addr, state: LineState, word_bit: u64) -> bool {
+        match state {
+            LineState::Modified => return true,
+            LineState::Exclusive => {
+                // Silent upgrade, no bus traffic
+                if let Some(line) = self.caches[hart].find_mut(line_addr) {
+                    line.state = LineState::Modified;
+                }
+                return true;
+            }
+            LineState::Shared | LineState::Owned => {
+                self.stats.record(BusTransaction::BusUpgr);
+                self.invalidate_others(hart, line_addr, word_bit);
+                if let Some(line) = self.caches[hart].find_mut(line_addr) {
+                    line.state = LineState::Modified;
+                }
+                return true;
+            }
+            LineState::Invalid => {}
+        }
+
+        // BusRdX: read for ownership, a dirty owner flushes as it invalidates
+        self.stats.record(BusTransaction::BusRdX);
+        if self.invalidate_others(hart, line_addr, word_bit) {
+            self.stats.record(BusTransaction::Flush);
+            self.stats.cache_to_cache += 1;
+        }
+        self.install(hart, line_addr, LineState::Modified);
+        false
+    }
+
+    /// Invalidate every other copy - returns true if a dirty owner supplied data
+    fn invalidate_others(&mut self, hart: usize, line_addr: Addr, word_bit: u64) -> bool {
+        let mut supplied = false;
+        for other in self.others(hart) {
+            let Some(line) = self.caches[other].find_mut(line_addr) else {
+                continue;
+            };
+            supplied |= matches!(line.state, LineState::Modified | LineState::Owned);
+            let false_sharing = line.words_touched & word_bit == 0;
+            line.state = LineState::Invalid;
+
+            self.stats.invalidations += 1;
+            let stats = self.stats.per_line.entry(line_addr).or_default();
+            stats.invalidations += 1;
+            if false_sharing {
+                stats.false_sharing += 1;
+            }
+        }
+        supplied
+    }
+
+    fn install(&mut self, hart: usize, line_addr: Addr, state: LineState) {
+        let victim = self.caches[hart].fill(line_addr, state, self.now);
+        if let Some(victim) = victim {
+            if matches!(victim.state, LineState::Modified | LineState::Owned) {
+                self.stats.record(BusTransaction::Flush);
+            }
+        }
+    }
+
+    fn others(&self, hart: usize) -> impl Iterator<Item = usize> {
+        (0..self.caches.len()).filter(move |&other| other != hart)
+    }
+}
+
+/// Multi-hart system with coherent private data caches
+pub struct CoherentSystem {
+    pub system: MultiHart,
+    pub bus: SnoopBus,
+}
+
+impl CoherentSystem {
+    pub fn new(system: MultiHart, protocol: Protocol, config: CacheConfig) -> Self {
+        let bus = SnoopBus::new(protocol, system.hart_count(), config);
+        Self { system, bus }
+    }
+
+    /// Execute one scheduled instruction and feed its data access to the caches
+    /// MMIO accesses are uncached and bypass the cache model
+    pub fn step(&mut self) -> HartStep {
+        let step = self.system.step();
+        if let Some(access) = step.access.filter(|access| !self.system.memory.bus().is_mmio(access.addr)) {
+            self.bus.access(step.hart, &access);
+        }
+        step
+    }
+
+    pub fn run_cycles(&mut self, count: usize) {
+        for _ in 0..count {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::AccessKind;
+    use crate::multi_hart::Schedule;
+    use crate::test_util::{addi, lw, sw};
+
+    fn read(addr: Addr) -> MemoryAccess {
+        MemoryAccess { kind: AccessKind::Read, addr, value: 0 }
+    }
+
+    fn write(addr: Addr) -> MemoryAccess {
+        MemoryAccess { kind: AccessKind::Write, addr, value: 1 }
+    }
+
+    #[test]
+    fn test_mesi_read_sharing_and_upgrade() {
+        let mut bus = SnoopBus::new(Protocol::Mesi, 2, CacheConfig::small());
+
+        bus.access(0, &read(0x100));
+        assert_eq!(bus.cache(0).state(0x100), LineState::Exclusive);
+
+        bus.access(1, &read(0x100));
+        assert_eq!(bus.cache(0).state(0x100), LineState::Shared);
+        assert_eq!(bus.cache(1).state(0x100), LineState::Shared);
+
+        bus.access(0, &write(0x100));
+        assert_eq!(bus.cache(0).state(0x100), LineState::Modified);
+        assert_eq!(bus.cache(1).state(0x100), LineState::Invalid);
+
+        let stats = bus.get_stats();
+        assert_eq!((stats.bus_rd, stats.bus_upgr, stats.invalidations), (2, 1, 1));
+        assert_eq!(stats.false_sharing(), 0);
+    }
+
+    #[test]
+    fn test_moesi_keeps_dirty_owner() {
+        let mut mesi = SnoopBus::new(Protocol::Mesi, 2, CacheConfig::small());
+        let mut moesi = SnoopBus::new(Protocol::Moesi, 2, CacheConfig::small());
+        for bus in [&mut mesi, &mut moesi] {
+            bus.access(0, &write(0x200));
+            bus.access(1, &read(0x200));
+        }
+
+        assert_eq!(mesi.cache(0).state(0x200), LineState::Shared);
+        assert_eq!(mesi.get_stats().flushes, 1);
+        assert_eq!(moesi.cache(0).state(0x200), LineState::Owned);
+        assert_eq!(moesi.get_stats().flushes, 0);
+    }
+
+    #[test]
+    fn test_write_miss_flushes_dirty_owner() {
+        for protocol in [Protocol::Mesi, Protocol::Moesi] {
+            let mut bus = SnoopBus::new(protocol, 2, CacheConfig::small());
+            bus.access(0, &write(0x300));
+            bus.access(1, &write(0x300));
+
+            assert_eq!(bus.cache(0).state(0x300), LineState::Invalid);
+            assert_eq!(bus.cache(1).state(0x300), LineState::Modified);
+            let stats = bus.get_stats();
+            assert_eq!((stats.bus_rdx, stats.flushes, stats.cache_to_cache), (2, 1, 1));
+        }
+    }
+
+    #[test]
+    fn test_false_sharing_between_harts() {
+        // Each hart repeatedly increments its own word of the same cache line
+        let counter = |offset: i16| vec![
+            lw(5, 0, 0x100 + offset),
+            addi(5, 5, 1),
+            sw(0, 5, 0x100 + offset),
+            InstructionEncoder::j_type(0b1101111, 0, -12),                             // j loop
+        ];
+        let mut system = MultiHart::new(2, Schedule::RoundRobin { quantum: 4 });
+        for (hart, offset) in [(0, 0), (1, 4)] {
+            let base = 0x40 * hart as Addr;
+            let program: Vec<_> = counter(offset).into_iter().enumerate()
+                .map(|(i, inst)| (base + 4 * i as Addr, inst)).collect();
+            system.load_program(&program);
+            system.harts[hart].control.set_pc(base);
+        }
+
+        let mut coherent = CoherentSystem::new(system, Protocol::Mesi, CacheConfig::small());
+        coherent.run_cycles(80);
+
+        let line = coherent.bus.get_stats().per_line[&0x100];
+        assert!(line.invalidations > 0);
+        assert_eq!(line.false_sharing, line.invalidations);
+    }
+}
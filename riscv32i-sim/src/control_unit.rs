@@ -6,6 +6,8 @@ pub struct ControlUnit {
     current_instruction: Instruction,
     control_signals: ControlSignals,
     program_counter: Addr,
+    /// PC after reset
+    reset_vector: Addr,
 }
 
 impl ControlUnit {
@@ -14,6 +16,7 @@ impl ControlUnit {
             current_instruction: Instruction::new(0),
             control_signals: ControlSignals::new(),
             program_counter: 0,
+            reset_vector: 0,
         }
     }
 
@@ -60,6 +63,7 @@ impl ControlUnit {
             0b1100011 => {
                 signals.alu_op = AluOp::Sub;  // For comparison
                 signals.branch = true;
+                signals.illegal = matches!(funct3, 0b010 | 0b011);
             }
             
             // Load instructions
@@ -69,6 +73,7 @@ impl ControlUnit {
                 signals.mem_read = true;
                 signals.mem_to_reg = true;
                 signals.reg_write = true;
+                signals.illegal = matches!(funct3, 0b011 | 0b110 | 0b111);
             }
             
             // Store instructions
@@ -76,6 +81,7 @@ impl ControlUnit {
                 signals.alu_op = AluOp::Add;
                 signals.alu_src = true;
                 signals.mem_write = true;
+                signals.illegal = funct3 > 0b010;
             }
             
             // I-type ALU operations
@@ -118,19 +124,79 @@ impl ControlUnit {
                     (0b101, 0b0100000) => AluOp::Sra,   // SRA
                     (0b110, _) => AluOp::Or,            // OR
                     (0b111, _) => AluOp::And,           // AND
-                    _ => AluOp::Add,
+                    _ => {
+                        signals.illegal = true;
+                        AluOp::Add
+                    }
                 };
             }
+
+            // FENCE, FENCE.I - single in-order hart, so a NOP
+            0b0001111 => {
+                signals.alu_op = AluOp::PassA;
+            }
             
-            // SYSTEM (ECALL, EBREAK)
+            // SYSTEM (ECALL, EBREAK, MRET, WFI, Zicsr)
             0b1110011 => {
-                // For now, treat as NOP
                 signals.alu_op = AluOp::PassA;
+
+                signals.system = match funct3 {
+                    0b000 => match inst.raw >> 20 {
+                        0x000 => SystemOp::Ecall,
+                        0x001 => SystemOp::Ebreak,
+                        0x302 => SystemOp::Mret,
+                        0x105 => SystemOp::Wfi,
+                        _ => {
+                            signals.illegal = true;
+                            SystemOp::None
+                        }
+                    },
+                    0b001 | 0b101 => SystemOp::Csr(CsrOp::Write),  // CSRRW, CSRRWI
+                    0b010 | 0b110 => SystemOp::Csr(CsrOp::Set),    // CSRRS, CSRRSI
+                    0b011 | 0b111 => SystemOp::Csr(CsrOp::Clear),  // CSRRC, CSRRCI
+                    _ => {
+                        signals.illegal = true;
+                        SystemOp::None
+                    }
+                };
+
+                // CSR instructions write the old CSR value to rd
+                signals.reg_write = matches!(signals.system, SystemOp::Csr(_));
+            }
+
+            // AMO (A extension) - address comes straight from rs1
+            0b0101111 if funct3 == 0b010 => {
+                let amo = match funct7 >> 2 {
+                    0b00010 => Some(AmoOp::Lr),
+                    0b00011 => Some(AmoOp::Sc),
+                    0b00001 => Some(AmoOp::Swap),
+                    0b00000 => Some(AmoOp::Add),
+                    0b00100 => Some(AmoOp::Xor),
+                    0b01100 => Some(AmoOp::And),
+                    0b01000 => Some(AmoOp::Or),
+                    0b10000 => Some(AmoOp::Min),
+                    0b10100 => Some(AmoOp::Max),
+                    0b11000 => Some(AmoOp::Minu),
+                    0b11100 => Some(AmoOp::Maxu),
+                    _ => None,
+                };
+
+                signals.alu_op = AluOp::PassA;
+                if let Some(op) = amo {
+                    signals.amo = Some(op);
+                    signals.mem_read = op != AmoOp::Sc;
+                    signals.mem_write = op != AmoOp::Lr;
+                    signals.mem_to_reg = true;
+                    signals.reg_write = true;
+                } else {
+                    signals.illegal = true;
+                }
             }
             
             _ => {
-                // Unknown instruction - NOP
+                // Unknown instruction - no side effects, flagged as illegal
                 signals.alu_op = AluOp::PassA;
+                signals.illegal = true;
             }
         }
 
@@ -169,8 +235,16 @@ impl ControlUnit {
         self.program_counter = pc & !0x3;
     }
 
+    pub fn get_reset_vector(&self) -> Addr {
+        self.reset_vector
+    }
+
+    pub fn set_reset_vector(&mut self, addr: Addr) {
+        self.reset_vector = addr & !0x3;
+    }
+
     pub fn reset(&mut self) {
-        self.program_counter = 0;
+        self.program_counter = self.reset_vector;
         self.control_signals = ControlSignals::new();
     }
 }
@@ -1,40 +1,113 @@
 use crate::types::*;
-use crate::memory::Memory;
+use crate::memory::{Memory, MemoryAccess, AccessKind};
 use crate::register_file::RegisterFile;
 use crate::control_unit::ControlUnit;
 use crate::alu::Alu;
+use crate::csr::{self, CsrFile, MIP_MEIP};
+use crate::syscall::{SyscallAction, SyscallHandler};
+use crate::htif::{Htif, TestFinisher};
+
+/// Synchronous exception raised by the last instruction that no trap
+/// handler took (mtvec = 0), so it is left for the host to act on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuEvent {
+    /// ECALL - PC has moved past it
+    Ecall,
+    /// EBREAK - PC has moved past it
+    Ebreak,
+    /// Undecodable instruction - PC still points at it
+    IllegalInstruction(Word),
+    /// The program exited through a syscall, HTIF or the test finisher
+    Exit(i32),
+}
 
 /// RISC-V CPU - integrates all submodules
 /// Implements RV32I base integer instruction set
+/// plus Zicsr, machine-mode interrupts and the A extension (word AMOs)
 pub struct Cpu {
     // Submodules
     pub memory: Memory,
     pub registers: RegisterFile,
     pub control: ControlUnit,
     pub alu: Alu,
+    pub csrs: CsrFile,
     
     // Pipeline state
     cycle_count: u64,
+    waiting_for_interrupt: bool,
+
+    // Cycles data accesses spent waiting for bus-master bursts
+    bus_stalls: u64,
+
+    // LR/SC reservation (word address)
+    reservation: Option<Addr>,
+
+    // Data memory access of the last instruction
+    last_access: Option<MemoryAccess>,
+
+    // Unhandled exception raised by the last instruction
+    last_event: Option<CpuEvent>,
+
+    // Host services for ECALL
+    syscalls: Option<Box<dyn SyscallHandler>>,
+
+    // Test-harness exit devices, watched on every store
+    htif: Option<Htif>,
+    test_finisher: Option<TestFinisher>,
 }
 
 impl Cpu {
     pub fn new() -> Self {
+        Self::with_hart_id(0)
+    }
+
+    /// CPU with a given mhartid (for multi-hart systems)
+    pub fn with_hart_id(hart_id: Word) -> Self {
         Self {
             memory: Memory::new(),
             registers: RegisterFile::new(),
             control: ControlUnit::new(),
             alu: Alu::new(),
+            csrs: CsrFile::new(hart_id),
             cycle_count: 0,
+            waiting_for_interrupt: false,
+            bus_stalls: 0,
+            reservation: None,
+            last_access: None,
+            last_event: None,
+            syscalls: None,
+            htif: None,
+            test_finisher: None,
         }
     }
 
     /// Single clock cycle - RISC-V fetch-decode-execute
     pub fn clock(&mut self) {
         self.cycle_count += 1;
+        self.csrs.tick();
+        self.tick_devices();
+        self.last_access = None;
+        self.last_event = None;
+
+        // INTERRUPTS: checked between instructions
+        let pc = self.control.get_pc();
+        if let Some(cause) = self.csrs.pending_interrupt() {
+            self.waiting_for_interrupt = false;
+            let handler = self.csrs.enter_trap(cause, pc, 0);
+            self.control.set_pc(handler);
+            return;
+        }
+
+        // WFI: stall until an enabled interrupt is pending (even if MIE = 0)
+        if self.waiting_for_interrupt {
+            if self.csrs.enabled_pending() == 0 {
+                return;
+            }
+            self.waiting_for_interrupt = false;
+        }
 
         // FETCH: Get instruction at PC
         // RISC-V: PC is byte-addressed, instructions are 4-byte aligned
-        let pc = self.control.get_pc();
         let instruction_word = self.memory.fetch(pc);
         let inst = Instruction::new(instruction_word);
 
@@ -42,6 +115,12 @@ impl Cpu {
         self.control.clock(inst);
         let ctrl = self.control.get_control_signals();
 
+        // ILLEGAL: no side effects, PC stays on the instruction (mepc)
+        if ctrl.illegal {
+            self.raise(CpuEvent::IllegalInstruction(instruction_word), pc);
+            return;
+        }
+
         // READ REGISTERS: Read rs1 and rs2
         let rs1 = inst.rs1();
         let rs2 = inst.rs2();
@@ -52,30 +131,38 @@ impl Cpu {
         let rs2_data = self.registers.get_read_data_b();
 
         // EXECUTE: ALU operation
-        let alu_operand_b = if ctrl.alu_src {
-            // Use immediate value
-            inst.imm_i() as Word
-        } else {
-            // Use rs2
-            rs2_data
-        };
-
-        // Special handling for AUIPC (add upper immediate to PC)
-        let alu_operand_a = if inst.opcode() == 0b0010111 {
-            pc  // AUIPC uses PC as operand A
-        } else if inst.opcode() == 0b0110111 {
-            0   // LUI uses 0 as operand A
-        } else {
-            rs1_data
-        };
+        let (alu_operand_a, alu_operand_b) = Self::alu_operands(&inst, &ctrl, pc, rs1_data, rs2_data);
 
         let alu_result = self.alu.execute(ctrl.alu_op, alu_operand_a, alu_operand_b);
 
+        // CSR: read old value, then read-modify-write
+        let mut csr_data = 0;
+        if let SystemOp::Csr(op) = ctrl.system {
+            let Some(old) = self.csr_access(&inst, op, rs1_data) else {
+                self.raise(CpuEvent::IllegalInstruction(instruction_word), pc);
+                return;
+            };
+            csr_data = old;
+        }
+
         // MEMORY: Load/Store operations
         let mut mem_data = 0;
-        if ctrl.mem_read || ctrl.mem_write {
-            self.memory.clock(ctrl.mem_read, ctrl.mem_write, alu_result, rs2_data);
-            mem_data = self.memory.get_read_data();
+        if ctrl.amo.is_some() || ctrl.mem_write || ctrl.mem_read {
+            self.wait_for_bus();
+        }
+        if let Some(op) = ctrl.amo {
+            mem_data = self.atomic_access(op, alu_result, rs2_data);
+        } else if ctrl.mem_write {
+            self.memory.store(alu_result, rs2_data, inst.funct3());
+            let value = rs2_data & (Word::MAX >> (32 - (8 << (inst.funct3() & 0b11))));
+            self.last_access = Some(MemoryAccess { kind: AccessKind::Write, addr: alu_result, value });
+        } else if ctrl.mem_read {
+            mem_data = self.memory.load(alu_result, inst.funct3());
+            self.last_access = Some(MemoryAccess { kind: AccessKind::Read, addr: alu_result, value: mem_data });
+        }
+
+        if let Some(access) = self.last_access.filter(MemoryAccess::is_write) {
+            self.host_write(access);
         }
 
         // WRITE BACK: Write result to register
@@ -85,6 +172,8 @@ impl Cpu {
             } else if ctrl.jump {
                 // JAL/JALR: Save return address (PC + 4)
                 pc.wrapping_add(4)
+            } else if let SystemOp::Csr(_) = ctrl.system {
+                csr_data
             } else {
                 alu_result
             };
@@ -93,13 +182,232 @@ impl Cpu {
         }
 
         // UPDATE PC
-        let branch_taken = self.should_branch(&inst, rs1_data, rs2_data);
-        let jump_target = self.calculate_jump_target(&inst, pc, rs1_data);
+        let branch_taken = Self::should_branch(&inst, rs1_data, rs2_data);
+        let jump_target = Self::calculate_jump_target(&inst, pc, rs1_data);
         self.control.update_pc(branch_taken, jump_target);
+
+        match ctrl.system {
+            SystemOp::Mret => {
+                let resume = self.csrs.mret();
+                self.control.set_pc(resume);
+            }
+            SystemOp::Wfi => self.waiting_for_interrupt = true,
+            SystemOp::Ecall => self.ecall(pc),
+            SystemOp::Ebreak => self.raise(CpuEvent::Ebreak, pc),
+            _ => {}
+        }
+
+        self.csrs.retire();
+    }
+
+    /// ECALL - the syscall handler gets first go, otherwise it is an exception
+    fn ecall(&mut self, pc: Addr) {
+        let Some(mut handler) = self.syscalls.take() else {
+            self.raise(CpuEvent::Ecall, pc);
+            return;
+        };
+        let action = handler.ecall(self);
+        self.syscalls = Some(handler);
+
+        match action {
+            SyscallAction::Continue => {}
+            SyscallAction::Exit(code) => self.last_event = Some(CpuEvent::Exit(code)),
+            SyscallAction::Unhandled => self.raise(CpuEvent::Ecall, pc),
+        }
+    }
+
+    /// Start of an instruction executed outside `clock` (fast interpreter):
+    /// the same cycle accounting and per-instruction state reset
+    pub(crate) fn begin_instruction(&mut self) {
+        self.cycle_count += 1;
+        self.csrs.tick();
+        self.tick_devices();
+        self.last_access = None;
+        self.last_event = None;
+    }
+
+    /// Data access made outside `clock` - stores are seen by the host devices
+    pub(crate) fn record_access(&mut self, access: MemoryAccess) {
+        self.last_access = Some(access);
+        self.last_event = None;
+        if access.is_write() {
+            self.host_write(access);
+        }
+    }
+
+    /// Cycles of `count` instructions in a native block (JIT), ticked
+    /// before a load or store so it sees devices as `clock` would
+    pub(crate) fn tick_native(&mut self, count: u64) {
+        for _ in 0..count {
+            self.cycle_count += 1;
+            self.csrs.tick();
+            self.tick_devices();
+        }
+    }
+
+    /// `count` instructions run as one native block (JIT), of which
+    /// `ticked` already had their cycles: cycle and retire accounting,
+    /// keeping the access only if the last instruction made it
+    pub(crate) fn retire_block(&mut self, count: u64, ticked: u64, ends_with_access: bool) {
+        self.tick_native(count.saturating_sub(ticked));
+        for _ in 0..count {
+            self.csrs.retire();
+        }
+        if !ends_with_access {
+            self.last_access = None;
+            self.last_event = None;
+        }
+    }
+
+    /// A data access waits out a bus master's burst (instruction fetch has
+    /// its own port). Devices keep ticking, but the CPU gets the bus next.
+    pub(crate) fn wait_for_bus(&mut self) {
+        let stall = std::mem::take(&mut self.memory.bus_owed);
+        if stall == 0 {
+            return;
+        }
+        for _ in 0..stall {
+            self.cycle_count += 1;
+            self.csrs.tick();
+            self.memory.bus().tick();
+        }
+        self.bus_stalls += stall;
+        self.csrs.set_pending(MIP_MEIP, self.memory.bus().irq_pending(self.csrs.hart_id() as usize));
+    }
+
+    /// Cycles data accesses waited for bus-master (DMA) bursts
+    pub fn bus_stall_cycles(&self) -> u64 {
+        self.bus_stalls
+    }
+
+    /// Clock the MMIO devices; their IRQ outputs drive mip.MEIP
+    fn tick_devices(&mut self) {
+        if !self.memory.bus().is_empty() {
+            self.memory.tick_devices();
+            self.csrs.set_pending(MIP_MEIP, self.memory.bus().irq_pending(self.csrs.hart_id() as usize));
+        }
+    }
+
+    /// Stores to the HTIF mailbox or test finisher can end the program
+    fn host_write(&mut self, access: MemoryAccess) {
+        let htif_exit = self.htif.as_mut().and_then(|htif| htif.observe(&mut self.memory, &access));
+        let finisher_exit = self.test_finisher.and_then(|finisher| finisher.observe(&access));
+        if let Some(code) = htif_exit.or(finisher_exit) {
+            self.last_event = Some(CpuEvent::Exit(code));
+        }
+    }
+
+    /// Synchronous exception at `pc` - trap if a handler is installed,
+    /// otherwise record the event for the host
+    fn raise(&mut self, event: CpuEvent, pc: Addr) {
+        if !self.csrs.has_trap_handler() {
+            self.last_event = Some(event);
+            return;
+        }
+
+        let (cause, tval) = match event {
+            CpuEvent::Ecall => (csr::CAUSE_ECALL_M, 0),
+            CpuEvent::Ebreak => (csr::CAUSE_BREAKPOINT, pc),
+            CpuEvent::IllegalInstruction(word) => (csr::CAUSE_ILLEGAL_INSTRUCTION, word),
+            CpuEvent::Exit(_) => unreachable!("exit is not an exception"),
+        };
+        let handler = self.csrs.enter_trap(cause, pc, tval);
+        self.control.set_pc(handler);
+    }
+
+    /// Zicsr instruction - returns the old CSR value for rd, None (and no
+    /// effect) for an unimplemented CSR or a write to a read-only one
+    fn csr_access(&mut self, inst: &Instruction, op: CsrOp, rs1_data: Word) -> Option<Word> {
+        let csr = (inst.raw >> 20) as u16;
+        let old = self.csrs.read(csr)?;
+
+        // Immediate forms use the rs1 field as a 5-bit zero-extended value
+        let source = if inst.funct3() & 0b100 != 0 { inst.rs1() as Word } else { rs1_data };
+
+        // CSRRS/CSRRC with rs1 = x0 (or zimm = 0) must not write
+        let new = match op {
+            CsrOp::Write => Some(source),
+            CsrOp::Set if inst.rs1() != 0 => Some(old | source),
+            CsrOp::Clear if inst.rs1() != 0 => Some(old & !source),
+            _ => None,
+        };
+        if let Some(value) = new {
+            if !self.csrs.write(csr, value) {
+                return None;
+            }
+        }
+
+        Some(old)
+    }
+
+    /// Atomic memory operation - returns the value for rd
+    fn atomic_access(&mut self, op: AmoOp, addr: Addr, rs2_data: Word) -> Word {
+        let addr = addr & !0x3;
+
+        if op == AmoOp::Sc {
+            // Store-conditional succeeds only with a valid reservation
+            let success = self.reservation.take() == Some(addr);
+            if success {
+                self.memory.store(addr, rs2_data, 0b010);
+                self.last_access = Some(MemoryAccess { kind: AccessKind::Atomic, addr, value: rs2_data });
+            }
+            return if success { 0 } else { 1 };
+        }
+
+        let old = self.memory.load(addr, 0b010);
+
+        if op == AmoOp::Lr {
+            self.reservation = Some(addr);
+            self.last_access = Some(MemoryAccess { kind: AccessKind::Read, addr, value: old });
+            return old;
+        }
+        let new = op.apply(old, rs2_data);
+
+        self.memory.store(addr, new, 0b010);
+        self.last_access = Some(MemoryAccess { kind: AccessKind::Atomic, addr, value: new });
+        old
+    }
+
+    /// Select the immediate for the instruction's format
+    /// RISC-V: S-type and U-type immediates are not in the I-type position
+    pub(crate) fn immediate(inst: &Instruction) -> Word {
+        match inst.opcode() {
+            0b0100011 => inst.imm_s() as Word,              // Store (S-type)
+            0b0110111 | 0b0010111 => inst.imm_u() as Word,  // LUI, AUIPC (U-type)
+            _ => inst.imm_i() as Word,                      // I-type
+        }
+    }
+
+    /// ALU operand multiplexers (operand A, operand B)
+    pub(crate) fn alu_operands(
+        inst: &Instruction,
+        ctrl: &ControlSignals,
+        pc: Addr,
+        rs1_data: Word,
+        rs2_data: Word,
+    ) -> (Word, Word) {
+        let operand_b = if ctrl.alu_src {
+            // Use immediate value
+            Self::immediate(inst)
+        } else {
+            // Use rs2
+            rs2_data
+        };
+
+        // Special handling for AUIPC (add upper immediate to PC)
+        let operand_a = if inst.opcode() == 0b0010111 {
+            pc  // AUIPC uses PC as operand A
+        } else if inst.opcode() == 0b0110111 {
+            0   // LUI uses 0 as operand A
+        } else {
+            rs1_data
+        };
+
+        (operand_a, operand_b)
     }
 
     /// Determine if branch should be taken (RISC-V branch conditions)
-    fn should_branch(&self, inst: &Instruction, rs1_data: Word, rs2_data: Word) -> bool {
+    pub(crate) fn should_branch(inst: &Instruction, rs1_data: Word, rs2_data: Word) -> bool {
         if inst.opcode() != 0b1100011 {
             return false;  // Not a branch instruction
         }
@@ -116,7 +424,7 @@ impl Cpu {
     }
 
     /// Calculate jump/branch target address
-    fn calculate_jump_target(&self, inst: &Instruction, pc: Addr, rs1_data: Word) -> Addr {
+    pub(crate) fn calculate_jump_target(inst: &Instruction, pc: Addr, rs1_data: Word) -> Addr {
         match inst.opcode() {
             0b1101111 => pc.wrapping_add(inst.imm_j() as u32),        // JAL
             0b1100111 => rs1_data.wrapping_add(inst.imm_i() as u32) & !1, // JALR (bit 0 = 0)
@@ -135,15 +443,103 @@ impl Cpu {
         self.cycle_count
     }
 
-    pub fn reset(&mut self) {
+    /// Clock this hart against an external (shared) memory
+    pub fn clock_with_memory(&mut self, memory: &mut Memory) {
+        std::mem::swap(&mut self.memory, memory);
+        self.clock();
+        std::mem::swap(&mut self.memory, memory);
+    }
+
+    /// Data memory access made by the last clock, if any
+    pub fn last_memory_access(&self) -> Option<MemoryAccess> {
+        self.last_access
+    }
+
+    /// Install the host services ECALL is routed to
+    pub fn set_syscall_handler(&mut self, handler: Box<dyn SyscallHandler>) {
+        self.syscalls = Some(handler);
+    }
+
+    /// Remove the syscall handler - ECALL goes back to being a plain exception
+    pub fn take_syscall_handler(&mut self) -> Option<Box<dyn SyscallHandler>> {
+        self.syscalls.take()
+    }
+
+    /// Watch the HTIF tohost mailbox
+    pub fn set_htif(&mut self, htif: Htif) {
+        self.htif = Some(htif);
+    }
+
+    /// Stop watching the HTIF mailbox, handing it back
+    pub fn take_htif(&mut self) -> Option<Htif> {
+        self.htif.take()
+    }
+
+    /// Map a SiFive test finisher
+    pub fn set_test_finisher(&mut self, finisher: TestFinisher) {
+        self.test_finisher = Some(finisher);
+    }
+
+    pub fn test_finisher(&self) -> Option<TestFinisher> {
+        self.test_finisher
+    }
+
+    /// Unhandled exception raised by the last clock, if any
+    pub fn last_event(&self) -> Option<CpuEvent> {
+        self.last_event
+    }
+
+    /// Current LR/SC reservation
+    pub fn reservation(&self) -> Option<Addr> {
+        self.reservation
+    }
+
+    /// Drop the LR/SC reservation (another hart wrote the reserved word)
+    pub fn invalidate_reservation(&mut self) {
+        self.reservation = None;
+    }
+
+    /// Hart is stalled in WFI
+    pub fn is_waiting_for_interrupt(&self) -> bool {
+        self.waiting_for_interrupt
+    }
+
+    /// PC after a reset (0 by default)
+    pub fn set_reset_vector(&mut self, addr: Addr) {
+        self.control.set_reset_vector(addr);
+    }
+
+    /// Warm reset: registers, CSRs and PC (to the reset vector) only -
+    /// memory, devices and host services keep their state
+    pub fn warm_reset(&mut self) {
         self.control.reset();
         self.registers.reset();
-        self.memory.reset();
+        self.csrs.reset();
         self.cycle_count = 0;
+        self.bus_stalls = 0;
+        self.waiting_for_interrupt = false;
+        self.reservation = None;
+        self.last_access = None;
+        self.last_event = None;
+    }
+
+    /// Cold reset: as at power-on, with RAM cleared and every device reset
+    /// (use `warm_reset` to keep a loaded program)
+    pub fn reset(&mut self) {
+        self.warm_reset();
+        self.memory.reset();
+        if let Some(handler) = self.syscalls.as_mut() {
+            handler.reset();
+        }
     }
 
     /// Load RISC-V program into memory
     pub fn load_program(&mut self, program: &[(Addr, Word)]) {
         self.memory.load_program(program);
     }
+
+    /// Load consecutive instruction words starting at `base`
+    pub fn load_words(&mut self, base: Addr, words: &[Word]) {
+        self.memory.load_words(base, words);
+    }
 }
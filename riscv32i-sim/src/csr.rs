@@ -0,0 +1,217 @@
+use crate::types::*;
+
+/// Machine-mode CSR addresses (RISC-V privileged spec)
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const MCYCLE: u16 = 0xB00;
+pub const MINSTRET: u16 = 0xB02;
+pub const MCYCLEH: u16 = 0xB80;
+pub const MINSTRETH: u16 = 0xB82;
+pub const CYCLE: u16 = 0xC00;
+pub const TIME: u16 = 0xC01;
+pub const INSTRET: u16 = 0xC02;
+pub const CYCLEH: u16 = 0xC80;
+pub const TIMEH: u16 = 0xC81;
+pub const INSTRETH: u16 = 0xC82;
+pub const MVENDORID: u16 = 0xF11;
+pub const MARCHID: u16 = 0xF12;
+pub const MIMPID: u16 = 0xF13;
+pub const MHARTID: u16 = 0xF14;
+
+/// mstatus fields
+pub const MSTATUS_MIE: Word = 1 << 3;
+pub const MSTATUS_MPIE: Word = 1 << 7;
+pub const MSTATUS_MPP: Word = 0b11 << 11;
+
+/// mip/mie bits (software, timer, external)
+pub const MIP_MSIP: Word = 1 << 3;
+pub const MIP_MTIP: Word = 1 << 7;
+pub const MIP_MEIP: Word = 1 << 11;
+
+/// mcause interrupt flag
+pub const MCAUSE_INTERRUPT: Word = 1 << 31;
+
+/// mcause exception codes
+pub const CAUSE_ILLEGAL_INSTRUCTION: Word = 2;
+pub const CAUSE_BREAKPOINT: Word = 3;
+pub const CAUSE_ECALL_M: Word = 11;
+
+/// RV32 machine-mode Control and Status Registers (Zicsr)
+/// Only M-mode is implemented, so MPP always reads as M (0b11)
+#[derive(Debug, Clone)]
+pub struct CsrFile {
+    hart_id: Word,
+    mstatus: Word,
+    mie: Word,
+    mip: Word,
+    mtvec: Word,
+    mscratch: Word,
+    mepc: Word,
+    mcause: Word,
+    mtval: Word,
+    mcycle: u64,
+    minstret: u64,
+}
+
+impl CsrFile {
+    pub fn new(hart_id: Word) -> Self {
+        Self {
+            hart_id,
+            mstatus: MSTATUS_MPP,
+            mie: 0,
+            mip: 0,
+            mtvec: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mcycle: 0,
+            minstret: 0,
+        }
+    }
+
+    /// CSR read - None for unimplemented CSRs
+    pub fn read(&self, addr: u16) -> Option<Word> {
+        let value = match addr {
+            MSTATUS => self.mstatus,
+            // MXL=1 (RV32), extensions I (bit 8) and A (bit 0)
+            MISA => (1 << 30) | (1 << 8) | 1,
+            MIE => self.mie,
+            MTVEC => self.mtvec,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MIP => self.mip,
+            MCYCLE | CYCLE | TIME => self.mcycle as Word,
+            MCYCLEH | CYCLEH | TIMEH => (self.mcycle >> 32) as Word,
+            MINSTRET | INSTRET => self.minstret as Word,
+            MINSTRETH | INSTRETH => (self.minstret >> 32) as Word,
+            MVENDORID | MARCHID | MIMPID => 0,
+            MHARTID => self.hart_id,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// CSR write - false for unimplemented or read-only CSRs
+    /// RISC-V rule: CSRs with address bits [11:10] = 0b11 are read-only
+    pub fn write(&mut self, addr: u16, value: Word) -> bool {
+        if addr >> 10 == 0b11 {
+            return false;
+        }
+
+        match addr {
+            MSTATUS => self.mstatus = (value & (MSTATUS_MIE | MSTATUS_MPIE)) | MSTATUS_MPP,
+            MISA => {}  // WARL: extensions are fixed
+            MIE => self.mie = value & (MIP_MSIP | MIP_MTIP | MIP_MEIP),
+            MTVEC => self.mtvec = value & !0b10,  // Modes 0 (direct) and 1 (vectored)
+            MSCRATCH => self.mscratch = value,
+            MEPC => self.mepc = value & !0x3,
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            MIP => {}  // MSIP/MTIP/MEIP are driven by the platform, not software
+            MCYCLE => self.mcycle = (self.mcycle & !0xFFFF_FFFF) | value as u64,
+            MCYCLEH => self.mcycle = (self.mcycle & 0xFFFF_FFFF) | ((value as u64) << 32),
+            MINSTRET => self.minstret = (self.minstret & !0xFFFF_FFFF) | value as u64,
+            MINSTRETH => self.minstret = (self.minstret & 0xFFFF_FFFF) | ((value as u64) << 32),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn hart_id(&self) -> Word {
+        self.hart_id
+    }
+
+    /// Drive an interrupt-pending line from the platform (e.g. CLINT msip)
+    pub fn set_pending(&mut self, mask: Word, level: bool) {
+        if level {
+            self.mip |= mask;
+        } else {
+            self.mip &= !mask;
+        }
+    }
+
+    /// Interrupts that are both pending and enabled in mie
+    pub fn enabled_pending(&self) -> Word {
+        self.mip & self.mie
+    }
+
+    /// Highest-priority interrupt to take now, if any (MEI > MSI > MTI)
+    pub fn pending_interrupt(&self) -> Option<Word> {
+        if self.mstatus & MSTATUS_MIE == 0 {
+            return None;
+        }
+        let pending = self.enabled_pending();
+        [(MIP_MEIP, 11), (MIP_MSIP, 3), (MIP_MTIP, 7)]
+            .iter()
+            .find(|(bit, _)| pending & bit != 0)
+            .map(|&(_, code)| MCAUSE_INTERRUPT | code)
+    }
+
+    /// Trap entry - returns the handler address
+    pub fn enter_trap(&mut self, cause: Word, pc: Addr, tval: Word) -> Addr {
+        self.mepc = pc;
+        self.mcause = cause;
+        self.mtval = tval;
+
+        // MPIE <= MIE, MIE <= 0
+        let mie = self.mstatus & MSTATUS_MIE != 0;
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
+        if mie {
+            self.mstatus |= MSTATUS_MPIE;
+        }
+
+        let base = self.mtvec & !0x3;
+        let vectored = self.mtvec & 0x1 != 0;
+        if vectored && cause & MCAUSE_INTERRUPT != 0 {
+            base.wrapping_add(4 * (cause & !MCAUSE_INTERRUPT))
+        } else {
+            base
+        }
+    }
+
+    /// A trap handler is installed (mtvec != 0)
+    /// With no handler, synchronous exceptions are reported to the host instead
+    pub fn has_trap_handler(&self) -> bool {
+        self.mtvec != 0
+    }
+
+    /// MRET - returns the address to resume at
+    pub fn mret(&mut self) -> Addr {
+        // MIE <= MPIE, MPIE <= 1
+        let mpie = self.mstatus & MSTATUS_MPIE != 0;
+        self.mstatus = (self.mstatus & !MSTATUS_MIE) | MSTATUS_MPIE;
+        if mpie {
+            self.mstatus |= MSTATUS_MIE;
+        }
+        self.mepc
+    }
+
+    /// Count one clock cycle
+    pub fn tick(&mut self) {
+        self.mcycle = self.mcycle.wrapping_add(1);
+    }
+
+    /// Count one retired instruction
+    pub fn retire(&mut self) {
+        self.minstret = self.minstret.wrapping_add(1);
+    }
+
+    pub fn get_instret(&self) -> u64 {
+        self.minstret
+    }
+
+    /// Reset all CSRs (mhartid is hardwired)
+    pub fn reset(&mut self) {
+        *self = Self::new(self.hart_id);
+    }
+}
@@ -0,0 +1,337 @@
+//! Multi-channel DMA controller
+//!
+//! Each channel copies COUNT units of 1, 2 or 4 bytes from SRC to DST over
+//! the bus, so either side can be RAM or a device register (a fixed address
+//! such as a UART's THR for memory-to-peripheral transfers).
+//!
+//! | offset          | register    |                                        |
+//! |-----------------|-------------|----------------------------------------|
+//! | 0x00            | CHANNELS    | read-only                              |
+//! | 0x04            | IRQ_STATUS  | bit per finished channel, write 1 to clear |
+//! | 0x08            | IRQ_ENABLE  | bit per channel                        |
+//! | 0x40 + 0x20 * n | SRC         | source address                         |
+//! | + 0x04          | DST         | destination address                    |
+//! | + 0x08          | COUNT       | units left                             |
+//! | + 0x0C          | CONTROL     | START, WIDTH, SRC/DST_FIXED, BURST     |
+//! | + 0x10          | STATUS      | busy / done / error, write 1 to clear  |
+//!
+//! CONTROL: bit 0 starts the channel (clearing it aborts), bits 1-2 are
+//! log2 of the unit size, bits 3 and 4 keep SRC / DST fixed, bits 8-15 are
+//! the burst length in units (0 = 1).
+//!
+//! One busy channel, round robin, moves a burst per DMA turn. A burst holds
+//! the bus for one cycle per access (a read and a write per unit), and CPU
+//! loads and stores wait for it - see `Cpu::bus_stall_cycles`.
+
+use crate::types::*;
+use crate::bus::{BusMaster, Device};
+
+pub const DMA_CHANNELS: Addr = 0x00;
+pub const DMA_IRQ_STATUS: Addr = 0x04;
+pub const DMA_IRQ_ENABLE: Addr = 0x08;
+/// Register block of channel 0
+pub const DMA_CHANNEL_BASE: Addr = 0x40;
+pub const DMA_CHANNEL_STRIDE: Addr = 0x20;
+
+pub const DMA_SRC: Addr = 0x00;
+pub const DMA_DST: Addr = 0x04;
+pub const DMA_COUNT: Addr = 0x08;
+pub const DMA_CONTROL: Addr = 0x0C;
+pub const DMA_STATUS: Addr = 0x10;
+/// Size of the register window to map
+pub const DMA_SIZE: Addr = 0x1000;
+
+pub const DMA_CONTROL_START: Word = 1 << 0;
+pub const DMA_CONTROL_SRC_FIXED: Word = 1 << 3;
+pub const DMA_CONTROL_DST_FIXED: Word = 1 << 4;
+pub const DMA_WIDTH_SHIFT: u32 = 1;
+pub const DMA_BURST_SHIFT: u32 = 8;
+
+pub const DMA_STATUS_BUSY: Word = 1 << 0;
+pub const DMA_STATUS_DONE: Word = 1 << 1;
+pub const DMA_STATUS_ERROR: Word = 1 << 2;
+
+/// Most channels one controller has
+const MAX_CHANNELS: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Channel {
+    src: Addr,
+    dst: Addr,
+    count: Word,
+    control: Word,
+    status: Word,
+}
+
+impl Channel {
+    fn busy(&self) -> bool {
+        self.status & DMA_STATUS_BUSY != 0
+    }
+
+    fn width(&self) -> usize {
+        1 << ((self.control >> DMA_WIDTH_SHIFT) & 0b11).min(2)
+    }
+
+    fn burst(&self) -> Word {
+        ((self.control >> DMA_BURST_SHIFT) & 0xFF).max(1)
+    }
+
+    /// An incrementing address would step past the top of memory
+    fn wraps(&self, addr: Addr, fixed: Word) -> bool {
+        self.control & fixed == 0 && addr as u64 + self.count as u64 * self.width() as u64 > 1 << 32
+    }
+
+    /// Start or abort - true if a start finished at once (nothing to
+    /// move, or misaligned or wrapping addresses)
+    fn write_control(&mut self, value: Word) -> bool {
+        self.control = value;
+        if value & DMA_CONTROL_START == 0 {
+            self.status &= !DMA_STATUS_BUSY;
+            return false;
+        }
+        let misaligned = (self.src | self.dst) as usize & (self.width() - 1) != 0;
+        let bad = misaligned || self.wraps(self.src, DMA_CONTROL_SRC_FIXED) || self.wraps(self.dst, DMA_CONTROL_DST_FIXED);
+        if !bad && self.count > 0 {
+            self.status = DMA_STATUS_BUSY;
+            return false;
+        }
+        self.finish(bad);
+        true
+    }
+
+    fn finish(&mut self, error: bool) {
+        self.status = if error { DMA_STATUS_DONE | DMA_STATUS_ERROR } else { DMA_STATUS_DONE };
+        self.control &= !DMA_CONTROL_START;
+    }
+
+    /// Move one burst - true when the channel has finished, or stopped
+    /// with an error at an unmapped address (SRC/DST/COUNT left there)
+    fn run_burst(&mut self, master: &mut BusMaster) -> bool {
+        let width = self.width();
+        for _ in 0..self.burst().min(self.count) {
+            let moved = master.read(self.src, width).and_then(|value| master.write(self.dst, value, width));
+            if moved.is_err() {
+                self.finish(true);
+                return true;
+            }
+            if self.control & DMA_CONTROL_SRC_FIXED == 0 {
+                self.src = self.src.wrapping_add(width as Addr);
+            }
+            if self.control & DMA_CONTROL_DST_FIXED == 0 {
+                self.dst = self.dst.wrapping_add(width as Addr);
+            }
+            self.count -= 1;
+        }
+        if self.count > 0 {
+            return false;
+        }
+        self.finish(false);
+        true
+    }
+}
+
+/// DMA controller
+pub struct DmaController {
+    channels: Vec<Channel>,
+    irq_status: Word,
+    irq_enable: Word,
+    /// Channel served last, for round robin
+    last: usize,
+}
+
+impl DmaController {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            channels: vec![Channel::default(); channels.clamp(1, MAX_CHANNELS)],
+            irq_status: 0,
+            irq_enable: 0,
+            last: 0,
+        }
+    }
+
+    /// Some channel has a transfer in flight
+    pub fn busy(&self) -> bool {
+        self.channels.iter().any(Channel::busy)
+    }
+
+    pub fn channel_status(&self, channel: usize) -> Word {
+        self.channels[channel].status
+    }
+
+    /// (channel index, register within the channel block)
+    fn channel_register(&self, offset: Addr) -> Option<(usize, Addr)> {
+        let relative = offset.checked_sub(DMA_CHANNEL_BASE)?;
+        let index = (relative / DMA_CHANNEL_STRIDE) as usize;
+        (index < self.channels.len()).then_some((index, relative % DMA_CHANNEL_STRIDE))
+    }
+}
+
+impl Device for DmaController {
+    fn read(&mut self, offset: Addr, _size: usize) -> Word {
+        let value = match offset & !3 {
+            DMA_CHANNELS => self.channels.len() as Word,
+            DMA_IRQ_STATUS => self.irq_status,
+            DMA_IRQ_ENABLE => self.irq_enable,
+            register => match self.channel_register(register) {
+                Some((index, register)) => {
+                    let channel = &self.channels[index];
+                    match register {
+                        DMA_SRC => channel.src,
+                        DMA_DST => channel.dst,
+                        DMA_COUNT => channel.count,
+                        DMA_CONTROL => channel.control,
+                        DMA_STATUS => channel.status,
+                        _ => 0,
+                    }
+                }
+                None => 0,
+            },
+        };
+        value >> (8 * (offset & 3))
+    }
+
+    fn write(&mut self, offset: Addr, value: Word, _size: usize) {
+        match offset & !3 {
+            DMA_IRQ_STATUS => self.irq_status &= !value,
+            DMA_IRQ_ENABLE => self.irq_enable = value,
+            register => {
+                let Some((index, register)) = self.channel_register(register) else {
+                    return;
+                };
+                let channel = &mut self.channels[index];
+                match register {
+                    // Addresses and count are fixed while a transfer runs
+                    DMA_SRC | DMA_DST | DMA_COUNT if channel.busy() => {}
+                    DMA_SRC => channel.src = value,
+                    DMA_DST => channel.dst = value,
+                    DMA_COUNT => channel.count = value,
+                    DMA_CONTROL => {
+                        let finished = channel.write_control(value);
+                        self.irq_status |= (finished as Word) << index;
+                    }
+                    DMA_STATUS => channel.status &= !(value & (DMA_STATUS_DONE | DMA_STATUS_ERROR)),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_status & self.irq_enable != 0
+    }
+
+    fn reset(&mut self) {
+        let count = self.channels.len();
+        *self = Self::new(count);
+    }
+
+    fn is_bus_master(&self) -> bool {
+        true
+    }
+
+    fn dma(&mut self, master: &mut BusMaster) {
+        let count = self.channels.len();
+        let Some(index) = (1..=count).map(|i| (self.last + i) % count).find(|&i| self.channels[i].busy()) else {
+            return;
+        };
+        self.last = index;
+        if self.channels[index].run_burst(master) {
+            self.irq_status |= 1 << index;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::uart::Uart16550;
+
+    const BASE: Addr = 0x8000;
+
+    fn channel(n: Addr, register: Addr) -> Addr {
+        BASE + DMA_CHANNEL_BASE + n * DMA_CHANNEL_STRIDE + register
+    }
+
+    /// CPU looping on a load, with 16 KiB of RAM and the controller mapped
+    fn cpu_with_dma() -> (Cpu, std::sync::Arc<std::sync::Mutex<DmaController>>) {
+        let mut cpu = Cpu::new();
+        cpu.memory = crate::memory::Memory::with_size(0x4000);
+        cpu.load_program(&[
+            (0, InstructionEncoder::i_type(0b0000011, 5, 0b010, 0, 0x100)),  // loop: lw t0, 0x100(x0)
+            (4, InstructionEncoder::j_type(0b1101111, 0, -4)),
+        ]);
+        let dma = cpu.memory.map_device("dma", BASE, DMA_SIZE, DmaController::new(4)).unwrap();
+        (cpu, dma)
+    }
+
+    #[test]
+    fn test_memory_copy_bursts_and_contention() {
+        let (mut cpu, dma) = cpu_with_dma();
+        let data: Vec<u8> = (0..=255).collect();
+        cpu.memory.write_bytes(0x1000, &data);
+        cpu.memory.store(BASE + DMA_IRQ_ENABLE, 0b10, 0b010);
+        for (register, value) in [(DMA_SRC, 0x1000), (DMA_DST, 0x2000), (DMA_COUNT, 64)] {
+            cpu.memory.store(channel(1, register), value, 0b010);
+        }
+        let control = DMA_CONTROL_START | (2 << DMA_WIDTH_SHIFT) | (8 << DMA_BURST_SHIFT);
+        cpu.memory.store(channel(1, DMA_CONTROL), control, 0b010);
+        assert!(dma.lock().unwrap().busy());
+
+        cpu.run_cycles(200);
+
+        assert_eq!(cpu.memory.read_bytes(0x2000, 256), data);
+        assert_eq!(dma.lock().unwrap().channel_status(1), DMA_STATUS_DONE);
+        assert_eq!(cpu.memory.load(BASE + DMA_IRQ_STATUS, 0b010), 0b10);
+        assert_eq!(cpu.memory.dma_accesses(), 128);
+        // Every burst held the bus for 16 cycles that the looping loads waited out
+        assert!(cpu.bus_stall_cycles() > 64);
+    }
+
+    #[test]
+    fn test_memory_to_peripheral_and_errors() {
+        let (mut cpu, _dma) = cpu_with_dma();
+        let (uart, output) = Uart16550::new().captured();
+        cpu.memory.map_device("uart", 0x3000, 0x100, uart).unwrap();
+        cpu.memory.write_bytes(0x1000, b"DMA!");
+
+        for (register, value) in [(DMA_SRC, 0x1000), (DMA_DST, 0x3000), (DMA_COUNT, 4)] {
+            cpu.memory.store(channel(0, register), value, 0b010);
+        }
+        cpu.memory.store(channel(0, DMA_CONTROL), DMA_CONTROL_START | DMA_CONTROL_DST_FIXED, 0b010);
+        cpu.run_cycles(20);
+        assert_eq!(output.contents(), "DMA!");
+
+        // Word transfers need aligned addresses
+        cpu.memory.store(channel(2, DMA_SRC), 0x1001, 0b010);
+        cpu.memory.store(channel(2, DMA_COUNT), 1, 0b010);
+        cpu.memory.store(channel(2, DMA_CONTROL), DMA_CONTROL_START | (2 << DMA_WIDTH_SHIFT), 0b010);
+        assert_eq!(cpu.memory.load(channel(2, DMA_STATUS), 0b010), DMA_STATUS_DONE | DMA_STATUS_ERROR);
+        assert_eq!(cpu.memory.load(BASE + DMA_IRQ_STATUS, 0b010), 0b101);
+    }
+
+    #[test]
+    fn test_unmapped_and_wrapping_addresses_stop_with_error() {
+        let (mut cpu, _dma) = cpu_with_dma();
+        cpu.memory.write_bytes(0x3FF8, &[0xAB; 8]);
+
+        // Source runs off the end of RAM after two words
+        for (register, value) in [(DMA_SRC, 0x3FF8), (DMA_DST, 0x1000), (DMA_COUNT, 4)] {
+            cpu.memory.store(channel(0, register), value, 0b010);
+        }
+        cpu.memory.store(channel(0, DMA_CONTROL), DMA_CONTROL_START | (2 << DMA_WIDTH_SHIFT), 0b010);
+        cpu.run_cycles(20);
+        assert_eq!(cpu.memory.load(channel(0, DMA_STATUS), 0b010), DMA_STATUS_DONE | DMA_STATUS_ERROR);
+        assert_eq!(cpu.memory.load(channel(0, DMA_SRC), 0b010), 0x4000);
+        assert_eq!(cpu.memory.load(channel(0, DMA_COUNT), 0b010), 2);
+        assert_eq!(cpu.memory.read_bytes(0x1000, 12), [&[0xAB; 8][..], &[0; 4]].concat());
+
+        // A destination that would wrap past 4 GiB is refused at START
+        for (register, value) in [(DMA_SRC, 0x1000), (DMA_DST, 0xFFFF_FFFC), (DMA_COUNT, 2)] {
+            cpu.memory.store(channel(1, register), value, 0b010);
+        }
+        cpu.memory.store(channel(1, DMA_CONTROL), DMA_CONTROL_START | (2 << DMA_WIDTH_SHIFT), 0b010);
+        assert_eq!(cpu.memory.load(channel(1, DMA_STATUS), 0b010), DMA_STATUS_DONE | DMA_STATUS_ERROR);
+        assert_eq!(cpu.memory.load(BASE + DMA_IRQ_STATUS, 0b010), 0b11);
+    }
+}
@@ -0,0 +1,411 @@
+//! Flattened device tree (DTB) generation
+//!
+//! Firmware and kernels find their hardware through a device tree passed in
+//! a1 at boot (a0 holds the hart ID). `machine_tree` describes a `Machine`:
+//! CPUs with their ISA string, RAM, the msip registers as an ACLINT MSWI
+//! (multi-hart machines - there is no CLINT timer to advertise), the
+//! PLIC (a context per hart) and every device, plus `/chosen` with bootargs
+//! and the console.
+//!
+//! Devices without a PLIC are wired straight to MEIP, so their interrupts
+//! are given as `interrupts-extended = <&cpu0_intc 11>`.
+
+use crate::types::*;
+use crate::machine::{MachineDescription, MachineDevice, RegionKind};
+use crate::multi_hart::CLINT_BASE;
+
+pub const FDT_MAGIC: u32 = 0xD00D_FEED;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMPATIBLE_VERSION: u32 = 16;
+const HEADER_SIZE: usize = 40;
+
+/// Rate of the `time` CSR, which counts cycles
+pub const TIMEBASE_FREQUENCY: u32 = 10_000_000;
+/// Input clock of the 16550 (as QEMU virt)
+pub const UART_CLOCK: u32 = 3_686_400;
+/// Size of the ACLINT MSWI window (msip registers)
+pub const MSWI_SIZE: Addr = 0x4000;
+
+/// Local interrupt numbers of the hart interrupt controller
+const IRQ_M_SOFT: u32 = 3;
+const IRQ_M_EXT: u32 = 11;
+
+/// Device tree node
+#[derive(Debug, Clone, Default)]
+pub struct Node {
+    pub name: String,
+    properties: Vec<(String, Vec<u8>)>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), ..Self::default() }
+    }
+
+    /// Property with raw bytes as its value
+    pub fn property(mut self, name: &str, value: Vec<u8>) -> Self {
+        self.properties.push((name.to_string(), value));
+        self
+    }
+
+    /// Property without a value (a flag such as `interrupt-controller`)
+    pub fn flag(self, name: &str) -> Self {
+        self.property(name, Vec::new())
+    }
+
+    pub fn cells(self, name: &str, cells: &[u32]) -> Self {
+        self.property(name, cells.iter().flat_map(|cell| cell.to_be_bytes()).collect())
+    }
+
+    pub fn string(self, name: &str, value: &str) -> Self {
+        self.strings(name, &[value])
+    }
+
+    /// String list, e.g. `compatible`
+    pub fn strings(self, name: &str, values: &[&str]) -> Self {
+        self.property(name, values.iter().flat_map(|value| value.bytes().chain([0])).collect())
+    }
+
+    pub fn child(mut self, node: Node) -> Self {
+        self.children.push(node);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.properties.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_slice())
+    }
+
+    pub fn children(&self) -> &[Node] {
+        &self.children
+    }
+
+    /// Descendant at a `/`-separated path of node names
+    pub fn find(&self, path: &str) -> Option<&Node> {
+        path.split('/').filter(|part| !part.is_empty()).try_fold(self, |node, part| node.children.iter().find(|child| child.name == part))
+    }
+}
+
+/// Device tree with its root node
+#[derive(Debug, Clone)]
+pub struct DeviceTree {
+    pub root: Node,
+    pub boot_cpuid: u32,
+}
+
+impl DeviceTree {
+    pub fn new(root: Node) -> Self {
+        Self { root, boot_cpuid: 0 }
+    }
+
+    /// Serialize as a version 17 flattened device tree
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut structure = Vec::new();
+        let mut strings = Vec::new();
+        write_node(&self.root, &mut structure, &mut strings);
+        push_u32(&mut structure, FDT_END);
+
+        // Header, an empty memory reservation map, structure, strings
+        let reservations = HEADER_SIZE;
+        let structure_offset = reservations + 16;
+        let strings_offset = structure_offset + structure.len();
+        let total = strings_offset + strings.len();
+
+        let mut blob = Vec::with_capacity(total);
+        for field in [
+            FDT_MAGIC,
+            total as u32,
+            structure_offset as u32,
+            strings_offset as u32,
+            reservations as u32,
+            FDT_VERSION,
+            FDT_LAST_COMPATIBLE_VERSION,
+            self.boot_cpuid,
+            strings.len() as u32,
+            structure.len() as u32,
+        ] {
+            push_u32(&mut blob, field);
+        }
+        blob.extend([0; 16]);
+        blob.extend(structure);
+        blob.extend(strings);
+        blob
+    }
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend(value.to_be_bytes());
+}
+
+fn pad(out: &mut Vec<u8>) {
+    out.resize(out.len().next_multiple_of(4), 0);
+}
+
+fn write_node(node: &Node, out: &mut Vec<u8>, strings: &mut Vec<u8>) {
+    push_u32(out, FDT_BEGIN_NODE);
+    out.extend(node.name.bytes().chain([0]));
+    pad(out);
+    for (name, value) in &node.properties {
+        push_u32(out, FDT_PROP);
+        push_u32(out, value.len() as u32);
+        push_u32(out, string_offset(strings, name));
+        out.extend(value);
+        pad(out);
+    }
+    for child in &node.children {
+        write_node(child, out, strings);
+    }
+    push_u32(out, FDT_END_NODE);
+}
+
+/// Offset of `name` in the strings block, adding it if new
+fn string_offset(strings: &mut Vec<u8>, name: &str) -> u32 {
+    let mut offset = 0;
+    for entry in strings.split(|&byte| byte == 0) {
+        if entry == name.as_bytes() && offset < strings.len() {
+            return offset as u32;
+        }
+        offset += entry.len() + 1;
+    }
+    let offset = strings.len();
+    strings.extend(name.bytes().chain([0]));
+    offset as u32
+}
+
+/// Phandle of hart `n`'s interrupt controller
+fn intc_phandle(hart: usize) -> u32 {
+    hart as u32 + 1
+}
+
+/// Device tree for a machine and its mapped devices
+pub fn machine_tree(machine: &MachineDescription, devices: &[MachineDevice], bootargs: Option<&str>) -> DeviceTree {
+    let plic_phandle = intc_phandle(machine.harts);
+    let plic = devices.iter().find(|device| device.kind == "plic");
+
+    let mut cpus = Node::new("cpus")
+        .cells("#address-cells", &[1])
+        .cells("#size-cells", &[0])
+        .cells("timebase-frequency", &[TIMEBASE_FREQUENCY]);
+    for hart in 0..machine.harts {
+        let intc = Node::new("interrupt-controller")
+            .cells("#interrupt-cells", &[1])
+            .flag("interrupt-controller")
+            .string("compatible", "riscv,cpu-intc")
+            .cells("phandle", &[intc_phandle(hart)]);
+        cpus = cpus.child(
+            Node::new(format!("cpu@{:x}", hart))
+                .string("device_type", "cpu")
+                .cells("reg", &[hart as u32])
+                .string("status", "okay")
+                .string("compatible", "riscv")
+                .string("riscv,isa", &machine.isa.to_ascii_lowercase())
+                .string("mmu-type", "riscv,none")
+                .child(intc),
+        );
+    }
+
+    // Two size cells, so a RAM region can span all 4 GiB
+    let mut root = Node::new("")
+        .cells("#address-cells", &[1])
+        .cells("#size-cells", &[2])
+        .string("model", &machine.name)
+        .string("compatible", "riscv32i-sim");
+
+    let mut chosen = Node::new("chosen");
+    if let Some(bootargs) = bootargs {
+        chosen = chosen.string("bootargs", bootargs);
+    }
+    if let Some(uart) = devices.iter().find(|device| device.kind == "uart16550") {
+        chosen = chosen.string("stdout-path", &format!("/soc/serial@{:x}", uart.base));
+    }
+    root = root.child(chosen).child(cpus);
+
+    for region in machine.memory.iter().filter(|region| region.kind == RegionKind::Ram) {
+        root = root.child(
+            Node::new(format!("memory@{:x}", region.base))
+                .string("device_type", "memory")
+                .cells("reg", &[region.base, (region.size >> 32) as u32, region.size as u32]),
+        );
+    }
+
+    let mut soc = Node::new("soc")
+        .cells("#address-cells", &[1])
+        .cells("#size-cells", &[1])
+        .string("compatible", "simple-bus")
+        .flag("ranges");
+
+    // msip registers exist when several harts share memory
+    if machine.harts > 1 {
+        let interrupts: Vec<u32> = (0..machine.harts).flat_map(|hart| [intc_phandle(hart), IRQ_M_SOFT]).collect();
+        soc = soc.child(
+            Node::new(format!("mswi@{:x}", CLINT_BASE))
+                .strings("compatible", &["riscv,aclint-mswi"])
+                .cells("reg", &[CLINT_BASE, MSWI_SIZE])
+                .cells("interrupts-extended", &interrupts),
+        );
+    }
+
+    // PLIC context N is hart N's machine external interrupt
+    let contexts: Vec<u32> = (0..machine.harts).flat_map(|hart| [intc_phandle(hart), IRQ_M_EXT]).collect();
+    for device in devices {
+        let mut node = device_node(device);
+        if device.kind == "plic" {
+            let sources = devices.iter().filter_map(|device| device.irq).max().unwrap_or(0);
+            node = node
+                .cells("#address-cells", &[0])
+                .cells("#interrupt-cells", &[1])
+                .flag("interrupt-controller")
+                .cells("riscv,ndev", &[sources])
+                .cells("interrupts-extended", &contexts)
+                .cells("phandle", &[plic_phandle]);
+        } else if let Some(irq) = device.irq {
+            node = match plic {
+                Some(_) => node.cells("interrupt-parent", &[plic_phandle]).cells("interrupts", &[irq]),
+                None => node.cells("interrupts-extended", &[intc_phandle(0), IRQ_M_EXT]),
+            };
+        }
+        soc = soc.child(node);
+    }
+
+    DeviceTree::new(root.child(soc))
+}
+
+/// Node name and compatible strings for a device type
+fn device_node(device: &MachineDevice) -> Node {
+    let (name, compatible): (&str, &[&str]) = match device.kind.as_str() {
+        "uart16550" => ("serial", &["ns16550a"]),
+        "plic" => ("interrupt-controller", &["sifive,plic-1.0.0", "riscv,plic0"]),
+        "sifive_test" => ("test", &["sifive,test0", "syscon"]),
+        "framebuffer" => ("framebuffer", &["riscv32i-sim,framebuffer"]),
+        "gpio" => ("gpio", &["riscv32i-sim,gpio"]),
+        "panel" => ("panel", &["riscv32i-sim,panel"]),
+        "block" => ("block", &["riscv32i-sim,block"]),
+        "dma" => ("dma-controller", &["riscv32i-sim,dma"]),
+        kind => (kind, &[]),
+    };
+    let mut node = Node::new(format!("{}@{:x}", name, device.base))
+        .strings("compatible", compatible)
+        .cells("reg", &[device.base, device.size]);
+    if device.kind == "uart16550" {
+        node = node.cells("clock-frequency", &[UART_CLOCK]).cells("reg-shift", &[0]).cells("reg-io-width", &[1]);
+    }
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (path, name, value) of every property, walking the structure block
+    fn properties(blob: &[u8]) -> Vec<(String, String, Vec<u8>)> {
+        let word = |offset: usize| u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap());
+        let (structure, strings) = (word(8) as usize, word(12) as usize);
+        let c_string = |offset: usize| {
+            let end = blob[offset..].iter().position(|&b| b == 0).unwrap();
+            String::from_utf8(blob[offset..offset + end].to_vec()).unwrap()
+        };
+
+        let mut path: Vec<String> = Vec::new();
+        let mut found = Vec::new();
+        let mut offset = structure;
+        loop {
+            let token = word(offset);
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_string(offset);
+                    offset = (offset + name.len() + 1).next_multiple_of(4);
+                    path.push(name);
+                }
+                FDT_PROP => {
+                    let (len, name) = (word(offset) as usize, c_string(strings + word(offset + 4) as usize));
+                    found.push((path.join("/"), name, blob[offset + 8..offset + 8 + len].to_vec()));
+                    offset = (offset + 8 + len).next_multiple_of(4);
+                }
+                FDT_END_NODE => {
+                    path.pop();
+                }
+                _ => return found,
+            }
+        }
+    }
+
+    #[test]
+    fn test_blob_layout() {
+        let tree = DeviceTree::new(
+            Node::new("")
+                .cells("#address-cells", &[1])
+                .child(Node::new("chosen").string("bootargs", "console=hvc0").cells("#address-cells", &[2])),
+        );
+        let blob = tree.to_bytes();
+        assert_eq!(&blob[0..4], FDT_MAGIC.to_be_bytes());
+        assert_eq!(u32::from_be_bytes(blob[4..8].try_into().unwrap()) as usize, blob.len());
+
+        let found = properties(&blob);
+        assert_eq!(found[0], ("".into(), "#address-cells".into(), vec![0, 0, 0, 1]));
+        assert_eq!(found[1], ("/chosen".into(), "bootargs".into(), b"console=hvc0\0".to_vec()));
+        // Repeated names share one string
+        assert_eq!(found[2].1, "#address-cells");
+        let strings = u32::from_be_bytes(blob[32..36].try_into().unwrap());
+        assert_eq!(strings as usize, "#address-cells\0bootargs\0".len());
+    }
+
+    #[test]
+    fn test_machine_tree() {
+        let description = MachineDescription::from_toml(
+            r#"
+            isa = "rv32ia_zicsr"
+            harts = 2
+            [[memory]]
+            name = "ram"
+            base = 0x8000_0000
+            size = "1M"
+            [[device]]
+            type = "plic"
+            base = 0x0C00_0000
+            [[device]]
+            type = "uart16550"
+            base = 0x1000_0000
+            irq = 10
+            console = "none"
+            "#,
+        )
+        .unwrap();
+        let devices = description.validate().unwrap();
+        let tree = machine_tree(&description, &devices, Some("earlycon"));
+        let root = &tree.root;
+
+        assert_eq!(root.find("cpus/cpu@1").unwrap().get("riscv,isa"), Some(&b"rv32ia_zicsr\0"[..]));
+        assert_eq!(root.find("memory@80000000").unwrap().get("reg"), Some(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0x10, 0, 0][..]));
+        assert_eq!(root.find("chosen").unwrap().get("stdout-path"), Some(&b"/soc/serial@10000000\0"[..]));
+        let uart = root.find("soc/serial@10000000").unwrap();
+        assert_eq!(uart.get("interrupts"), Some(&[0, 0, 0, 10][..]));
+        assert_eq!(uart.get("interrupt-parent"), root.find("soc/interrupt-controller@c000000").unwrap().get("phandle"));
+        // msip and a PLIC context for both harts
+        assert_eq!(root.find("soc/mswi@2000000").unwrap().get("interrupts-extended").unwrap().len(), 16);
+        let plic = root.find("soc/interrupt-controller@c000000").unwrap();
+        assert_eq!(plic.get("interrupts-extended"), Some(&[0, 0, 0, 1, 0, 0, 0, 11, 0, 0, 0, 2, 0, 0, 0, 11][..]));
+
+        let found = properties(&tree.to_bytes());
+        assert!(found.iter().any(|(path, name, _)| path == "/soc/serial@10000000" && name == "clock-frequency"));
+    }
+
+    #[test]
+    fn test_memory_spanning_4gib() {
+        let description = MachineDescription::from_toml(
+            r#"
+            [[memory]]
+            name = "ram"
+            base = 0
+            size = 0x1_0000_0000
+            "#,
+        )
+        .unwrap();
+        let tree = machine_tree(&description, &description.validate().unwrap(), None);
+        assert_eq!(tree.root.find("memory@0").unwrap().get("reg"), Some(&[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0][..]));
+    }
+}
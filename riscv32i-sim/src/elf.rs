@@ -0,0 +1,296 @@
+//! ELF32 executable loader
+//!
+//! Validates a little-endian RV32 executable, copies its PT_LOAD segments
+//! into `Memory` (zero-filling the .bss tail of each segment), and sets
+//! the PC to the entry point and gp to `__global_pointer$`.
+//!
+//! The memory must already cover the segments - e.g.
+//! `Memory::with_base(0x8000_0000, size)` for programs linked for Spike
+//! or QEMU virt.
+
+use std::path::Path;
+
+use goblin::elf::header::{EM_RISCV, ET_EXEC};
+use goblin::elf::program_header::{PT_LOAD, PT_PHDR};
+use goblin::elf::sym::{STT_FUNC, STT_OBJECT};
+use goblin::elf::Elf;
+
+use crate::types::*;
+use crate::cpu::Cpu;
+use crate::symbols::{Symbol, SymbolKind, SymbolTable};
+
+#[derive(Debug, thiserror::Error)]
+pub enum LoadError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Malformed ELF: {0}")]
+    Malformed(#[from] goblin::error::Error),
+
+    #[error("Not a 32-bit little-endian ELF")]
+    WrongClass,
+
+    #[error("Not a RISC-V ELF (e_machine = {0})")]
+    WrongMachine(u16),
+
+    #[error("Not an executable (e_type = {0})")]
+    NotExecutable(u16),
+
+    #[error("Segment 0x{addr:08x}..+0x{size:x} is outside memory")]
+    OutOfMemory { addr: Addr, size: u64 },
+}
+
+/// One PT_LOAD segment
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub addr: Addr,
+    /// File contents (p_filesz bytes)
+    pub data: Vec<u8>,
+    /// Size in memory (p_memsz) - the rest past `data` is zero-filled
+    pub mem_size: Word,
+}
+
+/// Parsed executable, ready to load
+#[derive(Debug, Clone)]
+pub struct ElfImage {
+    pub entry: Addr,
+    pub segments: Vec<Segment>,
+    pub symbols: SymbolTable,
+    /// `__global_pointer$` (loaded into gp)
+    pub global_pointer: Option<Addr>,
+    /// HTIF mailbox symbols
+    pub tohost: Option<Addr>,
+    pub fromhost: Option<Addr>,
+    /// Address of the loaded program headers (AT_PHDR), if a segment holds them
+    pub program_headers: Option<Addr>,
+    /// Number of program headers (AT_PHNUM)
+    pub program_header_count: u16,
+}
+
+impl ElfImage {
+    pub fn parse(bytes: &[u8]) -> Result<Self, LoadError> {
+        let elf = Elf::parse(bytes)?;
+
+        if elf.is_64 || !elf.little_endian {
+            return Err(LoadError::WrongClass);
+        }
+        if elf.header.e_machine != EM_RISCV {
+            return Err(LoadError::WrongMachine(elf.header.e_machine));
+        }
+        if elf.header.e_type != ET_EXEC {
+            return Err(LoadError::NotExecutable(elf.header.e_type));
+        }
+
+        let mut segments = Vec::new();
+        for header in elf.program_headers.iter().filter(|h| h.p_type == PT_LOAD) {
+            let range = header.file_range();
+            let data = bytes.get(range).ok_or(goblin::error::Error::Malformed(format!(
+                "segment at 0x{:x} extends past end of file",
+                header.p_vaddr
+            )))?;
+            segments.push(Segment {
+                addr: header.p_vaddr as Addr,
+                data: data.to_vec(),
+                mem_size: header.p_memsz as Word,
+            });
+        }
+
+        // PT_PHDR if there is one, else the PT_LOAD segment covering e_phoff
+        let phoff = elf.header.e_phoff;
+        let program_headers = match elf.program_headers.iter().find(|h| h.p_type == PT_PHDR) {
+            Some(header) => Some(header.p_vaddr as Addr),
+            None => elf
+                .program_headers
+                .iter()
+                .find(|h| h.p_type == PT_LOAD && (h.p_offset..h.p_offset + h.p_filesz).contains(&phoff))
+                .map(|h| (h.p_vaddr + phoff - h.p_offset) as Addr),
+        };
+
+        let symbols: SymbolTable = elf
+            .syms
+            .iter()
+            .filter_map(|sym| {
+                let name = elf.strtab.get_at(sym.st_name).filter(|name| !name.is_empty())?;
+                let kind = match sym.st_type() {
+                    STT_FUNC => SymbolKind::Function,
+                    STT_OBJECT => SymbolKind::Object,
+                    _ => SymbolKind::Other,
+                };
+                // Skip undefined symbols and section/file markers
+                if sym.st_shndx == 0 || (kind == SymbolKind::Other && sym.st_type() != 0) {
+                    return None;
+                }
+                Some(Symbol {
+                    name: name.to_string(),
+                    addr: sym.st_value as Addr,
+                    size: sym.st_size as Word,
+                    kind,
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            entry: elf.entry as Addr,
+            global_pointer: symbols.lookup("__global_pointer$"),
+            tohost: symbols.lookup("tohost"),
+            fromhost: symbols.lookup("fromhost"),
+            program_headers,
+            program_header_count: elf.header.e_phnum,
+            segments,
+            symbols,
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    /// Lowest loaded address
+    pub fn base(&self) -> Addr {
+        self.segments.iter().map(|s| s.addr).min().unwrap_or(self.entry)
+    }
+
+    /// One past the highest loaded address (end of .bss) - the initial program break
+    pub fn end(&self) -> u64 {
+        self.segments.iter().map(|s| s.addr as u64 + s.mem_size as u64).max().unwrap_or(self.entry as u64)
+    }
+
+    /// Copy segments into the CPU's memory and set PC (and gp)
+    pub fn load_into(&self, cpu: &mut Cpu) -> Result<(), LoadError> {
+        for segment in &self.segments {
+            if !cpu.memory.contains(segment.addr, segment.mem_size as usize) {
+                return Err(LoadError::OutOfMemory { addr: segment.addr, size: segment.mem_size as u64 });
+            }
+        }
+
+        for segment in &self.segments {
+            cpu.memory.write_bytes(segment.addr, &segment.data);
+            let bss = segment.mem_size as usize - segment.data.len().min(segment.mem_size as usize);
+            cpu.memory.write_bytes(segment.addr.wrapping_add(segment.data.len() as Addr), &vec![0; bss]);
+        }
+
+        cpu.control.set_pc(self.entry);
+        if let Some(gp) = self.global_pointer {
+            cpu.registers.poke(3, gp);
+        }
+        Ok(())
+    }
+}
+
+impl Cpu {
+    /// Load an ELF executable from disk - returns the image for its symbols
+    pub fn load_elf(&mut self, path: impl AsRef<Path>) -> Result<ElfImage, LoadError> {
+        let image = ElfImage::from_file(path)?;
+        image.load_into(self)?;
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+    use crate::run::{RunConfig, StopReason};
+    use crate::test_util::addi;
+
+    const BASE: Addr = 0x8000_0000;
+
+    /// Minimal ELF32: one PT_LOAD segment (code + `bss` zero bytes), .symtab/.strtab
+    fn build_elf(machine: u16, code: &[Word], bss: u32, symbols: &[(&str, Addr, u32, u8)]) -> Vec<u8> {
+        let code: Vec<u8> = code.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let code_offset = 52 + 32;
+
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 16];
+        for &(name, value, size, kind) in symbols {
+            symtab.extend((strtab.len() as u32).to_le_bytes());
+            symtab.extend(value.to_le_bytes());
+            symtab.extend(size.to_le_bytes());
+            symtab.extend([(1 << 4) | kind, 0]);  // STB_GLOBAL
+            symtab.extend(1u16.to_le_bytes());    // defined (any section index != 0)
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+        }
+        let shstrtab = b"\0.symtab\0.strtab\0.shstrtab\0".to_vec();
+
+        let symtab_offset = code_offset + code.len();
+        let strtab_offset = symtab_offset + symtab.len();
+        let shstrtab_offset = strtab_offset + strtab.len();
+        let shoff = (shstrtab_offset + shstrtab.len()).next_multiple_of(4);
+
+        let mut elf = vec![0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        for half in [2u16, machine] {
+            elf.extend(half.to_le_bytes());
+        }
+        for word in [1u32, BASE, 52, shoff as u32, 0] {
+            elf.extend(word.to_le_bytes());
+        }
+        for half in [52u16, 32, 1, 40, 4, 3] {
+            elf.extend(half.to_le_bytes());
+        }
+
+        let filesz = code.len() as u32;
+        for word in [1u32, code_offset as u32, BASE, BASE, filesz, filesz + bss, 5, 4] {
+            elf.extend(word.to_le_bytes());
+        }
+
+        elf.extend(&code);
+        elf.extend(&symtab);
+        elf.extend(&strtab);
+        elf.extend(&shstrtab);
+        elf.resize(shoff, 0);
+
+        let sections: [[u32; 10]; 4] = [
+            [0; 10],
+            [1, 2, 0, 0, symtab_offset as u32, symtab.len() as u32, 2, 1, 4, 16],  // .symtab -> .strtab
+            [9, 3, 0, 0, strtab_offset as u32, strtab.len() as u32, 0, 0, 1, 0],
+            [17, 3, 0, 0, shstrtab_offset as u32, shstrtab.len() as u32, 0, 0, 1, 0],
+        ];
+        for section in sections {
+            for word in section {
+                elf.extend(word.to_le_bytes());
+            }
+        }
+        elf
+    }
+
+    #[test]
+    fn test_load_and_run_elf() {
+        let code = [addi(10, 3, 0), addi(17, 0, 93), 0x0000_0073];  // a0 = gp; exit
+        let elf = build_elf(EM_RISCV, &code, 16, &[
+            ("_start", BASE, 12, STT_FUNC),
+            ("__global_pointer$", BASE + 0x800, 0, 0),
+            ("tohost", BASE + 12, 8, STT_OBJECT),
+        ]);
+        let image = ElfImage::parse(&elf).unwrap();
+
+        let mut cpu = Cpu::new();
+        cpu.memory = Memory::with_base(BASE, 64 * 1024);
+        cpu.memory.write_bytes(BASE + 12, &[0xAA; 16]);
+        image.load_into(&mut cpu).unwrap();
+
+        assert_eq!(cpu.control.get_pc(), BASE);
+        assert_eq!(image.tohost, Some(BASE + 12));
+        // The headers are in the file but before the loaded segment
+        assert_eq!((image.program_headers, image.program_header_count), (None, 1));
+        assert_eq!(image.end(), BASE as u64 + 28);
+        assert_eq!(cpu.memory.read_bytes(BASE + 12, 16), vec![0; 16]);
+        assert_eq!(image.symbols.format(BASE + 4), "_start+0x4");
+
+        let result = cpu.run(&RunConfig::new().with_max_cycles(10));
+        assert_eq!(result.reason, StopReason::Exit);
+        assert_eq!(result.exit_code, Some((BASE + 0x800) as i32));
+    }
+
+    #[test]
+    fn test_rejects_bad_images() {
+        let elf = build_elf(62, &[0], 0, &[]);  // x86-64
+        assert!(matches!(ElfImage::parse(&elf), Err(LoadError::WrongMachine(62))));
+
+        let image = ElfImage::parse(&build_elf(EM_RISCV, &[0], 0, &[])).unwrap();
+        let mut cpu = Cpu::new();  // 4 KiB at address 0
+        assert!(matches!(image.load_into(&mut cpu), Err(LoadError::OutOfMemory { addr: BASE, .. })));
+
+        assert!(matches!(ElfImage::parse(b"not an elf"), Err(LoadError::Malformed(_))));
+    }
+}
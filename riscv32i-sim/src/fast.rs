@@ -0,0 +1,613 @@
+//! Pre-decoded fast interpreter
+//!
+//! `Cpu::clock` decodes every instruction through `ControlUnit` and drives
+//! each module's signals - good for following the datapath, slow for real
+//! workloads. `FastInterpreter` decodes straight-line runs of instructions
+//! (basic blocks, ending at a jump or branch) once, caches them by start PC,
+//! and executes them with a tight loop over the decoded ops, updating the
+//! same `Cpu` state (registers, memory, CSR counters, cycle count).
+//!
+//! Anything with side effects beyond registers and memory - SYSTEM, AMOs,
+//! illegal instructions, interrupts, WFI - is handed to `Cpu::clock`, so
+//! the architectural result of `FastInterpreter::run` is identical to
+//! `Cpu::run`. A store over cached code drops the blocks it overlaps
+//! (self-modifying code). The cache is emptied at the start of each
+//! `run`, so host-side writes between runs are always seen.
+//!
+//! Interrupts are only taken between blocks (and after MMIO stores), so
+//! with devices attached an interrupt can arrive up to a block later than
+//! under `Cpu::run`.
+//!
+//! With the JIT tier enabled (`with_jit`, x86-64 Linux only), blocks that
+//! have run `jit::HOT_THRESHOLD` times are translated to native code.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::types::*;
+use crate::control_unit::ControlUnit;
+use crate::cpu::{Cpu, CpuEvent};
+use crate::memory::{AccessKind, MemoryAccess};
+use crate::run::{RunConfig, RunResult, RunState, StopReason};
+use crate::jit::{JitCompiler, NativeCode, HOT_THRESHOLD};
+
+/// Blocks are indexed by page so a store only checks nearby code
+const PAGE_SHIFT: u32 = 12;
+
+/// Longest block decoded in one go
+const MAX_BLOCK_LEN: usize = 64;
+
+const ECALL: Word = 0x0000_0073;
+
+/// Decoded instruction
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Op {
+    /// LUI / AUIPC - value known at decode time
+    Const { rd: u8, value: Word },
+    Alu { op: AluOp, rd: u8, rs1: u8, rs2: u8 },
+    AluImm { op: AluOp, rd: u8, rs1: u8, imm: Word },
+    Load { rd: u8, rs1: u8, imm: Word, funct3: u8 },
+    Store { rs1: u8, rs2: u8, imm: Word, funct3: u8 },
+    Branch { inst: Instruction, rs1: u8, rs2: u8, target: Addr },
+    Jal { rd: u8, target: Addr },
+    Jalr { rd: u8, rs1: u8, imm: Word },
+    /// FENCE
+    Nop,
+}
+
+impl Op {
+    pub(crate) fn is_control_flow(&self) -> bool {
+        matches!(self, Op::Branch { .. } | Op::Jal { .. } | Op::Jalr { .. })
+    }
+
+    fn is_memory(&self) -> bool {
+        matches!(self, Op::Load { .. } | Op::Store { .. })
+    }
+}
+
+/// Straight-line run of decoded instructions starting at `start`
+struct Block {
+    start: Addr,
+    ops: Vec<Op>,
+    /// Times run, counted towards translation
+    runs: Cell<u32>,
+    native: Cell<Option<NativeCode>>,
+}
+
+impl Block {
+    /// One past the last instruction
+    fn end(&self) -> Addr {
+        self.start.wrapping_add(4 * self.ops.len() as Addr)
+    }
+}
+
+/// How a block finished
+enum BlockExit {
+    /// Ran to its end (or left early after invalidating itself or an MMIO store)
+    Done,
+    Stop(StopReason, Option<i32>),
+}
+
+/// Cache counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FastStats {
+    pub blocks_decoded: u64,
+    /// Blocks dropped by stores into their code
+    pub invalidations: u64,
+    /// Instructions run from decoded blocks
+    pub fast_instructions: u64,
+    /// Instructions handed to `Cpu::clock`
+    pub slow_steps: u64,
+    pub blocks_translated: u64,
+    /// Instructions run as native code (included in `fast_instructions`)
+    pub native_instructions: u64,
+}
+
+/// Load for a decoded (or translated) instruction, seen by `Cpu` as its access
+pub(crate) fn load(cpu: &mut Cpu, addr: Addr, funct3: u8) -> Word {
+    cpu.wait_for_bus();
+    let value = cpu.memory.load(addr, funct3);
+    cpu.record_access(MemoryAccess { kind: AccessKind::Read, addr, value });
+    value
+}
+
+/// Store for a decoded (or translated) instruction - host devices see it
+pub(crate) fn store(cpu: &mut Cpu, addr: Addr, data: Word, funct3: u8) {
+    cpu.wait_for_bus();
+    cpu.memory.store(addr, data, funct3);
+    let value = data & (Word::MAX >> (32 - (8 << (funct3 & 0b11))));
+    cpu.record_access(MemoryAccess { kind: AccessKind::Write, addr, value });
+}
+
+/// Code bitmap lookup - one bit per memory word from `memory_base`
+pub(crate) fn is_code(code_words: &[u64], memory_base: Addr, addr: Addr) -> bool {
+    let bit = (addr.wrapping_sub(memory_base) >> 2) as usize;
+    code_words.get(bit / 64).is_some_and(|word| word & (1 << (bit % 64)) != 0)
+}
+
+/// Block-caching execution engine for `Cpu`
+pub struct FastInterpreter {
+    decoder: ControlUnit,
+    blocks: HashMap<Addr, Rc<Block>>,
+    /// Page number -> start PCs of blocks with code in it
+    code_pages: HashMap<Addr, Vec<Addr>>,
+    /// One bit per memory word holding decoded code, so most stores are
+    /// rejected without a lookup
+    code_words: Vec<u64>,
+    memory_base: Addr,
+    /// Native code tier, None when disabled or unsupported
+    jit: Option<JitCompiler>,
+    stats: FastStats,
+}
+
+impl Default for FastInterpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FastInterpreter {
+    pub fn new() -> Self {
+        Self {
+            decoder: ControlUnit::new(),
+            blocks: HashMap::new(),
+            code_pages: HashMap::new(),
+            code_words: Vec::new(),
+            memory_base: 0,
+            jit: None,
+            stats: FastStats::default(),
+        }
+    }
+
+    /// Translate hot blocks to native code - ignored where the JIT is
+    /// unsupported; disable it to debug the translator
+    pub fn with_jit(mut self, enabled: bool) -> Self {
+        self.jit = if enabled { JitCompiler::new() } else { None };
+        self
+    }
+
+    pub fn jit_enabled(&self) -> bool {
+        self.jit.is_some()
+    }
+
+    pub fn stats(&self) -> FastStats {
+        self.stats
+    }
+
+    /// Drop every decoded block (and its native code)
+    pub fn flush(&mut self) {
+        self.blocks.clear();
+        self.code_pages.clear();
+        self.code_words.fill(0);
+        if let Some(jit) = self.jit.as_mut() {
+            jit.reset();
+        }
+    }
+
+    /// Bit position in `code_words` for a byte address, None outside memory
+    fn code_bit(&self, addr: Addr) -> Option<usize> {
+        let word = (addr.wrapping_sub(self.memory_base) >> 2) as usize;
+        (word < self.code_words.len() * 64).then_some(word)
+    }
+
+    fn is_code(&self, addr: Addr) -> bool {
+        is_code(&self.code_words, self.memory_base, addr)
+    }
+
+    /// Set (or clear) the code bits for a block
+    fn mark(&mut self, block: &Block, code: bool) {
+        for addr in (block.start..block.end()).step_by(4) {
+            if let Some(bit) = self.code_bit(addr) {
+                if code {
+                    self.code_words[bit / 64] |= 1 << (bit % 64);
+                } else {
+                    self.code_words[bit / 64] &= !(1 << (bit % 64));
+                }
+            }
+        }
+    }
+
+    /// Same as `cpu.run(config)`, executing decoded blocks where possible
+    pub fn run(&mut self, cpu: &mut Cpu, config: &RunConfig) -> RunResult {
+        self.flush();
+        self.memory_base = cpu.memory.base();
+        self.code_words.resize(cpu.memory.size().div_ceil(4 * 64), 0);
+        let mut state = RunState::new(cpu);
+        cpu.memory.track_dma_writes = true;
+
+        let stop = loop {
+            if let Some(reason) = cpu.check_stop(config, &mut state) {
+                break (reason, None);
+            }
+
+            let pc = cpu.control.get_pc();
+            let block = self.block_at(cpu, pc).filter(|block| Self::can_run(cpu, config, &state, block));
+            let native = block.as_ref().and_then(|block| self.native_code(block));
+
+            let stop = match (block, native) {
+                (Some(block), Some(code)) => self.execute_native(cpu, config, &block, code),
+                (Some(block), None) => {
+                    self.sync_registers(cpu);
+                    match self.execute(cpu, config, &block) {
+                        BlockExit::Done => None,
+                        BlockExit::Stop(reason, exit_code) => Some((reason, exit_code)),
+                    }
+                }
+                (None, _) => {
+                    self.sync_registers(cpu);
+                    self.stats.slow_steps += 1;
+                    let inst = Instruction::new(cpu.memory.fetch(pc));
+                    let stop = cpu.run_step(config);
+                    self.after_slow_step(cpu, inst);
+                    stop
+                }
+            };
+            if !cpu.memory.dma_writes.is_empty() {
+                self.invalidate_dma_writes(cpu);
+            }
+            if let Some(stop) = stop {
+                break stop;
+            }
+        };
+
+        self.sync_registers(cpu);
+        cpu.memory.track_dma_writes = false;
+        cpu.memory.dma_writes.clear();
+        cpu.finish(stop.0, stop.1, &state)
+    }
+
+    /// Translated code for a block, translating it once it is hot
+    fn native_code(&mut self, block: &Block) -> Option<NativeCode> {
+        let jit = self.jit.as_mut()?;
+        if let Some(code) = block.native.get() {
+            return Some(code);
+        }
+        block.runs.set(block.runs.get() + 1);
+        if block.runs.get() < HOT_THRESHOLD {
+            return None;
+        }
+
+        let Some(code) = jit.translate(block.start, &block.ops) else {
+            // Code buffer full - start over, this run stays interpreted
+            self.flush();
+            return None;
+        };
+        block.native.set(Some(code));
+        self.stats.blocks_translated += 1;
+        Some(code)
+    }
+
+    /// Run a translated block - registers stay in the JIT context until
+    /// something else needs them (`sync_registers`)
+    fn execute_native(
+        &mut self,
+        cpu: &mut Cpu,
+        config: &RunConfig,
+        block: &Block,
+        code: NativeCode,
+    ) -> Option<(StopReason, Option<i32>)> {
+        let jit = self.jit.as_mut()?;
+        let exit = jit.execute(cpu, code, &self.code_words, self.memory_base);
+
+        // A block only leaves early after a store (to code or a device, or one that ended the program)
+        let count = exit.retired.unwrap_or(block.ops.len() as u32);
+        let ends_with_access = exit.retired.is_some() || block.ops.last().is_some_and(Op::is_memory);
+        cpu.retire_block(count as u64, exit.ticked as u64, ends_with_access);
+        cpu.control.set_pc(exit.pc);
+        self.stats.fast_instructions += count as u64;
+        self.stats.native_instructions += count as u64;
+
+        if exit.retired.is_some() {
+            if let Some(CpuEvent::Exit(code)) = cpu.last_event() {
+                return Some((StopReason::Exit, Some(code)));
+            }
+            if let Some(access) = cpu.last_memory_access() {
+                self.invalidate(access.addr, 4, Addr::MAX);
+            }
+            return None;
+        }
+
+        let last_pc = block.end().wrapping_sub(4);
+        let control_flow = block.ops.last().is_some_and(Op::is_control_flow);
+        if control_flow && exit.pc == last_pc && config.detect_self_loop && !cpu.interrupts_enabled() {
+            return Some((StopReason::SelfLoop(last_pc), None));
+        }
+        None
+    }
+
+    /// Write JIT-held registers back to the `Cpu`
+    fn sync_registers(&mut self, cpu: &mut Cpu) {
+        if let Some(jit) = self.jit.as_mut() {
+            jit.sync_out(cpu);
+        }
+    }
+
+    /// A block may run without per-instruction checks: nothing that needs
+    /// `Cpu::clock` is pending, it fits in the budgets, and no breakpoint
+    /// lies inside it
+    fn can_run(cpu: &Cpu, config: &RunConfig, state: &RunState, block: &Block) -> bool {
+        let len = block.ops.len() as u64;
+        let fits = |used: u64, max: Option<u64>| max.is_none_or(|max| used + len <= max);
+
+        cpu.csrs.pending_interrupt().is_none()
+            && !cpu.is_waiting_for_interrupt()
+            && fits(state.cycles(cpu), config.max_cycles)
+            && fits(state.instructions(cpu), config.max_instructions)
+            && !config.breakpoints.iter().any(|&bp| bp > block.start && bp < block.end())
+    }
+
+    /// Cached block at `pc`, decoding it if needed - None if the first
+    /// instruction has to go through `Cpu::clock`
+    fn block_at(&mut self, cpu: &Cpu, pc: Addr) -> Option<Rc<Block>> {
+        if let Some(block) = self.blocks.get(&pc) {
+            return Some(Rc::clone(block));
+        }
+
+        let mut ops = Vec::new();
+        let mut addr = pc;
+        // Only RAM is watched for stores, so code in ROM or device-backed
+        // regions always goes through Cpu::clock
+        while ops.len() < MAX_BLOCK_LEN && cpu.memory.contains(addr, 4) && !cpu.memory.bus().is_mmio(addr) {
+            let Some(op) = self.decode(Instruction::new(cpu.memory.fetch(addr)), addr) else {
+                break;
+            };
+            ops.push(op);
+            addr = addr.wrapping_add(4);
+            if op.is_control_flow() {
+                break;
+            }
+        }
+        if ops.is_empty() {
+            return None;
+        }
+
+        let block = Rc::new(Block { start: pc, ops, runs: Cell::new(0), native: Cell::new(None) });
+        let first_page = block.start >> PAGE_SHIFT;
+        let last_page = block.end().wrapping_sub(1) >> PAGE_SHIFT;
+        for page in [first_page, last_page] {
+            let starts = self.code_pages.entry(page).or_default();
+            if !starts.contains(&pc) {
+                starts.push(pc);
+            }
+        }
+        self.mark(&block, true);
+        self.blocks.insert(pc, Rc::clone(&block));
+        self.stats.blocks_decoded += 1;
+        Some(block)
+    }
+
+    /// Decode through the same `ControlUnit` as `Cpu` - None for
+    /// instructions left to `Cpu::clock`
+    fn decode(&mut self, inst: Instruction, pc: Addr) -> Option<Op> {
+        self.decoder.clock(inst);
+        let ctrl = self.decoder.get_control_signals();
+        if ctrl.illegal || ctrl.system != SystemOp::None || ctrl.amo.is_some() {
+            return None;
+        }
+
+        let (rd, rs1, rs2) = (inst.rd(), inst.rs1(), inst.rs2());
+        let op = match inst.opcode() {
+            0b0110111 => Op::Const { rd, value: inst.imm_u() as Word },
+            0b0010111 => Op::Const { rd, value: pc.wrapping_add(inst.imm_u() as Word) },
+            0b1101111 => Op::Jal { rd, target: Cpu::calculate_jump_target(&inst, pc, 0) & !0x3 },
+            0b1100111 => Op::Jalr { rd, rs1, imm: inst.imm_i() as Word },
+            0b1100011 => Op::Branch { inst, rs1, rs2, target: Cpu::calculate_jump_target(&inst, pc, 0) & !0x3 },
+            0b0001111 => Op::Nop,
+            _ if ctrl.mem_read => Op::Load { rd, rs1, imm: Cpu::immediate(&inst), funct3: inst.funct3() },
+            _ if ctrl.mem_write => Op::Store { rs1, rs2, imm: Cpu::immediate(&inst), funct3: inst.funct3() },
+            _ if ctrl.alu_src => Op::AluImm { op: ctrl.alu_op, rd, rs1, imm: Cpu::immediate(&inst) },
+            _ => Op::Alu { op: ctrl.alu_op, rd, rs1, rs2 },
+        };
+        Some(op)
+    }
+
+    /// Run a block's ops against the CPU state
+    fn execute(&mut self, cpu: &mut Cpu, config: &RunConfig, block: &Block) -> BlockExit {
+        let mut pc = block.start;
+
+        for op in &block.ops {
+            cpu.begin_instruction();
+            let reg = |cpu: &Cpu, r: u8| cpu.registers.peek(r);
+            let mut next_pc = pc.wrapping_add(4);
+            let mut leave = false;
+
+            match *op {
+                Op::Const { rd, value } => cpu.registers.poke(rd, value),
+                Op::Alu { op, rd, rs1, rs2 } => {
+                    let value = cpu.alu.execute(op, reg(cpu, rs1), reg(cpu, rs2));
+                    cpu.registers.poke(rd, value);
+                }
+                Op::AluImm { op, rd, rs1, imm } => {
+                    let value = cpu.alu.execute(op, reg(cpu, rs1), imm);
+                    cpu.registers.poke(rd, value);
+                }
+                Op::Load { rd, rs1, imm, funct3 } => {
+                    let value = load(cpu, reg(cpu, rs1).wrapping_add(imm), funct3);
+                    cpu.registers.poke(rd, value);
+                }
+                Op::Store { rs1, rs2, imm, funct3 } => {
+                    let addr = reg(cpu, rs1).wrapping_add(imm);
+                    store(cpu, addr, reg(cpu, rs2), funct3);
+                    // A device write may raise an interrupt - check before going on
+                    leave = self.invalidate(addr, 4, block.start) || cpu.memory.bus().is_mmio(addr);
+                }
+                Op::Branch { inst, rs1, rs2, target } => {
+                    if Cpu::should_branch(&inst, reg(cpu, rs1), reg(cpu, rs2)) {
+                        next_pc = target;
+                    }
+                }
+                Op::Jal { rd, target } => {
+                    cpu.registers.poke(rd, pc.wrapping_add(4));
+                    next_pc = target;
+                }
+                Op::Jalr { rd, rs1, imm } => {
+                    next_pc = reg(cpu, rs1).wrapping_add(imm) & !0x3;
+                    cpu.registers.poke(rd, pc.wrapping_add(4));
+                }
+                Op::Nop => {}
+            }
+
+            cpu.control.set_pc(next_pc);
+            cpu.csrs.retire();
+            self.stats.fast_instructions += 1;
+
+            if let Some(CpuEvent::Exit(code)) = cpu.last_event() {
+                return BlockExit::Stop(StopReason::Exit, Some(code));
+            }
+            if op.is_control_flow() && next_pc == pc && config.detect_self_loop && !cpu.interrupts_enabled() {
+                return BlockExit::Stop(StopReason::SelfLoop(pc), None);
+            }
+            if leave {
+                break;
+            }
+            pc = next_pc;
+        }
+        BlockExit::Done
+    }
+
+    /// AMOs store through `Cpu::clock`, and a syscall handler may write
+    /// guest memory anywhere (e.g. read(2)), so ECALL drops the whole cache
+    fn after_slow_step(&mut self, cpu: &Cpu, inst: Instruction) {
+        if let Some(access) = cpu.last_memory_access().filter(MemoryAccess::is_write) {
+            self.invalidate(access.addr, 4, Addr::MAX);
+        }
+        if inst.raw == ECALL {
+            self.flush();
+        }
+    }
+
+    /// Drop blocks bus masters wrote over
+    fn invalidate_dma_writes(&mut self, cpu: &mut Cpu) {
+        for (addr, len) in cpu.memory.take_dma_writes() {
+            for word in (addr & !3..addr.wrapping_add(len as Addr)).step_by(4) {
+                if self.is_code(word) {
+                    self.invalidate(word, 4, Addr::MAX);
+                }
+            }
+        }
+    }
+
+    /// Drop blocks overlapping [addr, addr + len) - true if the block
+    /// starting at `current` was one of them
+    fn invalidate(&mut self, addr: Addr, len: Addr, current: Addr) -> bool {
+        let end = addr.wrapping_add(len);
+        if !self.is_code(addr) && !self.is_code(end.wrapping_sub(1)) {
+            return false;
+        }
+
+        let mut dropped = Vec::new();
+        for page in [addr >> PAGE_SHIFT, end.wrapping_sub(1) >> PAGE_SHIFT] {
+            let Some(starts) = self.code_pages.get_mut(&page) else {
+                continue;
+            };
+            let blocks = &mut self.blocks;
+            starts.retain(|&start| match blocks.get(&start) {
+                Some(block) if block.start < end && block.end() > addr => {
+                    dropped.extend(blocks.remove(&start));
+                    false
+                }
+                Some(_) => true,
+                None => false,
+            });
+        }
+
+        // Blocks can overlap, so re-mark the survivors after clearing
+        for block in &dropped {
+            self.mark(block, false);
+        }
+        let survivors: Vec<Rc<Block>> = dropped
+            .iter()
+            .flat_map(|block| [block.start >> PAGE_SHIFT, block.end().wrapping_sub(1) >> PAGE_SHIFT])
+            .flat_map(|page| self.code_pages.get(&page).into_iter().flatten())
+            .filter_map(|start| self.blocks.get(start).cloned())
+            .collect();
+        for block in &survivors {
+            self.mark(block, true);
+        }
+
+        self.stats.invalidations += dropped.len() as u64;
+        dropped.iter().any(|block| block.start == current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{addi, cpu_with};
+
+    /// Sum 1..=100 into a byte array and read it back, then exit(x10)
+    fn sum_program() -> Vec<Word> {
+        vec![
+            addi(5, 0, 100),                                              // t0 = 100
+            addi(6, 0, 0x400),                                            // t1 = buffer
+            InstructionEncoder::r_type(0b0110011, 10, 0b000, 10, 5, 0),   // loop: a0 += t0
+            InstructionEncoder::s_type(0b0100011, 0b000, 6, 10, 0),       // sb a0, 0(t1)
+            InstructionEncoder::i_type(0b0000011, 7, 0b100, 6, 0),        // lbu t2, 0(t1)
+            InstructionEncoder::r_type(0b0110011, 11, 0b000, 11, 7, 0),   // a1 += t2
+            addi(6, 6, 1),                                                // t1 += 1
+            addi(5, 5, -1),                                               // t0 -= 1
+            InstructionEncoder::b_type(0b1100011, 0b001, 5, 0, -24),      // bnez t0, loop
+            InstructionEncoder::u_type(0b0110111, 12, 0x12345 << 12),     // lui a2
+            InstructionEncoder::i_type(0b1100111, 1, 0b000, 0, 48),       // jalr ra, 48(x0)
+            0,
+            addi(17, 0, 93),                                              // exit
+            0x0000_0073,
+        ]
+    }
+
+    #[test]
+    fn test_matches_cpu_run() {
+        let config = RunConfig::new();
+        let mut reference = cpu_with(&sum_program());
+        let expected = reference.run(&config);
+
+        let mut cpu = cpu_with(&sum_program());
+        let mut fast = FastInterpreter::new();
+        let result = fast.run(&mut cpu, &config);
+
+        assert_eq!(result, expected);
+        assert_eq!((result.reason, result.exit_code), (StopReason::Exit, Some(5050)));
+        assert_eq!(cpu.get_cycle_count(), reference.get_cycle_count());
+        for reg in 0..32 {
+            assert_eq!(cpu.registers.peek(reg), reference.registers.peek(reg), "x{}", reg);
+        }
+        for addr in (0x400..0x464).step_by(4) {
+            assert_eq!(cpu.memory.fetch(addr), reference.memory.fetch(addr));
+        }
+        assert!(fast.stats().fast_instructions > 500);
+
+        // Budgets land on the same instruction
+        for max in [1, 7, 64, 333] {
+            let mut reference = cpu_with(&sum_program());
+            let mut cpu = cpu_with(&sum_program());
+            let config = RunConfig::new().with_max_cycles(max);
+            assert_eq!(fast.run(&mut cpu, &config), reference.run(&config));
+            assert_eq!(cpu.registers.peek(11), reference.registers.peek(11));
+        }
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // Overwrite the addi at 16 with "addi x10, x10, 100" and run it again
+        let patched = addi(10, 10, 100);
+        let upper = patched.wrapping_add(0x800) & !0xFFF;
+        let mut cpu = cpu_with(&[
+            InstructionEncoder::u_type(0b0110111, 5, upper as i32),       // t0 = patched
+            addi(5, 5, patched.wrapping_sub(upper) as i32 as i16),
+            addi(6, 0, 2),                                              // two passes
+            addi(6, 6, -1),                                             // loop:
+            addi(10, 10, 1),                                            // patched
+            InstructionEncoder::s_type(0b0100011, 0b010, 0, 5, 16),     // sw t0, 16(x0)
+            InstructionEncoder::b_type(0b1100011, 0b001, 6, 0, -12),    // bnez t1, loop
+            InstructionEncoder::j_type(0b1101111, 0, 0),
+        ]);
+
+        let mut fast = FastInterpreter::new();
+        let result = fast.run(&mut cpu, &RunConfig::new());
+
+        assert_eq!(result.reason, StopReason::SelfLoop(28));
+        assert_eq!(cpu.registers.peek(10), 101);
+        assert!(fast.stats().invalidations > 0);
+    }
+}
@@ -0,0 +1,382 @@
+//! Linear framebuffer display
+//!
+//! One device window: 32-bit control registers in the first page, pixel
+//! memory (VRAM) from `VRAM_OFFSET`, `stride` bytes per line, top line first.
+//!
+//! | offset | register    |                                           |
+//! |--------|-------------|-------------------------------------------|
+//! | 0x00   | WIDTH       | read-only                                 |
+//! | 0x04   | HEIGHT      | read-only                                 |
+//! | 0x08   | FORMAT      | read-only, `PixelFormat` code             |
+//! | 0x0C   | STRIDE      | read-only, bytes per line                 |
+//! | 0x10   | CONTROL     | bit 0 display enable                      |
+//! | 0x14   | FRAME       | read-only, frames presented so far        |
+//! | 0x18   | PRESENT     | write: frame complete (snapshot if set)   |
+//!
+//! The host saves frames as PPM or PNG on demand (`save`), on every PRESENT
+//! and/or every N cycles (`with_snapshots`), and can render an ANSI-art
+//! preview for the terminal.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::types::*;
+use crate::bus::Device;
+
+/// Start of pixel memory within the device window
+pub const VRAM_OFFSET: Addr = 0x1000;
+
+pub const FB_WIDTH: Addr = 0x00;
+pub const FB_HEIGHT: Addr = 0x04;
+pub const FB_FORMAT: Addr = 0x08;
+pub const FB_STRIDE: Addr = 0x0C;
+pub const FB_CONTROL: Addr = 0x10;
+pub const FB_FRAME: Addr = 0x14;
+pub const FB_PRESENT: Addr = 0x18;
+
+pub const FB_CONTROL_ENABLE: Word = 1 << 0;
+
+/// Pixel layout in VRAM (little-endian)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 8-bit grey level
+    Gray8,
+    /// 16-bit RRRRRGGGGGGBBBBB
+    Rgb565,
+    /// 32-bit 0x00RRGGBB
+    Xrgb8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Gray8 => 1,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Xrgb8888 => 4,
+        }
+    }
+
+    /// Value of the FORMAT register
+    pub fn code(self) -> Word {
+        match self {
+            PixelFormat::Gray8 => 0,
+            PixelFormat::Rgb565 => 1,
+            PixelFormat::Xrgb8888 => 2,
+        }
+    }
+
+    /// Parse "gray8", "rgb565" or "xrgb8888"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "gray8" | "grey8" => Some(PixelFormat::Gray8),
+            "rgb565" => Some(PixelFormat::Rgb565),
+            "xrgb8888" | "rgb888" => Some(PixelFormat::Xrgb8888),
+            _ => None,
+        }
+    }
+
+    fn to_rgb(self, bytes: &[u8]) -> [u8; 3] {
+        match self {
+            PixelFormat::Gray8 => [bytes[0]; 3],
+            PixelFormat::Rgb565 => {
+                let v = u16::from_le_bytes([bytes[0], bytes[1]]);
+                let expand = |value: u16, bits: u32| ((value as u32 * 255) / ((1 << bits) - 1)) as u8;
+                [expand(v >> 11, 5), expand((v >> 5) & 0x3F, 6), expand(v & 0x1F, 5)]
+            }
+            PixelFormat::Xrgb8888 => [bytes[2], bytes[1], bytes[0]],
+        }
+    }
+}
+
+/// Snapshot file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// Binary PPM (P6)
+    Ppm,
+    Png,
+}
+
+impl SnapshotFormat {
+    /// From the file extension, PPM unless it is .png
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("png") => SnapshotFormat::Png,
+            _ => SnapshotFormat::Ppm,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            SnapshotFormat::Ppm => "ppm",
+            SnapshotFormat::Png => "png",
+        }
+    }
+}
+
+/// Automatic snapshots into a directory
+struct Snapshots {
+    dir: PathBuf,
+    format: SnapshotFormat,
+    every: Option<u64>,
+    saved: u64,
+}
+
+/// Framebuffer device
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    vram: Vec<u8>,
+    control: Word,
+    frame: Word,
+    cycles: u64,
+    snapshots: Option<Snapshots>,
+    last_error: Option<String>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32, format: PixelFormat) -> Self {
+        let vram = vec![0; width as usize * height as usize * format.bytes_per_pixel()];
+        Self { width, height, format, vram, control: 0, frame: 0, cycles: 0, snapshots: None, last_error: None }
+    }
+
+    /// Save `dir/frame-NNNNNN.<ext>` on every PRESENT and, with `every`,
+    /// each time that many cycles pass
+    pub fn with_snapshots(mut self, dir: impl Into<PathBuf>, format: SnapshotFormat, every: Option<u64>) -> Self {
+        self.snapshots = Some(Snapshots { dir: dir.into(), format, every, saved: 0 });
+        self
+    }
+
+    /// Size of the device window to map: registers plus VRAM
+    pub fn window_size(&self) -> Addr {
+        Self::window_size_for(self.width, self.height, self.format)
+    }
+
+    /// Window size of a `width` x `height` framebuffer, without allocating it
+    pub fn window_size_for(width: u32, height: u32, format: PixelFormat) -> Addr {
+        let vram = width as usize * height as usize * format.bytes_per_pixel();
+        VRAM_OFFSET + (vram as Addr).next_multiple_of(0x1000)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn stride(&self) -> usize {
+        self.width as usize * self.format.bytes_per_pixel()
+    }
+
+    /// Frames presented by the guest
+    pub fn frame(&self) -> Word {
+        self.frame
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.control & FB_CONTROL_ENABLE != 0
+    }
+
+    /// Raw pixel memory
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        let bpp = self.format.bytes_per_pixel();
+        let offset = y as usize * self.stride() + x as usize * bpp;
+        self.format.to_rgb(&self.vram[offset..offset + bpp])
+    }
+
+    /// Whole frame as packed RGB, 3 bytes per pixel
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let bpp = self.format.bytes_per_pixel();
+        self.vram.chunks_exact(bpp).flat_map(|pixel| self.format.to_rgb(pixel)).collect()
+    }
+
+    pub fn write_ppm(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.to_rgb())
+    }
+
+    pub fn write_png(&self, out: impl Write) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(out, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&self.to_rgb()).map_err(io::Error::other)
+    }
+
+    /// Save the current frame, format from the extension
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        match SnapshotFormat::from_path(path) {
+            SnapshotFormat::Ppm => self.write_ppm(&mut file)?,
+            SnapshotFormat::Png => self.write_png(&mut file)?,
+        }
+        file.flush()
+    }
+
+    /// Frame as ANSI truecolor half-blocks, at most `columns` wide
+    /// (two pixel rows per text line)
+    pub fn ansi_preview(&self, columns: u32) -> String {
+        let step = self.width.div_ceil(columns.max(1)).max(1);
+        let mut text = String::new();
+        for y in (0..self.height).step_by(2 * step as usize) {
+            for x in (0..self.width).step_by(step as usize) {
+                let [tr, tg, tb] = self.pixel(x, y);
+                let [br, bg, bb] = if y + step < self.height { self.pixel(x, y + step) } else { [0; 3] };
+                let _ = write!(text, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}", tr, tg, tb, br, bg, bb);
+            }
+            text.push_str("\x1b[0m\n");
+        }
+        text
+    }
+
+    /// Error from the last automatic snapshot, if it failed
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    fn snapshot(&mut self) {
+        let Some(snapshots) = &mut self.snapshots else {
+            return;
+        };
+        snapshots.saved += 1;
+        let name = format!("frame-{:06}.{}", snapshots.saved, snapshots.format.extension());
+        let path = snapshots.dir.join(name);
+        if let Err(e) = self.save(&path) {
+            self.last_error = Some(format!("{}: {}", path.display(), e));
+        }
+    }
+
+    fn register(&self, offset: Addr) -> Word {
+        match offset & !3 {
+            FB_WIDTH => self.width,
+            FB_HEIGHT => self.height,
+            FB_FORMAT => self.format.code(),
+            FB_STRIDE => self.stride() as Word,
+            FB_CONTROL => self.control,
+            FB_FRAME => self.frame,
+            _ => 0,
+        }
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: Addr, size: usize) -> Word {
+        if offset < VRAM_OFFSET {
+            return self.register(offset) >> (8 * (offset & 3));
+        }
+        let start = (offset - VRAM_OFFSET) as usize;
+        (0..size).fold(0, |value, i| value | (*self.vram.get(start + i).unwrap_or(&0) as Word) << (8 * i))
+    }
+
+    fn write(&mut self, offset: Addr, value: Word, size: usize) {
+        if offset < VRAM_OFFSET {
+            match offset & !3 {
+                FB_CONTROL => self.control = value,
+                FB_PRESENT => {
+                    self.frame = self.frame.wrapping_add(1);
+                    self.snapshot();
+                }
+                _ => {}
+            }
+            return;
+        }
+        let start = (offset - VRAM_OFFSET) as usize;
+        for (i, byte) in value.to_le_bytes().iter().take(size).enumerate() {
+            if let Some(slot) = self.vram.get_mut(start + i) {
+                *slot = *byte;
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+        if self.snapshots.as_ref().and_then(|s| s.every).is_some_and(|every| self.cycles.is_multiple_of(every.max(1))) {
+            self.snapshot();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.vram.fill(0);
+        self.control = 0;
+        self.frame = 0;
+        self.cycles = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_registers_pixels_and_ppm() {
+        let mut fb = Framebuffer::new(4, 2, PixelFormat::Rgb565);
+        assert_eq!((fb.read(FB_WIDTH, 4), fb.read(FB_STRIDE, 4), fb.read(FB_FORMAT, 4)), (4, 8, 1));
+
+        fb.write(VRAM_OFFSET, 0xF800, 2);                    // (0,0) red
+        fb.write(VRAM_OFFSET + 8 + 6, 0x001F, 2);            // (3,1) blue
+        fb.write(FB_CONTROL, FB_CONTROL_ENABLE, 4);
+        assert!(fb.enabled());
+        assert_eq!(fb.pixel(0, 0), [255, 0, 0]);
+        assert_eq!(fb.pixel(3, 1), [0, 0, 255]);
+        assert_eq!(fb.read(VRAM_OFFSET, 1), 0x00);
+
+        let mut ppm = Vec::new();
+        fb.write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n4 2\n255\n"));
+        assert_eq!(ppm.len(), 11 + 4 * 2 * 3);
+        assert_eq!(&ppm[11..14], &[255, 0, 0]);
+
+        let preview = fb.ansi_preview(80);
+        assert_eq!(preview.lines().count(), 1);
+        assert!(preview.contains("\x1b[38;2;255;0;0m\x1b[48;2;0;0;0m"));
+    }
+
+    #[test]
+    fn test_program_draws_and_presents() {
+        // Fill a 4x4 XRGB frame with green and press PRESENT
+        let dir = std::env::temp_dir().join(format!("fb-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fb = Framebuffer::new(4, 4, PixelFormat::Xrgb8888).with_snapshots(&dir, SnapshotFormat::Png, None);
+        let mut cpu = Cpu::new();
+        let fb = cpu.memory.map_device("fb", 0x4000, 0x2000, fb).unwrap();
+
+        let program = [
+            InstructionEncoder::u_type(0b0110111, 10, 0x5000),              // a0 = 0x5000 (VRAM)
+            InstructionEncoder::u_type(0b0110111, 11, 0x10000),
+            InstructionEncoder::i_type(0b0010011, 11, 0b000, 11, -0x100),   // a1 = 0x0000_FF00 green
+            InstructionEncoder::i_type(0b0010011, 12, 0b000, 10, 64),       // a2 = end of VRAM
+            InstructionEncoder::s_type(0b0100011, 0b010, 10, 11, 0),        // loop: sw a1, 0(a0)
+            InstructionEncoder::i_type(0b0010011, 10, 0b000, 10, 4),
+            InstructionEncoder::b_type(0b1100011, 0b001, 10, 12, -8),       // bne a0, a2, loop
+            InstructionEncoder::u_type(0b0110111, 10, 0x4000),
+            InstructionEncoder::s_type(0b0100011, 0b010, 10, 0, FB_PRESENT as i16),
+            InstructionEncoder::j_type(0b1101111, 0, 0),
+        ];
+        cpu.load_words(0, &program);
+        cpu.run_cycles(100);
+
+        let fb = fb.lock().unwrap();
+        assert_eq!(fb.frame(), 1);
+        assert!(fb.to_rgb().chunks(3).all(|pixel| pixel == [0, 255, 0]));
+
+        let decoder = png::Decoder::new(std::fs::File::open(dir.join("frame-000001.png")).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut image).unwrap();
+        assert_eq!(image, fb.to_rgb());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
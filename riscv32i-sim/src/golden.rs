@@ -0,0 +1,496 @@
+//! Architectural reference interpreter (golden model)
+//!
+//! Executes the same ISA as `Cpu` (RV32I, Zicsr, M-mode traps, word AMOs)
+//! straight from the instruction encoding, with no datapath signals - one
+//! `match` per opcode. It shares `CsrFile` and `Memory` with `Cpu` only as
+//! storage, so it can be run in lockstep with it (see `lockstep`).
+//!
+//! A model copied from a `Cpu` shares its devices, so it never drives them:
+//! stores to MMIO are dropped and loads from MMIO return the value handed
+//! in with `replay_device_load` (what the `Cpu` read). Instructions are
+//! still fetched from executable devices such as a boot ROM.
+//!
+//! Like `Cpu`, jump targets are word-aligned by clearing their low bits
+//! (there is no C extension) and misaligned data accesses are performed
+//! byte by byte rather than trapping.
+
+use crate::types::*;
+use crate::cpu::{Cpu, CpuEvent};
+use crate::csr::{self, CsrFile};
+use crate::memory::Memory;
+
+/// Architectural effects of one step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Commit {
+    pub pc: Addr,
+    /// Instruction word (0 when an interrupt was taken or the hart is in WFI)
+    pub instruction: Word,
+    pub next_pc: Addr,
+    /// rd and the value written (never x0)
+    pub reg_write: Option<(u8, Word)>,
+    /// Address and value of a store (value truncated to the access width)
+    pub mem_write: Option<(Addr, Word)>,
+    /// Exception left for the host (no trap handler installed)
+    pub event: Option<CpuEvent>,
+}
+
+/// Reference RV32I hart
+#[derive(Clone)]
+pub struct GoldenModel {
+    pub pc: Addr,
+    pub regs: [Word; 32],
+    pub memory: Memory,
+    pub csrs: CsrFile,
+    reservation: Option<Addr>,
+    waiting_for_interrupt: bool,
+    /// Result of this step's device load, from the `Cpu`
+    device_load: Option<Word>,
+}
+
+impl GoldenModel {
+    pub fn new(memory: Memory) -> Self {
+        Self {
+            pc: 0,
+            regs: [0; 32],
+            memory,
+            csrs: CsrFile::new(0),
+            reservation: None,
+            waiting_for_interrupt: false,
+            device_load: None,
+        }
+    }
+
+    /// Copy of a `Cpu`'s architectural state
+    pub fn from_cpu(cpu: &Cpu) -> Self {
+        Self {
+            pc: cpu.control.get_pc(),
+            regs: std::array::from_fn(|i| cpu.registers.peek(i as u8)),
+            memory: cpu.memory.clone(),
+            csrs: cpu.csrs.clone(),
+            reservation: cpu.reservation(),
+            waiting_for_interrupt: cpu.is_waiting_for_interrupt(),
+            device_load: None,
+        }
+    }
+
+    /// Value the next step's load or AMO from a device returns
+    pub fn replay_device_load(&mut self, value: Word) {
+        self.device_load = Some(value);
+    }
+
+    fn read(&self, reg: u8) -> Word {
+        self.regs[reg as usize]
+    }
+
+    fn is_device(&self, addr: Addr) -> bool {
+        self.memory.bus().is_mmio(addr)
+    }
+
+    /// Execute one instruction (or take an interrupt, or stall in WFI)
+    pub fn step(&mut self) -> Commit {
+        self.csrs.tick();
+        let pc = self.pc;
+        let mut commit = Commit { pc, instruction: 0, next_pc: pc, reg_write: None, mem_write: None, event: None };
+
+        if let Some(cause) = self.csrs.pending_interrupt() {
+            self.waiting_for_interrupt = false;
+            self.pc = self.csrs.enter_trap(cause, pc, 0) & !0x3;
+            commit.next_pc = self.pc;
+            return commit;
+        }
+        if self.waiting_for_interrupt {
+            if self.csrs.enabled_pending() == 0 {
+                return commit;
+            }
+            self.waiting_for_interrupt = false;
+        }
+
+        let inst = Instruction::new(self.memory.fetch(pc));
+        commit.instruction = inst.raw;
+
+        let executed = self.execute(inst, &mut commit);
+        self.device_load = None;
+        match executed {
+            Some(next_pc) => {
+                self.pc = next_pc & !0x3;
+                self.csrs.retire();
+            }
+            None => commit.event = Some(CpuEvent::IllegalInstruction(inst.raw)),
+        }
+        if let Some((rd, value)) = commit.reg_write {
+            self.regs[rd as usize] = value;
+        }
+        commit.event = commit.event.and_then(|event| self.raise(event, pc));
+
+        commit.next_pc = self.pc;
+        commit
+    }
+
+    /// Execute a decoded instruction - the next PC, or None if it is illegal
+    fn execute(&mut self, inst: Instruction, commit: &mut Commit) -> Option<Addr> {
+        let pc = commit.pc;
+        let rs1 = self.read(inst.rs1());
+        let rs2 = self.read(inst.rs2());
+        let next = pc.wrapping_add(4);
+        let mut rd_value = None;
+        let mut next_pc = next;
+
+        match inst.opcode() {
+            0b0110111 => rd_value = Some(inst.imm_u() as Word),                     // LUI
+            0b0010111 => rd_value = Some(pc.wrapping_add(inst.imm_u() as Word)),    // AUIPC
+            0b1101111 => {                                                          // JAL
+                rd_value = Some(next);
+                next_pc = pc.wrapping_add(inst.imm_j() as Word);
+            }
+            0b1100111 => {                                                          // JALR
+                rd_value = Some(next);
+                next_pc = rs1.wrapping_add(inst.imm_i() as Word) & !1;
+            }
+            0b1100011 => {                                                          // Branches
+                let taken = match inst.funct3() {
+                    0b000 => rs1 == rs2,
+                    0b001 => rs1 != rs2,
+                    0b100 => (rs1 as i32) < (rs2 as i32),
+                    0b101 => (rs1 as i32) >= (rs2 as i32),
+                    0b110 => rs1 < rs2,
+                    0b111 => rs1 >= rs2,
+                    _ => return None,
+                };
+                if taken {
+                    next_pc = pc.wrapping_add(inst.imm_b() as Word);
+                }
+            }
+            0b0000011 => {                                                          // Loads
+                let addr = rs1.wrapping_add(inst.imm_i() as Word);
+                let (size, signed) = match inst.funct3() {
+                    0b000 => (1, true),
+                    0b001 => (2, true),
+                    0b010 => (4, false),
+                    0b100 => (1, false),
+                    0b101 => (2, false),
+                    _ => return None,
+                };
+                if self.is_device(addr) {
+                    rd_value = Some(self.device_load.unwrap_or(0));
+                } else {
+                    let bytes = self.memory.read_bytes(addr, size);
+                    let value = bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as Word);
+                    let shift = 32 - 8 * size as u32;
+                    rd_value = Some(if signed { (((value << shift) as i32) >> shift) as Word } else { value });
+                }
+            }
+            0b0100011 => {                                                          // Stores
+                let addr = rs1.wrapping_add(inst.imm_s() as Word);
+                let size = match inst.funct3() {
+                    0b000 => 1,
+                    0b001 => 2,
+                    0b010 => 4,
+                    _ => return None,
+                };
+                if !self.is_device(addr) {
+                    self.memory.write_bytes(addr, &rs2.to_le_bytes()[..size]);
+                }
+                commit.mem_write = Some((addr, rs2 & (Word::MAX >> (32 - 8 * size as u32))));
+            }
+            0b0010011 => {                                                          // OP-IMM
+                let imm = inst.imm_i() as Word;
+                let shamt = imm & 0x1F;
+                rd_value = Some(match inst.funct3() {
+                    0b000 => rs1.wrapping_add(imm),
+                    0b010 => ((rs1 as i32) < (imm as i32)) as Word,
+                    0b011 => (rs1 < imm) as Word,
+                    0b100 => rs1 ^ imm,
+                    0b110 => rs1 | imm,
+                    0b111 => rs1 & imm,
+                    0b001 => rs1 << shamt,
+                    _ if inst.funct7() & 0x20 != 0 => ((rs1 as i32) >> shamt) as Word,
+                    _ => rs1 >> shamt,
+                });
+            }
+            0b0110011 => {                                                          // OP
+                let shamt = rs2 & 0x1F;
+                rd_value = Some(match (inst.funct3(), inst.funct7()) {
+                    (0b000, 0b0000000) => rs1.wrapping_add(rs2),
+                    (0b000, 0b0100000) => rs1.wrapping_sub(rs2),
+                    (0b001, _) => rs1 << shamt,
+                    (0b010, _) => ((rs1 as i32) < (rs2 as i32)) as Word,
+                    (0b011, _) => (rs1 < rs2) as Word,
+                    (0b100, _) => rs1 ^ rs2,
+                    (0b101, 0b0000000) => rs1 >> shamt,
+                    (0b101, 0b0100000) => ((rs1 as i32) >> shamt) as Word,
+                    (0b110, _) => rs1 | rs2,
+                    (0b111, _) => rs1 & rs2,
+                    _ => return None,
+                });
+            }
+            0b0001111 => {}                                                         // FENCE
+            0b1110011 => return self.system(inst, rs1, commit),
+            0b0101111 if inst.funct3() == 0b010 => {                                // AMO
+                rd_value = Some(self.atomic(inst.funct7() >> 2, rs1 & !0x3, rs2, commit)?);
+            }
+            _ => return None,
+        }
+
+        if inst.rd() != 0 {
+            commit.reg_write = rd_value.map(|value| (inst.rd(), value));
+        }
+        Some(next_pc)
+    }
+
+    /// SYSTEM opcode - ECALL, EBREAK, MRET, WFI and Zicsr
+    fn system(&mut self, inst: Instruction, rs1: Word, commit: &mut Commit) -> Option<Addr> {
+        let pc = commit.pc;
+        let next = pc.wrapping_add(4);
+
+        if inst.funct3() == 0b000 {
+            match inst.raw >> 20 {
+                0x000 => commit.event = Some(CpuEvent::Ecall),
+                0x001 => commit.event = Some(CpuEvent::Ebreak),
+                0x302 => return Some(self.csrs.mret()),
+                0x105 => self.waiting_for_interrupt = true,
+                _ => return None,
+            }
+            return Some(next);
+        }
+        if inst.funct3() == 0b100 {
+            return None;
+        }
+
+        let csr = (inst.raw >> 20) as u16;
+        let old = self.csrs.read(csr)?;
+        let source = if inst.funct3() & 0b100 != 0 { inst.rs1() as Word } else { rs1 };
+        let new = match inst.funct3() & 0b011 {
+            0b01 => Some(source),
+            0b10 if inst.rs1() != 0 => Some(old | source),
+            0b11 if inst.rs1() != 0 => Some(old & !source),
+            _ => None,
+        };
+        if new.is_some_and(|value| !self.csrs.write(csr, value)) {
+            return None;
+        }
+        if inst.rd() != 0 {
+            commit.reg_write = Some((inst.rd(), old));
+        }
+        Some(next)
+    }
+
+    /// LR.W / SC.W / AMO*.W - the value for rd, None for an unknown funct5
+    fn atomic(&mut self, funct5: u8, addr: Addr, rs2: Word, commit: &mut Commit) -> Option<Word> {
+        let device = self.is_device(addr);
+        let old = if device { self.device_load.unwrap_or(0) } else { self.memory.fetch(addr) };
+        let new = match funct5 {
+            0b00010 => {
+                self.reservation = Some(addr);
+                return Some(old);
+            }
+            0b00011 => {
+                if self.reservation.take() != Some(addr) {
+                    return Some(1);
+                }
+                if !device {
+                    self.memory.write_bytes(addr, &rs2.to_le_bytes());
+                }
+                commit.mem_write = Some((addr, rs2));
+                return Some(0);
+            }
+            0b00001 => rs2,
+            0b00000 => old.wrapping_add(rs2),
+            0b00100 => old ^ rs2,
+            0b01100 => old & rs2,
+            0b01000 => old | rs2,
+            0b10000 => (old as i32).min(rs2 as i32) as Word,
+            0b10100 => (old as i32).max(rs2 as i32) as Word,
+            0b11000 => old.min(rs2),
+            0b11100 => old.max(rs2),
+            _ => return None,
+        };
+        if !device {
+            self.memory.write_bytes(addr, &new.to_le_bytes());
+        }
+        commit.mem_write = Some((addr, new));
+        Some(old)
+    }
+
+    /// Synchronous exception - trap if mtvec is set, otherwise hand it back
+    /// for the host (ECALL/EBREAK have already moved the PC past themselves)
+    fn raise(&mut self, event: CpuEvent, pc: Addr) -> Option<CpuEvent> {
+        if !self.csrs.has_trap_handler() {
+            return Some(event);
+        }
+        let (cause, tval) = match event {
+            CpuEvent::Ecall => (csr::CAUSE_ECALL_M, 0),
+            CpuEvent::Ebreak => (csr::CAUSE_BREAKPOINT, pc),
+            CpuEvent::IllegalInstruction(word) => (csr::CAUSE_ILLEGAL_INSTRUCTION, word),
+            CpuEvent::Exit(_) => unreachable!("exit is not an exception"),
+        };
+        self.pc = self.csrs.enter_trap(cause, pc, tval) & !0x3;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{addi, lw, sw};
+
+    const OP: u8 = 0b0110011;
+    const OP_IMM: u8 = 0b0010011;
+    const LOAD: u8 = 0b0000011;
+    const STORE: u8 = 0b0100011;
+    const BRANCH: u8 = 0b1100011;
+    const SYSTEM: u8 = 0b1110011;
+    const AMO: u8 = 0b0101111;
+
+    fn model(program: &[Word]) -> GoldenModel {
+        let mut memory = Memory::new();
+        memory.load_words(0, program);
+        GoldenModel::new(memory)
+    }
+
+    fn steps(model: &mut GoldenModel, count: usize) -> Vec<Commit> {
+        (0..count).map(|_| model.step()).collect()
+    }
+
+    fn csr(rd: u8, funct3: u8, rs1: u8, csr: u16) -> Word {
+        InstructionEncoder::i_type(SYSTEM, rd, funct3, rs1, csr as i16)
+    }
+
+    fn amo(funct5: u8, rd: u8, rs1: u8, rs2: u8) -> Word {
+        InstructionEncoder::r_type(AMO, rd, 0b010, rs1, rs2, funct5 << 2)
+    }
+
+    #[test]
+    fn test_alu_and_upper_immediates() {
+        let mut model = model(&[
+            addi(1, 0, -5),
+            addi(2, 0, 3),
+            InstructionEncoder::r_type(OP, 3, 0b000, 1, 2, 0b0100000),   // sub
+            InstructionEncoder::r_type(OP, 4, 0b010, 1, 2, 0),           // slt
+            InstructionEncoder::r_type(OP, 5, 0b011, 1, 2, 0),           // sltu
+            InstructionEncoder::i_type(OP_IMM, 6, 0b101, 1, 0x401),      // srai x6, x1, 1
+            InstructionEncoder::i_type(OP_IMM, 7, 0b101, 1, 1),          // srli x7, x1, 1
+            InstructionEncoder::u_type(0b0110111, 8, 0x12345000),        // lui
+            InstructionEncoder::u_type(0b0010111, 9, 0x1000),            // auipc
+            addi(0, 1, 1),                                               // x0 stays 0
+        ]);
+
+        let commits = steps(&mut model, 10);
+
+        assert_eq!(commits[2].reg_write, Some((3, -8i32 as Word)));
+        assert_eq!(model.regs[4..10], [1, 0, -3i32 as Word, 0x7FFF_FFFD, 0x1234_5000, 0x1020]);
+        assert_eq!((commits[9].reg_write, model.regs[0]), (None, 0));
+        assert_eq!(commits[9].next_pc, 40);
+    }
+
+    #[test]
+    fn test_loads_and_stores() {
+        let mut model = model(&[
+            InstructionEncoder::i_type(LOAD, 1, 0b000, 0, 0x100),        // lb
+            InstructionEncoder::i_type(LOAD, 2, 0b100, 0, 0x100),        // lbu
+            InstructionEncoder::i_type(LOAD, 3, 0b001, 0, 0x102),        // lh
+            InstructionEncoder::i_type(LOAD, 4, 0b101, 0, 0x102),        // lhu
+            addi(5, 0, 0x7AB),
+            InstructionEncoder::s_type(STORE, 0b001, 0, 5, 0x104),       // sh
+            InstructionEncoder::s_type(STORE, 0b000, 0, 5, 0x106),       // sb
+            sw(0, 5, 0x108),
+            lw(6, 0, 0x104),
+        ]);
+        model.memory.write_bytes(0x100, &[0x80, 0x7F, 0xFF, 0xFF]);
+
+        let commits = steps(&mut model, 9);
+
+        assert_eq!(model.regs[1..5], [0xFFFF_FF80, 0x80, 0xFFFF_FFFF, 0xFFFF]);
+        assert_eq!(commits[5].mem_write, Some((0x104, 0x7AB)));
+        assert_eq!(commits[6].mem_write, Some((0x106, 0xAB)));
+        assert_eq!(commits[7].mem_write, Some((0x108, 0x7AB)));
+        assert_eq!(model.regs[6], 0x00AB_07AB);
+    }
+
+    #[test]
+    fn test_branches_and_jumps() {
+        let mut model = model(&[
+            addi(1, 0, 1),
+            InstructionEncoder::b_type(BRANCH, 0b001, 1, 0, 8),          // bne x1, x0, +8
+            addi(2, 0, 99),
+            InstructionEncoder::j_type(0b1101111, 3, 8),                 // jal x3, +8
+            InstructionEncoder::b_type(BRANCH, 0b000, 1, 0, -16),        // beq x1, x0 (not taken)
+            InstructionEncoder::i_type(0b1100111, 4, 0b000, 3, 1),       // jalr x4, 1(x3)
+        ]);
+
+        let commits = steps(&mut model, 6);
+
+        let pcs: Vec<_> = commits.iter().map(|c| c.next_pc).collect();
+        assert_eq!(pcs, [4, 12, 20, 16, 20, 16]);
+        assert_eq!((model.regs[2], model.regs[3], model.regs[4]), (0, 16, 24));
+    }
+
+    #[test]
+    fn test_csr_access_and_illegal_instructions() {
+        let mut model = model(&[
+            addi(2, 0, 5),
+            csr(1, 0b001, 2, csr::MSCRATCH),                             // csrrw x1, mscratch, x2
+            csr(3, 0b010, 0, csr::MSCRATCH),                             // csrr x3, mscratch
+            csr(4, 0b111, 1, csr::MSCRATCH),                             // csrrci x4, mscratch, 1
+            csr(5, 0b010, 0, 0x180),                                     // csrr x5, satp
+            csr(0, 0b001, 2, csr::MHARTID),                              // csrw mhartid, x2
+            0xFFFF_FFFF,
+        ]);
+
+        let commits = steps(&mut model, 7);
+
+        assert_eq!((model.regs[1], model.regs[3], model.regs[4]), (0, 5, 5));
+        assert_eq!(model.csrs.read(csr::MSCRATCH), Some(4));
+        for commit in &commits[4..] {
+            assert_eq!(commit.event, Some(CpuEvent::IllegalInstruction(commit.instruction)));
+            assert_eq!((commit.reg_write, commit.next_pc), (None, commit.pc));
+        }
+    }
+
+    #[test]
+    fn test_trap_entry_and_mret() {
+        let mut model = model(&[
+            addi(1, 0, 0x40),
+            csr(0, 0b001, 1, csr::MTVEC),                                // csrw mtvec, x1
+            0x0000_0073,                                                 // ecall
+            0x0010_0073,                                                 // ebreak
+        ]);
+        model.memory.load_words(0x40, &[
+            csr(2, 0b010, 0, csr::MEPC),                                 // csrr x2, mepc
+            addi(2, 2, 4),
+            csr(0, 0b001, 2, csr::MEPC),                                 // csrw mepc, x2
+            0x3020_0073,                                                 // mret
+        ]);
+
+        let commits = steps(&mut model, 3);
+        assert_eq!((commits[2].event, commits[2].next_pc), (None, 0x40));
+        assert_eq!(model.csrs.read(csr::MCAUSE), Some(csr::CAUSE_ECALL_M));
+        assert_eq!(model.csrs.read(csr::MEPC), Some(8));
+
+        let commits = steps(&mut model, 5);
+        assert_eq!(commits[3].next_pc, 12);
+        assert_eq!(commits[4].next_pc, 0x40);
+        assert_eq!(model.csrs.read(csr::MCAUSE), Some(csr::CAUSE_BREAKPOINT));
+        assert_eq!(model.csrs.read(csr::MTVAL), Some(12));
+    }
+
+    #[test]
+    fn test_amo_and_lr_sc() {
+        let mut model = model(&[
+            addi(1, 0, 0x100),
+            addi(2, 0, 5),
+            amo(0b00000, 3, 1, 2),                                       // amoadd.w x3, x2, (x1)
+            amo(0b00010, 4, 1, 0),                                       // lr.w x4, (x1)
+            amo(0b00011, 5, 1, 2),                                       // sc.w x5, x2, (x1)
+            amo(0b00011, 6, 1, 0),                                       // sc.w x6, x0, (x1) - no reservation
+            amo(0b10000, 7, 1, 0),                                       // amomin.w x7, x0, (x1)
+        ]);
+        model.memory.load_words(0x100, &[10]);
+
+        let commits = steps(&mut model, 7);
+
+        assert_eq!(commits[2].mem_write, Some((0x100, 15)));
+        assert_eq!(model.regs[3..8], [10, 15, 0, 1, 5]);
+        assert_eq!(commits[5].mem_write, None);
+        assert_eq!(model.memory.fetch(0x100), 0);
+    }
+}
@@ -0,0 +1,284 @@
+//! General-purpose I/O block
+//!
+//! 32 pins, each an input or an output, with edge-triggered interrupts:
+//!
+//! | offset | register    |                                             |
+//! |--------|-------------|---------------------------------------------|
+//! | 0x00   | INPUT_VAL   | read-only, pin levels                       |
+//! | 0x04   | OUTPUT_VAL  | levels driven on output pins                |
+//! | 0x08   | DIRECTION   | 1 = output                                  |
+//! | 0x0C   | RISE_IE     | interrupt on rising edge of an input pin    |
+//! | 0x10   | FALL_IE     | interrupt on falling edge of an input pin   |
+//! | 0x14   | IRQ_STATUS  | latched edges, write 1 to clear             |
+//! | 0x18   | OUT_SET     | write: set OUTPUT_VAL bits                  |
+//! | 0x1C   | OUT_CLEAR   | write: clear OUTPUT_VAL bits                |
+//! | 0x20   | OUT_TOGGLE  | write: invert OUTPUT_VAL bits               |
+//!
+//! The host drives input pins directly (`set_pins`), from a `Timeline`
+//! (inputs `pin0`..`pin31`, or `pins` for all 32), or from a script called
+//! every cycle. Changes to the driven outputs are logged as `pins`.
+
+use crate::types::*;
+use crate::bus::{merge_lanes, Device};
+use crate::timeline::{OutputLog, Timeline, TimelineError};
+
+pub const GPIO_INPUT_VAL: Addr = 0x00;
+pub const GPIO_OUTPUT_VAL: Addr = 0x04;
+pub const GPIO_DIRECTION: Addr = 0x08;
+pub const GPIO_RISE_IE: Addr = 0x0C;
+pub const GPIO_FALL_IE: Addr = 0x10;
+pub const GPIO_IRQ_STATUS: Addr = 0x14;
+pub const GPIO_OUT_SET: Addr = 0x18;
+pub const GPIO_OUT_CLEAR: Addr = 0x1C;
+pub const GPIO_OUT_TOGGLE: Addr = 0x20;
+/// Size of the register window to map
+pub const GPIO_SIZE: Addr = 0x100;
+
+/// Host-side script, called each cycle with the cycle number
+pub type GpioScript = Box<dyn FnMut(u64, &mut Gpio) + Send>;
+
+/// GPIO block
+#[derive(Default)]
+pub struct Gpio {
+    output: Word,
+    direction: Word,
+    /// Levels applied by the host to input pins
+    external: Word,
+    rise_ie: Word,
+    fall_ie: Word,
+    irq_status: Word,
+    cycle: u64,
+    timeline: Option<Timeline>,
+    script: Option<GpioScript>,
+    log: OutputLog,
+}
+
+impl Gpio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply input changes from `timeline` as the cycles pass
+    pub fn with_timeline(mut self, timeline: Timeline) -> Result<Self, TimelineError> {
+        timeline.validate(|name| pin_mask(name).is_some())?;
+        self.timeline = Some(timeline);
+        Ok(self)
+    }
+
+    /// Run `script` every cycle; it may read outputs and set inputs
+    pub fn with_script(mut self, script: impl FnMut(u64, &mut Gpio) + Send + 'static) -> Self {
+        self.script = Some(Box::new(script));
+        self
+    }
+
+    /// Output changes go to `log` (e.g. one with a file sink)
+    pub fn with_log(mut self, log: OutputLog) -> Self {
+        self.log = log;
+        self
+    }
+
+    /// Pin levels as the guest reads them
+    pub fn pins(&self) -> Word {
+        (self.external & !self.direction) | (self.output & self.direction)
+    }
+
+    /// Levels driven on output pins (inputs read as 0)
+    pub fn outputs(&self) -> Word {
+        self.output & self.direction
+    }
+
+    /// Drive input pins selected by `mask` to `levels`, latching edges
+    pub fn set_pins(&mut self, mask: Word, levels: Word) {
+        let before = self.pins();
+        self.external = (self.external & !mask) | (levels & mask);
+        let after = self.pins();
+        let inputs = !self.direction;
+        let rising = !before & after & inputs;
+        let falling = before & !after & inputs;
+        self.irq_status |= (rising & self.rise_ie) | (falling & self.fall_ie);
+    }
+
+    pub fn set_pin(&mut self, pin: u32, level: bool) {
+        self.set_pins(1 << pin, if level { Word::MAX } else { 0 });
+    }
+
+    /// Set a named input (`pinN` or `pins`)
+    pub fn set_input(&mut self, name: &str, value: Word) -> Result<(), TimelineError> {
+        let mask = pin_mask(name).ok_or_else(|| TimelineError::UnknownInput(name.to_string()))?;
+        let levels = if mask == Word::MAX { value } else if value != 0 { mask } else { 0 };
+        self.set_pins(mask, levels);
+        Ok(())
+    }
+
+    /// Cycles since reset
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    pub fn log(&self) -> &OutputLog {
+        &self.log
+    }
+
+    fn update_outputs(&mut self, output: Word, direction: Word) {
+        let before = self.outputs();
+        let pins_before = self.pins();
+        self.output = output;
+        self.direction = direction;
+        if self.outputs() != before {
+            self.log.record(self.cycle, "pins", self.outputs());
+        }
+        // A pin switched to input may read a new level
+        let changed = (pins_before ^ self.pins()) & !self.direction;
+        let rising = changed & self.pins();
+        self.irq_status |= (rising & self.rise_ie) | (changed & !rising & self.fall_ie);
+    }
+}
+
+/// Pins a named input covers
+fn pin_mask(name: &str) -> Option<Word> {
+    if name == "pins" {
+        return Some(Word::MAX);
+    }
+    let pin: u32 = name.strip_prefix("pin")?.parse().ok()?;
+    (pin < 32).then(|| 1 << pin)
+}
+
+impl Device for Gpio {
+    fn read(&mut self, offset: Addr, _size: usize) -> Word {
+        let value = match offset & !3 {
+            GPIO_INPUT_VAL => self.pins(),
+            GPIO_OUTPUT_VAL => self.output,
+            GPIO_DIRECTION => self.direction,
+            GPIO_RISE_IE => self.rise_ie,
+            GPIO_FALL_IE => self.fall_ie,
+            GPIO_IRQ_STATUS => self.irq_status,
+            _ => 0,
+        };
+        value >> (8 * (offset & 3))
+    }
+
+    fn write(&mut self, offset: Addr, value: Word, size: usize) {
+        let lanes = |register| merge_lanes(register, offset, value, size);
+        match offset & !3 {
+            GPIO_OUTPUT_VAL => self.update_outputs(lanes(self.output), self.direction),
+            GPIO_DIRECTION => self.update_outputs(self.output, lanes(self.direction)),
+            GPIO_RISE_IE => self.rise_ie = lanes(self.rise_ie),
+            GPIO_FALL_IE => self.fall_ie = lanes(self.fall_ie),
+            GPIO_IRQ_STATUS => self.irq_status &= !lanes(0),
+            GPIO_OUT_SET => self.update_outputs(self.output | lanes(0), self.direction),
+            GPIO_OUT_CLEAR => self.update_outputs(self.output & !lanes(0), self.direction),
+            GPIO_OUT_TOGGLE => self.update_outputs(self.output ^ lanes(0), self.direction),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        self.cycle += 1;
+        if let Some(mut timeline) = self.timeline.take() {
+            for event in timeline.take_due(self.cycle) {
+                // Names were checked in with_timeline
+                let _ = self.set_input(&event.name, event.value);
+            }
+            self.timeline = Some(timeline);
+        }
+        if let Some(mut script) = self.script.take() {
+            script(self.cycle, self);
+            self.script = Some(script);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_status != 0
+    }
+
+    fn reset(&mut self) {
+        self.output = 0;
+        self.direction = 0;
+        self.rise_ie = 0;
+        self.fall_ie = 0;
+        self.irq_status = 0;
+        self.cycle = 0;
+        if let Some(timeline) = &mut self.timeline {
+            timeline.rewind();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_directions_edges_and_irq() {
+        let mut gpio = Gpio::new();
+        gpio.write(GPIO_DIRECTION, 0x0F, 4);                // pins 0-3 outputs
+        gpio.write(GPIO_OUT_SET, 0x05, 4);
+        gpio.write(GPIO_OUT_TOGGLE, 0x03, 4);
+        assert_eq!(gpio.outputs(), 0x06);
+
+        gpio.write(GPIO_RISE_IE, 1 << 8, 4);
+        gpio.set_pins(0xFF0, 0x0F0);                        // pin 8 stays low, output bits ignored
+        assert!(!gpio.irq());
+        assert_eq!(gpio.read(GPIO_INPUT_VAL, 4), 0x0F6);
+        gpio.set_pin(8, true);
+        assert!(gpio.irq());
+        gpio.set_pin(8, false);                             // falling edge not enabled
+        gpio.write(GPIO_IRQ_STATUS, 1 << 8, 4);
+        assert!(!gpio.irq());
+
+        let changes: Vec<_> = gpio.log().changes("pins").map(|(_, value)| value).collect();
+        assert_eq!(changes, [0x05, 0x06]);
+    }
+
+    #[test]
+    fn test_byte_stores_update_their_lane() {
+        let mut memory = crate::memory::Memory::new();
+        let gpio = memory.map_device("gpio", 0x800, GPIO_SIZE, Gpio::new()).unwrap();
+        memory.store(0x800 + GPIO_DIRECTION, 0xFFFF_FFFF, 0b010);
+        for lane in 1..4 {
+            memory.store(0x800 + GPIO_OUTPUT_VAL + lane, 0xA0 + lane, 0b000);
+        }
+        assert_eq!(gpio.lock().unwrap().outputs(), 0xA3A2_A100);
+
+        // Halfword to the upper half, and set/clear/toggle on single lanes
+        memory.store(0x800 + GPIO_DIRECTION + 2, 0, 0b001);
+        memory.store(0x800 + GPIO_OUT_SET + 1, 0x0F, 0b000);
+        memory.store(0x800 + GPIO_OUT_CLEAR + 3, 0xFF, 0b000);
+        memory.store(0x800 + GPIO_OUT_TOGGLE + 2, 0x01, 0b000);
+        assert_eq!(memory.load(0x800 + GPIO_DIRECTION, 0b010), 0x0000_FFFF);
+        assert_eq!(memory.load(0x800 + GPIO_OUTPUT_VAL, 0b010), 0x00A3_AF00);
+        assert_eq!(gpio.lock().unwrap().outputs(), 0xAF00);
+    }
+
+    #[test]
+    fn test_timeline_drives_program() {
+        // Copy pin 4 (input) to pin 0 (LED) forever; a timeline presses and releases
+        let timeline = Timeline::parse("20 pin4 1\n60 pin4 0\n").unwrap();
+        let gpio = Gpio::new().with_timeline(timeline).unwrap();
+        assert!(Gpio::new().with_timeline(Timeline::parse("1 led0 1").unwrap()).is_err());
+
+        let mut cpu = Cpu::new();
+        let gpio = cpu.memory.map_device("gpio", 0x800, GPIO_SIZE, gpio).unwrap();
+        let program = [
+            InstructionEncoder::i_type(0b0010011, 10, 0b000, 0, 0x7FF),
+            InstructionEncoder::i_type(0b0010011, 10, 0b000, 10, 1),          // a0 = 0x800
+            InstructionEncoder::i_type(0b0010011, 5, 0b000, 0, 1),
+            InstructionEncoder::s_type(0b0100011, 0b010, 10, 5, GPIO_DIRECTION as i16),
+            InstructionEncoder::i_type(0b0000011, 6, 0b010, 10, GPIO_INPUT_VAL as i16), // loop:
+            InstructionEncoder::i_type(0b0010011, 6, 0b101, 6, 4),            // srli t1, t1, 4
+            InstructionEncoder::s_type(0b0100011, 0b010, 10, 6, GPIO_OUTPUT_VAL as i16),
+            InstructionEncoder::j_type(0b1101111, 0, -12),
+        ];
+        cpu.load_words(0, &program);
+        cpu.run_cycles(100);
+
+        let gpio = gpio.lock().unwrap();
+        let changes: Vec<_> = gpio.log().changes("pins").collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].1, 1);
+        assert!((20..30).contains(&changes[0].0));
+        assert_eq!(changes[1].1, 0);
+        assert!((60..70).contains(&changes[1].0));
+    }
+}
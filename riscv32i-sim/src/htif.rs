@@ -0,0 +1,192 @@
+//! Test-harness exit devices
+//!
+//! `Htif` watches the ELF's `tohost` word (Berkeley host-target interface,
+//! as used by riscv-tests and Spike). A store to the low word of tohost:
+//! - with bit 0 set ends the program with exit code `value >> 1`
+//!   (riscv-tests: 1 = pass, `(test << 1) | 1` = failing test number)
+//! - otherwise points at a pk-style `magic_mem` block of eight u64s
+//!   `[syscall, arg0, arg1, ...]`; the syscall is proxied on the host, its
+//!   result written back to `magic_mem[0]`, tohost cleared and fromhost set to 1
+//!
+//! `TestFinisher` is the SiFive test-finisher register (QEMU virt "sifive_test"):
+//! a word store of 0x5555 passes, `(code << 16) | 0x3333` fails with `code`.
+
+use std::io::{self, Write};
+
+use crate::types::*;
+use crate::elf::ElfImage;
+use crate::memory::{Memory, MemoryAccess};
+use crate::syscall::OutputCapture;
+
+/// QEMU virt address of the test finisher
+pub const DEFAULT_FINISHER_BASE: Addr = 0x0010_0000;
+
+pub const FINISHER_FAIL: Word = 0x3333;
+pub const FINISHER_PASS: Word = 0x5555;
+pub const FINISHER_RESET: Word = 0x7777;
+
+/// Proxied syscall numbers (same as Linux/pk)
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+
+/// Negative errno for syscalls the proxy doesn't implement
+const ENOSYS: i64 = 38;
+
+/// tohost/fromhost mailbox
+pub struct Htif {
+    tohost: Addr,
+    fromhost: Option<Addr>,
+    output: Box<dyn Write + Send>,
+}
+
+impl Htif {
+    pub fn new(tohost: Addr, fromhost: Option<Addr>) -> Self {
+        Self {
+            tohost,
+            fromhost,
+            output: Box::new(io::stdout()),
+        }
+    }
+
+    /// Mailbox at the image's `tohost`/`fromhost` symbols, if it has them
+    pub fn from_elf(image: &ElfImage) -> Option<Self> {
+        image.tohost.map(|tohost| Self::new(tohost, image.fromhost))
+    }
+
+    /// Proxied writes to fd 1/2 go to `output` (stdout by default)
+    pub fn with_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Proxied output collected in the returned buffer
+    pub fn captured(self) -> (Self, OutputCapture) {
+        let capture = OutputCapture::new();
+        (self.with_output(capture.clone()), capture)
+    }
+
+    pub fn tohost(&self) -> Addr {
+        self.tohost
+    }
+
+    /// React to a store - returns the exit code when the program finished
+    pub fn observe(&mut self, memory: &mut Memory, access: &MemoryAccess) -> Option<i32> {
+        if access.addr & !0x3 != self.tohost || access.value == 0 {
+            return None;
+        }
+
+        if access.value & 1 != 0 {
+            return Some((access.value >> 1) as i32);
+        }
+
+        // Syscall proxy through magic_mem
+        let magic = access.value;
+        let args: Vec<u64> = (0..8).map(|i| read_u64(memory, magic.wrapping_add(8 * i))).collect();
+        let result = match args[0] {
+            SYS_EXIT => return Some(args[1] as i32),
+            SYS_WRITE if args[1] == 1 || args[1] == 2 => {
+                let data = memory.read_bytes(args[2] as Addr, (args[3] as usize).min(memory.size()));
+                let _ = self.output.write_all(&data).and_then(|_| self.output.flush());
+                data.len() as i64
+            }
+            _ => -ENOSYS,
+        };
+
+        memory.write_bytes(magic, &result.to_le_bytes());
+        memory.write_bytes(self.tohost, &[0; 8]);
+        if let Some(fromhost) = self.fromhost {
+            memory.write_bytes(fromhost, &1u64.to_le_bytes());
+        }
+        None
+    }
+}
+
+fn read_u64(memory: &Memory, addr: Addr) -> u64 {
+    let bytes = memory.read_bytes(addr, 8);
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+/// SiFive test finisher register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestFinisher {
+    addr: Addr,
+}
+
+impl TestFinisher {
+    pub fn new(addr: Addr) -> Self {
+        Self { addr }
+    }
+
+    pub fn addr(&self) -> Addr {
+        self.addr
+    }
+
+    /// React to a store - returns the exit code (0 = pass) when the test finished
+    /// FINISHER_RESET is not supported and is ignored
+    pub fn observe(&self, access: &MemoryAccess) -> Option<i32> {
+        if access.addr != self.addr {
+            return None;
+        }
+        match access.value & 0xFFFF {
+            FINISHER_PASS => Some(0),
+            FINISHER_FAIL => Some((access.value >> 16) as i32),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::{RunConfig, StopReason};
+    use crate::test_util::{addi, cpu_with, sw};
+
+    const TOHOST: Addr = 0x700;
+    const FROMHOST: Addr = 0x740;
+
+    #[test]
+    fn test_tohost_exit_and_syscall_proxy() {
+        // magic_mem at 0x400: write(1, 0x480, 3), then riscv-tests style fail of test 5
+        let program = [
+            addi(5, 0, 0x400),
+            sw(0, 5, TOHOST as i16),       // tohost = &magic_mem
+            addi(5, 0, (5 << 1) | 1),
+            sw(0, 5, TOHOST as i16),       // tohost = fail(5)
+            InstructionEncoder::j_type(0b1101111, 0, 0),
+        ];
+        let mut cpu = cpu_with(&program);
+        for (i, arg) in [SYS_WRITE, 1, 0x480, 3].iter().enumerate() {
+            cpu.memory.write_bytes(0x400 + 8 * i as Addr, &arg.to_le_bytes());
+        }
+        cpu.memory.write_bytes(0x480, b"ok\n");
+        let (htif, output) = Htif::new(TOHOST, Some(FROMHOST)).captured();
+        cpu.set_htif(htif);
+
+        let result = cpu.run(&RunConfig::new().with_max_cycles(20));
+
+        assert_eq!(output.contents(), "ok\n");
+        assert_eq!(cpu.memory.read_bytes(0x400, 8), 3u64.to_le_bytes());
+        assert_eq!(cpu.memory.fetch(FROMHOST), 1);
+        assert_eq!((result.reason, result.exit_code), (StopReason::Exit, Some(5)));
+        assert!(!result.passed());
+    }
+
+    #[test]
+    fn test_finisher_pass_and_fail() {
+        let finisher = TestFinisher::new(DEFAULT_FINISHER_BASE);
+        let program = [
+            InstructionEncoder::u_type(0b0110111, 5, DEFAULT_FINISHER_BASE as i32),
+            InstructionEncoder::u_type(0b0110111, 6, 0x0002_3000),    // (2 << 16) | 0x3000
+            addi(6, 6, 0x333),
+            sw(5, 6, 0),
+        ];
+        let mut cpu = cpu_with(&program);
+        cpu.set_test_finisher(finisher);
+
+        let result = cpu.run(&RunConfig::new().with_max_cycles(20));
+        assert_eq!((result.reason, result.exit_code), (StopReason::Exit, Some(2)));
+
+        let pass = MemoryAccess { kind: crate::memory::AccessKind::Write, addr: DEFAULT_FINISHER_BASE, value: FINISHER_PASS };
+        assert_eq!(finisher.observe(&pass), Some(0));
+    }
+}
@@ -0,0 +1,427 @@
+//! Memory image loading and dumping
+//!
+//! Formats shared with RTL testbenches and programming tools:
+//! - flat binary at a base address
+//! - Intel HEX (record types 00-05)
+//! - Motorola S-records (S0-S9)
+//! - Verilog `$readmemh` / `$readmemb` text, byte- or word-granular
+//!
+//! Word-granular readmem files hold one 32-bit word per entry, matching a
+//! `logic [31:0] mem [N]` initializer; words are stored little-endian.
+//! `@addr` directives count entries (words or bytes) from the base address.
+
+use std::path::Path;
+
+use crate::types::*;
+use crate::memory::Memory;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImageError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Line {line}: {message}")]
+    Parse { line: usize, message: String },
+
+    #[error("Line {line}: checksum mismatch")]
+    Checksum { line: usize },
+
+    #[error("Image data at 0x{addr:08x}..+0x{len:x} is outside memory")]
+    OutOfRange { addr: Addr, len: usize },
+}
+
+/// Entry size of a $readmem file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    Byte,
+    Word,
+}
+
+impl Granularity {
+    fn bytes(self) -> usize {
+        match self {
+            Granularity::Byte => 1,
+            Granularity::Word => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Binary,
+    IntelHex,
+    SRecord,
+    ReadMemH(Granularity),
+    ReadMemB(Granularity),
+}
+
+impl ImageFormat {
+    /// Guess the format from the file extension (and, for the ambiguous
+    /// `.hex`, from whether the contents start with an Intel HEX ':')
+    pub fn detect(path: &Path, contents: &[u8]) -> Self {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        match extension.as_str() {
+            "ihex" | "ihx" => ImageFormat::IntelHex,
+            "hex" => {
+                let first = contents.iter().find(|b| !b.is_ascii_whitespace());
+                if first == Some(&b':') {
+                    ImageFormat::IntelHex
+                } else {
+                    ImageFormat::ReadMemH(Granularity::Word)
+                }
+            }
+            "srec" | "s19" | "s28" | "s37" | "mot" => ImageFormat::SRecord,
+            "mem" | "memh" | "vmem" => ImageFormat::ReadMemH(Granularity::Word),
+            "memb" => ImageFormat::ReadMemB(Granularity::Word),
+            _ => ImageFormat::Binary,
+        }
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> ImageError {
+    ImageError::Parse { line, message: message.into() }
+}
+
+impl Memory {
+    /// Write a block of bytes, refusing data that falls outside memory
+    fn load_block(&mut self, addr: Addr, bytes: &[u8]) -> Result<(), ImageError> {
+        if !self.contains(addr, bytes.len()) {
+            return Err(ImageError::OutOfRange { addr, len: bytes.len() });
+        }
+        self.write_bytes(addr, bytes);
+        Ok(())
+    }
+
+    /// Load an image in any format - returns the start address if the image records one
+    pub fn load_image(&mut self, format: ImageFormat, base: Addr, contents: &[u8]) -> Result<Option<Addr>, ImageError> {
+        let text = || String::from_utf8_lossy(contents).into_owned();
+        match format {
+            ImageFormat::Binary => self.load_binary(base, contents).map(|_| None),
+            ImageFormat::IntelHex => self.load_ihex(&text()),
+            ImageFormat::SRecord => self.load_srec(&text()),
+            ImageFormat::ReadMemH(granularity) => self.load_readmem(&text(), base, granularity, 16).map(|_| None),
+            ImageFormat::ReadMemB(granularity) => self.load_readmem(&text(), base, granularity, 2).map(|_| None),
+        }
+    }
+
+    /// Dump [base, base + len) in any format
+    pub fn dump_image(&self, format: ImageFormat, base: Addr, len: usize) -> Vec<u8> {
+        match format {
+            ImageFormat::Binary => self.read_bytes(base, len),
+            ImageFormat::IntelHex => self.dump_ihex(base, len).into_bytes(),
+            ImageFormat::SRecord => self.dump_srec(base, len).into_bytes(),
+            ImageFormat::ReadMemH(granularity) => self.dump_readmem(base, len, granularity, 16).into_bytes(),
+            ImageFormat::ReadMemB(granularity) => self.dump_readmem(base, len, granularity, 2).into_bytes(),
+        }
+    }
+
+    /// Load an image file, detecting the format from its name and contents
+    pub fn load_image_file(&mut self, path: impl AsRef<Path>, base: Addr) -> Result<Option<Addr>, ImageError> {
+        let contents = std::fs::read(path.as_ref())?;
+        self.load_image(ImageFormat::detect(path.as_ref(), &contents), base, &contents)
+    }
+
+    /// Flat binary at `base`
+    pub fn load_binary(&mut self, base: Addr, bytes: &[u8]) -> Result<(), ImageError> {
+        self.load_block(base, bytes)
+    }
+
+    /// Intel HEX - returns the start address from a type 03/05 record
+    pub fn load_ihex(&mut self, text: &str) -> Result<Option<Addr>, ImageError> {
+        let mut upper: Addr = 0;
+        let mut start = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let record = line.strip_prefix(':').ok_or_else(|| parse_error(number, "record does not start with ':'"))?;
+            let bytes = decode_hex_bytes(record).ok_or_else(|| parse_error(number, "invalid hex digits"))?;
+            if bytes.len() < 5 || bytes.len() != 5 + bytes[0] as usize {
+                return Err(parse_error(number, "record length mismatch"));
+            }
+            if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+                return Err(ImageError::Checksum { line: number });
+            }
+
+            let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as Addr;
+            let data = &bytes[4..bytes.len() - 1];
+            match bytes[3] {
+                0x00 => self.load_block(upper.wrapping_add(offset), data)?,
+                0x01 => break,
+                0x02 if data.len() == 2 => upper = (u16::from_be_bytes([data[0], data[1]]) as Addr) << 4,
+                0x04 if data.len() == 2 => upper = (u16::from_be_bytes([data[0], data[1]]) as Addr) << 16,
+                0x03 if data.len() == 4 => {
+                    // CS:IP
+                    let cs = u16::from_be_bytes([data[0], data[1]]) as Addr;
+                    let ip = u16::from_be_bytes([data[2], data[3]]) as Addr;
+                    start = Some((cs << 4).wrapping_add(ip));
+                }
+                0x05 if data.len() == 4 => start = Some(Addr::from_be_bytes([data[0], data[1], data[2], data[3]])),
+                kind => return Err(parse_error(number, format!("unsupported record type {:02X}", kind))),
+            }
+        }
+        Ok(start)
+    }
+
+    /// Intel HEX, 16 data bytes per record, with type 04 records as needed
+    pub fn dump_ihex(&self, base: Addr, len: usize) -> String {
+        let mut out = String::new();
+        let mut upper = None;
+
+        for (chunk_index, chunk) in self.read_bytes(base, len).chunks(16).enumerate() {
+            let addr = base.wrapping_add((chunk_index * 16) as Addr);
+            if upper != Some(addr >> 16) {
+                upper = Some(addr >> 16);
+                out += &ihex_record(0x04, 0, &((addr >> 16) as u16).to_be_bytes());
+            }
+            out += &ihex_record(0x00, addr as u16, chunk);
+        }
+        out += &ihex_record(0x01, 0, &[]);
+        out
+    }
+
+    /// Motorola S-records - returns the start address from an S7/S8/S9 record
+    pub fn load_srec(&mut self, text: &str) -> Result<Option<Addr>, ImageError> {
+        let mut start = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (kind, record) = match line.as_bytes() {
+                [b'S', kind, ..] if kind.is_ascii_digit() => (kind - b'0', &line[2..]),
+                _ => return Err(parse_error(number, "record does not start with 'S<type>'")),
+            };
+            let bytes = decode_hex_bytes(record).ok_or_else(|| parse_error(number, "invalid hex digits"))?;
+            if bytes.is_empty() || bytes.len() != 1 + bytes[0] as usize {
+                return Err(parse_error(number, "record length mismatch"));
+            }
+            if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0xFF {
+                return Err(ImageError::Checksum { line: number });
+            }
+
+            let address_len = match kind {
+                0 | 1 | 5 | 9 => 2,
+                2 | 6 | 8 => 3,
+                3 | 7 => 4,
+                _ => return Err(parse_error(number, format!("unsupported record type S{}", kind))),
+            };
+            if bytes.len() < 2 + address_len {
+                return Err(parse_error(number, "record too short"));
+            }
+            let addr = bytes[1..1 + address_len].iter().fold(0, |addr, &b| (addr << 8) | b as Addr);
+            let data = &bytes[1 + address_len..bytes.len() - 1];
+
+            match kind {
+                1..=3 => self.load_block(addr, data)?,
+                7..=9 => start = Some(addr),
+                _ => {}  // S0 header, S5/S6 record counts
+            }
+        }
+        Ok(start)
+    }
+
+    /// Motorola S-records: S0 header, S3 data (16 bytes each), S7 with `base`
+    pub fn dump_srec(&self, base: Addr, len: usize) -> String {
+        let mut out = srec_record(0, &[0, 0], b"riscv32i-sim");
+        for (chunk_index, chunk) in self.read_bytes(base, len).chunks(16).enumerate() {
+            let addr = base.wrapping_add((chunk_index * 16) as Addr);
+            out += &srec_record(3, &addr.to_be_bytes(), chunk);
+        }
+        out += &srec_record(7, &base.to_be_bytes(), &[]);
+        out
+    }
+
+    /// `$readmemh` file (hex values)
+    pub fn load_readmemh(&mut self, text: &str, base: Addr, granularity: Granularity) -> Result<(), ImageError> {
+        self.load_readmem(text, base, granularity, 16)
+    }
+
+    /// `$readmemb` file (binary values)
+    pub fn load_readmemb(&mut self, text: &str, base: Addr, granularity: Granularity) -> Result<(), ImageError> {
+        self.load_readmem(text, base, granularity, 2)
+    }
+
+    pub fn dump_readmemh(&self, base: Addr, len: usize, granularity: Granularity) -> String {
+        self.dump_readmem(base, len, granularity, 16)
+    }
+
+    pub fn dump_readmemb(&self, base: Addr, len: usize, granularity: Granularity) -> String {
+        self.dump_readmem(base, len, granularity, 2)
+    }
+
+    fn load_readmem(&mut self, text: &str, base: Addr, granularity: Granularity, radix: u32) -> Result<(), ImageError> {
+        let unit = granularity.bytes();
+        let mut index: Addr = 0;
+        let mut in_block_comment = false;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let number = line_index + 1;
+            let mut rest = line;
+
+            while !rest.is_empty() {
+                if in_block_comment {
+                    match rest.find("*/") {
+                        Some(end) => {
+                            rest = &rest[end + 2..];
+                            in_block_comment = false;
+                        }
+                        None => break,
+                    }
+                    continue;
+                }
+
+                rest = rest.trim_start();
+                if rest.is_empty() || rest.starts_with("//") {
+                    break;
+                }
+                if let Some(after) = rest.strip_prefix("/*") {
+                    rest = after;
+                    in_block_comment = true;
+                    continue;
+                }
+
+                let end = rest.find(|c: char| c.is_whitespace() || c == '/').unwrap_or(rest.len());
+                let token = &rest[..end];
+                rest = &rest[end..];
+
+                if let Some(addr) = token.strip_prefix('@') {
+                    index = Addr::from_str_radix(&addr.replace('_', ""), 16)
+                        .map_err(|_| parse_error(number, format!("invalid address '{}'", token)))?;
+                    continue;
+                }
+
+                let value = u32::from_str_radix(&token.replace('_', ""), radix)
+                    .map_err(|_| parse_error(number, format!("invalid value '{}'", token)))?;
+                if unit == 1 && value > 0xFF {
+                    return Err(parse_error(number, format!("value '{}' does not fit in a byte", token)));
+                }
+
+                let addr = base.wrapping_add(index.wrapping_mul(unit as Addr));
+                self.load_block(addr, &value.to_le_bytes()[..unit])?;
+                index = index.wrapping_add(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// One entry per line, preceded by an `@0` address line
+    fn dump_readmem(&self, base: Addr, len: usize, granularity: Granularity, radix: u32) -> String {
+        let unit = granularity.bytes();
+        let mut out = String::from("@0\n");
+        for chunk in self.read_bytes(base, len).chunks(unit) {
+            let mut bytes = [0u8; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            let value = u32::from_le_bytes(bytes);
+            let line = match (radix, granularity) {
+                (16, Granularity::Byte) => format!("{:02x}\n", value),
+                (16, Granularity::Word) => format!("{:08x}\n", value),
+                (_, Granularity::Byte) => format!("{:08b}\n", value),
+                (_, Granularity::Word) => format!("{:032b}\n", value),
+            };
+            out += &line;
+        }
+        out
+    }
+}
+
+fn decode_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn ihex_record(kind: u8, offset: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(offset.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    let checksum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)).wrapping_neg();
+    bytes.push(checksum);
+    format!(":{}\n", hex_string(&bytes))
+}
+
+fn srec_record(kind: u8, addr: &[u8], data: &[u8]) -> String {
+    let mut bytes = vec![(addr.len() + data.len() + 1) as u8];
+    bytes.extend(addr);
+    bytes.extend(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    bytes.push(checksum);
+    format!("S{}{}\n", kind, hex_string(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_memory() -> Memory {
+        let mut memory = Memory::with_base(0x1000, 256);
+        let bytes: Vec<u8> = (0..40u8).map(|b| b.wrapping_mul(7)).collect();
+        memory.load_binary(0x1010, &bytes).unwrap();
+        memory
+    }
+
+    #[test]
+    fn test_round_trip_all_formats() {
+        let original = sample_memory();
+        let formats = [
+            ImageFormat::Binary,
+            ImageFormat::IntelHex,
+            ImageFormat::SRecord,
+            ImageFormat::ReadMemH(Granularity::Word),
+            ImageFormat::ReadMemH(Granularity::Byte),
+            ImageFormat::ReadMemB(Granularity::Word),
+            ImageFormat::ReadMemB(Granularity::Byte),
+        ];
+
+        for format in formats {
+            let image = original.dump_image(format, 0x1010, 40);
+            let mut copy = Memory::with_base(0x1000, 256);
+            copy.load_image(format, 0x1010, &image).unwrap();
+            assert_eq!(copy.read_bytes(0x1000, 256), original.read_bytes(0x1000, 256), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_ihex_and_srec_records() {
+        // Extended linear address 0x0000, data at 0x1000, start linear address, EOF
+        let hex = ":020000040000FA\n:04100000DEADBEEFB4\n:0400000500001000E7\n:00000001FF\n";
+        let mut memory = Memory::with_base(0x1000, 256);
+        assert_eq!(memory.load_ihex(hex).unwrap(), Some(0x1000));
+        assert_eq!(memory.fetch(0x1000), 0xEFBE_ADDE);
+
+        let bad = ":04100000DEADBEEFB5\n";
+        assert!(matches!(memory.load_ihex(bad), Err(ImageError::Checksum { line: 1 })));
+
+        let srec = "S00600004844521B\nS309000010041122334438\nS70500001000EA\n";
+        assert_eq!(memory.load_srec(srec).unwrap(), Some(0x1000));
+        assert_eq!(memory.fetch(0x1004), 0x4433_2211);
+    }
+
+    #[test]
+    fn test_readmemh_comments_and_addresses() {
+        let text = "// boot image\n@2 0000_0013 /* nop */ 00a00513\n/* multi\n line */ @0 deadbeef // first\n";
+        let mut memory = Memory::new();
+        memory.load_readmemh(text, 0x100, Granularity::Word).unwrap();
+
+        assert_eq!(memory.fetch(0x100), 0xDEAD_BEEF);
+        assert_eq!(memory.fetch(0x108), 0x0000_0013);
+        assert_eq!(memory.fetch(0x10C), 0x00A0_0513);
+
+        assert!(matches!(
+            memory.load_readmemh("@0 123", 0x1000, Granularity::Word),
+            Err(ImageError::OutOfRange { addr: 0x1000, .. })
+        ));
+        assert!(memory.load_readmemb("102", 0, Granularity::Byte).is_err());
+    }
+}
@@ -0,0 +1,636 @@
+//! Dynamic binary translation of hot blocks to x86-64 (JIT tier)
+//!
+//! The fast interpreter hands a block to `JitCompiler` once it has run
+//! `HOT_THRESHOLD` times. Each decoded op becomes a short template of
+//! x86-64 code working on the guest registers in `JitContext` (addressed
+//! off rbx); loads and stores call back into Rust so `Memory`, the host
+//! devices and code invalidation behave exactly as in the interpreter.
+//!
+//! Before each load and store, translated code catches up on the cycles
+//! (CSRs, devices, DMA turns) of the instructions before it, so an MMIO
+//! access sees the same device state as in the interpreter.
+//!
+//! Translated code returns to the interpreter at the end of the block, or
+//! early after a store that ended the program (HTIF, test finisher), went
+//! to an MMIO device or wrote over decoded code. Traps never happen inside a block - SYSTEM,
+//! AMO and illegal instructions are never decoded into one.
+//!
+//! Native code needs an executable mapping, so the tier is only available
+//! on x86-64 Linux; elsewhere `JitCompiler::new` returns None and the fast
+//! interpreter runs everything itself.
+
+use std::mem::offset_of;
+
+use crate::types::*;
+use crate::cpu::Cpu;
+use crate::fast::{self, Op};
+
+/// Runs of a block before it is translated
+pub const HOT_THRESHOLD: u32 = 16;
+
+/// Code buffer size - everything is retranslated when it fills up
+const CODE_BUFFER_SIZE: usize = 4 << 20;
+
+/// Guest state shared with translated code
+#[repr(C)]
+pub(crate) struct JitContext {
+    regs: [Word; 32],
+    pc: Addr,
+    /// Instructions completed when the block left early (during a load or
+    /// store callback: the number of the instruction making it, from 1)
+    retired: u32,
+    /// Instructions whose cycle has already been ticked
+    ticked: u32,
+    cpu: *mut Cpu,
+    code_words: *const u64,
+    code_words_len: usize,
+    memory_base: Addr,
+}
+
+const PC: i32 = offset_of!(JitContext, pc) as i32;
+const RETIRED: i32 = offset_of!(JitContext, retired) as i32;
+
+/// Entry point of a translated block - returns 0 at the end of the block,
+/// 1 when it left early
+#[derive(Clone, Copy)]
+pub(crate) struct NativeCode(unsafe extern "C" fn(*mut JitContext) -> u32);
+
+/// Where a translated block stopped
+pub(crate) struct NativeExit {
+    pub pc: Addr,
+    /// Instructions completed if the block left early
+    pub retired: Option<u32>,
+    /// Instructions whose cycle was ticked inside the block
+    pub ticked: u32,
+}
+
+/// x86-64 translator and code cache
+pub struct JitCompiler {
+    buffer: CodeBuffer,
+    context: Box<JitContext>,
+    /// Guest registers are held in `context`, not the `Cpu`
+    registers_live: bool,
+}
+
+impl JitCompiler {
+    /// None where native code cannot be generated or mapped
+    pub fn new() -> Option<Self> {
+        if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+            return None;
+        }
+        Some(Self {
+            buffer: CodeBuffer::new(CODE_BUFFER_SIZE)?,
+            context: Box::new(JitContext {
+                regs: [0; 32],
+                pc: 0,
+                retired: 0,
+                ticked: 0,
+                cpu: std::ptr::null_mut(),
+                code_words: std::ptr::null(),
+                code_words_len: 0,
+                memory_base: 0,
+            }),
+            registers_live: false,
+        })
+    }
+
+    /// Drop all translated code
+    pub fn reset(&mut self) {
+        self.buffer.reset();
+    }
+
+    /// Translate a decoded block starting at `start` - None if the code
+    /// buffer is full
+    pub(crate) fn translate(&mut self, start: Addr, ops: &[Op]) -> Option<NativeCode> {
+        let code = Emitter::translate(start, ops);
+        let entry = self.buffer.install(&code)?;
+        // SAFETY: `entry` points at the code just emitted, which follows the
+        // C calling convention for `fn(*mut JitContext) -> u32`
+        Some(NativeCode(unsafe { std::mem::transmute::<*const u8, unsafe extern "C" fn(*mut JitContext) -> u32>(entry) }))
+    }
+
+    /// Run a translated block against `cpu` (registers move into the
+    /// context on first use and stay there until `sync_out`)
+    pub(crate) fn execute(&mut self, cpu: &mut Cpu, code: NativeCode, code_words: &[u64], memory_base: Addr) -> NativeExit {
+        if !self.registers_live {
+            for (reg, value) in self.context.regs.iter_mut().enumerate() {
+                *value = cpu.registers.peek(reg as u8);
+            }
+            self.registers_live = true;
+        }
+
+        let context = &mut *self.context;
+        context.cpu = cpu;
+        context.code_words = code_words.as_ptr();
+        context.code_words_len = code_words.len();
+        context.memory_base = memory_base;
+        context.ticked = 0;
+
+        // SAFETY: the code was generated by `translate` for this context
+        // layout; `cpu` and `code_words` outlive the call
+        let status = unsafe { (code.0)(context) };
+
+        context.cpu = std::ptr::null_mut();
+        NativeExit { pc: context.pc, retired: (status != 0).then_some(context.retired), ticked: context.ticked }
+    }
+
+    /// Write the guest registers back to the `Cpu`
+    pub(crate) fn sync_out(&mut self, cpu: &mut Cpu) {
+        if self.registers_live {
+            for (reg, &value) in self.context.regs.iter().enumerate() {
+                cpu.registers.poke(reg as u8, value);
+            }
+            self.registers_live = false;
+        }
+    }
+}
+
+/// Tick the cycles of the instructions up to and including the one making
+/// an access, as the interpreter would have before it
+fn catch_up(context: &mut JitContext, cpu: &mut Cpu) {
+    cpu.tick_native(context.retired.saturating_sub(context.ticked) as u64);
+    context.ticked = context.ticked.max(context.retired);
+}
+
+/// Called by translated loads
+extern "C" fn jit_load(context: *mut JitContext, addr: Addr, funct3: u32) -> Word {
+    // SAFETY: only called from translated code, while `execute` holds the Cpu
+    let context = unsafe { &mut *context };
+    let cpu = unsafe { &mut *context.cpu };
+    catch_up(context, cpu);
+    fast::load(cpu, addr, funct3 as u8)
+}
+
+/// Called by translated stores - nonzero if the block has to stop
+extern "C" fn jit_store(context: *mut JitContext, addr: Addr, data: Word, funct3: u32) -> u32 {
+    // SAFETY: as for `jit_load`; `code_words` is borrowed for the call
+    let context = unsafe { &mut *context };
+    let cpu = unsafe { &mut *context.cpu };
+    catch_up(context, cpu);
+    let code_words = unsafe { std::slice::from_raw_parts(context.code_words, context.code_words_len) };
+
+    fast::store(cpu, addr, data, funct3 as u8);
+    let exited = cpu.last_event().is_some() || cpu.memory.bus().is_mmio(addr);
+    let hit_code = [addr, addr.wrapping_add(3)].iter().any(|&a| fast::is_code(code_words, context.memory_base, a));
+    (exited || hit_code) as u32
+}
+
+/// x86-64 registers used by the templates
+const EAX: u8 = 0;
+const ECX: u8 = 1;
+const EDX: u8 = 2;
+const EBX: u8 = 3;
+
+/// Template code generator - guest registers live at [rbx + 4 * reg]
+struct Emitter {
+    code: Vec<u8>,
+}
+
+impl Emitter {
+    fn translate(start: Addr, ops: &[Op]) -> Vec<u8> {
+        let mut e = Emitter { code: Vec::with_capacity(ops.len() * 24 + 16) };
+
+        e.bytes(&[0x53]);              // push rbx
+        e.bytes(&[0x48, 0x89, 0xFB]);  // mov rbx, rdi
+
+        let mut pc = start;
+        for (i, op) in ops.iter().enumerate() {
+            e.op(op, pc, i as u32 + 1);
+            pc = pc.wrapping_add(4);
+        }
+
+        if !ops.last().is_some_and(Op::is_control_flow) {
+            e.store_imm(PC, pc);
+        }
+        e.bytes(&[0x31, 0xC0]);        // xor eax, eax
+        e.bytes(&[0x5B, 0xC3]);        // pop rbx; ret
+        e.code
+    }
+
+    fn op(&mut self, op: &Op, pc: Addr, retired: u32) {
+        let next = pc.wrapping_add(4);
+        match *op {
+            Op::Const { rd, value } => {
+                if rd != 0 {
+                    self.store_imm(reg_offset(rd), value);
+                }
+            }
+            Op::Alu { op, rd, rs1, rs2 } => {
+                self.load(EAX, rs1);
+                self.load(ECX, rs2);
+                self.alu(op);
+                self.store(rd, EAX);
+            }
+            Op::AluImm { op, rd, rs1, imm } => {
+                self.load(EAX, rs1);
+                self.mov_imm(ECX, imm);
+                self.alu(op);
+                self.store(rd, EAX);
+            }
+            Op::Load { rd, rs1, imm, funct3 } => {
+                self.store_imm(RETIRED, retired);
+                self.address(rs1, imm);
+                self.mov_imm(EDX, funct3 as Word);
+                self.call(jit_load as *const () as u64);
+                self.store(rd, EAX);
+            }
+            Op::Store { rs1, rs2, imm, funct3 } => {
+                // State for an early exit after this instruction
+                self.store_imm(PC, next);
+                self.store_imm(RETIRED, retired);
+                self.address(rs1, imm);
+                self.load(EDX, rs2);
+                self.mov_imm(ECX, funct3 as Word);
+                self.call(jit_store as *const () as u64);
+                self.bytes(&[0x85, 0xC0]);                      // test eax, eax
+                self.bytes(&[0x74, 0x07]);                      // jz +7
+                self.bytes(&[0xB8, 0x01, 0x00, 0x00, 0x00]);    // mov eax, 1
+                self.bytes(&[0x5B, 0xC3]);                      // pop rbx; ret
+            }
+            Op::Branch { inst, rs1, rs2, target } => {
+                // Inverted condition skips the taken-path PC store
+                let skip = match inst.funct3() {
+                    0b000 => 0x75,  // BEQ: jne
+                    0b001 => 0x74,  // BNE: je
+                    0b100 => 0x7D,  // BLT: jge
+                    0b101 => 0x7C,  // BGE: jl
+                    0b110 => 0x73,  // BLTU: jae
+                    _ => 0x72,      // BGEU: jb
+                };
+                self.load(EAX, rs1);
+                self.load(ECX, rs2);
+                self.store_imm(PC, next);
+                self.bytes(&[0x39, 0xC8]);                      // cmp eax, ecx
+                self.bytes(&[skip, 10]);
+                self.store_imm(PC, target);                     // 10 bytes
+            }
+            Op::Jal { rd, target } => {
+                if rd != 0 {
+                    self.store_imm(reg_offset(rd), next);
+                }
+                self.store_imm(PC, target);
+            }
+            Op::Jalr { rd, rs1, imm } => {
+                self.load(EAX, rs1);
+                self.mov_imm(ECX, imm);
+                self.bytes(&[0x01, 0xC8]);                      // add eax, ecx
+                self.bytes(&[0x25]);                            // and eax, !3
+                self.imm32(!0x3);
+                self.store_offset(PC, EAX);
+                if rd != 0 {
+                    self.store_imm(reg_offset(rd), next);
+                }
+            }
+            Op::Nop => {}
+        }
+    }
+
+    /// eax = eax op ecx
+    fn alu(&mut self, op: AluOp) {
+        match op {
+            AluOp::Add => self.bytes(&[0x01, 0xC8]),
+            AluOp::Sub => self.bytes(&[0x29, 0xC8]),
+            AluOp::And => self.bytes(&[0x21, 0xC8]),
+            AluOp::Or => self.bytes(&[0x09, 0xC8]),
+            AluOp::Xor => self.bytes(&[0x31, 0xC8]),
+            // x86 masks 32-bit shift counts to 5 bits, as RISC-V does
+            AluOp::Sll => self.bytes(&[0xD3, 0xE0]),
+            AluOp::Srl => self.bytes(&[0xD3, 0xE8]),
+            AluOp::Sra => self.bytes(&[0xD3, 0xF8]),
+            AluOp::Slt => self.bytes(&[0x39, 0xC8, 0x0F, 0x9C, 0xC0, 0x0F, 0xB6, 0xC0]),   // setl
+            AluOp::Sltu => self.bytes(&[0x39, 0xC8, 0x0F, 0x92, 0xC0, 0x0F, 0xB6, 0xC0]),  // setb
+            AluOp::PassA => {}
+            AluOp::PassB => self.bytes(&[0x89, 0xC8]),
+        }
+    }
+
+    /// Helper call arguments: rdi = context, esi = rs1 + imm
+    fn address(&mut self, rs1: u8, imm: Word) {
+        self.load(EAX, rs1);
+        self.mov_imm(ECX, imm);
+        self.bytes(&[0x01, 0xC8]);          // add eax, ecx
+        self.bytes(&[0x89, 0xC6]);          // mov esi, eax
+        self.bytes(&[0x48, 0x89, 0xDF]);    // mov rdi, rbx
+    }
+
+    fn call(&mut self, target: u64) {
+        self.bytes(&[0x48, 0xB8]);          // mov rax, target
+        self.bytes(&target.to_le_bytes());
+        self.bytes(&[0xFF, 0xD0]);          // call rax
+    }
+
+    /// mov r32, [rbx + 4 * reg]
+    fn load(&mut self, r32: u8, reg: u8) {
+        self.bytes(&[0x8B, modrm_rbx(r32)]);
+        self.imm32(reg_offset(reg) as Word);
+    }
+
+    /// mov [rbx + 4 * reg], r32 (x0 is never written)
+    fn store(&mut self, reg: u8, r32: u8) {
+        if reg != 0 {
+            self.store_offset(reg_offset(reg), r32);
+        }
+    }
+
+    fn store_offset(&mut self, offset: i32, r32: u8) {
+        self.bytes(&[0x89, modrm_rbx(r32)]);
+        self.imm32(offset as Word);
+    }
+
+    /// mov dword [rbx + offset], imm32
+    fn store_imm(&mut self, offset: i32, value: Word) {
+        self.bytes(&[0xC7, modrm_rbx(EAX)]);
+        self.imm32(offset as Word);
+        self.imm32(value);
+    }
+
+    fn mov_imm(&mut self, r32: u8, value: Word) {
+        self.bytes(&[0xB8 + r32]);
+        self.imm32(value);
+    }
+
+    fn imm32(&mut self, value: Word) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+}
+
+/// ModRM for [rbx + disp32] with `r32` in the reg field
+fn modrm_rbx(r32: u8) -> u8 {
+    0b10_000_000 | (r32 << 3) | EBX
+}
+
+fn reg_offset(reg: u8) -> i32 {
+    offset_of!(JitContext, regs) as i32 + 4 * reg as i32
+}
+
+/// Executable memory for translated code (W^X: writable only while
+/// installing)
+struct CodeBuffer {
+    base: *mut u8,
+    size: usize,
+    used: usize,
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+impl CodeBuffer {
+    fn new(size: usize) -> Option<Self> {
+        // SAFETY: fresh anonymous mapping, checked for failure
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_EXEC,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        (base != libc::MAP_FAILED).then_some(Self { base: base.cast(), size, used: 0 })
+    }
+
+    /// Copy code in - its address, or None if the buffer is full
+    fn install(&mut self, code: &[u8]) -> Option<*const u8> {
+        if self.used + code.len() > self.size {
+            return None;
+        }
+        // SAFETY: the range is inside the mapping; no translated code runs
+        // while it is writable
+        unsafe {
+            self.protect(libc::PROT_READ | libc::PROT_WRITE)?;
+            let entry = self.base.add(self.used);
+            std::ptr::copy_nonoverlapping(code.as_ptr(), entry, code.len());
+            self.protect(libc::PROT_READ | libc::PROT_EXEC)?;
+            self.used += code.len().next_multiple_of(16);
+            Some(entry)
+        }
+    }
+
+    unsafe fn protect(&self, prot: libc::c_int) -> Option<()> {
+        (libc::mprotect(self.base.cast(), self.size, prot) == 0).then_some(())
+    }
+
+    fn reset(&mut self) {
+        self.used = 0;
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+impl Drop for CodeBuffer {
+    fn drop(&mut self) {
+        // SAFETY: unmapping our own mapping; no code from it runs after this
+        unsafe {
+            libc::munmap(self.base.cast(), self.size);
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+impl CodeBuffer {
+    fn new(_size: usize) -> Option<Self> {
+        None
+    }
+
+    fn install(&mut self, _code: &[u8]) -> Option<*const u8> {
+        None
+    }
+
+    fn reset(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast::FastInterpreter;
+    use crate::htif::TestFinisher;
+    use crate::run::{RunConfig, StopReason};
+    use crate::test_util::{addi, cpu_with};
+
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn op(rd: u8, funct3: u8, rs1: u8, rs2: u8, funct7: u8) -> Word {
+        InstructionEncoder::r_type(0b0110011, rd, funct3, rs1, rs2, funct7)
+    }
+
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn op_imm(rd: u8, funct3: u8, rs1: u8, imm: i16) -> Word {
+        InstructionEncoder::i_type(0b0010011, rd, funct3, rs1, imm)
+    }
+
+    /// Every RV32I instruction a block can hold, in a loop long enough to
+    /// get translated; x20 counts down, x21..x31 accumulate results
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn instruction_suite() -> Vec<Word> {
+        let mut words = vec![
+            addi(20, 0, 40),                                            // iterations
+            InstructionEncoder::u_type(0b0110111, 1, 0x8000_0000u32 as i32), // lui x1, 0x80000
+            addi(2, 0, -7),
+        ];
+        let body = vec![
+            // loop: derive operands from the counter
+            op(3, 0b000, 1, 20, 0x00),                                  // add
+            op(4, 0b000, 2, 20, 0x20),                                  // sub
+            op(5, 0b001, 20, 20, 0x00),                                 // sll
+            op(6, 0b010, 2, 20, 0x00),                                  // slt
+            op(7, 0b011, 2, 20, 0x00),                                  // sltu
+            op(8, 0b100, 3, 4, 0x00),                                   // xor
+            op(9, 0b101, 1, 20, 0x00),                                  // srl
+            op(10, 0b101, 1, 20, 0x20),                                 // sra
+            op(11, 0b110, 8, 5, 0x00),                                  // or
+            op(12, 0b111, 8, 9, 0x00),                                  // and
+            op_imm(13, 0b010, 20, 20),                                  // slti
+            op_imm(14, 0b011, 2, -1),                                   // sltiu
+            op_imm(15, 0b100, 3, -0x555),                               // xori
+            op_imm(16, 0b110, 4, 0x0F0),                                // ori
+            op_imm(17, 0b111, 10, 0x7FF),                               // andi
+            op_imm(18, 0b001, 4, 3),                                    // slli
+            op_imm(19, 0b101, 10, 5),                                   // srli
+            op_imm(19, 0b101, 19, 0x400 | 2),                           // srai
+            InstructionEncoder::u_type(0b0010111, 22, 0x1000),          // auipc
+            // memory: sized stores (one misaligned) and sign/zero-extending loads
+            InstructionEncoder::s_type(0b0100011, 0b010, 20, 10, 0x300),// sw x10, 0x300(x20)
+            InstructionEncoder::s_type(0b0100011, 0b001, 20, 15, 0x381),// sh x15, 0x381(x20)
+            InstructionEncoder::s_type(0b0100011, 0b000, 20, 19, 0x302),// sb x19, 0x302(x20)
+            InstructionEncoder::i_type(0b0000011, 23, 0b000, 20, 0x302),// lb
+            InstructionEncoder::i_type(0b0000011, 24, 0b001, 20, 0x300),// lh
+            InstructionEncoder::i_type(0b0000011, 25, 0b010, 20, 0x300),// lw
+            InstructionEncoder::i_type(0b0000011, 26, 0b100, 20, 0x303),// lbu
+            InstructionEncoder::i_type(0b0000011, 27, 0b101, 20, 0x381),// lhu (misaligned)
+            // accumulate
+            op(21, 0b000, 21, 3, 0), op(21, 0b100, 21, 6, 0), op(21, 0b000, 21, 7, 0),
+            op(21, 0b100, 21, 11, 0), op(21, 0b000, 21, 12, 0), op(21, 0b100, 21, 13, 0),
+            op(21, 0b000, 21, 14, 0), op(21, 0b100, 21, 16, 0), op(21, 0b000, 21, 17, 0),
+            op(21, 0b100, 21, 18, 0), op(21, 0b000, 21, 19, 0), op(21, 0b100, 21, 23, 0),
+            op(21, 0b000, 21, 24, 0), op(21, 0b100, 21, 25, 0), op(21, 0b000, 21, 26, 0),
+            op(21, 0b100, 21, 27, 0),
+            // branches, each taken on some iterations: bump x28..x31 when taken
+            op_imm(3, 0b111, 20, 3),                                    // x3 = x20 & 3
+            InstructionEncoder::b_type(0b1100011, 0b000, 3, 0, 8),      // beq x3, x0
+            addi(28, 28, 1),
+            InstructionEncoder::b_type(0b1100011, 0b001, 3, 0, 8),      // bne x3, x0
+            addi(28, 28, 3),
+            InstructionEncoder::b_type(0b1100011, 0b100, 2, 3, 8),      // blt x2, x3
+            addi(29, 29, 1),
+            InstructionEncoder::b_type(0b1100011, 0b101, 3, 2, 8),      // bge x3, x2
+            addi(29, 29, 5),
+            InstructionEncoder::b_type(0b1100011, 0b110, 2, 3, 8),      // bltu x2, x3
+            addi(30, 30, 1),
+            InstructionEncoder::b_type(0b1100011, 0b111, 3, 20, 8),     // bgeu x3, x20
+            addi(30, 30, 7),
+            // call and return: jal to a leaf, jalr back
+            InstructionEncoder::j_type(0b1101111, 1, 12),               // jal ra, leaf
+            addi(20, 20, -1),
+            InstructionEncoder::j_type(0b1101111, 0, 12),               // j next
+            addi(31, 31, 1),                                            // leaf:
+            InstructionEncoder::i_type(0b1100111, 0, 0b000, 1, 0),      // jalr x0, 0(ra)
+        ];
+        let back = -4 * (body.len() as i16);
+        words.extend(body);
+        words.push(InstructionEncoder::b_type(0b1100011, 0b001, 20, 0, back)); // next: bnez x20, loop
+        words.push(InstructionEncoder::j_type(0b1101111, 0, 0));
+        words
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_instruction_suite_matches_cpu() {
+        let config = RunConfig::new().with_max_cycles(100_000);
+        let mut reference = cpu_with(&instruction_suite());
+        let expected = reference.run(&config);
+        assert!(matches!(expected.reason, StopReason::SelfLoop(_)));
+
+        let mut cpu = cpu_with(&instruction_suite());
+        let mut fast = FastInterpreter::new().with_jit(true);
+        let result = fast.run(&mut cpu, &config);
+        assert!(fast.jit_enabled());
+
+        assert_eq!(result, expected);
+        assert_eq!(cpu.get_cycle_count(), reference.get_cycle_count());
+        for reg in 0..32 {
+            assert_eq!(cpu.registers.peek(reg), reference.registers.peek(reg), "x{}", reg);
+        }
+        for addr in (0x300..0x3C0).step_by(4) {
+            assert_eq!(cpu.memory.fetch(addr), reference.memory.fetch(addr), "mem[0x{:x}]", addr);
+        }
+        assert!(fast.stats().native_instructions > 1000);
+    }
+
+    #[test]
+    fn test_native_exits_on_device_store_and_budget() {
+        // Stores walk up to the test finisher; the 41st hits it and fails with code 7
+        let finisher: Addr = 0x7F0;
+        let program = [
+            addi(5, 0, (finisher - 0x10 * 40) as i16),
+            InstructionEncoder::u_type(0b0110111, 6, 0x0007_3000),      // lui t1, 0x73
+            addi(6, 6, 0x333),                                          // t1 = (7 << 16) | FAIL
+            InstructionEncoder::s_type(0b0100011, 0b010, 5, 6, 0),      // loop: sw t1, 0(t0)
+            addi(5, 5, 0x10),
+            InstructionEncoder::j_type(0b1101111, 0, -8),
+        ];
+
+        let run = |jit: bool, max_cycles: u64| {
+            let mut cpu = cpu_with(&program);
+            cpu.set_test_finisher(TestFinisher::new(finisher));
+            let mut fast = FastInterpreter::new().with_jit(jit);
+            let result = fast.run(&mut cpu, &RunConfig::new().with_max_cycles(max_cycles));
+            (result, cpu.registers.peek(5), fast.stats().native_instructions)
+        };
+
+        let (interpreted, t0, _) = run(false, 10_000);
+        let (native, native_t0, translated) = run(true, 10_000);
+        assert_eq!((interpreted.reason, interpreted.exit_code), (StopReason::Exit, Some(7)));
+        assert_eq!(native, interpreted);
+        assert_eq!(native_t0, t0);
+        if JitCompiler::new().is_some() {
+            assert!(translated > 0);
+        }
+
+        // Cycle limit in the middle of the translated loop
+        assert_eq!(run(true, 100).0, run(false, 100).0);
+        assert_eq!(run(true, 100).1, run(false, 100).1);
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_device_polling_matches_cpu() {
+        use crate::dma::*;
+
+        // Poll a DMA channel until it is done, counting iterations in x28
+        let dma: Addr = 0x8000;
+        let status = (DMA_CHANNEL_BASE + DMA_STATUS) as i16;
+        let program = [
+            InstructionEncoder::u_type(0b0110111, 5, dma as i32),        // lui t0, dma
+            InstructionEncoder::i_type(0b0000011, 7, 0b010, 5, status), // loop: lw t2, STATUS(t0)
+            addi(28, 28, 1),
+            addi(29, 29, 3),
+            op_imm(7, 0b111, 7, DMA_STATUS_DONE as i16),
+            InstructionEncoder::b_type(0b1100011, 0b000, 7, 0, -16),    // beqz t2, loop
+            InstructionEncoder::j_type(0b1101111, 0, 0),
+        ];
+
+        let run = |jit: bool| {
+            let mut cpu = cpu_with(&program);
+            cpu.memory.map_device("dma", dma, DMA_SIZE, DmaController::new(1)).unwrap();
+            for (register, value) in [(DMA_SRC, 0x400), (DMA_DST, 0x800), (DMA_COUNT, 200)] {
+                cpu.memory.store(dma + DMA_CHANNEL_BASE + register, value, 0b010);
+            }
+            let control = DMA_CONTROL_START | (2 << DMA_WIDTH_SHIFT) | (3 << DMA_BURST_SHIFT);
+            cpu.memory.store(dma + DMA_CHANNEL_BASE + DMA_CONTROL, control, 0b010);
+
+            let mut fast = FastInterpreter::new().with_jit(jit);
+            let result = fast.run(&mut cpu, &RunConfig::new().with_max_cycles(100_000));
+            (result, cpu.get_cycle_count(), cpu.registers.peek(28), cpu.bus_stall_cycles(), fast.stats().native_instructions)
+        };
+
+        let (interpreted, cycles, polls, stalls, _) = run(false);
+        assert!(matches!(interpreted.reason, StopReason::SelfLoop(_)));
+        assert!(polls > 10 && stalls > 0);
+
+        let (native, native_cycles, native_polls, native_stalls, translated) = run(true);
+        assert!(translated > 0);
+        assert_eq!(native, interpreted);
+        assert_eq!((native_cycles, native_polls, native_stalls), (cycles, polls, stalls));
+    }
+}
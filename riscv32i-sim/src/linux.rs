@@ -0,0 +1,642 @@
+//! Linux user-mode syscall emulation
+//!
+//! `LinuxSyscalls` implements the subset of the RISC-V Linux ABI that
+//! statically linked newlib, picolibc and musl programs need. Syscall
+//! number in a7, arguments in a0-a5, result (or -errno) in a0.
+//!
+//! Syscall 62 is the kernel's `_llseek` (64-bit offset in two halves,
+//! result stored through a pointer) and `statx` serves musl's `stat` and
+//! `fstat`. `fstat` itself fills pk's 128-byte `struct kernel_stat`, as
+//! rv32 newlib (via libgloss/pk) expects.
+//!
+//! Files are sandboxed: `openat` and `statx` only reach paths under a host
+//! root directory. `..` components are refused, and so is a symlink that
+//! resolves outside the root. Without a root, every open fails with EACCES.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::*;
+use crate::cpu::Cpu;
+use crate::elf::ElfImage;
+use crate::syscall::{OutputCapture, SyscallAction, SyscallHandler, A0, A7};
+
+/// Syscall numbers (asm-generic/unistd.h)
+pub const SYS_IOCTL: Word = 29;
+pub const SYS_OPENAT: Word = 56;
+pub const SYS_CLOSE: Word = 57;
+pub const SYS_LLSEEK: Word = 62;
+pub const SYS_READ: Word = 63;
+pub const SYS_WRITE: Word = 64;
+pub const SYS_WRITEV: Word = 66;
+pub const SYS_FSTAT: Word = 80;
+pub const SYS_EXIT: Word = 93;
+pub const SYS_EXIT_GROUP: Word = 94;
+pub const SYS_SET_TID_ADDRESS: Word = 96;
+pub const SYS_CLOCK_GETTIME: Word = 113;
+pub const SYS_BRK: Word = 214;
+pub const SYS_MUNMAP: Word = 215;
+pub const SYS_MMAP: Word = 222;
+pub const SYS_STATX: Word = 291;
+pub const SYS_CLOCK_GETTIME64: Word = 403;
+
+/// errno values
+pub const ENOENT: i32 = 2;
+pub const EBADF: i32 = 9;
+pub const ENOMEM: i32 = 12;
+pub const EACCES: i32 = 13;
+pub const EINVAL: i32 = 22;
+pub const ENOTTY: i32 = 25;
+pub const ENOSYS: i32 = 38;
+
+/// openat flags
+const O_ACCMODE: Word = 0o3;
+const O_WRONLY: Word = 0o1;
+const O_RDWR: Word = 0o2;
+const O_CREAT: Word = 0o100;
+const O_TRUNC: Word = 0o1000;
+const O_APPEND: Word = 0o2000;
+
+const AT_EMPTY_PATH: Word = 0x1000;
+const STATX_BASIC_STATS: Word = 0x7FF;
+
+const MAP_ANONYMOUS: Word = 0x20;
+const PAGE_SIZE: Addr = 4096;
+
+/// Stack reserved below the top of memory (mmap allocates below it)
+pub const DEFAULT_STACK_SIZE: Addr = 64 * 1024;
+
+/// Auxiliary vector tags
+const AT_NULL: Word = 0;
+const AT_PHDR: Word = 3;
+const AT_PHENT: Word = 4;
+const AT_PHNUM: Word = 5;
+const AT_PAGESZ: Word = 6;
+const AT_ENTRY: Word = 9;
+const AT_RANDOM: Word = 25;
+
+/// Size of an ELF32 program header
+const PHDR_SIZE: Word = 32;
+
+/// File mode bits for fstat
+const S_IFCHR: Word = 0o020000;
+const S_IFDIR: Word = 0o040000;
+const S_IFREG: Word = 0o100000;
+
+type SyscallResult = Result<Word, i32>;
+
+/// Linux syscall personality
+pub struct LinuxSyscalls {
+    stdin: Box<dyn Read + Send>,
+    stdout: Box<dyn Write + Send>,
+    stderr: Box<dyn Write + Send>,
+    root: Option<PathBuf>,
+    files: HashMap<Word, File>,
+    next_fd: Word,
+    initial_brk: Option<Addr>,
+    brk: Option<Addr>,
+    mmap_top: Option<Addr>,
+}
+
+impl LinuxSyscalls {
+    pub fn new(
+        stdin: impl Read + Send + 'static,
+        stdout: impl Write + Send + 'static,
+        stderr: impl Write + Send + 'static,
+    ) -> Self {
+        Self {
+            stdin: Box::new(stdin),
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            root: None,
+            files: HashMap::new(),
+            next_fd: 3,
+            initial_brk: None,
+            brk: None,
+            mmap_top: None,
+        }
+    }
+
+    /// Host stdin/stdout/stderr
+    pub fn stdio() -> Self {
+        Self::new(io::stdin(), io::stdout(), io::stderr())
+    }
+
+    /// Fixed stdin; stdout and stderr both collected in the returned buffer
+    pub fn captured(input: &str) -> (Self, OutputCapture) {
+        let capture = OutputCapture::new();
+        let handler = Self::new(io::Cursor::new(input.as_bytes().to_vec()), capture.clone(), capture.clone());
+        (handler, capture)
+    }
+
+    /// Host directory that guest paths resolve under
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Initial program break - normally the end of the loaded image
+    /// Defaults to the middle of memory
+    pub fn with_program_break(mut self, addr: Addr) -> Self {
+        self.initial_brk = Some(addr);
+        self.brk = Some(addr);
+        self
+    }
+
+    /// Anonymous mmap allocates downwards from here
+    /// Defaults to just below the stack reserved at the top of memory
+    pub fn with_mmap_top(mut self, addr: Addr) -> Self {
+        self.mmap_top = Some(addr);
+        self
+    }
+
+    /// Host path for a guest path, refusing anything that leaves the root
+    fn resolve(&self, path: &str) -> Result<PathBuf, i32> {
+        let root = self.root.as_ref().ok_or(EACCES)?.canonicalize().map_err(|e| host_errno(&e))?;
+        let mut resolved = root.clone();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => return Err(EACCES),
+            }
+        }
+
+        // Symlinks are followed on the host, so check where they lead
+        let host_path = match resolved.canonicalize() {
+            Ok(path) => path,
+            // A dangling symlink could be created through
+            Err(_) if resolved.symlink_metadata().is_ok() => return Err(EACCES),
+            // Not there yet (O_CREAT): its directory has to be inside
+            Err(_) => {
+                let parent = resolved.parent().unwrap_or(&root).canonicalize().map_err(|e| host_errno(&e))?;
+                resolved.file_name().map_or(parent.clone(), |name| parent.join(name))
+            }
+        };
+        if !host_path.starts_with(&root) {
+            return Err(EACCES);
+        }
+        Ok(host_path)
+    }
+
+    fn openat(&mut self, cpu: &Cpu, path: Addr, flags: Word) -> SyscallResult {
+        let path = String::from_utf8_lossy(&cpu.memory.read_cstring(path, PAGE_SIZE as usize)).into_owned();
+        let host_path = self.resolve(&path)?;
+
+        let mut options = OpenOptions::new();
+        match flags & O_ACCMODE {
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => options.read(true),
+        };
+        options
+            .create(flags & O_CREAT != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .append(flags & O_APPEND != 0);
+
+        let file = options.open(host_path).map_err(|e| host_errno(&e))?;
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    fn read(&mut self, cpu: &mut Cpu, fd: Word, buf: Addr, count: Word) -> SyscallResult {
+        let mut data = vec![0u8; (count as usize).min(cpu.memory.size())];
+        let n = match fd {
+            0 => self.stdin.read(&mut data),
+            1 | 2 => return Err(EBADF),
+            _ => self.files.get_mut(&fd).ok_or(EBADF)?.read(&mut data),
+        }
+        .map_err(|e| host_errno(&e))?;
+
+        cpu.memory.write_bytes(buf, &data[..n]);
+        Ok(n as Word)
+    }
+
+    fn write(&mut self, fd: Word, data: &[u8]) -> SyscallResult {
+        let result = match fd {
+            0 => return Err(EBADF),
+            1 => self.stdout.write_all(data).and_then(|_| self.stdout.flush()),
+            2 => self.stderr.write_all(data).and_then(|_| self.stderr.flush()),
+            _ => self.files.get_mut(&fd).ok_or(EBADF)?.write_all(data),
+        };
+        result.map_err(|e| host_errno(&e))?;
+        Ok(data.len() as Word)
+    }
+
+    /// writev - iovec is { base: u32, len: u32 }
+    fn writev(&mut self, cpu: &Cpu, fd: Word, iov: Addr, count: Word) -> SyscallResult {
+        let mut data = Vec::new();
+        for i in 0..count {
+            let entry = iov.wrapping_add(i * 8);
+            let base = read_word(cpu, entry);
+            let len = read_word(cpu, entry.wrapping_add(4));
+            data.extend(cpu.memory.read_bytes(base, (len as usize).min(cpu.memory.size())));
+        }
+        self.write(fd, &data)
+    }
+
+    /// _llseek(fd, offset_high, offset_low, result, whence) - the new
+    /// 64-bit position is stored at `result`
+    fn llseek(&mut self, cpu: &mut Cpu, fd: Word, high: Word, low: Word, result: Addr, whence: Word) -> SyscallResult {
+        let offset = ((high as u64) << 32 | low as u64) as i64;
+        let file = self.files.get_mut(&fd).ok_or(EBADF)?;
+        let position = match whence {
+            0 => SeekFrom::Start(u64::try_from(offset).map_err(|_| EINVAL)?),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(EINVAL),
+        };
+        let new = file.seek(position).map_err(|e| host_errno(&e))?;
+        cpu.memory.write_bytes(result, &new.to_le_bytes());
+        Ok(0)
+    }
+
+    /// (mode, size, mtime) of an open file
+    fn fd_info(&self, fd: Word) -> Result<(Word, u64, u64), i32> {
+        match fd {
+            0..=2 => Ok((S_IFCHR | 0o620, 0, 0)),
+            _ => {
+                let metadata = self.files.get(&fd).ok_or(EBADF)?.metadata().map_err(|e| host_errno(&e))?;
+                Ok(file_info(&metadata))
+            }
+        }
+    }
+
+    /// fstat into pk/newlib `struct kernel_stat` (128 bytes)
+    fn fstat(&mut self, cpu: &mut Cpu, fd: Word, buf: Addr) -> SyscallResult {
+        let (mode, size, mtime) = self.fd_info(fd)?;
+
+        let mut stat = [0u8; 128];
+        stat[16..20].copy_from_slice(&mode.to_le_bytes());       // st_mode
+        stat[20..24].copy_from_slice(&1u32.to_le_bytes());       // st_nlink
+        stat[48..56].copy_from_slice(&size.to_le_bytes());       // st_size
+        stat[56..60].copy_from_slice(&PAGE_SIZE.to_le_bytes());  // st_blksize
+        stat[64..72].copy_from_slice(&size.div_ceil(512).to_le_bytes());  // st_blocks
+        for time in [72, 88, 104] {
+            stat[time..time + 8].copy_from_slice(&mtime.to_le_bytes());  // st_atim/mtim/ctim
+        }
+        cpu.memory.write_bytes(buf, &stat);
+        Ok(0)
+    }
+
+    /// statx into `struct statx` (256 bytes) - a path under the root, or
+    /// `fd` itself for an empty path with AT_EMPTY_PATH
+    fn statx(&mut self, cpu: &mut Cpu, fd: Word, path: Addr, flags: Word, buf: Addr) -> SyscallResult {
+        let path = String::from_utf8_lossy(&cpu.memory.read_cstring(path, PAGE_SIZE as usize)).into_owned();
+        let (mode, size, mtime) = if !path.is_empty() {
+            file_info(&std::fs::metadata(self.resolve(&path)?).map_err(|e| host_errno(&e))?)
+        } else if flags & AT_EMPTY_PATH != 0 {
+            self.fd_info(fd)?
+        } else {
+            return Err(ENOENT);
+        };
+
+        let mut stat = [0u8; 256];
+        stat[0..4].copy_from_slice(&STATX_BASIC_STATS.to_le_bytes());  // stx_mask
+        stat[4..8].copy_from_slice(&PAGE_SIZE.to_le_bytes());          // stx_blksize
+        stat[16..20].copy_from_slice(&1u32.to_le_bytes());             // stx_nlink
+        stat[28..30].copy_from_slice(&(mode as u16).to_le_bytes());    // stx_mode
+        stat[40..48].copy_from_slice(&size.to_le_bytes());             // stx_size
+        stat[48..56].copy_from_slice(&size.div_ceil(512).to_le_bytes());  // stx_blocks
+        for time in [64, 96, 112] {
+            stat[time..time + 8].copy_from_slice(&mtime.to_le_bytes());  // stx_atime/ctime/mtime
+        }
+        cpu.memory.write_bytes(buf, &stat);
+        Ok(0)
+    }
+
+    /// brk - returns the (possibly unchanged) break, never an error
+    fn brk(&mut self, cpu: &Cpu, addr: Addr) -> Word {
+        let initial = *self.initial_brk.get_or_insert(cpu.memory.base().wrapping_add((cpu.memory.size() / 2) as Addr));
+        let current = self.brk.unwrap_or(initial);
+        if addr >= initial && addr <= self.mmap_top(cpu) {
+            self.brk = Some(addr);
+            addr
+        } else {
+            current
+        }
+    }
+
+    fn mmap_top(&mut self, cpu: &Cpu) -> Addr {
+        *self.mmap_top.get_or_insert(cpu.memory.end().saturating_sub(DEFAULT_STACK_SIZE as u64) as Addr)
+    }
+
+    /// Anonymous mappings only, carved downwards from mmap_top
+    fn mmap(&mut self, cpu: &mut Cpu, len: Word, flags: Word) -> SyscallResult {
+        if flags & MAP_ANONYMOUS == 0 {
+            return Err(ENOSYS);
+        }
+        if len == 0 {
+            return Err(EINVAL);
+        }
+
+        let len = len.checked_next_multiple_of(PAGE_SIZE).ok_or(ENOMEM)?;
+        let top = self.mmap_top(cpu);
+        let brk = self.brk.or(self.initial_brk).unwrap_or(0);
+        let base = top.checked_sub(len).filter(|&base| base >= brk).ok_or(ENOMEM)?;
+
+        cpu.memory.write_bytes(base, &vec![0; len as usize]);
+        self.mmap_top = Some(base);
+        Ok(base)
+    }
+
+    /// clock_gettime into a 64-bit-time timespec { tv_sec: i64, tv_nsec: i32 }
+    fn clock_gettime(&mut self, cpu: &mut Cpu, buf: Addr) -> SyscallResult {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        cpu.memory.write_bytes(buf, &now.as_secs().to_le_bytes());
+        cpu.memory.write_bytes(buf.wrapping_add(8), &now.subsec_nanos().to_le_bytes());
+        Ok(0)
+    }
+}
+
+impl SyscallHandler for LinuxSyscalls {
+    fn ecall(&mut self, cpu: &mut Cpu) -> SyscallAction {
+        let args: [Word; 6] = std::array::from_fn(|i| cpu.registers.peek(A0 + i as u8));
+
+        let result = match cpu.registers.peek(A7) {
+            SYS_EXIT | SYS_EXIT_GROUP => return SyscallAction::Exit(args[0] as i32),
+            SYS_READ => self.read(cpu, args[0], args[1], args[2]),
+            SYS_WRITE => {
+                let data = cpu.memory.read_bytes(args[1], (args[2] as usize).min(cpu.memory.size()));
+                self.write(args[0], &data)
+            }
+            SYS_WRITEV => self.writev(cpu, args[0], args[1], args[2]),
+            SYS_OPENAT => self.openat(cpu, args[1], args[2]),
+            SYS_CLOSE => match args[0] {
+                0..=2 => Ok(0),
+                fd => self.files.remove(&fd).map(|_| 0).ok_or(EBADF),
+            },
+            SYS_LLSEEK => self.llseek(cpu, args[0], args[1], args[2], args[3], args[4]),
+            SYS_FSTAT => self.fstat(cpu, args[0], args[1]),
+            SYS_STATX => self.statx(cpu, args[0], args[1], args[2], args[4]),
+            SYS_BRK => Ok(self.brk(cpu, args[0])),
+            SYS_MMAP => self.mmap(cpu, args[1], args[3]),
+            SYS_MUNMAP => Ok(0),
+            SYS_CLOCK_GETTIME | SYS_CLOCK_GETTIME64 => self.clock_gettime(cpu, args[1]),
+            SYS_SET_TID_ADDRESS => Ok(1),
+            SYS_IOCTL => Err(ENOTTY),
+            _ => Err(ENOSYS),
+        };
+
+        let value = result.unwrap_or_else(|errno| (-errno) as Word);
+        cpu.registers.poke(A0, value);
+        SyscallAction::Continue
+    }
+
+    fn reset(&mut self) {
+        self.files.clear();
+        self.next_fd = 3;
+        self.brk = self.initial_brk;
+    }
+}
+
+fn read_word(cpu: &Cpu, addr: Addr) -> Word {
+    let bytes = cpu.memory.read_bytes(addr, 4);
+    Word::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// (mode, size, mtime) for fstat and statx
+fn file_info(metadata: &std::fs::Metadata) -> (Word, u64, u64) {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let mode = if metadata.is_dir() { S_IFDIR | 0o755 } else { S_IFREG | 0o644 };
+    (mode, metadata.len(), mtime)
+}
+
+fn host_errno(error: &io::Error) -> i32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        _ => error.raw_os_error().unwrap_or(EINVAL),
+    }
+}
+
+/// Build the initial process stack at the top of memory and point sp at it
+///
+/// Layout from sp upwards: argc, argv[] + NULL, envp[] + NULL,
+/// auxv pairs (AT_PAGESZ, AT_RANDOM, AT_NULL), then the strings and the
+/// 16 AT_RANDOM bytes. sp is 16-byte aligned. Returns sp.
+pub fn setup_stack(cpu: &mut Cpu, args: &[&str], env: &[&str]) -> Addr {
+    build_stack(cpu, args, env, &[])
+}
+
+/// `setup_stack` for a loaded executable: the auxv also gives its entry
+/// point and program headers (AT_ENTRY, AT_PHDR, AT_PHENT, AT_PHNUM), as
+/// musl's static startup needs
+pub fn setup_process_stack(cpu: &mut Cpu, image: &ElfImage, args: &[&str], env: &[&str]) -> Addr {
+    let mut auxv = vec![(AT_ENTRY, image.entry)];
+    if let Some(phdr) = image.program_headers {
+        auxv.extend([(AT_PHDR, phdr), (AT_PHENT, PHDR_SIZE), (AT_PHNUM, image.program_header_count as Word)]);
+    }
+    build_stack(cpu, args, env, &auxv)
+}
+
+fn build_stack(cpu: &mut Cpu, args: &[&str], env: &[&str], auxv: &[(Word, Word)]) -> Addr {
+    // Wrapping arithmetic: a memory ending at 4 GiB has top = 0
+    let mut top = (cpu.memory.end() as Addr) & !0xF;
+
+    // Strings and random bytes at the very top
+    let mut place = |bytes: &[u8]| {
+        top = top.wrapping_sub(bytes.len() as Addr);
+        cpu.memory.write_bytes(top, bytes);
+        top
+    };
+    let random = place(&[0x5A; 16]);
+    let env_ptrs: Vec<Addr> = env.iter().map(|s| place(&[s.as_bytes(), &[0]].concat())).collect();
+    let arg_ptrs: Vec<Addr> = args.iter().map(|s| place(&[s.as_bytes(), &[0]].concat())).collect();
+
+    let mut words = vec![args.len() as Word];
+    words.extend(&arg_ptrs);
+    words.push(0);
+    words.extend(&env_ptrs);
+    words.push(0);
+    words.extend(auxv.iter().flat_map(|&(tag, value)| [tag, value]));
+    words.extend([AT_PAGESZ, PAGE_SIZE, AT_RANDOM, random, AT_NULL, 0]);
+
+    let sp = top.wrapping_sub(4 * words.len() as Addr) & !0xF;
+    for (i, word) in words.iter().enumerate() {
+        cpu.memory.write_bytes(sp.wrapping_add(4 * i as Addr), &word.to_le_bytes());
+    }
+    cpu.registers.poke(2, sp);
+    sp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+    use crate::run::{RunConfig, StopReason};
+    use crate::syscall::A1;
+    use crate::test_util::{addi, cpu_with_memory};
+
+    const ECALL: Word = 0x0000_0073;
+
+    /// CPU with 256 KiB of memory running `program` under `handler`
+    fn linux_cpu(program: &[Word], handler: LinuxSyscalls) -> Cpu {
+        let mut cpu = cpu_with_memory(Memory::with_size(256 * 1024), program);
+        cpu.set_syscall_handler(Box::new(handler));
+        cpu
+    }
+
+    #[test]
+    fn test_write_brk_and_exit_group() {
+        let program = [
+            addi(A0, 0, 1), addi(A1, 0, 0x400), addi(12, 0, 6), addi(A7, 0, SYS_WRITE as i16), ECALL,
+            addi(A0, 0, 0), addi(A7, 0, SYS_BRK as i16), ECALL,           // brk(0) -> current
+            addi(5, A0, 0),
+            addi(A0, A0, 64), addi(A7, 0, SYS_BRK as i16), ECALL,         // brk(cur + 64)
+            addi(A7, 0, 999), ECALL,                                      // unknown -> -ENOSYS
+            addi(6, A0, 0),
+            addi(A0, 0, 42), addi(A7, 0, SYS_EXIT_GROUP as i16), ECALL,
+        ];
+        let (handler, output) = LinuxSyscalls::captured("");
+        let mut cpu = linux_cpu(&program, handler.with_program_break(0x1000));
+        cpu.memory.write_bytes(0x400, b"hello\n");
+
+        let result = cpu.run(&RunConfig::new().with_max_cycles(100));
+
+        assert_eq!((result.reason, result.exit_code), (StopReason::Exit, Some(42)));
+        assert_eq!(output.contents(), "hello\n");
+        assert_eq!(cpu.registers.peek(5), 0x1000);
+        assert_eq!(cpu.registers.peek(6) as i32, -ENOSYS);
+    }
+
+    #[test]
+    fn test_openat_reads_sandboxed_file() {
+        let root = std::env::temp_dir().join(format!("riscv-linux-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("data.txt"), b"sandboxed").unwrap();
+
+        let program = [
+            addi(A0, 0, -100), addi(A1, 0, 0x400), addi(12, 0, 0), addi(A7, 0, SYS_OPENAT as i16), ECALL,
+            addi(8, A0, 0),                                               // s0 = fd
+            addi(A0, 8, 0), addi(A1, 0, 0x500), addi(12, 0, 32), addi(A7, 0, SYS_READ as i16), ECALL,
+            addi(9, A0, 0),                                               // s1 = bytes read
+            addi(A0, 0, -100), addi(A1, 0, 0x440), addi(12, 0, 0), addi(A7, 0, SYS_OPENAT as i16), ECALL,
+            addi(A7, 0, SYS_EXIT as i16), ECALL,
+        ];
+        let (handler, _) = LinuxSyscalls::captured("");
+        let mut cpu = linux_cpu(&program, handler.with_root(&root));
+        cpu.memory.write_bytes(0x400, b"/data.txt\0");
+        cpu.memory.write_bytes(0x440, b"../escape\0");
+
+        let result = cpu.run(&RunConfig::new().with_max_cycles(100));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(cpu.registers.peek(8), 3);
+        assert_eq!(cpu.registers.peek(9), 9);
+        assert_eq!(cpu.memory.read_bytes(0x500, 9), b"sandboxed");
+        assert_eq!(result.exit_code, Some(-EACCES));
+    }
+
+    #[test]
+    fn test_setup_stack_layout() {
+        let mut cpu = Cpu::new();
+        let sp = setup_stack(&mut cpu, &["prog", "-v"], &["HOME=/"]);
+
+        assert_eq!(sp % 16, 0);
+        assert_eq!(cpu.registers.peek(2), sp);
+        assert_eq!(read_word(&cpu, sp), 2);
+        assert_eq!(cpu.memory.read_cstring(read_word(&cpu, sp + 4), 16), b"prog");
+        assert_eq!(cpu.memory.read_cstring(read_word(&cpu, sp + 8), 16), b"-v");
+        assert_eq!(read_word(&cpu, sp + 12), 0);
+        assert_eq!(cpu.memory.read_cstring(read_word(&cpu, sp + 16), 16), b"HOME=/");
+        assert_eq!(read_word(&cpu, sp + 20), 0);
+        assert_eq!([read_word(&cpu, sp + 24), read_word(&cpu, sp + 28)], [AT_PAGESZ, PAGE_SIZE]);
+    }
+
+    #[test]
+    fn test_process_stack_auxv() {
+        let mut cpu = Cpu::new();
+        let image = ElfImage {
+            entry: 0x100,
+            segments: Vec::new(),
+            symbols: crate::symbols::SymbolTable::new(),
+            global_pointer: None,
+            tohost: None,
+            fromhost: None,
+            program_headers: Some(0x34),
+            program_header_count: 2,
+        };
+        let sp = setup_process_stack(&mut cpu, &image, &["prog"], &[]);
+
+        let auxv: Vec<Word> = (0..8).map(|i| read_word(&cpu, sp + 16 + 4 * i)).collect();
+        assert_eq!(auxv, [AT_ENTRY, 0x100, AT_PHDR, 0x34, AT_PHENT, PHDR_SIZE, AT_PHNUM, 2]);
+        assert_eq!([read_word(&cpu, sp + 48), read_word(&cpu, sp + 52)], [AT_PAGESZ, PAGE_SIZE]);
+    }
+
+    /// Run one syscall directly - returns a0
+    fn syscall(cpu: &mut Cpu, handler: &mut LinuxSyscalls, number: Word, args: &[Word]) -> i32 {
+        for (i, &arg) in args.iter().enumerate() {
+            cpu.registers.poke(A0 + i as u8, arg);
+        }
+        cpu.registers.poke(A7, number);
+        handler.ecall(cpu);
+        cpu.registers.peek(A0) as i32
+    }
+
+    #[test]
+    fn test_llseek_statx_and_symlinks() {
+        let dir = std::env::temp_dir().join(format!("riscv-linux-statx-{}", std::process::id()));
+        let root = dir.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("data.txt"), b"0123456789").unwrap();
+        std::fs::write(dir.join("secret"), b"outside").unwrap();
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("secret"), root.join("escape")).unwrap();
+            std::os::unix::fs::symlink(dir.join("missing"), root.join("dangling")).unwrap();
+            std::os::unix::fs::symlink("data.txt", root.join("inner")).unwrap();
+        }
+
+        let (handler, _) = LinuxSyscalls::captured("");
+        let mut handler = handler.with_root(&root);
+        let mut cpu = Cpu::new();
+        let open = |cpu: &mut Cpu, handler: &mut LinuxSyscalls, path: &[u8], flags: Word| {
+            cpu.memory.write_bytes(0x400, path);
+            syscall(cpu, handler, SYS_OPENAT, &[-100i32 as Word, 0x400, flags])
+        };
+
+        let fd = open(&mut cpu, &mut handler, b"/data.txt\0", 0) as Word;
+        assert_eq!(syscall(&mut cpu, &mut handler, SYS_LLSEEK, &[fd, 0, 4, 0x600, 0]), 0);
+        assert_eq!(cpu.memory.read_bytes(0x600, 8), 4u64.to_le_bytes());
+        assert_eq!(syscall(&mut cpu, &mut handler, SYS_READ, &[fd, 0x700, 3]), 3);
+        assert_eq!(cpu.memory.read_bytes(0x700, 3), b"456");
+        // Offset -2 from the end, split into high and low words
+        assert_eq!(syscall(&mut cpu, &mut handler, SYS_LLSEEK, &[fd, Word::MAX, -2i32 as Word, 0x600, 2]), 0);
+        assert_eq!(cpu.memory.read_bytes(0x600, 8), 8u64.to_le_bytes());
+        assert_eq!(syscall(&mut cpu, &mut handler, SYS_LLSEEK, &[fd, 0, 0, 0x600, 7]), -EINVAL);
+
+        // By path, and for the open fd with AT_EMPTY_PATH
+        cpu.memory.write_bytes(0x400, b"data.txt\0");
+        assert_eq!(syscall(&mut cpu, &mut handler, SYS_STATX, &[-100i32 as Word, 0x400, 0, 0x7FF, 0x800]), 0);
+        assert_eq!(cpu.memory.read_bytes(0x800 + 40, 8), 10u64.to_le_bytes());
+        assert_eq!(read_word(&cpu, 0x800 + 28) & 0xF000, S_IFREG);
+        cpu.memory.write_bytes(0x400, b"\0");
+        assert_eq!(syscall(&mut cpu, &mut handler, SYS_STATX, &[fd, 0x400, AT_EMPTY_PATH, 0x7FF, 0x900]), 0);
+        assert_eq!(cpu.memory.read_bytes(0x900 + 40, 8), 10u64.to_le_bytes());
+        assert_eq!(syscall(&mut cpu, &mut handler, SYS_STATX, &[fd, 0x400, 0, 0x7FF, 0x900]), -ENOENT);
+
+        // New files are fine, symlinks out of the root are not
+        assert!(open(&mut cpu, &mut handler, b"/new.txt\0", O_CREAT | O_WRONLY) > 0);
+        #[cfg(unix)]
+        {
+            assert!(open(&mut cpu, &mut handler, b"/inner\0", 0) > 0);
+            assert_eq!(open(&mut cpu, &mut handler, b"/escape\0", 0), -EACCES);
+            assert_eq!(open(&mut cpu, &mut handler, b"/dangling\0", O_CREAT | O_WRONLY), -EACCES);
+            cpu.memory.write_bytes(0x400, b"escape\0");
+            assert_eq!(syscall(&mut cpu, &mut handler, SYS_STATX, &[-100i32 as Word, 0x400, 0, 0x7FF, 0x800]), -EACCES);
+        }
+        let created = dir.join("missing").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!created);
+    }
+}
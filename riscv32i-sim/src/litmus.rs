@@ -0,0 +1,444 @@
+//! Memory consistency model explorer for litmus tests
+//!
+//! Runs small multi-hart programs over a shared memory and enumerates
+//! every interleaving permitted by a memory model, reporting which final
+//! outcomes are observable.
+//!
+//! Each hart executes on the regular `Cpu` datapath; its stores are
+//! intercepted into a per-hart store buffer instead of reaching shared
+//! memory directly:
+//! - `SequentialConsistency`: no store buffer, stores commit immediately
+//! - `Tso` (Ztso): FIFO store buffer, loads forward from their own buffer
+//! - `Rvwmo`: stores to different addresses may also commit out of order
+//!
+//! FENCE with predecessor W and successor R waits for the store buffer to
+//! drain; FENCE W,W orders the stores before it ahead of the stores after
+//! it. AMOs drain the store buffer and act atomically on shared memory.
+//!
+//! Loads always perform in program order, so RVWMO relaxations that
+//! reorder loads with each other are not modeled. LR/SC is not supported.
+//! Litmus data must live above the code of every hart (code starts at 0).
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::types::*;
+use crate::cpu::Cpu;
+use crate::memory::{Memory, AccessKind};
+
+/// Memory consistency model used for exploration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryModel {
+    SequentialConsistency,
+    Tso,
+    Rvwmo,
+}
+
+/// A litmus test: initial memory, one program per hart, observed locations
+#[derive(Debug, Clone)]
+pub struct LitmusTest {
+    pub name: String,
+    pub init: Vec<(Addr, Word)>,
+    pub harts: Vec<Vec<Word>>,
+    pub observe_regs: Vec<(usize, u8)>,
+    pub observe_mem: Vec<Addr>,
+}
+
+impl LitmusTest {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            init: Vec::new(),
+            harts: Vec::new(),
+            observe_regs: Vec::new(),
+            observe_mem: Vec::new(),
+        }
+    }
+
+    /// Initial value of a shared word (defaults to 0)
+    pub fn init(mut self, addr: Addr, value: Word) -> Self {
+        self.init.push((addr, value));
+        self
+    }
+
+    /// Add a hart running `program` from address 0
+    pub fn hart(mut self, program: Vec<Word>) -> Self {
+        self.harts.push(program);
+        self
+    }
+
+    /// Report register `reg` of `hart` in every outcome
+    pub fn observe_reg(mut self, hart: usize, reg: u8) -> Self {
+        self.observe_regs.push((hart, reg));
+        self
+    }
+
+    /// Report shared word `addr` in every outcome
+    pub fn observe_mem(mut self, addr: Addr) -> Self {
+        self.observe_mem.push(addr);
+        self
+    }
+
+    /// SB (store buffering): can both harts read the old value?
+    /// Observable without fences under TSO and RVWMO, never under SC.
+    pub fn store_buffering(fenced: bool) -> Self {
+        let (x, y) = (0x100, 0x104);
+        let hart = |store: i16, load: i16| {
+            let mut program = vec![
+                InstructionEncoder::addi(5, 0, 1),
+                InstructionEncoder::sw(0, 5, store),
+            ];
+            if fenced {
+                program.push(fence(0b0011, 0b0011));  // fence rw,rw
+            }
+            program.push(InstructionEncoder::lw(10, 0, load));
+            program
+        };
+
+        Self::new(if fenced { "SB+fences" } else { "SB" })
+            .hart(hart(x, y))
+            .hart(hart(y, x))
+            .observe_reg(0, 10)
+            .observe_reg(1, 10)
+    }
+
+    /// MP (message passing): can the reader see the flag but stale data?
+    /// Observable without fences under RVWMO only.
+    pub fn message_passing(fenced: bool) -> Self {
+        let (data, flag) = (0x100, 0x104);
+        let mut writer = vec![
+            InstructionEncoder::addi(5, 0, 42),
+            InstructionEncoder::sw(0, 5, data),
+            InstructionEncoder::addi(6, 0, 1),
+        ];
+        if fenced {
+            writer.push(fence(0b0001, 0b0001));  // fence w,w
+        }
+        writer.push(InstructionEncoder::sw(0, 6, flag));
+
+        let mut reader = vec![InstructionEncoder::lw(10, 0, flag)];
+        if fenced {
+            reader.push(fence(0b0010, 0b0010));  // fence r,r
+        }
+        reader.push(InstructionEncoder::lw(11, 0, data));
+
+        Self::new(if fenced { "MP+fences" } else { "MP" })
+            .hart(writer)
+            .hart(reader)
+            .observe_reg(1, 10)
+            .observe_reg(1, 11)
+    }
+}
+
+/// FENCE pred, succ (each a 4-bit I/O/R/W set)
+pub fn fence(pred: u8, succ: u8) -> Word {
+    (((pred & 0xF) as Word) << 24) | (((succ & 0xF) as Word) << 20) | 0b0001111
+}
+
+/// Final values of the observed registers and memory words
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Outcome {
+    pub regs: BTreeMap<(usize, u8), Word>,
+    pub mem: BTreeMap<Addr, Word>,
+}
+
+impl Outcome {
+    pub fn reg(&self, hart: usize, reg: u8) -> Option<Word> {
+        self.regs.get(&(hart, reg)).copied()
+    }
+
+    pub fn mem(&self, addr: Addr) -> Option<Word> {
+        self.mem.get(&addr).copied()
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        for (&(hart, reg), value) in &self.regs {
+            parts.push(format!("P{}:x{}={}", hart, reg, value));
+        }
+        for (addr, value) in &self.mem {
+            parts.push(format!("[0x{:X}]={}", addr, value));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Every outcome observable for one test under one model
+#[derive(Debug, Clone)]
+pub struct LitmusReport {
+    pub test: String,
+    pub model: MemoryModel,
+    pub outcomes: Vec<Outcome>,
+    pub states_explored: usize,
+    /// Exploration hit the state limit; outcomes may be incomplete
+    pub truncated: bool,
+}
+
+impl LitmusReport {
+    /// Is any observable outcome accepted by `condition`?
+    pub fn observable<F: Fn(&Outcome) -> bool>(&self, condition: F) -> bool {
+        self.outcomes.iter().any(condition)
+    }
+}
+
+impl fmt::Display for LitmusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Test {} under {:?}: {} outcomes ({} states{})",
+            self.test, self.model, self.outcomes.len(), self.states_explored,
+            if self.truncated { ", truncated" } else { "" })?;
+        for outcome in &self.outcomes {
+            writeln!(f, "  {}", outcome)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BufferedStore {
+    addr: Addr,
+    value: Word,
+    epoch: u32,  // FENCE W,W generation the store belongs to
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct HartState {
+    pc: Addr,
+    regs: [Word; 32],
+    epoch: u32,
+    buffer: Vec<BufferedStore>,  // Oldest first
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct State {
+    harts: Vec<HartState>,
+    memory: BTreeMap<Addr, Word>,
+}
+
+/// Exhaustive interleaving explorer
+pub struct LitmusExplorer {
+    model: MemoryModel,
+    max_states: usize,
+}
+
+impl LitmusExplorer {
+    pub fn new(model: MemoryModel) -> Self {
+        Self { model, max_states: 1_000_000 }
+    }
+
+    /// Stop exploring after this many distinct states
+    pub fn with_max_states(mut self, max_states: usize) -> Self {
+        self.max_states = max_states;
+        self
+    }
+
+    pub fn run(&self, test: &LitmusTest) -> LitmusReport {
+        let code_end = test.harts.iter().map(|program| 4 * program.len() as Addr).max().unwrap_or(0);
+        for &(addr, _) in &test.init {
+            assert!(addr >= code_end, "litmus data at 0x{:X} overlaps hart code", addr);
+        }
+
+        // One scratch core per hart, reloaded from the explored state each step
+        let mut cores: Vec<Cpu> = test.harts.iter().map(|_| Cpu::new()).collect();
+
+        let initial = State {
+            harts: test.harts.iter().map(|_| HartState {
+                pc: 0,
+                regs: [0; 32],
+                epoch: 0,
+                buffer: Vec::new(),
+            }).collect(),
+            memory: test.init.iter().map(|&(addr, value)| (addr & !0x3, value)).collect(),
+        };
+
+        let mut visited = HashSet::new();
+        let mut outcomes = Vec::new();
+        let mut pending = vec![initial];
+        let mut truncated = false;
+
+        while let Some(state) = pending.pop() {
+            if visited.contains(&state) {
+                continue;
+            }
+            if visited.len() >= self.max_states {
+                truncated = true;
+                break;
+            }
+
+            let successors = self.successors(test, &mut cores, &state);
+            if successors.is_empty() && self.is_final(test, &state) {
+                let outcome = Self::outcome(test, &state);
+                if !outcomes.contains(&outcome) {
+                    outcomes.push(outcome);
+                }
+            }
+            visited.insert(state);
+            pending.extend(successors);
+        }
+
+        outcomes.sort();
+        LitmusReport {
+            test: test.name.clone(),
+            model: self.model,
+            outcomes,
+            states_explored: visited.len(),
+            truncated,
+        }
+    }
+
+    fn is_final(&self, test: &LitmusTest, state: &State) -> bool {
+        state.harts.iter().zip(&test.harts)
+            .all(|(hart, program)| hart.pc >= 4 * program.len() as Addr && hart.buffer.is_empty())
+    }
+
+    fn outcome(test: &LitmusTest, state: &State) -> Outcome {
+        Outcome {
+            regs: test.observe_regs.iter()
+                .map(|&(hart, reg)| ((hart, reg), if reg == 0 { 0 } else { state.harts[hart].regs[reg as usize] }))
+                .collect(),
+            mem: test.observe_mem.iter()
+                .map(|&addr| (addr, state.memory.get(&(addr & !0x3)).copied().unwrap_or(0)))
+                .collect(),
+        }
+    }
+
+    /// All states reachable with one hart step or one store-buffer commit
+    fn successors(&self, test: &LitmusTest, cores: &mut [Cpu], state: &State) -> Vec<State> {
+        let mut next = Vec::new();
+
+        for (hart, core) in cores.iter_mut().enumerate() {
+            if let Some(stepped) = self.step_hart(test, core, state, hart) {
+                next.push(stepped);
+            }
+
+            let buffer = &state.harts[hart].buffer;
+            for index in 0..buffer.len() {
+                if self.may_commit(buffer, index) {
+                    let mut committed = state.clone();
+                    let store = committed.harts[hart].buffer.remove(index);
+                    committed.memory.insert(store.addr, store.value);
+                    next.push(committed);
+                }
+            }
+        }
+
+        next
+    }
+
+    /// Which buffered stores may reach memory next
+    fn may_commit(&self, buffer: &[BufferedStore], index: usize) -> bool {
+        match self.model {
+            MemoryModel::SequentialConsistency => false,
+            MemoryModel::Tso => index == 0,
+            MemoryModel::Rvwmo => {
+                let store = &buffer[index];
+                buffer[..index].iter().all(|older| older.addr != store.addr && older.epoch == store.epoch)
+            }
+        }
+    }
+
+    /// Execute the next instruction of `hart`, if it may run now
+    fn step_hart(&self, test: &LitmusTest, core: &mut Cpu, state: &State, hart: usize) -> Option<State> {
+        let program = &test.harts[hart];
+        let current = &state.harts[hart];
+        let index = (current.pc / 4) as usize;
+        if index >= program.len() {
+            return None;
+        }
+
+        // FENCE and AMO ordering against this hart's store buffer
+        let inst = Instruction::new(program[index]);
+        let drained = current.buffer.is_empty();
+        let mut new_epoch = false;
+        match inst.opcode() {
+            0b0001111 if inst.funct3() == 0 => {
+                let pred = (inst.raw >> 24) & 0xF;
+                let succ = (inst.raw >> 20) & 0xF;
+                let pred_w = pred & 0b0001 != 0;
+                if pred_w && succ & 0b0010 != 0 && !drained {
+                    return None;
+                }
+                new_epoch = pred_w && succ & 0b0001 != 0;
+            }
+            0b0101111 if !drained => return None,
+            _ => {}
+        }
+
+        // This hart's view of memory: shared memory plus its own buffered stores
+        let mut view = Memory::new();
+        view.load_words(0, program);
+        view.load_program(&state.memory.iter().map(|(&addr, &value)| (addr, value)).collect::<Vec<_>>());
+        view.load_program(&current.buffer.iter().map(|store| (store.addr, store.value)).collect::<Vec<_>>());
+
+        core.control.set_pc(current.pc);
+        for reg in 1..32u8 {
+            core.registers.poke(reg, current.regs[reg as usize]);
+        }
+        core.clock_with_memory(&mut view);
+
+        let mut next = state.clone();
+        let stepped = &mut next.harts[hart];
+        stepped.pc = core.control.get_pc();
+        for reg in 1..32u8 {
+            stepped.regs[reg as usize] = core.registers.peek(reg);
+        }
+        if new_epoch {
+            stepped.epoch += 1;
+        }
+
+        if let Some(access) = core.last_memory_access() {
+            let addr = access.addr & !0x3;
+            // Partial stores merge into the word as seen by this hart
+            let value = view.fetch(addr);
+            match access.kind {
+                AccessKind::Write if self.model != MemoryModel::SequentialConsistency => {
+                    let epoch = stepped.epoch;
+                    stepped.buffer.push(BufferedStore { addr, value, epoch });
+                }
+                AccessKind::Write | AccessKind::Atomic => {
+                    next.memory.insert(addr, value);
+                }
+                AccessKind::Read => {}
+            }
+        }
+
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn both_zero(report: &LitmusReport) -> bool {
+        report.observable(|o| o.reg(0, 10) == Some(0) && o.reg(1, 10) == Some(0))
+    }
+
+    fn stale_data(report: &LitmusReport) -> bool {
+        report.observable(|o| o.reg(1, 10) == Some(1) && o.reg(1, 11) == Some(0))
+    }
+
+    #[test]
+    fn test_store_buffering() {
+        let sb = LitmusTest::store_buffering(false);
+        assert!(!both_zero(&LitmusExplorer::new(MemoryModel::SequentialConsistency).run(&sb)));
+        assert!(both_zero(&LitmusExplorer::new(MemoryModel::Tso).run(&sb)));
+        assert!(both_zero(&LitmusExplorer::new(MemoryModel::Rvwmo).run(&sb)));
+
+        let fenced = LitmusTest::store_buffering(true);
+        assert!(!both_zero(&LitmusExplorer::new(MemoryModel::Rvwmo).run(&fenced)));
+    }
+
+    #[test]
+    fn test_message_passing() {
+        let mp = LitmusTest::message_passing(false);
+        assert!(!stale_data(&LitmusExplorer::new(MemoryModel::Tso).run(&mp)));
+        assert!(stale_data(&LitmusExplorer::new(MemoryModel::Rvwmo).run(&mp)));
+
+        let fenced = LitmusTest::message_passing(true);
+        let report = LitmusExplorer::new(MemoryModel::Rvwmo).run(&fenced);
+        assert!(!stale_data(&report));
+        assert!(!report.truncated);
+    }
+}
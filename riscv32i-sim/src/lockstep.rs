@@ -0,0 +1,323 @@
+//! Lockstep checking of `Cpu` against the golden model
+//!
+//! Both harts start from the same architectural state and are stepped one
+//! instruction at a time. After every step the PC, all 32 registers and
+//! the memory words either side stored to are compared, and the first
+//! difference is reported with the instruction that caused it.
+//!
+//! The golden model never drives the `Cpu`'s devices. After each `Cpu`
+//! step it is handed what they did: the value of a device load, RAM
+//! written by bus masters, and the external interrupt line. HTIF and test
+//! finisher exits end the run; run the `Cpu` without a syscall handler, as
+//! ECALL, EBREAK and illegal instructions stop the run instead.
+
+use std::fmt;
+
+use crate::types::*;
+use crate::cpu::{Cpu, CpuEvent};
+use crate::csr::{self, MIP_MEIP};
+use crate::golden::{Commit, GoldenModel};
+use crate::memory::AccessKind;
+use crate::run::{RunConfig, RunResult, RunState};
+
+/// Architectural state of one side at a divergence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchState {
+    pub pc: Addr,
+    pub regs: [Word; 32],
+}
+
+/// What differed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    Pc { cpu: Addr, golden: Addr },
+    Register { reg: u8, cpu: Word, golden: Word },
+    /// Word at `addr` after a store by either side
+    Memory { addr: Addr, cpu: Word, golden: Word },
+    Event { cpu: Option<CpuEvent>, golden: Option<CpuEvent> },
+}
+
+/// First divergence between `Cpu` and the golden model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Steps completed before the diverging one
+    pub step: u64,
+    pub pc: Addr,
+    pub instruction: Word,
+    pub mismatch: Mismatch,
+    pub cpu: ArchState,
+    pub golden: ArchState,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Divergence at step {}: pc 0x{:08x}, instruction 0x{:08x}", self.step, self.pc, self.instruction)?;
+        match self.mismatch {
+            Mismatch::Pc { cpu, golden } => writeln!(f, "  next pc: cpu 0x{:08x}, golden 0x{:08x}", cpu, golden)?,
+            Mismatch::Register { reg, cpu, golden } => {
+                writeln!(f, "  x{}: cpu 0x{:08x}, golden 0x{:08x}", reg, cpu, golden)?
+            }
+            Mismatch::Memory { addr, cpu, golden } => {
+                writeln!(f, "  mem[0x{:08x}]: cpu 0x{:08x}, golden 0x{:08x}", addr, cpu, golden)?
+            }
+            Mismatch::Event { cpu, golden } => writeln!(f, "  event: cpu {:?}, golden {:?}", cpu, golden)?,
+        }
+
+        writeln!(f, "  {:>4}  {:>10}  {:>10}", "", "cpu", "golden")?;
+        writeln!(f, "  {:>4}  0x{:08x}  0x{:08x}", "pc", self.cpu.pc, self.golden.pc)?;
+        for reg in 1..32 {
+            let (cpu, golden) = (self.cpu.regs[reg], self.golden.regs[reg]);
+            if cpu != 0 || golden != 0 {
+                let marker = if cpu != golden { " <" } else { "" };
+                writeln!(f, "  {:>4}  0x{:08x}  0x{:08x}{}", format!("x{}", reg), cpu, golden, marker)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a lockstep run ended without diverging
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockstepStop {
+    /// ECALL, EBREAK or illegal instruction with no trap handler, or an
+    /// HTIF / test finisher exit
+    Event(CpuEvent),
+    /// Jump to itself - the program has halted
+    SelfLoop(Addr),
+    StepLimit,
+}
+
+/// `Cpu` and golden model run side by side
+pub struct Lockstep {
+    pub cpu: Cpu,
+    pub golden: GoldenModel,
+    steps: u64,
+}
+
+impl Lockstep {
+    /// Check `cpu` from its current state
+    pub fn new(mut cpu: Cpu) -> Self {
+        let golden = GoldenModel::from_cpu(&cpu);
+        cpu.memory.track_dma_writes = true;
+        cpu.memory.dma_writes.clear();
+        Self { cpu, golden, steps: 0 }
+    }
+
+    /// Fresh `Cpu` (default memory) with `program` loaded at its addresses
+    pub fn with_program(program: &[(Addr, Word)]) -> Self {
+        let mut cpu = Cpu::new();
+        cpu.load_program(program);
+        Self::new(cpu)
+    }
+
+    /// Steps completed without divergence
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Step both harts once and compare them
+    pub fn step(&mut self) -> Result<Commit, Box<Divergence>> {
+        let pc = self.cpu.control.get_pc();
+        self.cpu.clock();
+        self.mirror_devices(pc);
+        let mut commit = self.golden.step();
+
+        // The golden model has no HTIF or test finisher to exit through
+        let host_exit = self.cpu.last_event().filter(|event| matches!(event, CpuEvent::Exit(_)));
+        if commit.event.is_none() {
+            commit.event = host_exit;
+        }
+
+        let cpu_pc = self.cpu.control.get_pc();
+        let mismatch = if cpu_pc != self.golden.pc {
+            Some(Mismatch::Pc { cpu: cpu_pc, golden: self.golden.pc })
+        } else if self.cpu.last_event() != commit.event {
+            Some(Mismatch::Event { cpu: self.cpu.last_event(), golden: commit.event })
+        } else {
+            self.compare_registers().or_else(|| self.compare_stores(&commit))
+        };
+
+        match mismatch {
+            Some(mismatch) => Err(Box::new(Divergence {
+                step: self.steps,
+                pc: commit.pc,
+                instruction: commit.instruction,
+                mismatch,
+                cpu: ArchState { pc: cpu_pc, regs: std::array::from_fn(|i| self.cpu.registers.peek(i as u8)) },
+                golden: ArchState { pc: self.golden.pc, regs: self.golden.regs },
+            })),
+            None => {
+                self.steps += 1;
+                Ok(commit)
+            }
+        }
+    }
+
+    /// Step until a divergence, an event, a self-loop or `max_steps`
+    pub fn run(&mut self, max_steps: u64) -> Result<LockstepStop, Box<Divergence>> {
+        for _ in 0..max_steps {
+            let commit = self.step()?;
+            if let Some(event) = commit.event {
+                return Ok(LockstepStop::Event(event));
+            }
+            if commit.instruction != 0 && commit.next_pc == commit.pc {
+                return Ok(LockstepStop::SelfLoop(commit.pc));
+            }
+        }
+        Ok(LockstepStop::StepLimit)
+    }
+
+    /// Hand the golden model what the devices did during the `Cpu` step
+    /// of the instruction at `pc`
+    fn mirror_devices(&mut self, pc: Addr) {
+        let access = self.cpu.last_memory_access();
+        if let Some(access) = access.filter(|a| a.kind != AccessKind::Write && self.cpu.memory.bus().is_mmio(a.addr)) {
+            let value = match access.kind {
+                AccessKind::Read => access.value,
+                // An AMO records the value it stored; the old one went to rd
+                _ => self.cpu.registers.peek(Instruction::new(self.cpu.memory.fetch(pc)).rd()),
+            };
+            self.golden.replay_device_load(value);
+        }
+        for (addr, len) in self.cpu.memory.take_dma_writes() {
+            let bytes = self.cpu.memory.read_bytes(addr, len);
+            self.golden.memory.write_bytes(addr, &bytes);
+        }
+        let meip = self.cpu.csrs.read(csr::MIP).unwrap_or(0) & MIP_MEIP != 0;
+        self.golden.csrs.set_pending(MIP_MEIP, meip);
+    }
+
+    /// Step until `config` stops the `Cpu` as it would stop `Cpu::run`, or
+    /// until the first divergence
+    pub fn run_with(&mut self, config: &RunConfig) -> Result<RunResult, Box<Divergence>> {
+        let mut state = RunState::new(&self.cpu);
+        loop {
+            if let Some(reason) = self.cpu.check_stop(config, &mut state) {
+                return Ok(self.cpu.finish(reason, None, &state));
+            }
+            let pc = self.cpu.control.get_pc();
+            let inst = Instruction::new(self.cpu.memory.fetch(pc));
+            self.step()?;
+            if let Some((reason, exit_code)) = self.cpu.stop_after(pc, inst, config) {
+                return Ok(self.cpu.finish(reason, exit_code, &state));
+            }
+        }
+    }
+
+    fn compare_registers(&self) -> Option<Mismatch> {
+        (1..32u8).find_map(|reg| {
+            let (cpu, golden) = (self.cpu.registers.peek(reg), self.golden.regs[reg as usize]);
+            (cpu != golden).then_some(Mismatch::Register { reg, cpu, golden })
+        })
+    }
+
+    /// Words touched by either side's store (two words for a misaligned one)
+    fn compare_stores(&self, commit: &Commit) -> Option<Mismatch> {
+        let cpu_store = self.cpu.last_memory_access().filter(|access| access.is_write()).map(|access| access.addr);
+        let golden_store = commit.mem_write.map(|(addr, _)| addr);
+
+        [cpu_store, golden_store]
+            .into_iter()
+            .flatten()
+            .flat_map(|addr| [addr & !0x3, addr.wrapping_add(3) & !0x3])
+            .find_map(|addr| {
+                let (cpu, golden) = (self.cpu.memory.fetch(addr), self.golden.memory.fetch(addr));
+                (cpu != golden).then_some(Mismatch::Memory { addr, cpu, golden })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::htif::TestFinisher;
+    use crate::test_util::cpu_with;
+    use crate::uart::{Uart16550, UART_SIZE};
+
+    #[test]
+    fn test_sized_loads_and_stores_agree() {
+        let words = [
+            InstructionEncoder::u_type(0b0110111, 1, 0x8765_4000u32 as i32),  // lui x1, 0x87654
+            InstructionEncoder::i_type(0b0010011, 1, 0b000, 1, 0x321),         // addi x1, x1, 0x321
+            InstructionEncoder::s_type(0b0100011, 0b010, 0, 1, 0x200),         // sw x1, 0x200(x0)
+            InstructionEncoder::s_type(0b0100011, 0b000, 0, 0, 0x201),         // sb x0, 0x201(x0)
+            InstructionEncoder::s_type(0b0100011, 0b001, 0, 1, 0x206),         // sh x1, 0x206(x0)
+            InstructionEncoder::i_type(0b0000011, 2, 0b000, 0, 0x203),         // lb x2, 0x203(x0)
+            InstructionEncoder::i_type(0b0000011, 3, 0b101, 0, 0x200),         // lhu x3, 0x200(x0)
+            InstructionEncoder::i_type(0b0000011, 4, 0b001, 0, 0x206),         // lh x4, 0x206(x0)
+            InstructionEncoder::s_type(0b0100011, 0b010, 0, 1, 0x20A),         // sw x1, 0x20a(x0) (misaligned)
+            InstructionEncoder::i_type(0b0000011, 5, 0b010, 0, 0x20A),         // lw x5, 0x20a(x0)
+            InstructionEncoder::j_type(0b1101111, 0, 0),
+        ];
+        let mut lockstep = Lockstep::new(cpu_with(&words));
+
+        assert_eq!(lockstep.run(100), Ok(LockstepStop::SelfLoop(40)));
+        let regs = &lockstep.golden.regs;
+        assert_eq!(regs[2], 0xFFFF_FF87);
+        assert_eq!(regs[3], 0x0021);
+        assert_eq!(regs[4], 0x4321);
+        assert_eq!(regs[5], 0x8765_4321);
+        assert_eq!(lockstep.cpu.memory.fetch(0x200), 0x8765_0021);
+    }
+
+    #[test]
+    fn test_reports_first_divergence() {
+        let words = [
+            InstructionEncoder::i_type(0b0010011, 1, 0b000, 0, 5),   // addi x1, x0, 5
+            InstructionEncoder::i_type(0b0010011, 2, 0b000, 1, 1),   // addi x2, x1, 1
+            0x0010_0073,                                             // ebreak
+        ];
+        let mut lockstep = Lockstep::new(cpu_with(&words));
+        lockstep.step().unwrap();
+
+        // Corrupt the golden side so the next step disagrees on x2
+        lockstep.golden.regs[1] = 6;
+        let divergence = lockstep.step().unwrap_err();
+        assert_eq!(divergence.step, 1);
+        assert_eq!(divergence.pc, 4);
+        assert_eq!(divergence.mismatch, Mismatch::Register { reg: 1, cpu: 5, golden: 6 });
+        assert!(divergence.to_string().contains("x2"));
+    }
+
+    #[test]
+    fn test_device_loads_replayed_and_host_exit() {
+        // Wait for THRE, send 'A', then pass through the test finisher
+        let mut cpu = cpu_with(&[
+            InstructionEncoder::u_type(0b0110111, 10, 0x2000),                 // lui a0, 0x2
+            InstructionEncoder::i_type(0b0000011, 5, 0b100, 10, 5),            // wait: lbu t0, LSR(a0)
+            InstructionEncoder::i_type(0b0010011, 5, 0b111, 5, 0x20),
+            InstructionEncoder::b_type(0b1100011, 0b000, 5, 0, -8),            // beqz t0, wait
+            InstructionEncoder::addi(6, 0, b'A' as i16),
+            InstructionEncoder::s_type(0b0100011, 0b000, 10, 6, 0),            // sb t1, THR(a0)
+            InstructionEncoder::u_type(0b0110111, 7, 0x10_0000),
+            InstructionEncoder::u_type(0b0110111, 8, 0x5000),
+            InstructionEncoder::addi(8, 8, 0x555),
+            InstructionEncoder::sw(7, 8, 0),                                   // exit(0)
+        ]);
+        let (uart, output) = Uart16550::new().captured();
+        cpu.memory.map_device("uart", 0x2000, UART_SIZE, uart).unwrap();
+        cpu.set_test_finisher(TestFinisher::new(0x10_0000));
+
+        let mut lockstep = Lockstep::new(cpu);
+        assert_eq!(lockstep.run(100), Ok(LockstepStop::Event(CpuEvent::Exit(0))));
+        assert_eq!(lockstep.golden.regs[5], 0x20);
+        assert_eq!(output.contents(), "A");
+    }
+
+    #[test]
+    fn test_run_with_stops_like_cpu_run() {
+        let program = [
+            InstructionEncoder::addi(1, 0, 5),
+            InstructionEncoder::addi(2, 1, 1),
+            InstructionEncoder::j_type(0b1101111, 0, 0),
+        ];
+        let config = RunConfig::new().with_max_cycles(100);
+        let expected = cpu_with(&program).run(&config);
+
+        let mut lockstep = Lockstep::new(cpu_with(&program));
+        let result = lockstep.run_with(&config).unwrap();
+        assert_eq!(result.reason, expected.reason);
+        assert_eq!((result.pc, result.instructions), (expected.pc, expected.instructions));
+        assert_eq!(lockstep.golden.regs[2], 6);
+    }
+}
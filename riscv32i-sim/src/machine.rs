@@ -0,0 +1,886 @@
+//! Machine description files
+//!
+//! A TOML (or JSON) file describes a simulated platform: ISA, hart count,
+//! reset vector, memory regions and devices. `MachineDescription::build`
+//! checks it and returns a `Machine` ready to load a program into.
+//!
+//! ```toml
+//! name = "virt-lite"
+//! isa = "rv32ia_zicsr_zifencei"
+//! harts = 1
+//! reset_vector = 0x8000_0000
+//!
+//! [[memory]]
+//! name = "ram"
+//! base = 0x8000_0000
+//! size = "16M"
+//!
+//! [[memory]]
+//! name = "bootrom"
+//! type = "rom"
+//! base = 0x1000
+//! size = "4K"
+//! file = "boot.bin"
+//!
+//! [[device]]
+//! type = "uart16550"
+//! base = 0x1000_0000
+//! irq = 10
+//! console = "stdio"
+//! ```
+//!
+//! The first `ram` region is main memory (`Memory`); any other region is
+//! mapped on the bus as a `MemoryRegion`. Files are raw binaries, relative
+//! to the description file. JSON has no hex literals, so numbers may also be
+//! strings (`"0x1000"`, `"64K"`).
+//!
+//! With a `plic` device, every device IRQ number is a PLIC source ID;
+//! without one, IRQs are wired straight to mip.MEIP. Numbers must be
+//! distinct either way.
+//!
+//! `build` also places a device tree (see `dtb`) at the top of main RAM and
+//! starts hart N with a0 = N and a1 = its address. With `boot_rom = ADDR`,
+//! a `BootRom` there is the reset vector and sets a0 / a1 itself before
+//! jumping to the program (main RAM's base until `Machine::set_entry`).
+//! `Machine::warm_reset` restarts the loaded program; `cold_reset` also
+//! clears RAM and reloads it from the description.
+//!
+//! | type        | options                                       |
+//! |-------------|-----------------------------------------------|
+//! | uart16550   | console: stdio (default), pty, none, or a file |
+//! | framebuffer | width, height, format                         |
+//! | gpio        | inputs: timeline file                         |
+//! | panel       | leds, inputs                                  |
+//! | block       | image (required), cow                         |
+//! | dma         | channels                                      |
+//! | plic        | -                                             |
+//! | sifive_test | -                                             |
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::types::*;
+use crate::block::{BlockDevice, BLK_SIZE};
+use crate::cpu::Cpu;
+use crate::dma::{DmaController, DMA_SIZE};
+use crate::dtb;
+use crate::framebuffer::{Framebuffer, PixelFormat};
+use crate::gpio::{Gpio, GPIO_SIZE};
+use crate::htif::TestFinisher;
+use crate::memory::Memory;
+use crate::multi_hart::{MultiHart, Schedule};
+use crate::panel::{Panel, PANEL_SIZE};
+use crate::plic::{Plic, PLIC_SIZE, PLIC_SOURCES};
+use crate::region::{BootRom, MemoryRegion, BOOT_ROM_SIZE};
+use crate::timeline::Timeline;
+use crate::uart::{Uart16550, UART_SIZE};
+
+/// Device types a description may use
+pub const DEVICE_TYPES: [&str; 8] = ["uart16550", "framebuffer", "gpio", "panel", "block", "dma", "plic", "sifive_test"];
+
+/// Window claimed by the test finisher (QEMU virt's size)
+const FINISHER_SIZE: Addr = 0x1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MachineError {
+    #[error("Cannot read {path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+
+    #[error("Invalid machine description: {0}")]
+    Parse(String),
+
+    #[error("Unsupported ISA '{isa}': {reason}")]
+    Isa { isa: String, reason: String },
+
+    #[error("A machine needs at least one hart")]
+    NoHarts,
+
+    #[error("No RAM region (add a [[memory]] with type = \"ram\")")]
+    NoRam,
+
+    #[error("'{name}' has an empty or wrapping range")]
+    BadRange { name: String },
+
+    #[error("'{name}' at 0x{base:08x}..+0x{size:x} overlaps '{other}'")]
+    Overlap { name: String, base: Addr, size: u64, other: String },
+
+    #[error("Two regions or devices are named '{0}'")]
+    DuplicateName(String),
+
+    #[error("Unknown device type '{kind}' for '{name}' (known: {})", DEVICE_TYPES.join(", "))]
+    UnknownDevice { name: String, kind: String },
+
+    #[error("IRQ {irq} is used by both '{name}' and '{other}'")]
+    DuplicateIrq { irq: u32, name: String, other: String },
+
+    #[error("Device '{name}': {message}")]
+    Device { name: String, message: String },
+
+    #[error("Reset vector 0x{0:08x} is not in a memory region")]
+    ResetVector(Addr),
+
+    #[error("Cannot load {path} into '{name}': {message}")]
+    File { name: String, path: PathBuf, message: String },
+
+    #[error("Main RAM is too small for the {0}-byte device tree")]
+    DeviceTreeSpace(usize),
+}
+
+/// Platform as read from a description file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineDescription {
+    #[serde(default = "default_name")]
+    pub name: String,
+
+    #[serde(default = "default_isa")]
+    pub isa: String,
+
+    #[serde(default = "default_harts")]
+    pub harts: usize,
+
+    /// Start PC of every hart - the boot ROM, or main RAM's base, if not given
+    #[serde(default, deserialize_with = "optional_number")]
+    pub reset_vector: Option<Addr>,
+
+    /// Map a `BootRom` trampoline here
+    #[serde(default, deserialize_with = "optional_number")]
+    pub boot_rom: Option<Addr>,
+
+    #[serde(default)]
+    pub memory: Vec<RegionDescription>,
+
+    #[serde(default, rename = "device", alias = "devices")]
+    pub devices: Vec<DeviceDescription>,
+
+    /// Kernel command line for the device tree's /chosen node
+    pub bootargs: Option<String>,
+
+    /// Directory that file paths are relative to
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+    #[default]
+    Ram,
+    Rom,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegionDescription {
+    pub name: String,
+
+    #[serde(rename = "type", default)]
+    pub kind: RegionKind,
+
+    #[serde(deserialize_with = "address")]
+    pub base: Addr,
+
+    #[serde(deserialize_with = "number")]
+    pub size: u64,
+
+    /// Raw binary loaded at the region base
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceDescription {
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    /// Defaults to the type, numbered if there are several
+    pub name: Option<String>,
+
+    #[serde(deserialize_with = "address")]
+    pub base: Addr,
+
+    pub irq: Option<u32>,
+
+    /// Type-specific settings
+    #[serde(flatten)]
+    pub options: BTreeMap<String, OptionValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Bool(bool),
+    Integer(u64),
+    Text(String),
+}
+
+/// Device as mapped, for listing and device tree generation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineDevice {
+    pub name: String,
+    pub kind: String,
+    pub base: Addr,
+    pub size: Addr,
+    pub irq: Option<u32>,
+    /// Host pseudo-terminal of a `console = "pty"` UART
+    pub pty: Option<PathBuf>,
+}
+
+/// Configured platform - a CPU with its memory and devices in place
+pub struct Machine {
+    pub description: MachineDescription,
+    pub cpu: Cpu,
+    pub devices: Vec<MachineDevice>,
+    /// Device tree blob, and where it was placed
+    pub dtb: Vec<u8>,
+    pub dtb_addr: Addr,
+    pub boot_rom: Option<Arc<Mutex<BootRom>>>,
+    /// Main RAM's file contents, reloaded by a cold reset
+    ram_image: Vec<u8>,
+}
+
+fn default_name() -> String {
+    "riscv32".to_string()
+}
+
+fn default_isa() -> String {
+    "rv32ia_zicsr_zifencei".to_string()
+}
+
+fn default_harts() -> usize {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Integer(u64),
+    Text(String),
+}
+
+/// Integer, or a string in hex / binary / decimal with an optional K, M or
+/// G suffix
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match Number::deserialize(deserializer)? {
+        Number::Integer(value) => Ok(value),
+        Number::Text(text) => parse_number(&text).ok_or_else(|| serde::de::Error::custom(format!("invalid number '{}'", text))),
+    }
+}
+
+fn address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Addr, D::Error> {
+    let value = number(deserializer)?;
+    Addr::try_from(value).map_err(|_| serde::de::Error::custom(format!("address 0x{:x} is above 4 GiB", value)))
+}
+
+fn optional_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Addr>, D::Error> {
+    address(deserializer).map(Some)
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    let text = text.trim().replace('_', "");
+    let (digits, scale) = match text.char_indices().last()? {
+        (end, 'K' | 'k') => (&text[..end], 1 << 10),
+        (end, 'M' | 'm') => (&text[..end], 1 << 20),
+        (end, 'G' | 'g') => (&text[..end], 1 << 30),
+        _ => (&text[..], 1),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        u64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    value.checked_mul(scale)
+}
+
+/// Check an ISA string against what `Cpu` implements: RV32I with the A
+/// extension, Zicsr and Zifencei
+pub fn check_isa(isa: &str) -> Result<(), MachineError> {
+    let error = |reason: String| MachineError::Isa { isa: isa.to_string(), reason };
+    let lower = isa.to_ascii_lowercase();
+    let rest = lower.strip_prefix("rv32").ok_or_else(|| error("only rv32 is supported".to_string()))?;
+    let mut parts = rest.split('_');
+    let letters = parts.next().unwrap_or("");
+    let mut chars = letters.chars();
+    match chars.next() {
+        Some('i') => {}
+        Some('g') => return Err(error("G includes M, F and D, which are not implemented".to_string())),
+        Some(base) => return Err(error(format!("base '{}' is not implemented (use i)", base))),
+        None => return Err(error("missing base ISA".to_string())),
+    }
+    for extension in chars {
+        if extension != 'a' {
+            return Err(error(format!("extension '{}' is not implemented", extension)));
+        }
+    }
+    for extension in parts {
+        if !matches!(extension, "zicsr" | "zifencei") {
+            return Err(error(format!("extension '{}' is not implemented", extension)));
+        }
+    }
+    Ok(())
+}
+
+impl MachineDescription {
+    pub fn from_toml(text: &str) -> Result<Self, MachineError> {
+        toml::from_str(text).map_err(|e| MachineError::Parse(e.to_string()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, MachineError> {
+        serde_json::from_str(text).map_err(|e| MachineError::Parse(e.to_string()))
+    }
+
+    /// Read a `.json` or (any other extension) TOML file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, MachineError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| MachineError::Io { path: path.to_path_buf(), source })?;
+        let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let mut description = if is_json { Self::from_json(&text)? } else { Self::from_toml(&text)? };
+        description.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(description)
+    }
+
+    /// Names given to the devices, in order
+    pub fn device_names(&self) -> Vec<String> {
+        self.devices
+            .iter()
+            .enumerate()
+            .map(|(index, device)| match &device.name {
+                Some(name) => name.clone(),
+                None if self.devices.iter().filter(|d| d.name.is_none() && d.kind == device.kind).count() > 1 => {
+                    let number = self.devices[..index].iter().filter(|d| d.name.is_none() && d.kind == device.kind).count();
+                    format!("{}{}", device.kind, number)
+                }
+                None => device.kind.clone(),
+            })
+            .collect()
+    }
+
+    /// First RAM region, which becomes `Memory`
+    pub fn main_memory(&self) -> Option<&RegionDescription> {
+        self.memory.iter().find(|region| region.kind == RegionKind::Ram)
+    }
+
+    pub fn reset_vector(&self) -> Addr {
+        self.reset_vector.or(self.boot_rom).or(self.main_memory().map(|ram| ram.base)).unwrap_or(0)
+    }
+
+    /// Check everything that can be checked without opening files
+    pub fn validate(&self) -> Result<Vec<MachineDevice>, MachineError> {
+        check_isa(&self.isa)?;
+        if self.harts == 0 {
+            return Err(MachineError::NoHarts);
+        }
+        if self.main_memory().is_none() {
+            return Err(MachineError::NoRam);
+        }
+
+        let plics = self.devices.iter().filter(|device| device.kind == "plic").count();
+        let mut devices = Vec::new();
+        for (device, name) in self.devices.iter().zip(self.device_names()) {
+            let size = device_size(device, &name)?;
+            let error = |message: &str| MachineError::Device { name: name.clone(), message: message.to_string() };
+            match device.irq {
+                Some(0) => return Err(error("IRQ 0 means no interrupt")),
+                Some(_) if device.kind == "plic" => return Err(error("the PLIC has no IRQ of its own")),
+                Some(irq) if plics > 0 && irq >= PLIC_SOURCES => {
+                    return Err(error(&format!("IRQ {} is above the PLIC's {} sources", irq, PLIC_SOURCES - 1)));
+                }
+                _ if device.kind == "plic" && plics > 1 => return Err(error("only one PLIC is supported")),
+                _ => {}
+            }
+            if let Some(other) = devices.iter().find(|d: &&MachineDevice| d.irq.is_some() && d.irq == device.irq) {
+                return Err(MachineError::DuplicateIrq { irq: device.irq.unwrap(), name, other: other.name.clone() });
+            }
+            devices.push(MachineDevice { name, kind: device.kind.clone(), base: device.base, size, irq: device.irq, pty: None });
+        }
+
+        // Every range, regions first
+        let ranges: Vec<(&str, Addr, u64)> = self
+            .memory
+            .iter()
+            .map(|region| (region.name.as_str(), region.base, region.size))
+            .chain(self.boot_rom.map(|base| ("boot_rom", base, BOOT_ROM_SIZE as u64)))
+            .chain(devices.iter().map(|device| (device.name.as_str(), device.base, device.size as u64)))
+            .collect();
+        for (index, &(name, base, size)) in ranges.iter().enumerate() {
+            if size == 0 || base as u64 + size > 1 << 32 {
+                return Err(MachineError::BadRange { name: name.to_string() });
+            }
+            for &(other, other_base, other_size) in &ranges[..index] {
+                if other == name {
+                    return Err(MachineError::DuplicateName(name.to_string()));
+                }
+                if (base as u64) < other_base as u64 + other_size && (other_base as u64) < base as u64 + size {
+                    return Err(MachineError::Overlap { name: name.to_string(), base, size, other: other.to_string() });
+                }
+            }
+        }
+
+        let reset_vector = self.reset_vector();
+        let in_region = |&(_, base, size): &(&str, Addr, u64)| (reset_vector as u64).wrapping_sub(base as u64) < size;
+        if !ranges[..self.memory.len() + self.boot_rom.iter().count()].iter().any(in_region) {
+            return Err(MachineError::ResetVector(reset_vector));
+        }
+        Ok(devices)
+    }
+
+    /// Validate, then set up memory and devices on a CPU for hart 0
+    pub fn build(&self) -> Result<Machine, MachineError> {
+        let mut devices = self.validate()?;
+        let mut cpu = Cpu::new();
+        let main = self.main_memory().unwrap();
+        cpu.memory = Memory::with_base(main.base, main.size as usize);
+        cpu.set_reset_vector(self.reset_vector());
+
+        let mut ram_image = Vec::new();
+        for region in &self.memory {
+            let contents = match &region.file {
+                Some(path) => self.read_file(&region.name, path, region.size)?,
+                None => Vec::new(),
+            };
+            if std::ptr::eq(region, main) {
+                ram_image = contents;
+                continue;
+            }
+            let device = match region.kind {
+                RegionKind::Rom => MemoryRegion::rom(region.size as usize, &contents),
+                RegionKind::Ram => MemoryRegion::ram(region.size as usize).with_image(&contents),
+            };
+            self.map(&mut cpu, &region.name, region.base, region.size as Addr, device)?;
+        }
+
+        let mut plic = None;
+        for (description, device) in self.devices.iter().zip(&mut devices) {
+            if description.kind == "plic" {
+                let mapped = cpu.memory.map_device(&device.name, device.base, device.size, Plic::with_harts(self.harts));
+                plic = Some(mapped.map_err(|e| MachineError::Device { name: device.name.clone(), message: e.to_string() })?);
+            } else {
+                self.create_device(&mut cpu, description, device)?;
+            }
+        }
+        if let Some(plic) = plic {
+            for device in &devices {
+                if let (Some(irq), Some(line)) = (device.irq, cpu.memory.bus_mut().route_irq(&device.name)) {
+                    plic.lock().unwrap().connect(irq, line);
+                }
+            }
+        }
+
+        // Device tree at the top of RAM, 8-byte aligned as the format requires
+        let dtb = dtb::machine_tree(self, &devices, self.bootargs.as_deref()).to_bytes();
+        let dtb_addr = cpu
+            .memory
+            .end()
+            .checked_sub(dtb.len() as u64)
+            .map(|addr| addr & !0x7)
+            .filter(|&addr| addr >= main.base as u64 + ram_image.len() as u64)
+            .ok_or(MachineError::DeviceTreeSpace(dtb.len()))? as Addr;
+
+        let boot_rom = match self.boot_rom {
+            Some(base) => {
+                let rom = cpu.memory.map_device("boot_rom", base, BOOT_ROM_SIZE, BootRom::new(main.base, dtb_addr));
+                Some(rom.map_err(|e| MachineError::Device { name: "boot_rom".to_string(), message: e.to_string() })?)
+            }
+            None => None,
+        };
+
+        let mut machine = Machine { description: self.clone(), cpu, devices, dtb, dtb_addr, boot_rom, ram_image };
+        machine.load_ram();
+        machine.warm_reset();
+        Ok(machine)
+    }
+
+    fn read_file(&self, name: &str, path: &Path, size: u64) -> Result<Vec<u8>, MachineError> {
+        let path = self.dir.join(path);
+        let error = |message: String| MachineError::File { name: name.to_string(), path: path.clone(), message };
+        let contents = std::fs::read(&path).map_err(|e| error(e.to_string()))?;
+        if contents.len() as u64 > size {
+            return Err(error(format!("{} bytes do not fit in 0x{:x}", contents.len(), size)));
+        }
+        Ok(contents)
+    }
+
+    fn map<D: crate::bus::Device + 'static>(&self, cpu: &mut Cpu, name: &str, base: Addr, size: Addr, device: D) -> Result<(), MachineError> {
+        // Ranges were checked in validate
+        cpu.memory.map_device(name, base, size, device).map(|_| ()).map_err(|e| MachineError::Device { name: name.to_string(), message: e.to_string() })
+    }
+
+    fn create_device(&self, cpu: &mut Cpu, description: &DeviceDescription, device: &mut MachineDevice) -> Result<(), MachineError> {
+        let options = Options { name: &device.name, values: &description.options };
+        let error = |message: String| MachineError::Device { name: device.name.clone(), message };
+        let timeline = |path: Option<&str>| -> Result<Option<Timeline>, MachineError> {
+            match path {
+                Some(path) => Timeline::from_file(self.dir.join(path)).map(Some).map_err(|e| error(format!("{}: {}", path, e))),
+                None => Ok(None),
+            }
+        };
+        let (name, base, size) = (device.name.as_str(), device.base, device.size);
+
+        match description.kind.as_str() {
+            "uart16550" => {
+                let uart = match options.text("console")?.unwrap_or("stdio") {
+                    "stdio" => Uart16550::stdio(),
+                    "none" => Uart16550::new(),
+                    #[cfg(unix)]
+                    "pty" => {
+                        let (uart, path) = Uart16550::pty().map_err(|e| error(format!("cannot open pty: {}", e)))?;
+                        device.pty = Some(path);
+                        uart
+                    }
+                    path => {
+                        let file = std::fs::File::create(self.dir.join(path)).map_err(|e| error(format!("cannot create {}: {}", path, e)))?;
+                        Uart16550::new().with_output(file)
+                    }
+                };
+                self.map(cpu, name, base, size, uart)
+            }
+            "framebuffer" => {
+                let (width, height, format) = framebuffer_options(&options)?;
+                self.map(cpu, name, base, size, Framebuffer::new(width, height, format))
+            }
+            "gpio" => {
+                let mut gpio = Gpio::new();
+                if let Some(timeline) = timeline(options.text("inputs")?)? {
+                    gpio = gpio.with_timeline(timeline).map_err(|e| error(e.to_string()))?;
+                }
+                self.map(cpu, name, base, size, gpio)
+            }
+            "panel" => {
+                let mut panel = Panel::with_leds(options.integer("leds")?.unwrap_or(10) as u32);
+                if let Some(timeline) = timeline(options.text("inputs")?)? {
+                    panel = panel.with_timeline(timeline).map_err(|e| error(e.to_string()))?;
+                }
+                self.map(cpu, name, base, size, panel)
+            }
+            "block" => {
+                let image = options.text("image")?.ok_or_else(|| error("needs an image".to_string()))?;
+                let path = self.dir.join(image);
+                let disk = if options.bool("cow")?.unwrap_or(false) { BlockDevice::open_cow(&path) } else { BlockDevice::open(&path) };
+                let disk = disk.map_err(|e| error(format!("cannot open {}: {}", image, e)))?;
+                self.map(cpu, name, base, size, disk)
+            }
+            "dma" => {
+                let channels = options.integer("channels")?.unwrap_or(4) as usize;
+                self.map(cpu, name, base, size, DmaController::new(channels))
+            }
+            "sifive_test" => {
+                cpu.set_test_finisher(TestFinisher::new(base));
+                Ok(())
+            }
+            // Mapped by build, which wires up its sources
+            "plic" => Ok(()),
+            kind => Err(MachineError::UnknownDevice { name: name.to_string(), kind: kind.to_string() }),
+        }
+    }
+}
+
+/// Options of one device, type-checked on access
+struct Options<'a> {
+    name: &'a str,
+    values: &'a BTreeMap<String, OptionValue>,
+}
+
+impl<'a> Options<'a> {
+    fn error(&self, message: String) -> MachineError {
+        MachineError::Device { name: self.name.to_string(), message }
+    }
+
+    fn text(&self, key: &str) -> Result<Option<&'a str>, MachineError> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(OptionValue::Text(text)) => Ok(Some(text)),
+            Some(_) => Err(self.error(format!("'{}' must be a string", key))),
+        }
+    }
+
+    fn integer(&self, key: &str) -> Result<Option<u64>, MachineError> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(OptionValue::Integer(value)) => Ok(Some(*value)),
+            Some(OptionValue::Text(text)) => parse_number(text).map(Some).ok_or_else(|| self.error(format!("invalid number '{}' for '{}'", text, key))),
+            Some(_) => Err(self.error(format!("'{}' must be a number", key))),
+        }
+    }
+
+    fn bool(&self, key: &str) -> Result<Option<bool>, MachineError> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(OptionValue::Bool(value)) => Ok(Some(*value)),
+            Some(_) => Err(self.error(format!("'{}' must be true or false", key))),
+        }
+    }
+
+    /// Reject options the device type does not have
+    fn allow(&self, known: &[&str]) -> Result<(), MachineError> {
+        match self.values.keys().find(|key| !known.contains(&key.as_str())) {
+            Some(key) => Err(self.error(format!("unknown option '{}' (expected: {})", key, known.join(", ")))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn framebuffer_options(options: &Options) -> Result<(u32, u32, PixelFormat), MachineError> {
+    let width = options.integer("width")?.unwrap_or(640);
+    let height = options.integer("height")?.unwrap_or(480);
+    let format = options.text("format")?.unwrap_or("xrgb8888");
+    let format = PixelFormat::from_name(format).ok_or_else(|| options.error(format!("unknown pixel format '{}'", format)))?;
+    if width == 0 || height == 0 || width.checked_mul(height).is_none_or(|pixels| pixels > 1 << 24) {
+        return Err(options.error(format!("bad size {}x{}", width, height)));
+    }
+    Ok((width as u32, height as u32, format))
+}
+
+/// Window a device claims, after checking its type and options
+fn device_size(device: &DeviceDescription, name: &str) -> Result<Addr, MachineError> {
+    let options = Options { name, values: &device.options };
+    let (known, size): (&[&str], Addr) = match device.kind.as_str() {
+        "uart16550" => (&["console"], UART_SIZE),
+        "framebuffer" => {
+            let (width, height, format) = framebuffer_options(&options)?;
+            (&["width", "height", "format"], Framebuffer::window_size_for(width, height, format))
+        }
+        "gpio" => (&["inputs"], GPIO_SIZE),
+        "panel" => (&["leds", "inputs"], PANEL_SIZE),
+        "block" => (&["image", "cow"], BLK_SIZE),
+        "dma" => (&["channels"], DMA_SIZE),
+        "plic" => (&[], PLIC_SIZE),
+        "sifive_test" => (&[], FINISHER_SIZE),
+        kind => return Err(MachineError::UnknownDevice { name: name.to_string(), kind: kind.to_string() }),
+    };
+    options.allow(known)?;
+    Ok(size)
+}
+
+impl Machine {
+    /// Build the machine a description file describes
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, MachineError> {
+        MachineDescription::from_file(path)?.build()
+    }
+
+    /// Point the boot ROM (if any) at a loaded program's entry point
+    pub fn set_entry(&mut self, entry: Addr) {
+        if let Some(rom) = &self.boot_rom {
+            rom.lock().unwrap().set_entry(entry);
+        }
+    }
+
+    /// Registers and PC back to the reset vector; memory and devices keep
+    /// their state, so a loaded program runs again
+    pub fn warm_reset(&mut self) {
+        self.cpu.warm_reset();
+        self.boot_registers();
+    }
+
+    /// Power cycle: RAM cleared and reloaded from the description's files,
+    /// device tree placed again, every device reset
+    pub fn cold_reset(&mut self) {
+        self.cpu.reset();
+        self.load_ram();
+        self.boot_registers();
+    }
+
+    fn load_ram(&mut self) {
+        let base = self.cpu.memory.base();
+        self.cpu.memory.write_bytes(base, &self.ram_image);
+        self.cpu.memory.write_bytes(self.dtb_addr, &self.dtb);
+    }
+
+    /// Without a boot ROM, the hart starts with a0 / a1 already set
+    fn boot_registers(&mut self) {
+        if self.boot_rom.is_none() {
+            self.cpu.registers.poke(10, 0);
+            self.cpu.registers.poke(11, self.dtb_addr);
+        }
+    }
+
+    /// All harts, sharing this machine's memory and devices; every hart can
+    /// exit through the test finisher, hart 0 through HTIF
+    pub fn into_multi_hart(mut self, schedule: Schedule) -> MultiHart {
+        let mut system = MultiHart::new(self.description.harts, schedule);
+        let reset_vector = self.description.reset_vector();
+        let finisher = self.cpu.test_finisher();
+        if let Some(htif) = self.cpu.take_htif() {
+            system.harts[0].set_htif(htif);
+        }
+        for (id, hart) in system.harts.iter_mut().enumerate() {
+            hart.set_reset_vector(reset_vector);
+            hart.warm_reset();
+            if let Some(finisher) = finisher {
+                hart.set_test_finisher(finisher);
+            }
+            if self.boot_rom.is_none() {
+                hart.registers.poke(10, id as Word);
+                hart.registers.poke(11, self.dtb_addr);
+            }
+        }
+        system.memory = self.cpu.memory;
+        system
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CpuEvent;
+
+    const VIRT: &str = r#"
+        name = "test"
+        reset_vector = 0x1000
+
+        [[memory]]
+        name = "ram"
+        base = 0x8000_0000
+        size = "64K"
+
+        [[memory]]
+        name = "bootrom"
+        type = "rom"
+        base = 0x1000
+        size = 0x100
+
+        [[device]]
+        type = "uart16550"
+        base = 0x1000_0000
+        irq = 10
+        console = "none"
+
+        [[device]]
+        type = "dma"
+        base = 0x1000_1000
+        irq = 11
+        channels = 2
+
+        [[device]]
+        type = "dma"
+        base = 0x1000_2000
+    "#;
+
+    #[test]
+    fn test_build_from_toml_and_json() {
+        let machine = MachineDescription::from_toml(VIRT).unwrap().build().unwrap();
+        assert_eq!(machine.cpu.memory.base(), 0x8000_0000);
+        assert_eq!(machine.cpu.memory.size(), 0x10000);
+        assert_eq!(machine.cpu.control.get_pc(), 0x1000);
+        // Device tree at the top of RAM, its address in a1
+        assert_eq!(machine.cpu.registers.peek(11), machine.dtb_addr);
+        assert!(machine.dtb_addr as u64 + machine.dtb.len() as u64 <= 0x8001_0000);
+        assert_eq!(machine.cpu.memory.read_bytes(machine.dtb_addr, 4), [0xD0, 0x0D, 0xFE, 0xED]);
+        let names: Vec<_> = machine.devices.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["uart16550", "dma0", "dma1"]);
+        assert_eq!(machine.devices[1].irq, Some(11));
+        let mapped: Vec<_> = machine.cpu.memory.bus().mappings().iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(mapped, ["bootrom", "uart16550", "dma0", "dma1"]);
+        // CHANNELS register of the first DMA controller
+        let mut cpu = machine.cpu;
+        assert_eq!(cpu.memory.load(0x1000_1000, 0b010), 2);
+
+        let json = r#"{
+            "isa": "RV32I",
+            "harts": 2,
+            "memory": [{ "name": "ram", "base": "0x2000", "size": 4096 }],
+            "devices": [{ "type": "sifive_test", "base": "0x100000" }]
+        }"#;
+        let mut system = MachineDescription::from_json(json).unwrap().build().unwrap().into_multi_hart(Schedule::RoundRobin { quantum: 1 });
+        assert_eq!(system.hart_count(), 2);
+        assert_eq!(system.harts[1].control.get_pc(), 0x2000);
+        assert_eq!(system.memory.base(), 0x2000);
+        // Hart 1 passes through the finisher it inherited
+        system.load_program(&[
+            (0x2000, InstructionEncoder::u_type(0b0110111, 5, 0x100000)),
+            (0x2004, InstructionEncoder::u_type(0b0110111, 6, 0x5000)),
+            (0x2008, InstructionEncoder::i_type(0b0010011, 6, 0b000, 6, 0x555)),
+            (0x200C, InstructionEncoder::s_type(0b0100011, 0b010, 5, 6, 0)),
+        ]);
+        for _ in 0..4 {
+            system.step_hart(1);
+        }
+        assert_eq!(system.harts[1].last_event(), Some(CpuEvent::Exit(0)));
+    }
+
+    #[test]
+    fn test_boot_rom_and_resets() {
+        let toml = "boot_rom = 0x1000\n[[memory]]\nname = \"ram\"\nbase = 0x8000_0000\nsize = \"64K\"";
+        let mut machine = MachineDescription::from_toml(toml).unwrap().build().unwrap();
+        assert_eq!(machine.cpu.control.get_pc(), 0x1000);
+        // Program at 0x8000_0100: loop
+        machine.cpu.memory.store(0x8000_0100, InstructionEncoder::j_type(0b1101111, 0, 0), 0b010);
+        machine.set_entry(0x8000_0100);
+        machine.cpu.run_cycles(10);
+        assert_eq!(machine.cpu.control.get_pc(), 0x8000_0100);
+        assert_eq!(machine.cpu.registers.peek(10), 0);
+        assert_eq!(machine.cpu.registers.peek(11), machine.dtb_addr);
+
+        // Warm reset keeps the program, cold reset clears it but not the device tree
+        machine.cpu.registers.poke(11, 0);
+        machine.warm_reset();
+        assert_eq!(machine.cpu.control.get_pc(), 0x1000);
+        assert_eq!(machine.cpu.registers.peek(11), 0);
+        machine.cpu.run_cycles(10);
+        assert_eq!(machine.cpu.control.get_pc(), 0x8000_0100);
+        machine.cpu.memory.write_bytes(machine.dtb_addr, &[0; 4]);
+        machine.cold_reset();
+        assert_eq!(machine.cpu.control.get_pc(), 0x1000);
+        assert_eq!(machine.cpu.memory.load(0x8000_0100, 0b010), 0);
+        assert_eq!(machine.cpu.memory.read_bytes(machine.dtb_addr, 4), [0xD0, 0x0D, 0xFE, 0xED]);
+    }
+
+    #[test]
+    fn test_ram_image_leaves_room_for_device_tree() {
+        let dir = std::env::temp_dir().join(format!("riscv-machine-dtb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml = "[[memory]]\nname = \"ram\"\nbase = 0x8000_0000\nsize = \"64K\"\nfile = \"ram.bin\"";
+        let mut description = MachineDescription::from_toml(toml).unwrap();
+        description.dir = dir.clone();
+        let build = |len: usize| {
+            std::fs::write(dir.join("ram.bin"), vec![0xAA; len]).unwrap();
+            description.build()
+        };
+
+        // The tree is 8-byte aligned below the top, so the image gets less
+        // than size - dtb.len() whenever the tree's length isn't a multiple of 8
+        let dtb_len = build(0).unwrap().dtb.len();
+        let fits = 0x10000 - dtb_len.next_multiple_of(8);
+        let machine = build(fits).unwrap();
+        assert_eq!(machine.dtb_addr, 0x8000_0000 + fits as Addr);
+        assert_eq!(machine.cpu.memory.read_bytes(machine.dtb_addr - 1, 5), [0xAA, 0xD0, 0x0D, 0xFE, 0xED]);
+        let too_big = build(fits + 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(too_big, Err(MachineError::DeviceTreeSpace(_))));
+    }
+
+    #[test]
+    fn test_invalid_descriptions() {
+        let error = |edit: &dyn Fn(&mut MachineDescription)| {
+            let mut description = MachineDescription::from_toml(VIRT).unwrap();
+            edit(&mut description);
+            description.build().err().expect("description should be rejected")
+        };
+
+        assert!(matches!(error(&|d| d.memory[1].base = 0x8000_f000), MachineError::Overlap { .. }));
+        assert!(matches!(error(&|d| d.devices[2].base = 0x1000_1800), MachineError::Overlap { .. }));
+        assert!(matches!(error(&|d| d.devices[2].kind = "aclint".into()), MachineError::UnknownDevice { .. }));
+        assert!(matches!(error(&|d| {
+            d.devices[2].kind = "plic".into();
+            d.devices[2].irq = Some(12);
+        }), MachineError::Device { .. }));
+        assert!(matches!(error(&|d| d.devices[2].irq = Some(10)), MachineError::DuplicateIrq { irq: 10, .. }));
+        assert!(matches!(error(&|d| d.isa = "rv32imac".into()), MachineError::Isa { .. }));
+        assert!(matches!(error(&|d| d.reset_vector = Some(0x4000)), MachineError::ResetVector(0x4000)));
+        assert!(matches!(error(&|d| d.boot_rom = Some(0x1080)), MachineError::Overlap { .. }));
+        assert!(matches!(error(&|d| d.memory[0].kind = RegionKind::Rom), MachineError::NoRam));
+        assert!(matches!(error(&|d| d.devices[0].name = Some("bootrom".into())), MachineError::DuplicateName(_)));
+        let message = error(&|d| {
+            d.devices[1].options.insert("width".into(), OptionValue::Integer(2));
+        })
+        .to_string();
+        assert_eq!(message, "Device 'dma0': unknown option 'width' (expected: channels)");
+
+        assert!(matches!(MachineDescription::from_toml("harts = 1\nbogus = 2"), Err(MachineError::Parse(_))));
+        assert!(check_isa("rv32ia_zicsr_zifencei").is_ok());
+        assert!(check_isa("rv64i").is_err());
+    }
+}
@@ -1,13 +1,4 @@
-mod types;
-mod memory;
-mod register_file;
-mod control_unit;
-mod alu;
-mod cpu;
-
-use types::*;
-use alu::Alu;
-use cpu::Cpu;
+use riscv32i_sim::*;
 
 fn test_alu_operations() {
     println!("\n=== RISC-V ALU Operations Test ===\n");
@@ -66,7 +57,7 @@ fn test_risc_v_instructions() {
     ];
     
     cpu.load_program(&program);
-    cpu.reset();
+    cpu.warm_reset();
     
     println!("Executing 5 RISC-V instructions...\n");
     println!("Initial state:");
@@ -104,7 +95,7 @@ fn test_register_zero() {
     ];
     
     cpu.load_program(&program);
-    cpu.reset();
+    cpu.warm_reset();
     cpu.run_cycles(2);
     
     println!("After attempting to write to x0:");
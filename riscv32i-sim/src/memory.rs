@@ -1,12 +1,44 @@
+use std::sync::{Arc, Mutex};
+
 use crate::types::*;
+use crate::bus::{BusError, Device, MmioBus};
+
+/// Kind of data memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Atomic,  // Read-modify-write (AMO, successful SC)
+}
+
+/// Data memory access performed by one instruction
+/// `value` is the data read (Read) or written (Write, Atomic)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub addr: Addr,
+    pub value: Word,
+}
+
+impl MemoryAccess {
+    pub fn is_write(&self) -> bool {
+        self.kind != AccessKind::Read
+    }
+}
 
 /// RISC-V Memory module
 /// - Little-endian byte ordering
 /// - 4-byte aligned word access (RISC-V requirement)
-/// - 1024 words (4096 bytes) of addressable memory
+/// - 1024 words (4096 bytes) of addressable memory at address 0 by default
+/// - Addresses outside [base, base + size) read as 0 and ignore writes (no aliasing)
+/// - Sized loads and stores to a mapped device go over the MMIO bus instead
+#[derive(Clone)]
 pub struct Memory {
     // Memory array: 1024 words of 32-bit data
-    data: [Word; 1024],
+    data: Vec<Word>,
+
+    // Address of data[0] (word aligned)
+    base: Addr,
     
     // Control signals
     read_enable: bool,
@@ -19,33 +51,127 @@ pub struct Memory {
     
     // Byte enable for partial word writes (RISC-V SB, SH)
     write_mask: u8,  // 0b1111 for word, 0b0011 for halfword, 0b0001 for byte
+
+    // Memory-mapped peripherals
+    bus: MmioBus,
+
+    // RAM ranges written by bus masters, until taken (when tracking)
+    pub(crate) dma_writes: Vec<(Addr, usize)>,
+    pub(crate) track_dma_writes: bool,
+    // Bus accesses made by bus masters
+    pub(crate) dma_accesses: u64,
+    // Cycles the bus stays held by the last master's burst
+    pub(crate) bus_owed: u64,
 }
 
 impl Memory {
     pub fn new() -> Self {
+        Self::with_size(4096)
+    }
+
+    /// Memory of `bytes` bytes (rounded up to whole words)
+    pub fn with_size(bytes: usize) -> Self {
+        Self::with_base(0, bytes)
+    }
+
+    /// Memory of `bytes` bytes starting at `base` (e.g. 0x8000_0000 for
+    /// programs linked for QEMU virt / Spike)
+    pub fn with_base(base: Addr, bytes: usize) -> Self {
         Self {
-            data: [0; 1024],
+            data: vec![0; bytes.div_ceil(4)],
+            base: base & !0x3,
             read_enable: false,
             write_enable: false,
             address: 0,
             write_data: 0,
             read_data: 0,
             write_mask: 0b1111,
+            bus: MmioBus::new(),
+            dma_writes: Vec::new(),
+            track_dma_writes: false,
+            dma_accesses: 0,
+            bus_owed: 0,
         }
     }
 
+    /// Map a peripheral at [base, base + size) - returns a handle to it
+    pub fn map_device<D: Device + 'static>(
+        &mut self,
+        name: &str,
+        base: Addr,
+        size: Addr,
+        device: D,
+    ) -> Result<Arc<Mutex<D>>, BusError> {
+        let device = Arc::new(Mutex::new(device));
+        self.bus.map(name, base, size, device.clone())?;
+        Ok(device)
+    }
+
+    pub fn bus(&self) -> &MmioBus {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut MmioBus {
+        &mut self.bus
+    }
+
+    /// Clock the devices, then give bus masters their DMA turn if the bus
+    /// is free. A turn holds the bus for one cycle per access it made.
+    pub fn tick_devices(&mut self) {
+        self.bus.tick();
+        if self.bus_owed > 0 {
+            self.bus_owed -= 1;
+        } else if self.bus.has_masters() {
+            let bus = std::mem::take(&mut self.bus);
+            let before = self.dma_accesses;
+            bus.run_masters(self);
+            self.bus = bus;
+            self.bus_owed = self.dma_accesses - before;
+        }
+    }
+
+    /// Cycles a data access now has to wait for the bus
+    pub fn bus_busy(&self) -> u64 {
+        self.bus_owed
+    }
+
+    /// RAM ranges bus masters wrote since the last call (recorded only
+    /// while an engine caching code asks for them)
+    pub fn take_dma_writes(&mut self) -> Vec<(Addr, usize)> {
+        std::mem::take(&mut self.dma_writes)
+    }
+
+    /// Bus accesses made by bus masters since reset
+    pub fn dma_accesses(&self) -> u64 {
+        self.dma_accesses
+    }
+
     /// Combinational read - like always @(*)
     /// RISC-V: Word addresses must be 4-byte aligned
     fn combinational_read(&mut self, addr: Addr) {
         // Convert byte address to word address
-        let word_addr = (addr >> 2) as usize % self.data.len();
-        self.read_data = self.data[word_addr];
+        self.read_data = match self.word_index(addr) {
+            Some(word_addr) => self.data[word_addr],
+            None => 0,
+        };
+    }
+
+    /// Word index for a byte address, None outside the memory array
+    fn word_index(&self, addr: Addr) -> Option<usize> {
+        let word_addr = (addr.wrapping_sub(self.base) >> 2) as usize;
+        if addr >= self.base && word_addr < self.data.len() {
+            Some(word_addr)
+        } else {
+            None
+        }
     }
 
     /// Sequential write - like always @(posedge clk)
     /// RISC-V: Supports byte, halfword, and word writes
     fn sequential_write(&mut self, addr: Addr, data: Word, mask: u8) {
-        let word_addr = (addr >> 2) as usize % self.data.len();
+        let Some(word_addr) = self.word_index(addr) else {
+            return;
+        };
         let byte_offset = (addr & 0x3) as usize;
         
         let mut current = self.data[word_addr];
@@ -105,28 +231,145 @@ impl Memory {
         self.write_mask = mask;
     }
 
+    /// Sized load for a load instruction's funct3 (LB, LH, LW, LBU, LHU)
+    /// Misaligned accesses are split into byte reads
+    pub fn load(&mut self, addr: Addr, funct3: u8) -> Word {
+        let size = 1 << (funct3 & 0b11);
+        let mmio = if self.bus.is_empty() { None } else { self.bus.read(addr, size as usize) };
+        let raw = if let Some(value) = mmio {
+            value
+        } else if addr & (size - 1) == 0 {
+            self.clock(true, false, addr, 0);
+            self.read_data >> ((addr & 0x3) * 8)
+        } else {
+            (0..size).fold(0, |value, i| {
+                let byte_addr = addr.wrapping_add(i);
+                self.clock(true, false, byte_addr, 0);
+                value | ((self.read_data >> ((byte_addr & 0x3) * 8)) & 0xFF) << (8 * i)
+            })
+        };
+
+        match funct3 {
+            0b000 => raw as u8 as i8 as i32 as Word,    // LB
+            0b001 => raw as u16 as i16 as i32 as Word,  // LH
+            0b100 => raw & 0xFF,                        // LBU
+            0b101 => raw & 0xFFFF,                      // LHU
+            _ => raw,                                   // LW
+        }
+    }
+
+    /// Sized store for a store instruction's funct3 (SB, SH, SW)
+    /// Misaligned accesses are split into byte writes
+    pub fn store(&mut self, addr: Addr, data: Word, funct3: u8) {
+        let size: Addr = 1 << (funct3 & 0b11);
+        if !self.bus.is_empty() && self.bus.write(addr, data, size as usize) {
+            return;
+        }
+        if addr & (size - 1) == 0 {
+            self.write_mask = match size {
+                1 => 0b0001,
+                2 => 0b0011,
+                _ => 0b1111,
+            };
+            self.clock(false, true, addr, data);
+        } else {
+            self.write_mask = 0b0001;
+            for i in 0..size {
+                self.clock(false, true, addr.wrapping_add(i), data >> (8 * i));
+            }
+        }
+        self.write_mask = 0b1111;
+    }
+
     /// Initialize memory with program (4-byte aligned addresses)
     /// RISC-V rule: Instructions must be 4-byte aligned
     pub fn load_program(&mut self, program: &[(Addr, Word)]) {
         for &(addr, data) in program {
             // Ensure 4-byte alignment
             if addr & 0x3 == 0 {
-                let word_addr = (addr >> 2) as usize;
-                if word_addr < self.data.len() {
+                if let Some(word_addr) = self.word_index(addr) {
                     self.data[word_addr] = data;
                 }
             }
         }
     }
 
-    /// Direct read for fetch (always word-aligned)
+    /// Load consecutive instruction words starting at `base`
+    pub fn load_words(&mut self, base: Addr, words: &[Word]) {
+        let program: Vec<(Addr, Word)> = words.iter().enumerate().map(|(i, &word)| (base.wrapping_add(4 * i as Addr), word)).collect();
+        self.load_program(&program);
+    }
+
+    /// Direct read for fetch (always word-aligned) - devices read as 0
+    /// unless executable (`Device::fetch`)
     pub fn fetch(&self, addr: Addr) -> Word {
-        let word_addr = (addr >> 2) as usize % self.data.len();
-        self.data[word_addr]
+        if self.bus.is_mmio(addr) {
+            return self.bus.fetch(addr).unwrap_or(0);
+        }
+        match self.word_index(addr) {
+            Some(word_addr) => self.data[word_addr],
+            None => 0,
+        }
+    }
+
+    /// Memory size in bytes
+    pub fn size(&self) -> usize {
+        self.data.len() * 4
+    }
+
+    /// Lowest mapped address
+    pub fn base(&self) -> Addr {
+        self.base
+    }
+
+    /// One past the highest mapped address (u64: a memory can end at 4 GiB)
+    pub fn end(&self) -> u64 {
+        self.base as u64 + self.size() as u64
+    }
+
+    /// [addr, addr + len) lies entirely inside the memory
+    pub fn contains(&self, addr: Addr, len: usize) -> bool {
+        addr >= self.base && addr as u64 + len as u64 <= self.end()
+    }
+
+    /// Direct byte read (host side, e.g. syscalls) - bypasses the bus signals
+    pub fn read_byte(&self, addr: Addr) -> u8 {
+        (self.fetch(addr & !0x3) >> ((addr & 0x3) * 8)) as u8
+    }
+
+    /// Direct byte write (host side) - out-of-range writes are ignored
+    pub fn write_byte(&mut self, addr: Addr, value: u8) {
+        if let Some(word_addr) = self.word_index(addr) {
+            let shift = (addr & 0x3) * 8;
+            let word = &mut self.data[word_addr];
+            *word = (*word & !(0xFF << shift)) | ((value as Word) << shift);
+        }
+    }
+
+    pub fn read_bytes(&self, addr: Addr, len: usize) -> Vec<u8> {
+        (0..len).map(|i| self.read_byte(addr.wrapping_add(i as Addr))).collect()
+    }
+
+    pub fn write_bytes(&mut self, addr: Addr, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.write_byte(addr.wrapping_add(i as Addr), byte);
+        }
+    }
+
+    /// NUL-terminated string at `addr` (without the NUL), at most `max_len` bytes
+    pub fn read_cstring(&self, addr: Addr, max_len: usize) -> Vec<u8> {
+        (0..max_len)
+            .map(|i| self.read_byte(addr.wrapping_add(i as Addr)))
+            .take_while(|&byte| byte != 0)
+            .collect()
     }
 
     pub fn reset(&mut self) {
-        self.data = [0; 1024];
+        self.data.fill(0);
         self.read_data = 0;
+        self.dma_writes.clear();
+        self.dma_accesses = 0;
+        self.bus_owed = 0;
+        self.bus.reset();
     }
 }
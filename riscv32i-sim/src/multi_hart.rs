@@ -0,0 +1,350 @@
+//! Multi-hart simulation with shared memory
+//!
+//! Several harts, each with its own `RegisterFile`, `ControlUnit` and
+//! CSRs (mhartid = hart index), execute against one shared `Memory`.
+//! Harts are interleaved deterministically one instruction at a time
+//! according to a `Schedule`.
+//!
+//! Inter-processor interrupts use CLINT-style msip registers: a word
+//! store to `CLINT_BASE + 4 * hart` sets (bit 0 = 1) or clears the
+//! machine software interrupt of that hart. msip is write-only here and
+//! reads back as 0.
+//!
+//! `MultiHart::run` applies `Cpu::run`'s stop conditions to every hart.
+
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use crate::types::*;
+use crate::cpu::Cpu;
+use crate::csr::MIP_MSIP;
+use crate::memory::{Memory, MemoryAccess};
+use crate::run::{RunConfig, RunResult, StopReason, TIMEOUT_CHECK_INTERVAL};
+
+/// Base address of the msip registers (same as QEMU virt / SiFive CLINT)
+pub const CLINT_BASE: Addr = 0x0200_0000;
+
+/// Order in which harts execute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Each hart runs `quantum` instructions, then the next hart
+    RoundRobin { quantum: usize },
+    /// Repeating sequence of hart indices, e.g. [0, 0, 1]
+    Pattern(Vec<usize>),
+}
+
+/// Result of executing one instruction on one hart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HartStep {
+    pub hart: usize,
+    pub access: Option<MemoryAccess>,
+}
+
+/// Harts sharing one memory system
+pub struct MultiHart {
+    pub harts: Vec<Cpu>,
+    pub memory: Memory,
+
+    schedule: Schedule,
+    position: usize,
+    msip: Vec<bool>,
+}
+
+impl MultiHart {
+    pub fn new(hart_count: usize, schedule: Schedule) -> Self {
+        assert!(hart_count > 0, "need at least one hart");
+        match &schedule {
+            Schedule::RoundRobin { quantum } => assert!(*quantum > 0, "quantum must be at least 1"),
+            Schedule::Pattern(order) => {
+                assert!(!order.is_empty(), "schedule pattern is empty");
+                assert!(order.iter().all(|&hart| hart < hart_count), "schedule names a missing hart");
+            }
+        }
+
+        Self {
+            harts: (0..hart_count).map(|id| Cpu::with_hart_id(id as Word)).collect(),
+            memory: Memory::new(),
+            schedule,
+            position: 0,
+            msip: vec![false; hart_count],
+        }
+    }
+
+    pub fn hart_count(&self) -> usize {
+        self.harts.len()
+    }
+
+    /// Hart that the schedule runs next
+    pub fn next_hart(&self) -> usize {
+        match &self.schedule {
+            Schedule::RoundRobin { quantum } => (self.position / quantum) % self.harts.len(),
+            Schedule::Pattern(order) => order[self.position % order.len()],
+        }
+    }
+
+    /// Execute one instruction on the next scheduled hart
+    pub fn step(&mut self) -> HartStep {
+        let hart = self.next_hart();
+        self.position += 1;
+        self.step_hart(hart)
+    }
+
+    /// Execute one instruction on a specific hart (outside the schedule)
+    pub fn step_hart(&mut self, hart: usize) -> HartStep {
+        self.harts[hart].clock_with_memory(&mut self.memory);
+        let access = self.harts[hart].last_memory_access();
+
+        if let Some(access) = access.filter(MemoryAccess::is_write) {
+            self.observe_write(hart, access);
+        }
+
+        HartStep { hart, access }
+    }
+
+    /// Platform side effects of a store from `hart`
+    fn observe_write(&mut self, hart: usize, access: MemoryAccess) {
+        // msip registers
+        let offset = access.addr.wrapping_sub(CLINT_BASE) as usize;
+        if offset < 4 * self.harts.len() {
+            self.set_msip(offset / 4, access.value & 1 != 0);
+        }
+
+        // A store to a reserved word breaks other harts' LR/SC reservations
+        let word = access.addr & !0x3;
+        for (other, cpu) in self.harts.iter_mut().enumerate() {
+            if other != hart && cpu.reservation() == Some(word) {
+                cpu.invalidate_reservation();
+            }
+        }
+    }
+
+    /// Raise or clear a hart's machine software interrupt
+    pub fn set_msip(&mut self, hart: usize, level: bool) {
+        self.msip[hart] = level;
+        self.harts[hart].csrs.set_pending(MIP_MSIP, level);
+    }
+
+    pub fn get_msip(&self, hart: usize) -> bool {
+        self.msip[hart]
+    }
+
+    /// Run `count` scheduled steps (instructions across all harts)
+    pub fn run_cycles(&mut self, count: usize) {
+        for _ in 0..count {
+            self.step();
+        }
+    }
+
+    /// Follow the schedule until a hart stops as `Cpu::run` would stop it,
+    /// or a budget in `config` runs out (budgets count scheduled steps
+    /// across all harts). A hart stuck in a self-loop, such as a parked
+    /// secondary, only ends the run once every hart is stuck.
+    /// Returns the hart that stopped the run with its result
+    pub fn run(&mut self, config: &RunConfig) -> (usize, RunResult) {
+        let start = Instant::now();
+        let start_steps = self.position;
+        let start_instret: Vec<u64> = self.harts.iter().map(|hart| hart.csrs.get_instret()).collect();
+        let mut stuck = vec![false; self.harts.len()];
+        loop {
+            let hart = self.next_hart();
+            let pc = self.harts[hart].control.get_pc();
+            let steps = (self.position - start_steps) as u64;
+            let instructions = self.instructions_since(&start_instret);
+
+            let budget = if steps > 0 && config.breakpoints.contains(&pc) {
+                Some(StopReason::Breakpoint(pc))
+            } else if config.max_cycles.is_some_and(|max| steps >= max) {
+                Some(StopReason::CycleLimit)
+            } else if config.max_instructions.is_some_and(|max| instructions >= max) {
+                Some(StopReason::InstructionLimit)
+            } else if !steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) {
+                None
+            } else if config.timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                Some(StopReason::Timeout)
+            } else if config.interrupt.as_ref().is_some_and(|interrupt| interrupt.swap(false, Ordering::Relaxed)) {
+                Some(StopReason::Interrupted)
+            } else {
+                None
+            };
+            if let Some(reason) = budget {
+                return self.finish(hart, reason, None, start_steps, &start_instret);
+            }
+
+            let inst = Instruction::new(self.memory.fetch(pc));
+            self.step();
+            match self.harts[hart].stop_after(pc, inst, config) {
+                Some((StopReason::SelfLoop(_), _)) => {
+                    stuck[hart] = true;
+                    if stuck.iter().all(|&stuck| stuck) {
+                        return self.finish(hart, StopReason::SelfLoop(pc), None, start_steps, &start_instret);
+                    }
+                }
+                Some((reason, exit_code)) => return self.finish(hart, reason, exit_code, start_steps, &start_instret),
+                None => stuck[hart] = false,
+            }
+        }
+    }
+
+    fn instructions_since(&self, start_instret: &[u64]) -> u64 {
+        self.harts.iter().zip(start_instret).map(|(hart, start)| hart.csrs.get_instret() - start).sum()
+    }
+
+    fn finish(
+        &self,
+        hart: usize,
+        reason: StopReason,
+        exit_code: Option<i32>,
+        start_steps: usize,
+        start_instret: &[u64],
+    ) -> (usize, RunResult) {
+        let result = RunResult {
+            reason,
+            exit_code,
+            pc: self.harts[hart].control.get_pc(),
+            cycles: (self.position - start_steps) as u64,
+            instructions: self.instructions_since(start_instret),
+        };
+        (hart, result)
+    }
+
+    /// Total scheduled steps so far
+    pub fn get_step_count(&self) -> usize {
+        self.position
+    }
+
+    pub fn reset(&mut self) {
+        for hart in 0..self.harts.len() {
+            self.harts[hart].reset();
+            self.set_msip(hart, false);
+        }
+        self.memory.reset();
+        self.position = 0;
+    }
+
+    /// Load a program into the shared memory (all harts start at PC 0)
+    pub fn load_program(&mut self, program: &[(Addr, Word)]) {
+        self.memory.load_program(program);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr;
+    use crate::test_util::{addi, lw, sw};
+
+    const LOCK: i16 = 0x100;
+    const COUNTER: i16 = 0x104;
+
+    fn csrrs(rd: u8, csr: u16, rs1: u8) -> Word {
+        InstructionEncoder::i_type(0b1110011, rd, 0b010, rs1, csr as i16)
+    }
+
+    fn csrrw(rd: u8, csr: u16, rs1: u8) -> Word {
+        InstructionEncoder::i_type(0b1110011, rd, 0b001, rs1, csr as i16)
+    }
+
+    fn amoswap(rd: u8, rs1: u8, rs2: u8) -> Word {
+        InstructionEncoder::r_type(0b0101111, rd, 0b010, rs1, rs2, 0b00001 << 2)
+    }
+
+    fn bne(rs1: u8, rs2: u8, imm: i16) -> Word {
+        InstructionEncoder::b_type(0b1100011, 0b001, rs1, rs2, imm)
+    }
+
+    #[test]
+    fn test_mhartid_per_hart() {
+        let mut system = MultiHart::new(3, Schedule::RoundRobin { quantum: 1 });
+        system.memory.load_words(0, &[csrrs(10, csr::MHARTID, 0)]);
+
+        system.run_cycles(3);
+
+        for (id, hart) in system.harts.iter().enumerate() {
+            assert_eq!(hart.registers.peek(10), id as Word);
+        }
+    }
+
+    #[test]
+    fn test_spinlock_protects_counter() {
+        // Each hart increments the shared counter 10 times under an amoswap lock
+        let program = [
+            addi(5, 0, 1),          // t0 = 1
+            addi(8, 0, LOCK),       // s0 = &lock
+            addi(9, 0, COUNTER),    // s1 = &counter
+            addi(7, 0, 10),         // t2 = iterations
+            amoswap(6, 8, 5),       // acquire: t1 = swap(lock, 1)
+            bne(6, 0, -4),          //   spin while lock was held
+            lw(28, 9, 0),           // t3 = counter
+            addi(28, 28, 1),
+            sw(9, 28, 0),           // counter = t3
+            sw(8, 0, 0),            // release
+            addi(7, 7, -1),
+            bne(7, 0, -28),         // next iteration
+            InstructionEncoder::j_type(0b1101111, 0, 0),  // done: j done
+        ];
+        let mut system = MultiHart::new(2, Schedule::RoundRobin { quantum: 1 });
+        system.memory.load_words(0, &program);
+
+        system.run_cycles(2000);
+
+        assert_eq!(system.memory.fetch(COUNTER as Addr), 20);
+        assert_eq!(system.memory.fetch(LOCK as Addr), 0);
+    }
+
+    #[test]
+    fn test_msip_interrupts_other_hart() {
+        // Hart 0 (pattern only runs it after hart 1 is waiting) writes msip[1];
+        // hart 1 enables MSIE, waits in WFI and traps to its handler
+        let program = [
+            csrrs(10, csr::MHARTID, 0),
+            bne(10, 0, 16),                                          // hart 1 -> waiter
+            InstructionEncoder::u_type(0b0110111, 5, CLINT_BASE as i32),
+            addi(6, 0, 1),
+            sw(5, 6, 4),                                             // msip[1] = 1
+            addi(5, 0, 0x80),                                        // waiter: mtvec = handler
+            csrrw(0, csr::MTVEC, 5),
+            addi(5, 0, 8),
+            csrrw(0, csr::MIE, 5),                                   // mie.MSIE
+            csrrw(0, csr::MSTATUS, 5),                               // mstatus.MIE
+            0x1050_0073,                                             // wfi
+        ];
+        let handler = [addi(11, 0, 42)];
+        let mut system = MultiHart::new(2, Schedule::Pattern(vec![1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 1, 1]));
+        system.memory.load_words(0, &program);
+        system.memory.load_words(0x80, &handler);
+
+        system.run_cycles(15);
+
+        assert!(system.get_msip(1));
+        assert_eq!(system.harts[1].registers.peek(11), 42);
+        assert_eq!(system.harts[1].csrs.read(csr::MCAUSE), Some(csr::MCAUSE_INTERRUPT | 3));
+        assert_eq!(system.harts[0].registers.peek(11), 0);
+    }
+
+    #[test]
+    fn test_run_stops_on_exit_not_parked_hart() {
+        // Hart 1 parks in a self-loop; hart 0 counts to 5 and exits with it
+        let program = [
+            csrrs(10, csr::MHARTID, 0),
+            bne(10, 0, 24),                                          // hart 1 -> park
+            addi(10, 10, 1),                                         // count: a0 += 1
+            addi(5, 0, 5),
+            bne(10, 5, -8),
+            addi(17, 0, 93),
+            0x0000_0073,                                             // ecall exit(a0)
+            InstructionEncoder::j_type(0b1101111, 0, 0),             // park: j park
+        ];
+        let mut system = MultiHart::new(2, Schedule::RoundRobin { quantum: 1 });
+        system.memory.load_words(0, &program);
+
+        let (hart, result) = system.run(&RunConfig::new());
+        assert_eq!(hart, 0);
+        assert_eq!((result.reason, result.exit_code), (StopReason::Exit, Some(5)));
+
+        // With every hart parked the run ends on the last one to get stuck
+        let mut system = MultiHart::new(2, Schedule::RoundRobin { quantum: 1 });
+        system.memory.load_words(0, &[InstructionEncoder::j_type(0b1101111, 0, 0)]);
+        let (hart, result) = system.run(&RunConfig::new());
+        assert_eq!((hart, result.reason, result.cycles), (1, StopReason::SelfLoop(0), 2));
+    }
+}
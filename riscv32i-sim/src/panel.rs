@@ -0,0 +1,316 @@
+//! Lab board panel: LEDs, 7-segment displays, switches and push buttons
+//!
+//! Register layout after the DE-series FPGA boards used in lab courses:
+//!
+//! | offset | register    |                                                |
+//! |--------|-------------|------------------------------------------------|
+//! | 0x00   | LEDS        | bit per LED                                    |
+//! | 0x10   | HEX3_0      | digits 0-3, a byte each (bit 0 = a .. 6 = g)   |
+//! | 0x14   | HEX7_4      | digits 4-7                                     |
+//! | 0x20   | SWITCHES    | read-only                                      |
+//! | 0x30   | BUTTONS     | read-only, 1 = pressed                         |
+//! | 0x38   | BUTTON_IE   | interrupt on press                             |
+//! | 0x3C   | BUTTON_EDGE | latched presses, write 1 to clear              |
+//!
+//! Inputs are `swN`, `switches`, `btnN` and `buttons` for timelines and
+//! scripts; outputs are logged as `leds`, `hex3_0` and `hex7_4`.
+
+use std::fmt::Write as _;
+
+use crate::types::*;
+use crate::bus::{merge_lanes, Device};
+use crate::timeline::{OutputLog, Timeline, TimelineError};
+
+pub const PANEL_LEDS: Addr = 0x00;
+pub const PANEL_HEX3_0: Addr = 0x10;
+pub const PANEL_HEX7_4: Addr = 0x14;
+pub const PANEL_SWITCHES: Addr = 0x20;
+pub const PANEL_BUTTONS: Addr = 0x30;
+pub const PANEL_BUTTON_IE: Addr = 0x38;
+pub const PANEL_BUTTON_EDGE: Addr = 0x3C;
+/// Size of the register window to map
+pub const PANEL_SIZE: Addr = 0x100;
+
+/// Segment patterns for 0-9 and A-F (bit 0 = a .. bit 6 = g)
+pub const SEVEN_SEGMENT_DIGITS: [u8; 16] = [
+    0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07,
+    0x7F, 0x6F, 0x77, 0x7C, 0x39, 0x5E, 0x79, 0x71,
+];
+
+/// Host-side script, called each cycle with the cycle number
+pub type PanelScript = Box<dyn FnMut(u64, &mut Panel) + Send>;
+
+/// LED / 7-segment / switch panel
+pub struct Panel {
+    led_count: u32,
+    leds: Word,
+    hex: [u8; 8],
+    switches: Word,
+    buttons: Word,
+    button_ie: Word,
+    button_edge: Word,
+    cycle: u64,
+    timeline: Option<Timeline>,
+    script: Option<PanelScript>,
+    log: OutputLog,
+}
+
+impl Default for Panel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Panel {
+    /// 10 LEDs as on a DE1-SoC (all 8 digits and 32 switches and buttons
+    /// are always there)
+    pub fn new() -> Self {
+        Self::with_leds(10)
+    }
+
+    pub fn with_leds(led_count: u32) -> Self {
+        Self {
+            led_count: led_count.min(32),
+            leds: 0,
+            hex: [0; 8],
+            switches: 0,
+            buttons: 0,
+            button_ie: 0,
+            button_edge: 0,
+            cycle: 0,
+            timeline: None,
+            script: None,
+            log: OutputLog::default(),
+        }
+    }
+
+    /// Apply input changes from `timeline` as the cycles pass
+    pub fn with_timeline(mut self, timeline: Timeline) -> Result<Self, TimelineError> {
+        timeline.validate(|name| input_mask(name).is_some())?;
+        self.timeline = Some(timeline);
+        Ok(self)
+    }
+
+    /// Run `script` every cycle; it may read outputs and set inputs
+    pub fn with_script(mut self, script: impl FnMut(u64, &mut Panel) + Send + 'static) -> Self {
+        self.script = Some(Box::new(script));
+        self
+    }
+
+    /// Output changes go to `log` (e.g. one with a file sink)
+    pub fn with_log(mut self, log: OutputLog) -> Self {
+        self.log = log;
+        self
+    }
+
+    pub fn leds(&self) -> Word {
+        self.leds
+    }
+
+    /// Raw segments of each digit
+    pub fn digits(&self) -> [u8; 8] {
+        self.hex
+    }
+
+    /// Digit `index` as the hex character it shows, '?' for other patterns
+    pub fn digit_char(&self, index: usize) -> char {
+        match self.hex[index] & 0x7F {
+            0 => ' ',
+            0x40 => '-',
+            segments => SEVEN_SEGMENT_DIGITS
+                .iter()
+                .position(|&pattern| pattern == segments)
+                .and_then(|value| char::from_digit(value as u32, 16))
+                .map_or('?', |c| c.to_ascii_uppercase()),
+        }
+    }
+
+    /// One-line view, e.g. `LEDS ●○○○○○○○○●  HEX 0042`
+    pub fn render(&self) -> String {
+        let mut text = String::from("LEDS ");
+        for led in (0..self.led_count).rev() {
+            text.push(if self.leds >> led & 1 != 0 { '\u{25CF}' } else { '\u{25CB}' });
+        }
+        let _ = write!(text, "  HEX ");
+        text.extend((0..8).rev().map(|digit| self.digit_char(digit)));
+        text
+    }
+
+    /// Set a named input (`swN`, `switches`, `btnN`, `buttons`)
+    pub fn set_input(&mut self, name: &str, value: Word) -> Result<(), TimelineError> {
+        let (is_button, mask) = input_mask(name).ok_or_else(|| TimelineError::UnknownInput(name.to_string()))?;
+        let levels = if mask == Word::MAX { value } else if value != 0 { mask } else { 0 };
+        if is_button {
+            let buttons = (self.buttons & !mask) | (levels & mask);
+            self.button_edge |= buttons & !self.buttons & self.button_ie;
+            self.buttons = buttons;
+        } else {
+            self.switches = (self.switches & !mask) | (levels & mask);
+        }
+        Ok(())
+    }
+
+    /// Cycles since reset
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    pub fn log(&self) -> &OutputLog {
+        &self.log
+    }
+
+    /// Byte and halfword stores update only the digits they cover
+    /// Digits `first..first + 4` as a register, a byte each
+    fn hex_word(&self, first: usize) -> Word {
+        Word::from_le_bytes(self.hex[first..first + 4].try_into().unwrap())
+    }
+
+    fn write_hex(&mut self, first: usize, word: Word, name: &str) {
+        let digits = word.to_le_bytes();
+        if self.hex[first..first + 4] != digits {
+            self.hex[first..first + 4].copy_from_slice(&digits);
+            self.log.record(self.cycle, name, Word::from_le_bytes(digits));
+        }
+    }
+}
+
+/// (is a button, bits) of a named input
+fn input_mask(name: &str) -> Option<(bool, Word)> {
+    match name {
+        "switches" => return Some((false, Word::MAX)),
+        "buttons" => return Some((true, Word::MAX)),
+        _ => {}
+    }
+    let (is_button, index) = match name.strip_prefix("sw") {
+        Some(index) => (false, index),
+        None => (true, name.strip_prefix("btn")?),
+    };
+    let bit: u32 = index.parse().ok()?;
+    (bit < 32).then(|| (is_button, 1 << bit))
+}
+
+impl Device for Panel {
+    fn read(&mut self, offset: Addr, _size: usize) -> Word {
+        let value = match offset & !3 {
+            PANEL_LEDS => self.leds,
+            PANEL_HEX3_0 => self.hex_word(0),
+            PANEL_HEX7_4 => self.hex_word(4),
+            PANEL_SWITCHES => self.switches,
+            PANEL_BUTTONS => self.buttons,
+            PANEL_BUTTON_IE => self.button_ie,
+            PANEL_BUTTON_EDGE => self.button_edge,
+            _ => 0,
+        };
+        value >> (8 * (offset & 3))
+    }
+
+    fn write(&mut self, offset: Addr, value: Word, size: usize) {
+        let lanes = |register| merge_lanes(register, offset, value, size);
+        match offset & !3 {
+            PANEL_LEDS => {
+                let leds = lanes(self.leds) & (Word::MAX >> (32 - self.led_count.max(1)));
+                if leds != self.leds {
+                    self.leds = leds;
+                    self.log.record(self.cycle, "leds", leds);
+                }
+            }
+            PANEL_HEX3_0 => self.write_hex(0, lanes(self.hex_word(0)), "hex3_0"),
+            PANEL_HEX7_4 => self.write_hex(4, lanes(self.hex_word(4)), "hex7_4"),
+            PANEL_BUTTON_IE => self.button_ie = lanes(self.button_ie),
+            PANEL_BUTTON_EDGE => self.button_edge &= !lanes(0),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        self.cycle += 1;
+        if let Some(mut timeline) = self.timeline.take() {
+            for event in timeline.take_due(self.cycle) {
+                // Names were checked in with_timeline
+                let _ = self.set_input(&event.name, event.value);
+            }
+            self.timeline = Some(timeline);
+        }
+        if let Some(mut script) = self.script.take() {
+            script(self.cycle, self);
+            self.script = Some(script);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.button_edge != 0
+    }
+
+    fn reset(&mut self) {
+        self.leds = 0;
+        self.hex = [0; 8];
+        self.button_ie = 0;
+        self.button_edge = 0;
+        self.cycle = 0;
+        if let Some(timeline) = &mut self.timeline {
+            timeline.rewind();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outputs_render_and_log() {
+        let mut panel = Panel::with_leds(4);
+        panel.write(PANEL_LEDS, 0xFF, 4);
+        panel.write(PANEL_LEDS, 0x0F, 4);                   // unchanged after masking
+        panel.write(PANEL_HEX3_0, u32::from_le_bytes([0x5B, 0x06, 0x40, 0]), 4);
+        assert_eq!(panel.leds(), 0x0F);
+        assert_eq!(panel.render(), "LEDS \u{25CF}\u{25CF}\u{25CF}\u{25CF}  HEX      -12");
+        assert_eq!(panel.log().entries().len(), 2);
+
+        panel.write(PANEL_HEX3_0 + 1, 0x7F, 1);
+        assert_eq!((panel.digit_char(0), panel.digit_char(1), panel.digit_char(2)), ('2', '8', '-'));
+    }
+
+    #[test]
+    fn test_scripted_inputs_and_button_irq() {
+        // Script mirrors the switches onto button 0 after cycle 5
+        let mut panel = Panel::new()
+            .with_timeline(Timeline::parse("3 sw2 1\n4 switches 0x201\n").unwrap())
+            .unwrap()
+            .with_script(|cycle, panel| {
+                if cycle == 5 {
+                    panel.set_input("btn0", 1).unwrap();
+                }
+            });
+        panel.write(PANEL_BUTTON_IE, 1, 4);
+
+        for _ in 0..3 {
+            panel.tick();
+        }
+        assert_eq!(panel.read(PANEL_SWITCHES, 4), 0x004);
+        panel.tick();
+        assert_eq!(panel.read(PANEL_SWITCHES, 4), 0x201);
+        assert!(!panel.irq());
+        panel.tick();
+        assert!(panel.irq());
+        assert_eq!(panel.read(PANEL_BUTTONS, 4), 1);
+        panel.write(PANEL_BUTTON_EDGE, 1, 4);
+        assert!(!panel.irq());
+        assert!(panel.set_input("led0", 1).is_err());
+    }
+
+    #[test]
+    fn test_byte_stores_update_their_lane() {
+        let mut panel = Panel::new();
+        panel.write(PANEL_LEDS, 0x0F, 4);
+        panel.write(PANEL_LEDS + 1, 0x02, 1);                // LED 9
+        assert_eq!(panel.leds(), 0x20F);
+
+        panel.write(PANEL_BUTTON_IE + 1, 0x01, 1);
+        assert_eq!(panel.read(PANEL_BUTTON_IE, 4), 0x100);
+        panel.write(PANEL_BUTTON_IE, 0x03, 1);
+        panel.set_input("buttons", 0x103).unwrap();
+        panel.write(PANEL_BUTTON_EDGE + 1, 0xFF, 1);        // clears button 8 only
+        assert_eq!(panel.read(PANEL_BUTTON_EDGE, 4), 0x003);
+    }
+}
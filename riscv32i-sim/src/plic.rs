@@ -0,0 +1,271 @@
+//! Platform-level interrupt controller
+//!
+//! SiFive PLIC register layout with one context per hart (M-mode only,
+//! context N is hart N). Each connected device IRQ line is a source with an
+//! ID from 1; context N's output drives hart N's mip.MEIP in place of the
+//! devices' own lines (see `MmioBus::route_irq`).
+//!
+//! | offset               | register  |                                    |
+//! |----------------------|-----------|------------------------------------|
+//! | 0x000000             | PRIORITY  | word per source ID, 0-7 (0 = never) |
+//! | 0x001000             | PENDING   | bit per source ID, read-only       |
+//! | 0x002000 + 0x80 * N  | ENABLE    | bit per source ID                  |
+//! | 0x200000 + 0x1000 * N | THRESHOLD | only priorities above it interrupt |
+//! | 0x200004 + 0x1000 * N | CLAIM     | read: claim the best pending ID (0 = none); write: complete it |
+//!
+//! Sources are level-triggered: a claimed source is not sampled again until
+//! its ID is written back to CLAIM.
+
+use crate::types::*;
+use crate::bus::{lock, merge_lanes, Device, SharedDevice};
+
+pub const PLIC_PRIORITY: Addr = 0x00_0000;
+pub const PLIC_PENDING: Addr = 0x00_1000;
+pub const PLIC_ENABLE: Addr = 0x00_2000;
+pub const PLIC_THRESHOLD: Addr = 0x20_0000;
+pub const PLIC_CLAIM: Addr = 0x20_0004;
+/// Distance between contexts' ENABLE and THRESHOLD/CLAIM registers
+pub const PLIC_ENABLE_STRIDE: Addr = 0x80;
+pub const PLIC_CONTEXT_STRIDE: Addr = 0x1000;
+/// Size of the register window to map
+pub const PLIC_SIZE: Addr = 0x40_0000;
+
+/// Source IDs 1..PLIC_SOURCES (0 is "no interrupt")
+pub const PLIC_SOURCES: u32 = 64;
+pub const PLIC_MAX_PRIORITY: Word = 7;
+
+/// Per-hart enables and threshold
+#[derive(Debug, Clone, Copy, Default)]
+struct Context {
+    enable: u64,
+    threshold: Word,
+}
+
+/// Interrupt controller
+pub struct Plic {
+    sources: Vec<Option<SharedDevice>>,
+    priority: Vec<Word>,
+    pending: u64,
+    claimed: u64,
+    contexts: Vec<Context>,
+}
+
+impl Default for Plic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Plic {
+    /// One context, for hart 0
+    pub fn new() -> Self {
+        Self::with_harts(1)
+    }
+
+    /// One context per hart
+    pub fn with_harts(harts: usize) -> Self {
+        assert!(harts > 0, "need at least one context");
+        Self {
+            sources: vec![None; PLIC_SOURCES as usize],
+            priority: vec![0; PLIC_SOURCES as usize],
+            pending: 0,
+            claimed: 0,
+            contexts: vec![Context::default(); harts],
+        }
+    }
+
+    /// Sample `device`'s IRQ line as source `id`
+    pub fn connect(&mut self, id: u32, device: SharedDevice) {
+        assert!((1..PLIC_SOURCES).contains(&id), "PLIC source IDs are 1 to {}", PLIC_SOURCES - 1);
+        self.sources[id as usize] = Some(device);
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    /// Highest-priority pending source `context` enables above its
+    /// threshold (lowest ID on ties)
+    fn best(&self, context: usize) -> Option<u32> {
+        let Context { enable, threshold } = self.contexts[context];
+        let candidates = self.pending & enable;
+        (1..PLIC_SOURCES)
+            .filter(|&id| candidates >> id & 1 != 0 && self.priority[id as usize] > threshold)
+            .max_by_key(|&id| (self.priority[id as usize], std::cmp::Reverse(id)))
+    }
+
+    /// Context of a per-context register, and the register's offset in context 0
+    fn context_register(&self, register: Addr) -> Option<(usize, Addr)> {
+        let (base, stride) = match register {
+            PLIC_THRESHOLD.. => (PLIC_THRESHOLD, PLIC_CONTEXT_STRIDE),
+            PLIC_ENABLE.. => (PLIC_ENABLE, PLIC_ENABLE_STRIDE),
+            _ => return None,
+        };
+        let context = ((register - base) / stride) as usize;
+        (context < self.contexts.len()).then_some((context, base + (register - base) % stride))
+    }
+}
+
+impl Device for Plic {
+    fn read(&mut self, offset: Addr, _size: usize) -> Word {
+        let register = offset & !3;
+        let value = match register {
+            PLIC_PRIORITY..PLIC_PENDING => self.priority.get((register / 4) as usize).copied().unwrap_or(0),
+            PLIC_PENDING => self.pending as Word,
+            0x1004 => (self.pending >> 32) as Word,
+            _ => match self.context_register(register) {
+                Some((context, PLIC_ENABLE)) => self.contexts[context].enable as Word,
+                Some((context, 0x2004)) => (self.contexts[context].enable >> 32) as Word,
+                Some((context, PLIC_THRESHOLD)) => self.contexts[context].threshold,
+                Some((context, PLIC_CLAIM)) => match self.best(context) {
+                    Some(id) => {
+                        self.pending &= !(1 << id);
+                        self.claimed |= 1 << id;
+                        id
+                    }
+                    None => 0,
+                },
+                _ => 0,
+            },
+        };
+        value >> (8 * (offset & 3))
+    }
+
+    fn write(&mut self, offset: Addr, value: Word, size: usize) {
+        let lanes = |register| merge_lanes(register, offset, value, size);
+        let register = offset & !3;
+        if let PLIC_PRIORITY..PLIC_PENDING = register {
+            if let Some(priority) = self.priority.get_mut((register / 4) as usize) {
+                *priority = lanes(*priority).min(PLIC_MAX_PRIORITY);
+            }
+            return;
+        }
+        let Some((context, register)) = self.context_register(register) else {
+            return;
+        };
+        let Context { enable, threshold } = &mut self.contexts[context];
+        match register {
+            // Source 0 does not exist
+            PLIC_ENABLE => *enable = (*enable & !0xFFFF_FFFF) | (lanes(*enable as Word) & !1) as u64,
+            0x2004 => *enable = (*enable & 0xFFFF_FFFF) | (lanes((*enable >> 32) as Word) as u64) << 32,
+            PLIC_THRESHOLD => *threshold = lanes(*threshold).min(PLIC_MAX_PRIORITY),
+            PLIC_CLAIM => {
+                let id = lanes(0);
+                if id < PLIC_SOURCES {
+                    self.claimed &= !(1 << id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        for (id, source) in self.sources.iter().enumerate() {
+            let Some(device) = source else {
+                continue;
+            };
+            if self.claimed >> id & 1 == 0 && lock(device).irq() {
+                self.pending |= 1 << id;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.hart_irq(0)
+    }
+
+    fn hart_irq(&self, hart: usize) -> bool {
+        hart < self.contexts.len() && self.best(hart).is_some()
+    }
+
+    fn reset(&mut self) {
+        self.priority.fill(0);
+        self.pending = 0;
+        self.claimed = 0;
+        self.contexts.fill(Context::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::{Gpio, GPIO_IRQ_STATUS, GPIO_RISE_IE, GPIO_SIZE};
+    use crate::memory::Memory;
+
+    const BASE: Addr = 0x0C00_0000;
+
+    #[test]
+    fn test_claim_complete_and_routing() {
+        let mut memory = Memory::new();
+        let gpio = memory.map_device("gpio", 0x800, GPIO_SIZE, Gpio::new()).unwrap();
+        let plic = memory.map_device("plic", BASE, PLIC_SIZE, Plic::new()).unwrap();
+        let line = memory.bus_mut().route_irq("gpio").unwrap();
+        plic.lock().unwrap().connect(5, line);
+
+        memory.store(0x800 + GPIO_RISE_IE, 1, 0b010);
+        gpio.lock().unwrap().set_pin(0, true);
+        memory.tick_devices();
+        // Pending, but priority 0 never interrupts - and the GPIO no longer drives MEIP
+        assert_eq!(memory.load(BASE + PLIC_PENDING, 0b010), 1 << 5);
+        assert!(!memory.bus().irq_pending(0));
+
+        memory.store(BASE + PLIC_PRIORITY + 4 * 5, 3, 0b010);
+        memory.store(BASE + PLIC_ENABLE, 1 << 5, 0b010);
+        assert!(memory.bus().irq_pending(0));
+        memory.store(BASE + PLIC_THRESHOLD, 3, 0b010);
+        assert!(!memory.bus().irq_pending(0));
+        memory.store(BASE + PLIC_THRESHOLD, 0, 0b010);
+
+        assert_eq!(memory.load(BASE + PLIC_CLAIM, 0b010), 5);
+        assert_eq!(memory.load(BASE + PLIC_CLAIM, 0b010), 0);
+        // Still high, but not sampled until completed
+        memory.tick_devices();
+        assert!(!memory.bus().irq_pending(0));
+        memory.store(0x800 + GPIO_IRQ_STATUS, 1, 0b010);
+        memory.store(BASE + PLIC_CLAIM, 5, 0b010);
+        memory.tick_devices();
+        assert_eq!(memory.load(BASE + PLIC_PENDING, 0b010), 0);
+    }
+
+    #[test]
+    fn test_byte_stores_update_their_lane() {
+        let mut memory = Memory::new();
+        memory.map_device("plic", BASE, PLIC_SIZE, Plic::new()).unwrap();
+        memory.store(BASE + PLIC_ENABLE, 0x0000_0010, 0b010);
+        memory.store(BASE + PLIC_ENABLE + 1, 0x02, 0b000);
+        memory.store(BASE + PLIC_ENABLE + 3, 0x80, 0b000);
+        assert_eq!(memory.load(BASE + PLIC_ENABLE, 0b010), 0x8000_0210);
+
+        memory.store(BASE + 0x2004 + 2, 0x01, 0b000);
+        assert_eq!(memory.load(BASE + 0x2004, 0b010), 0x0001_0000);
+        memory.store(BASE + PLIC_PRIORITY + 4 * 5, 3, 0b000);
+        assert_eq!(memory.load(BASE + PLIC_PRIORITY + 4 * 5, 0b010), 3);
+    }
+
+    #[test]
+    fn test_context_per_hart() {
+        let mut memory = Memory::new();
+        let gpio = memory.map_device("gpio", 0x800, GPIO_SIZE, Gpio::new()).unwrap();
+        let plic = memory.map_device("plic", BASE, PLIC_SIZE, Plic::with_harts(2)).unwrap();
+        let line = memory.bus_mut().route_irq("gpio").unwrap();
+        plic.lock().unwrap().connect(5, line);
+        memory.store(0x800 + GPIO_RISE_IE, 1, 0b010);
+        gpio.lock().unwrap().set_pin(0, true);
+        memory.tick_devices();
+
+        // Only hart 1's context enables source 5
+        memory.store(BASE + PLIC_PRIORITY + 4 * 5, 1, 0b010);
+        memory.store(BASE + PLIC_ENABLE + PLIC_ENABLE_STRIDE, 1 << 5, 0b010);
+        assert!(!memory.bus().irq_pending(0));
+        assert!(memory.bus().irq_pending(1));
+        assert!(!memory.bus().irq_pending(2));
+
+        memory.store(BASE + PLIC_THRESHOLD + PLIC_CONTEXT_STRIDE, 1, 0b010);
+        assert!(!memory.bus().irq_pending(1));
+        memory.store(BASE + PLIC_THRESHOLD + PLIC_CONTEXT_STRIDE, 0, 0b010);
+        assert_eq!(memory.load(BASE + PLIC_CLAIM, 0b010), 0);
+        assert_eq!(memory.load(BASE + PLIC_CLAIM + PLIC_CONTEXT_STRIDE, 0b010), 5);
+        // No third context
+        assert_eq!(memory.load(BASE + PLIC_ENABLE + 2 * PLIC_ENABLE_STRIDE, 0b010), 0);
+    }
+}
@@ -0,0 +1,197 @@
+//! RAM and ROM regions beside main memory
+//!
+//! `Memory` holds one contiguous RAM. Any further region of a machine (a
+//! boot ROM, a second RAM bank) is a device on the bus that can also be
+//! executed from - see `Device::fetch`.
+//!
+//! ROM ignores stores. A reset (cold reset of the machine) returns either
+//! kind to its power-on image: zeros, then any preloaded contents.
+//!
+//! `BootRom` is the reset trampoline: it passes the hart ID in a0 and the
+//! device tree address in a1, then jumps to the loaded program.
+
+use crate::types::*;
+use crate::bus::Device;
+
+/// Byte-addressed RAM or ROM
+pub struct MemoryRegion {
+    data: Vec<u8>,
+    /// Power-on contents from offset 0
+    image: Vec<u8>,
+    writable: bool,
+}
+
+impl MemoryRegion {
+    pub fn ram(size: usize) -> Self {
+        Self { data: vec![0; size], image: Vec::new(), writable: true }
+    }
+
+    /// ROM of `size` bytes starting with `contents` (truncated to fit)
+    pub fn rom(size: usize, contents: &[u8]) -> Self {
+        Self { writable: false, ..Self::ram(size) }.with_image(contents)
+    }
+
+    /// Power-on contents, loaded now and again on every reset
+    pub fn with_image(mut self, contents: &[u8]) -> Self {
+        self.image = contents[..contents.len().min(self.data.len())].to_vec();
+        self.reset();
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_rom(&self) -> bool {
+        !self.writable
+    }
+
+    /// Host-side copy into the region (works on ROM too)
+    pub fn load(&mut self, offset: usize, bytes: &[u8]) {
+        let start = offset.min(self.data.len());
+        let len = bytes.len().min(self.data.len() - start);
+        self.data[start..start + len].copy_from_slice(&bytes[..len]);
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Device for MemoryRegion {
+    fn read(&mut self, offset: Addr, size: usize) -> Word {
+        let offset = offset as usize;
+        (0..size).fold(0, |value, i| value | (*self.data.get(offset + i).unwrap_or(&0) as Word) << (8 * i))
+    }
+
+    fn write(&mut self, offset: Addr, value: Word, size: usize) {
+        if !self.writable {
+            return;
+        }
+        for (i, byte) in value.to_le_bytes().iter().take(size).enumerate() {
+            if let Some(slot) = self.data.get_mut(offset as usize + i) {
+                *slot = *byte;
+            }
+        }
+    }
+
+    fn fetch(&mut self, offset: Addr) -> Option<Word> {
+        Some(self.read(offset & !3, 4))
+    }
+
+    fn reset(&mut self) {
+        self.data.fill(0);
+        self.data[..self.image.len()].copy_from_slice(&self.image);
+    }
+}
+
+/// Size of the boot ROM window
+pub const BOOT_ROM_SIZE: Addr = 0x1000;
+/// Offsets of the entry point and device tree address words
+const BOOT_ENTRY: usize = 20;
+const BOOT_DTB: usize = 24;
+
+/// Reset trampoline ROM:
+///
+/// ```text
+/// auipc t0, 0
+/// csrr  a0, mhartid
+/// lw    a1, 24(t0)     # device tree
+/// lw    t0, 20(t0)     # entry point
+/// jr    t0
+/// .word entry, dtb
+/// ```
+pub struct BootRom {
+    rom: MemoryRegion,
+}
+
+impl BootRom {
+    pub fn new(entry: Addr, dtb: Addr) -> Self {
+        let code = [
+            InstructionEncoder::u_type(0b0010111, 5, 0),
+            InstructionEncoder::i_type(0b1110011, 10, 0b010, 0, crate::csr::MHARTID as i16),
+            InstructionEncoder::i_type(0b0000011, 11, 0b010, 5, BOOT_DTB as i16),
+            InstructionEncoder::i_type(0b0000011, 5, 0b010, 5, BOOT_ENTRY as i16),
+            InstructionEncoder::i_type(0b1100111, 0, 0b000, 5, 0),
+            entry,
+            dtb,
+        ];
+        let contents: Vec<u8> = code.iter().flat_map(|word| word.to_le_bytes()).collect();
+        Self { rom: MemoryRegion::rom(BOOT_ROM_SIZE as usize, &contents) }
+    }
+
+    /// Where the trampoline jumps (e.g. a loaded ELF's entry point)
+    pub fn set_entry(&mut self, entry: Addr) {
+        self.rom.image[BOOT_ENTRY..BOOT_ENTRY + 4].copy_from_slice(&entry.to_le_bytes());
+        self.rom.reset();
+    }
+
+    pub fn entry(&self) -> Addr {
+        self.word(BOOT_ENTRY)
+    }
+
+    pub fn dtb(&self) -> Addr {
+        self.word(BOOT_DTB)
+    }
+
+    fn word(&self, offset: usize) -> Word {
+        Word::from_le_bytes(self.rom.data[offset..offset + 4].try_into().unwrap())
+    }
+}
+
+impl Device for BootRom {
+    fn read(&mut self, offset: Addr, size: usize) -> Word {
+        self.rom.read(offset, size)
+    }
+
+    fn write(&mut self, _offset: Addr, _value: Word, _size: usize) {}
+
+    fn fetch(&mut self, offset: Addr) -> Option<Word> {
+        self.rom.fetch(offset)
+    }
+
+    fn reset(&mut self) {
+        self.rom.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_execute_from_rom() {
+        // Boot ROM at 0x1000 jumps to RAM at 0, which stores to the ROM (ignored)
+        // and to a second RAM bank
+        let boot = [
+            InstructionEncoder::i_type(0b0010011, 5, 0b000, 0, 7),          // li t0, 7
+            InstructionEncoder::i_type(0b1100111, 0, 0b000, 0, 0),          // jr x0
+        ];
+        let boot: Vec<u8> = boot.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+        let mut cpu = Cpu::new();
+        cpu.memory.map_device("rom", 0x1000, 0x100, MemoryRegion::rom(0x100, &boot)).unwrap();
+        let ram = cpu.memory.map_device("ram1", 0x2000, 0x100, MemoryRegion::ram(0x100)).unwrap();
+        cpu.load_program(&[
+            (0, InstructionEncoder::u_type(0b0110111, 6, 0x1000)),            // lui t1, 0x1
+            (4, InstructionEncoder::s_type(0b0100011, 0b010, 6, 5, 0)),       // sw t0, 0(t1)
+            (8, InstructionEncoder::u_type(0b0110111, 6, 0x2000)),            // lui t1, 0x2
+            (12, InstructionEncoder::s_type(0b0100011, 0b000, 6, 5, 0)),      // sb t0, 0(t1)
+            (16, InstructionEncoder::j_type(0b1101111, 0, 0)),
+        ]);
+        cpu.control.set_pc(0x1000);
+        cpu.run_cycles(10);
+
+        assert_eq!(cpu.registers.peek(5), 7);
+        assert_eq!(cpu.memory.load(0x1000, 0b010), boot_word(&boot));
+        assert_eq!(ram.lock().unwrap().bytes()[0], 7);
+        cpu.reset();
+        assert_eq!(cpu.memory.fetch(0x1000), boot_word(&boot));
+        assert_eq!(cpu.memory.fetch(0x2000), 0);
+    }
+
+    fn boot_word(boot: &[u8]) -> Word {
+        Word::from_le_bytes(boot[..4].try_into().unwrap())
+    }
+}
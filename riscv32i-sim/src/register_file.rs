@@ -81,6 +81,22 @@ impl RegisterFile {
         self.read_data_b
     }
 
+    /// Debug access - read a register without driving a port
+    pub fn peek(&self, reg: u8) -> Word {
+        if reg == 0 || (reg as usize) >= self.registers.len() {
+            0
+        } else {
+            self.registers[reg as usize]
+        }
+    }
+
+    /// Debug access - write a register without a clock edge (x0 stays 0)
+    pub fn poke(&mut self, reg: u8, value: Word) {
+        if reg != 0 && (reg as usize) < self.registers.len() {
+            self.registers[reg as usize] = value;
+        }
+    }
+
     /// Debug access - display RISC-V ABI register names
     pub fn dump_registers(&self, start: usize, count: usize) {
         let abi_names = [
@@ -102,3 +118,77 @@ impl RegisterFile {
         self.registers = [0; 32];
     }
 }
+
+/// Multi-ported RISC-V Register File for wide-issue cores
+/// N read ports and M write ports, x0 hardwired to zero
+/// All writes land on the same clock edge, then every read port is updated
+pub struct MultiPortRegisterFile {
+    registers: [Word; 32],
+    read_ports: usize,
+    write_ports: usize,
+
+    // Read port outputs (one per read port)
+    read_data: Vec<Word>,
+}
+
+impl MultiPortRegisterFile {
+    pub fn new(read_ports: usize, write_ports: usize) -> Self {
+        Self {
+            registers: [0; 32],
+            read_ports,
+            write_ports,
+            read_data: vec![0; read_ports],
+        }
+    }
+
+    /// Sequential write then combinational read - like RegisterFile::clock
+    /// Panics if more ports are driven than the register file has
+    pub fn clock(&mut self, read_addrs: &[u8], writes: &[(u8, Word)]) {
+        assert!(read_addrs.len() <= self.read_ports,
+            "{} reads requested, register file has {} read ports",
+            read_addrs.len(), self.read_ports);
+        assert!(writes.len() <= self.write_ports,
+            "{} writes requested, register file has {} write ports",
+            writes.len(), self.write_ports);
+
+        // Write on clock edge
+        // RISC-V RULE: Ignore writes to x0
+        for &(addr, data) in writes {
+            if addr != 0 && (addr as usize) < self.registers.len() {
+                self.registers[addr as usize] = data;
+            }
+        }
+
+        // Update read outputs
+        for (port, &addr) in read_addrs.iter().enumerate() {
+            self.read_data[port] = self.peek(addr);
+        }
+    }
+
+    pub fn get_read_data(&self, port: usize) -> Word {
+        self.read_data[port]
+    }
+
+    pub fn read_port_count(&self) -> usize {
+        self.read_ports
+    }
+
+    pub fn write_port_count(&self) -> usize {
+        self.write_ports
+    }
+
+    /// Debug access - read a register without driving a port
+    pub fn peek(&self, reg: u8) -> Word {
+        if reg == 0 || (reg as usize) >= self.registers.len() {
+            0
+        } else {
+            self.registers[reg as usize]
+        }
+    }
+
+    /// Reset all registers (except x0 which is always 0)
+    pub fn reset(&mut self) {
+        self.registers = [0; 32];
+        self.read_data.iter_mut().for_each(|data| *data = 0);
+    }
+}
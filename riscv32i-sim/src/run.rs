@@ -0,0 +1,388 @@
+//! Run-until-halt execution
+//!
+//! `Cpu::run` clocks the CPU until something stops it and reports why,
+//! so callers don't have to guess a cycle count or loop forever.
+//!
+//! A program exits through its syscall handler. ECALLs that reach the run
+//! loop unserviced still exit if they are an exit service of
+//! `RunConfig::exit_abi` (Linux unless changed). Any other ECALL is ignored.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::types::*;
+use crate::cpu::{Cpu, CpuEvent};
+use crate::csr::{self, MSTATUS_MIE};
+
+/// Why `Cpu::run` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Program exited through ECALL (code in `RunResult::exit_code`)
+    Exit,
+    /// EBREAK with no trap handler installed
+    Ebreak,
+    /// PC reached a breakpoint (the instruction has not executed)
+    Breakpoint(Addr),
+    /// Undecodable instruction with no trap handler installed
+    IllegalInstruction { pc: Addr, word: Word },
+    /// Cycle budget used up
+    CycleLimit,
+    /// Instruction budget used up
+    InstructionLimit,
+    /// Wall-clock budget used up
+    Timeout,
+    /// `RunConfig::interrupt` was raised (e.g. Ctrl-C in a debugger)
+    Interrupted,
+    /// PC can never change again - a jump/branch to itself (e.g. `jal x0, 0`)
+    /// or WFI, with no interrupt enabled to break out of it
+    SelfLoop(Addr),
+}
+
+/// Exit services of a syscall ABI, for ECALLs no handler serviced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitAbi {
+    /// a7 = 93 exit or 94 exit_group, code in a0
+    Linux,
+    /// a7 = 10 exit with code 0, a7 = 17 exit2 with code in a0 (RARS/SPIM)
+    Rars,
+}
+
+/// Stop conditions for `Cpu::run` (all budgets are per call)
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    pub max_cycles: Option<u64>,
+    pub max_instructions: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub breakpoints: Vec<Addr>,
+    pub stop_on_ebreak: bool,
+    pub detect_self_loop: bool,
+    /// Exit convention for unserviced ECALLs (`None` never exits)
+    pub exit_abi: Option<ExitAbi>,
+    /// Checked with the timeout; cleared when it stops a run
+    pub interrupt: Option<Arc<AtomicBool>>,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            max_cycles: None,
+            max_instructions: None,
+            timeout: None,
+            breakpoints: Vec::new(),
+            stop_on_ebreak: true,
+            detect_self_loop: true,
+            exit_abi: Some(ExitAbi::Linux),
+            interrupt: None,
+        }
+    }
+}
+
+impl RunConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_cycles(mut self, cycles: u64) -> Self {
+        self.max_cycles = Some(cycles);
+        self
+    }
+
+    pub fn with_max_instructions(mut self, instructions: u64) -> Self {
+        self.max_instructions = Some(instructions);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_breakpoint(mut self, addr: Addr) -> Self {
+        self.breakpoints.push(addr);
+        self
+    }
+
+    pub fn with_exit_abi(mut self, abi: ExitAbi) -> Self {
+        self.exit_abi = Some(abi);
+        self
+    }
+
+    pub fn with_interrupt(mut self, interrupt: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+}
+
+/// Outcome of `Cpu::run`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunResult {
+    pub reason: StopReason,
+    /// Exit code for `StopReason::Exit`
+    pub exit_code: Option<i32>,
+    /// PC when the run stopped
+    pub pc: Addr,
+    /// Cycles clocked during this run
+    pub cycles: u64,
+    /// Instructions retired during this run
+    pub instructions: u64,
+}
+
+impl RunResult {
+    /// Program exited with code 0 (test harness pass)
+    pub fn passed(&self) -> bool {
+        self.reason == StopReason::Exit && self.exit_code == Some(0)
+    }
+}
+
+/// Wall clock and interrupt flag are only sampled every this many cycles
+pub(crate) const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// Bookkeeping for one `run` call (shared with the fast interpreter)
+pub(crate) struct RunState {
+    start: Instant,
+    start_cycles: u64,
+    start_instret: u64,
+    next_timeout_check: u64,
+    first: bool,
+}
+
+impl RunState {
+    pub(crate) fn new(cpu: &Cpu) -> Self {
+        Self {
+            start: Instant::now(),
+            start_cycles: cpu.get_cycle_count(),
+            start_instret: cpu.csrs.get_instret(),
+            next_timeout_check: 0,
+            first: true,
+        }
+    }
+
+    pub(crate) fn cycles(&self, cpu: &Cpu) -> u64 {
+        cpu.get_cycle_count() - self.start_cycles
+    }
+
+    pub(crate) fn instructions(&self, cpu: &Cpu) -> u64 {
+        cpu.csrs.get_instret() - self.start_instret
+    }
+}
+
+impl Cpu {
+    /// Clock until a stop condition from `config` is hit
+    /// A breakpoint on the starting PC is ignored so a stopped run can resume
+    pub fn run(&mut self, config: &RunConfig) -> RunResult {
+        let mut state = RunState::new(self);
+        loop {
+            if let Some(reason) = self.check_stop(config, &mut state) {
+                return self.finish(reason, None, &state);
+            }
+            if let Some((reason, exit_code)) = self.run_step(config) {
+                return self.finish(reason, exit_code, &state);
+            }
+        }
+    }
+
+    /// Stop conditions checked before the instruction at the PC runs
+    pub(crate) fn check_stop(&self, config: &RunConfig, state: &mut RunState) -> Option<StopReason> {
+        let cycles = state.cycles(self);
+        let instructions = state.instructions(self);
+        let pc = self.control.get_pc();
+
+        if !state.first && config.breakpoints.contains(&pc) {
+            return Some(StopReason::Breakpoint(pc));
+        }
+        if config.max_cycles.is_some_and(|max| cycles >= max) {
+            return Some(StopReason::CycleLimit);
+        }
+        if config.max_instructions.is_some_and(|max| instructions >= max) {
+            return Some(StopReason::InstructionLimit);
+        }
+        if (config.timeout.is_some() || config.interrupt.is_some()) && cycles >= state.next_timeout_check {
+            state.next_timeout_check = cycles + TIMEOUT_CHECK_INTERVAL;
+            if config.timeout.is_some_and(|timeout| state.start.elapsed() >= timeout) {
+                return Some(StopReason::Timeout);
+            }
+            if config.interrupt.as_ref().is_some_and(|interrupt| interrupt.swap(false, Ordering::Relaxed)) {
+                return Some(StopReason::Interrupted);
+            }
+        }
+        state.first = false;
+        None
+    }
+
+    /// Clock once and act on what the instruction did - the stop reason
+    /// (and exit code) if the run is over
+    pub(crate) fn run_step(&mut self, config: &RunConfig) -> Option<(StopReason, Option<i32>)> {
+        let pc = self.control.get_pc();
+        let inst = Instruction::new(self.memory.fetch(pc));
+        self.clock();
+        self.stop_after(pc, inst, config)
+    }
+
+    /// What the instruction `inst` at `pc` just did ends the run (with an
+    /// exit code) under `config`
+    pub(crate) fn stop_after(&self, pc: Addr, inst: Instruction, config: &RunConfig) -> Option<(StopReason, Option<i32>)> {
+        match self.last_event() {
+            Some(CpuEvent::Ecall) => {
+                if let Some(code) = config.exit_abi.and_then(|abi| self.exit_request(abi)) {
+                    return Some((StopReason::Exit, Some(code)));
+                }
+            }
+            Some(CpuEvent::Exit(code)) => return Some((StopReason::Exit, Some(code))),
+            Some(CpuEvent::Ebreak) if config.stop_on_ebreak => return Some((StopReason::Ebreak, None)),
+            Some(CpuEvent::IllegalInstruction(word)) => {
+                return Some((StopReason::IllegalInstruction { pc, word }, None));
+            }
+            _ => {}
+        }
+
+        if config.detect_self_loop && self.control.get_pc() == pc {
+            let control_flow = matches!(inst.opcode(), 0b1101111 | 0b1100111 | 0b1100011);
+            let stuck_jump = control_flow && !self.interrupts_enabled();
+            let stuck_wfi = self.is_waiting_for_interrupt() && !self.can_wake();
+            if stuck_jump || stuck_wfi {
+                return Some((StopReason::SelfLoop(pc), None));
+            }
+        }
+        None
+    }
+
+    /// Exit code if the ECALL that just executed was an exit service of `abi`
+    fn exit_request(&self, abi: ExitAbi) -> Option<i32> {
+        let a0 = self.registers.peek(10) as i32;
+        match (abi, self.registers.peek(17)) {
+            (ExitAbi::Linux, 93 | 94) => Some(a0),  // exit, exit_group
+            (ExitAbi::Rars, 10) => Some(0),         // exit
+            (ExitAbi::Rars, 17) => Some(a0),        // exit2
+            _ => None,
+        }
+    }
+
+    /// Some interrupt could still arrive and move the PC
+    pub(crate) fn interrupts_enabled(&self) -> bool {
+        let mstatus = self.csrs.read(csr::MSTATUS).unwrap_or(0);
+        let mie = self.csrs.read(csr::MIE).unwrap_or(0);
+        mstatus & MSTATUS_MIE != 0 && mie != 0
+    }
+
+    /// Some interrupt could still end a WFI stall - it resumes on any
+    /// enabled pending interrupt, whatever mstatus.MIE says
+    fn can_wake(&self) -> bool {
+        self.csrs.read(csr::MIE).unwrap_or(0) != 0
+    }
+
+    pub(crate) fn finish(&self, reason: StopReason, exit_code: Option<i32>, state: &RunState) -> RunResult {
+        RunResult {
+            reason,
+            exit_code,
+            pc: self.control.get_pc(),
+            cycles: state.cycles(self),
+            instructions: state.instructions(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{addi, cpu_with};
+
+    const ECALL: Word = 0x0000_0073;
+    const EBREAK: Word = 0x0010_0073;
+    const WFI: Word = 0x1050_0073;
+
+    #[test]
+    fn test_ecall_exit_code() {
+        let mut cpu = cpu_with(&[addi(10, 0, 7), addi(17, 0, 93), ECALL, addi(10, 0, 1)]);
+
+        let result = cpu.run(&RunConfig::new());
+
+        assert_eq!(result.reason, StopReason::Exit);
+        assert_eq!(result.exit_code, Some(7));
+        assert_eq!(result.instructions, 3);
+        assert_eq!(result.pc, 12);
+    }
+
+    #[test]
+    fn test_exit_services_follow_abi() {
+        let program = [addi(10, 0, 3), addi(17, 0, 17), ECALL, addi(17, 0, 93), ECALL];
+
+        let linux = cpu_with(&program).run(&RunConfig::new());
+        assert_eq!((linux.reason, linux.exit_code, linux.pc), (StopReason::Exit, Some(3), 20));
+
+        let rars = cpu_with(&program).run(&RunConfig::new().with_exit_abi(ExitAbi::Rars));
+        assert_eq!((rars.reason, rars.exit_code, rars.pc), (StopReason::Exit, Some(3), 12));
+    }
+
+    #[test]
+    fn test_ebreak_illegal_and_self_loop() {
+        let mut cpu = cpu_with(&[addi(5, 0, 1), EBREAK, InstructionEncoder::j_type(0b1101111, 0, 0)]);
+        assert_eq!(cpu.run(&RunConfig::new()).reason, StopReason::Ebreak);
+        assert_eq!(cpu.run(&RunConfig::new()).reason, StopReason::SelfLoop(8));
+
+        let mut cpu = cpu_with(&[addi(5, 0, 1), 0xFFFF_FFFF]);
+        assert_eq!(
+            cpu.run(&RunConfig::new()).reason,
+            StopReason::IllegalInstruction { pc: 4, word: 0xFFFF_FFFF }
+        );
+        assert_eq!(cpu.control.get_pc(), 4);
+    }
+
+    #[test]
+    fn test_budgets_and_breakpoints() {
+        // Endless counting loop: addi t0, t0, 1; j -4
+        let program = [addi(5, 5, 1), InstructionEncoder::j_type(0b1101111, 0, -4)];
+
+        let mut cpu = cpu_with(&program);
+        let result = cpu.run(&RunConfig::new().with_max_cycles(10));
+        assert_eq!((result.reason, result.cycles), (StopReason::CycleLimit, 10));
+
+        let mut cpu = cpu_with(&program);
+        let result = cpu.run(&RunConfig::new().with_timeout(Duration::from_millis(1)));
+        assert_eq!(result.reason, StopReason::Timeout);
+
+        // Raised from another thread; the run clears it
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let raise = {
+            let interrupt = interrupt.clone();
+            std::thread::spawn(move || interrupt.store(true, Ordering::Relaxed))
+        };
+        raise.join().unwrap();
+        let result = cpu.run(&RunConfig::new().with_interrupt(interrupt.clone()));
+        assert_eq!((result.reason, result.instructions), (StopReason::Interrupted, 0));
+        assert!(!interrupt.load(Ordering::Relaxed));
+
+        // Breakpoint on the loop body; resuming runs exactly one iteration
+        let mut cpu = cpu_with(&program);
+        let config = RunConfig::new().with_breakpoint(4);
+        assert_eq!(cpu.run(&config).reason, StopReason::Breakpoint(4));
+        let result = cpu.run(&config);
+        assert_eq!((result.reason, result.instructions), (StopReason::Breakpoint(4), 2));
+        assert_eq!(cpu.registers.peek(5), 2);
+    }
+
+    #[test]
+    fn test_wfi_wakes_with_interrupts_disabled() {
+        use crate::gpio::{Gpio, GPIO_RISE_IE, GPIO_SIZE};
+        use crate::timeline::Timeline;
+
+        // wfi; addi t0, x0, 1; j . - a GPIO edge at cycle 20 raises MEIP
+        let program = [WFI, addi(5, 0, 1), InstructionEncoder::j_type(0b1101111, 0, 0)];
+        let mut cpu = cpu_with(&program);
+        let gpio = Gpio::new().with_timeline(Timeline::parse("20 pin0 1").unwrap()).unwrap();
+        cpu.memory.map_device("gpio", 0x2000, GPIO_SIZE, gpio).unwrap();
+        cpu.memory.store(0x2000 + GPIO_RISE_IE, 1, 0b010);
+        cpu.csrs.write(csr::MIE, csr::MIP_MEIP);
+
+        // mstatus.MIE is clear: WFI resumes without trapping
+        let result = cpu.run(&RunConfig::new().with_max_cycles(1000));
+        assert_eq!(result.reason, StopReason::SelfLoop(8));
+        assert_eq!(cpu.registers.peek(5), 1);
+        assert!(result.cycles > 20);
+
+        // Nothing enabled in mie: stuck for good, before the next instruction
+        let mut cpu = cpu_with(&program);
+        assert_eq!(cpu.run(&RunConfig::new()).reason, StopReason::SelfLoop(4));
+        assert_eq!(cpu.registers.peek(5), 0);
+    }
+}
@@ -0,0 +1,515 @@
+//! Superscalar in-order issue model
+//!
+//! An N-wide in-order core: every cycle up to `width` consecutive
+//! instructions issue together, as long as they obey the pairing rules:
+//! - no RAW or WAW dependency on an older instruction in the same group
+//! - enough ALU, memory and branch units for the group
+//! - enough register file read and write ports
+//! - a branch/jump ends the group, SYSTEM instructions issue alone
+//!
+//! AMOs use a memory port and depend on rs1 and rs2 like a store.
+//!
+//! Reports IPC and per-cycle issue-slot utilization.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::types::*;
+use crate::memory::Memory;
+use crate::register_file::MultiPortRegisterFile;
+use crate::control_unit::ControlUnit;
+use crate::alu::Alu;
+use crate::cpu::Cpu;
+
+/// Issue width and structural resources of the core
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IssueConfig {
+    pub width: usize,
+    pub alu_units: usize,
+    pub mem_ports: usize,
+    pub branch_units: usize,
+    pub rf_read_ports: usize,
+    pub rf_write_ports: usize,
+}
+
+impl IssueConfig {
+    /// Classic dual-issue pairing: two ALUs, one memory port, one branch unit
+    pub fn dual_issue() -> Self {
+        Self {
+            width: 2,
+            alu_units: 2,
+            mem_ports: 1,
+            branch_units: 1,
+            rf_read_ports: 4,
+            rf_write_ports: 2,
+        }
+    }
+
+    /// N-wide core with a full set of units and ports for every slot
+    pub fn wide(width: usize) -> Self {
+        Self {
+            width,
+            alu_units: width,
+            mem_ports: width,
+            branch_units: 1,
+            rf_read_ports: 2 * width,
+            rf_write_ports: width,
+        }
+    }
+}
+
+/// Functional unit class an instruction needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueClass {
+    Alu,
+    Memory,
+    Branch,
+    System,
+}
+
+/// Why a cycle issued fewer than `width` instructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IssueStall {
+    DataHazard,   // RAW/WAW on an older instruction in the group
+    AluBusy,      // All ALUs taken
+    MemPortBusy,  // All memory ports taken
+    BranchBusy,   // All branch units taken
+    ReadPorts,    // Not enough register file read ports
+    WritePorts,   // Not enough register file write ports
+    ControlFlow,  // Group ended by a branch/jump
+    Serializing,  // SYSTEM instruction issues alone
+}
+
+/// Instructions issued in one cycle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssueGroup {
+    pub pcs: Vec<Addr>,
+    pub stall: Option<IssueStall>,
+}
+
+/// Issue statistics
+#[derive(Debug, Clone)]
+pub struct IssueStats {
+    pub width: usize,
+    pub cycles: u64,
+    pub instructions: u64,
+    /// `slot_fill[k]` = number of cycles that issued exactly k instructions
+    pub slot_fill: Vec<u64>,
+    pub stalls: HashMap<IssueStall, u64>,
+}
+
+impl IssueStats {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            cycles: 0,
+            instructions: 0,
+            slot_fill: vec![0; width + 1],
+            stalls: HashMap::new(),
+        }
+    }
+
+    /// Instructions per cycle
+    pub fn ipc(&self) -> f64 {
+        if self.cycles == 0 {
+            0.0
+        } else {
+            self.instructions as f64 / self.cycles as f64
+        }
+    }
+
+    /// Fraction of all issue slots that were filled
+    pub fn utilization(&self) -> f64 {
+        if self.cycles == 0 {
+            0.0
+        } else {
+            self.instructions as f64 / (self.cycles * self.width as u64) as f64
+        }
+    }
+
+    /// Fraction of cycles in which issue slot `slot` held an instruction
+    pub fn slot_utilization(&self, slot: usize) -> f64 {
+        if self.cycles == 0 {
+            return 0.0;
+        }
+        let busy: u64 = self.slot_fill.iter().skip(slot + 1).sum();
+        busy as f64 / self.cycles as f64
+    }
+
+    pub fn stall_count(&self, stall: IssueStall) -> u64 {
+        self.stalls.get(&stall).copied().unwrap_or(0)
+    }
+}
+
+impl fmt::Display for IssueStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Cycles:       {}", self.cycles)?;
+        writeln!(f, "Instructions: {}", self.instructions)?;
+        writeln!(f, "IPC:          {:.3}", self.ipc())?;
+        writeln!(f, "Utilization:  {:.1}%", self.utilization() * 100.0)?;
+        for (issued, cycles) in self.slot_fill.iter().enumerate() {
+            writeln!(f, "  {} issued: {} cycles", issued, cycles)?;
+        }
+        for slot in 0..self.width {
+            writeln!(f, "  slot {}: {:.1}% busy", slot, self.slot_utilization(slot) * 100.0)?;
+        }
+        let mut stalls: Vec<_> = self.stalls.iter().collect();
+        stalls.sort_by_key(|(_, &count)| std::cmp::Reverse(count));
+        for (stall, count) in stalls {
+            writeln!(f, "  {:?}: {}", stall, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// One decoded instruction waiting to issue
+struct Slot {
+    pc: Addr,
+    inst: Instruction,
+    ctrl: ControlSignals,
+    class: IssueClass,
+    sources: [Option<u8>; 2],
+    dest: Option<u8>,
+}
+
+impl Slot {
+    fn new(pc: Addr, inst: Instruction, ctrl: ControlSignals) -> Self {
+        let class = if inst.opcode() == 0b1110011 {
+            IssueClass::System
+        } else if ctrl.branch || ctrl.jump {
+            IssueClass::Branch
+        } else if ctrl.mem_read || ctrl.mem_write {
+            IssueClass::Memory
+        } else {
+            IssueClass::Alu
+        };
+
+        // x0 reads are hardwired and never need a read port
+        let used = |reg: u8| if reg == 0 { None } else { Some(reg) };
+        let sources = match inst.opcode() {
+            0b0100011 | 0b1100011 | 0b0110011 | 0b0101111 => [used(inst.rs1()), used(inst.rs2())],  // Store, Branch, R-type, AMO
+            0b1100111 | 0b0000011 | 0b0010011 => [used(inst.rs1()), None],              // JALR, Load, I-type
+            _ => [None, None],                                                          // LUI, AUIPC, JAL, SYSTEM
+        };
+
+        let dest = if ctrl.reg_write && inst.rd() != 0 { Some(inst.rd()) } else { None };
+
+        Self { pc, inst, ctrl, class, sources, dest }
+    }
+
+    fn reads(&self) -> usize {
+        self.sources.iter().flatten().count()
+    }
+
+    fn writes(&self) -> usize {
+        self.dest.iter().count()
+    }
+}
+
+/// N-wide in-order RV32I core
+pub struct SuperscalarCore {
+    pub memory: Memory,
+    pub registers: MultiPortRegisterFile,
+    pub control: ControlUnit,
+
+    // One datapath ALU per issue slot (address generation, compares);
+    // `alu_units` limits how many integer instructions may pair
+    alus: Vec<Alu>,
+
+    config: IssueConfig,
+    stats: IssueStats,
+
+    // LR/SC reservation (word address)
+    reservation: Option<Addr>,
+}
+
+impl SuperscalarCore {
+    pub fn new(config: IssueConfig) -> Self {
+        assert!(config.width > 0, "issue width must be at least 1");
+        assert!(config.alu_units > 0 && config.mem_ports > 0 && config.branch_units > 0,
+            "every instruction class needs at least one unit");
+        assert!(config.rf_read_ports >= 2 && config.rf_write_ports >= 1,
+            "register file needs at least 2 read ports and 1 write port");
+
+        Self {
+            memory: Memory::new(),
+            registers: MultiPortRegisterFile::new(config.rf_read_ports, config.rf_write_ports),
+            control: ControlUnit::new(),
+            alus: (0..config.width).map(|_| Alu::new()).collect(),
+            config,
+            stats: IssueStats::new(config.width),
+            reservation: None,
+        }
+    }
+
+    /// Single clock cycle - issue and execute one group
+    pub fn clock(&mut self) -> IssueGroup {
+        let (group, stall) = self.form_group();
+
+        // READ REGISTERS: every source operand of the group in one cycle
+        let mut read_addrs = Vec::new();
+        let mut read_port = Vec::with_capacity(group.len());
+        for slot in &group {
+            let ports = slot.sources.map(|reg| {
+                reg.map(|reg| {
+                    read_addrs.push(reg);
+                    read_addrs.len() - 1
+                })
+            });
+            read_port.push(ports);
+        }
+        self.registers.clock(&read_addrs, &[]);
+
+        // EXECUTE + MEMORY in program order
+        let mut writes = Vec::new();
+        let mut next_pc = group[0].pc;
+        for (i, slot) in group.iter().enumerate() {
+            let operand = |port: Option<usize>| port.map_or(0, |port| self.registers.get_read_data(port));
+            let rs1_data = operand(read_port[i][0]);
+            let rs2_data = operand(read_port[i][1]);
+
+            let (a, b) = Cpu::alu_operands(&slot.inst, &slot.ctrl, slot.pc, rs1_data, rs2_data);
+            let alu_result = self.alus[i].execute(slot.ctrl.alu_op, a, b);
+
+            let mut mem_data = 0;
+            if let Some(op) = slot.ctrl.amo {
+                mem_data = self.atomic_access(op, alu_result, rs2_data);
+            } else if slot.ctrl.mem_write {
+                self.memory.store(alu_result, rs2_data, slot.inst.funct3());
+            } else if slot.ctrl.mem_read {
+                mem_data = self.memory.load(alu_result, slot.inst.funct3());
+            }
+
+            if let Some(rd) = slot.dest {
+                let write_data = if slot.ctrl.mem_to_reg {
+                    mem_data
+                } else if slot.ctrl.jump {
+                    slot.pc.wrapping_add(4)
+                } else {
+                    alu_result
+                };
+                writes.push((rd, write_data));
+            }
+
+            // Only the last instruction of a group can redirect the PC
+            self.control.set_pc(slot.pc);
+            self.control.clock(slot.inst);
+            let taken = Cpu::should_branch(&slot.inst, rs1_data, rs2_data);
+            let target = Cpu::calculate_jump_target(&slot.inst, slot.pc, rs1_data);
+            self.control.update_pc(taken, target);
+            next_pc = self.control.get_pc();
+        }
+
+        // WRITE BACK: all results on the same clock edge
+        self.registers.clock(&[], &writes);
+        self.control.set_pc(next_pc);
+
+        self.stats.cycles += 1;
+        self.stats.instructions += group.len() as u64;
+        self.stats.slot_fill[group.len()] += 1;
+        if let Some(stall) = stall {
+            *self.stats.stalls.entry(stall).or_insert(0) += 1;
+        }
+
+        IssueGroup {
+            pcs: group.iter().map(|slot| slot.pc).collect(),
+            stall,
+        }
+    }
+
+    /// Atomic memory operation - returns the value for rd
+    fn atomic_access(&mut self, op: AmoOp, addr: Addr, rs2_data: Word) -> Word {
+        let addr = addr & !0x3;
+        match op {
+            AmoOp::Lr => {
+                self.reservation = Some(addr);
+                self.memory.load(addr, 0b010)
+            }
+            AmoOp::Sc if self.reservation.take() == Some(addr) => {
+                self.memory.store(addr, rs2_data, 0b010);
+                0
+            }
+            AmoOp::Sc => 1,
+            _ => {
+                let old = self.memory.load(addr, 0b010);
+                self.memory.store(addr, op.apply(old, rs2_data), 0b010);
+                old
+            }
+        }
+    }
+
+    /// Collect the next in-order group that may issue together
+    fn form_group(&mut self) -> (Vec<Slot>, Option<IssueStall>) {
+        let mut group: Vec<Slot> = Vec::with_capacity(self.config.width);
+        let mut pc = self.control.get_pc();
+
+        while group.len() < self.config.width {
+            let inst = Instruction::new(self.memory.fetch(pc));
+            self.control.clock(inst);
+            let slot = Slot::new(pc, inst, self.control.get_control_signals());
+
+            if let Some(stall) = self.pairing_conflict(&group, &slot) {
+                return (group, Some(stall));
+            }
+
+            let class = slot.class;
+            group.push(slot);
+
+            if group.len() < self.config.width {
+                match class {
+                    IssueClass::Branch => return (group, Some(IssueStall::ControlFlow)),
+                    IssueClass::System => return (group, Some(IssueStall::Serializing)),
+                    _ => {}
+                }
+            }
+            pc = pc.wrapping_add(4);
+        }
+
+        (group, None)
+    }
+
+    /// Pairing rules - returns the reason `slot` cannot join `group`
+    fn pairing_conflict(&self, group: &[Slot], slot: &Slot) -> Option<IssueStall> {
+        // The oldest instruction always issues
+        if group.is_empty() {
+            return None;
+        }
+        if slot.class == IssueClass::System {
+            return Some(IssueStall::Serializing);
+        }
+
+        let dests: Vec<u8> = group.iter().filter_map(|older| older.dest).collect();
+        let raw = slot.sources.iter().flatten().any(|reg| dests.contains(reg));
+        let waw = slot.dest.is_some_and(|rd| dests.contains(&rd));
+        if raw || waw {
+            return Some(IssueStall::DataHazard);
+        }
+
+        let in_class = group.iter().filter(|older| older.class == slot.class).count();
+        match slot.class {
+            IssueClass::Alu if in_class >= self.config.alu_units => return Some(IssueStall::AluBusy),
+            IssueClass::Memory if in_class >= self.config.mem_ports => return Some(IssueStall::MemPortBusy),
+            IssueClass::Branch if in_class >= self.config.branch_units => return Some(IssueStall::BranchBusy),
+            _ => {}
+        }
+
+        let reads: usize = group.iter().map(Slot::reads).sum();
+        if reads + slot.reads() > self.config.rf_read_ports {
+            return Some(IssueStall::ReadPorts);
+        }
+        let writes: usize = group.iter().map(Slot::writes).sum();
+        if writes + slot.writes() > self.config.rf_write_ports {
+            return Some(IssueStall::WritePorts);
+        }
+
+        None
+    }
+
+    pub fn run_cycles(&mut self, count: usize) {
+        for _ in 0..count {
+            self.clock();
+        }
+    }
+
+    pub fn get_stats(&self) -> &IssueStats {
+        &self.stats
+    }
+
+    pub fn get_config(&self) -> IssueConfig {
+        self.config
+    }
+
+    pub fn reset(&mut self) {
+        self.control.reset();
+        self.registers.reset();
+        self.memory.reset();
+        self.reservation = None;
+        self.stats = IssueStats::new(self.config.width);
+    }
+
+    /// Load RISC-V program into memory
+    pub fn load_program(&mut self, program: &[(Addr, Word)]) {
+        self.memory.load_program(program);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{addi, lw};
+
+    #[test]
+    fn test_independent_instructions_dual_issue() {
+        let mut core = SuperscalarCore::new(IssueConfig::dual_issue());
+        core.memory.load_words(0, &[addi(1, 0, 1), addi(2, 0, 2), addi(3, 0, 3), addi(4, 0, 4)]);
+
+        assert_eq!(core.clock().pcs, vec![0, 4]);
+        assert_eq!(core.clock().pcs, vec![8, 12]);
+        assert_eq!(core.get_stats().ipc(), 2.0);
+        assert_eq!(core.registers.peek(4), 4);
+    }
+
+    #[test]
+    fn test_dependent_chain_single_issue() {
+        let mut core = SuperscalarCore::new(IssueConfig::dual_issue());
+        core.memory.load_words(0, &[addi(1, 0, 1), addi(1, 1, 1), addi(1, 1, 1), addi(1, 1, 1)]);
+
+        core.run_cycles(3);
+
+        assert_eq!(core.registers.peek(1), 3);
+        assert_eq!(core.get_stats().ipc(), 1.0);
+        assert_eq!(core.get_stats().stall_count(IssueStall::DataHazard), 3);
+    }
+
+    #[test]
+    fn test_memory_port_limit() {
+        let mut core = SuperscalarCore::new(IssueConfig::dual_issue());
+        core.memory.load_words(0, &[lw(1, 0, 64), lw(2, 0, 68)]);
+        core.load_program(&[(64, 11), (68, 22)]);
+
+        let group = core.clock();
+        assert_eq!(group.pcs, vec![0]);
+        assert_eq!(group.stall, Some(IssueStall::MemPortBusy));
+        core.clock();
+
+        assert_eq!(core.registers.peek(1), 11);
+        assert_eq!(core.registers.peek(2), 22);
+    }
+
+    #[test]
+    fn test_loop_matches_sequential_result() {
+        // x1 = 5; loop: x2 += x1; x1 -= 1; bne x1, x0, loop
+        let program = [
+            addi(1, 0, 5),
+            InstructionEncoder::r_type(0b0110011, 2, 0b000, 2, 1, 0),
+            addi(1, 1, -1),
+            InstructionEncoder::b_type(0b1100011, 0b001, 1, 0, -8),
+        ];
+        let mut core = SuperscalarCore::new(IssueConfig::wide(4));
+        core.memory.load_words(0, &program);
+
+        while core.control.get_pc() != 16 {
+            core.clock();
+        }
+
+        assert_eq!(core.registers.peek(2), 15);
+        assert_eq!(core.get_stats().instructions, 16);
+        assert!(core.get_stats().stall_count(IssueStall::ControlFlow) > 0);
+    }
+
+    #[test]
+    fn test_amo_waits_for_its_operands() {
+        // x1 = 64; x2 = 5; amoadd.w x3, x2, (x1)
+        let amoadd = InstructionEncoder::r_type(0b0101111, 3, 0b010, 1, 2, 0);
+        let mut core = SuperscalarCore::new(IssueConfig::wide(4));
+        core.memory.load_words(0, &[addi(1, 0, 64), addi(2, 0, 5), amoadd]);
+        core.load_program(&[(64, 10)]);
+
+        let group = core.clock();
+        assert_eq!((group.pcs, group.stall), (vec![0, 4], Some(IssueStall::DataHazard)));
+        core.clock();
+
+        assert_eq!(core.registers.peek(3), 10);
+        assert_eq!(core.memory.load(64, 0b010), 15);
+    }
+}
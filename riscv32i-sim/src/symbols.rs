@@ -0,0 +1,148 @@
+//! Program symbols (from ELF .symtab) for the debugger, tracer and profiler
+
+use std::collections::HashMap;
+
+use crate::types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Object,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: Addr,
+    pub size: Word,
+    pub kind: SymbolKind,
+}
+
+impl Symbol {
+    /// Address lies inside the symbol (size 0 symbols only cover their own address)
+    pub fn contains(&self, addr: Addr) -> bool {
+        addr == self.addr || (addr > self.addr && addr - self.addr < self.size)
+    }
+}
+
+/// Symbols sorted by address, with lookup by name
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    by_name: HashMap<String, usize>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one symbol; a repeated name finds the highest-addressed one
+    pub fn insert(&mut self, symbol: Symbol) {
+        let index = self.symbols.partition_point(|s| s.addr <= symbol.addr);
+        for i in self.by_name.values_mut().filter(|i| **i >= index) {
+            *i += 1;
+        }
+        let slot = self.by_name.entry(symbol.name.clone()).or_insert(index);
+        *slot = (*slot).max(index);
+        self.symbols.insert(index, symbol);
+    }
+
+    /// Sort by address (stable, so equal addresses keep insertion order) and
+    /// rebuild the name index in one pass
+    fn reindex(&mut self) {
+        self.symbols.sort_by_key(|s| s.addr);
+        self.by_name = self.symbols.iter().enumerate().map(|(i, s)| (s.name.clone(), i)).collect();
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.by_name.get(name).map(|&i| &self.symbols[i])
+    }
+
+    /// Address of a symbol by name
+    pub fn lookup(&self, name: &str) -> Option<Addr> {
+        self.get(name).map(|s| s.addr)
+    }
+
+    /// Symbol covering `addr` and the offset into it (functions preferred)
+    pub fn symbolize(&self, addr: Addr) -> Option<(&Symbol, Word)> {
+        let end = self.symbols.partition_point(|s| s.addr <= addr);
+        let candidates = self.symbols[..end].iter().rev().filter(|s| s.contains(addr));
+        let mut best = None;
+        for symbol in candidates {
+            if symbol.kind == SymbolKind::Function {
+                best = Some(symbol);
+                break;
+            }
+            best = best.or(Some(symbol));
+        }
+        best.map(|s| (s, addr - s.addr))
+    }
+
+    /// "name+0xoff", "name", or the bare address when nothing covers it
+    pub fn format(&self, addr: Addr) -> String {
+        match self.symbolize(addr) {
+            Some((symbol, 0)) => symbol.name.clone(),
+            Some((symbol, offset)) => format!("{}+0x{:x}", symbol.name, offset),
+            None => format!("0x{:08x}", addr),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// Bulk loading sorts once instead of inserting one by one
+impl Extend<Symbol> for SymbolTable {
+    fn extend<I: IntoIterator<Item = Symbol>>(&mut self, symbols: I) {
+        self.symbols.extend(symbols);
+        self.reindex();
+    }
+}
+
+impl FromIterator<Symbol> for SymbolTable {
+    fn from_iter<I: IntoIterator<Item = Symbol>>(symbols: I) -> Self {
+        let mut table = Self::new();
+        table.extend(symbols);
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str, addr: Addr) -> Symbol {
+        Symbol { name: name.to_string(), addr, size: 4, kind: SymbolKind::Function }
+    }
+
+    #[test]
+    fn test_insert_matches_bulk_load() {
+        let symbols = [symbol("c", 0x30), symbol("a", 0x10), symbol("dup", 0x40), symbol("b", 0x20), symbol("dup", 0x08), symbol("a2", 0x10)];
+        let mut inserted = SymbolTable::new();
+        for symbol in symbols.iter().cloned() {
+            inserted.insert(symbol);
+        }
+        let collected: SymbolTable = symbols.iter().cloned().collect();
+
+        for table in [&inserted, &collected] {
+            let order: Vec<_> = table.iter().map(|s| s.name.as_str()).collect();
+            assert_eq!(order, ["dup", "a", "a2", "b", "c", "dup"]);
+            for name in ["a", "a2", "b", "c"] {
+                assert_eq!(table.get(name).unwrap().name, name);
+            }
+            assert_eq!(table.lookup("dup"), Some(0x40));
+            assert_eq!(table.format(0x22), "b+0x2");
+        }
+    }
+}
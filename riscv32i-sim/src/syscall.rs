@@ -0,0 +1,334 @@
+//! ECALL system-call layer
+//!
+//! The `Cpu` hands every ECALL to its `SyscallHandler`, if one is
+//! installed. Without a handler (or when the handler doesn't know the
+//! service) ECALL is an ordinary exception: it traps to mtvec or is
+//! reported as `CpuEvent::Ecall`.
+//!
+//! `RarsSyscalls` implements the RARS/SPIM teaching-simulator services,
+//! selected by a7 with arguments in a0/a1 and results in a0/a1.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::*;
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+
+/// ABI register numbers used by the calling convention
+pub const A0: u8 = 10;
+pub const A1: u8 = 11;
+pub const A7: u8 = 17;
+
+/// What the CPU should do after a system call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallAction {
+    /// Service done, continue after the ECALL
+    Continue,
+    /// Program requested exit with this code
+    Exit(i32),
+    /// Not a service this handler implements - raise the ECALL exception
+    Unhandled,
+}
+
+/// Host-side implementation of ECALL services
+pub trait SyscallHandler: Send {
+    /// Service the ECALL that just executed (PC already points past it)
+    fn ecall(&mut self, cpu: &mut Cpu) -> SyscallAction;
+
+    /// CPU reset - drop per-program state such as the heap break
+    fn reset(&mut self) {}
+}
+
+/// Shared byte buffer that collects program output (for tests and embedders)
+#[derive(Debug, Clone, Default)]
+pub struct OutputCapture(Arc<Mutex<Vec<u8>>>);
+
+impl OutputCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Output so far as text (invalid UTF-8 replaced)
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl Write for OutputCapture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Default start of the sbrk heap - the upper half of `memory`
+pub fn default_heap_base(memory: &Memory) -> Addr {
+    memory.base().wrapping_add((memory.size() / 2) as Addr & !3)
+}
+
+/// Longest string print_string will read
+const MAX_STRING: usize = 4096;
+
+/// RARS/SPIM-compatible services
+///
+/// | a7 | service       | arguments / result                     |
+/// |----|---------------|----------------------------------------|
+/// | 1  | print int     | a0 = value                             |
+/// | 4  | print string  | a0 = address of NUL-terminated string  |
+/// | 5  | read int      | a0 <- value                            |
+/// | 8  | read string   | a0 = buffer, a1 = buffer size          |
+/// | 9  | sbrk          | a0 = bytes, a0 <- old break            |
+/// | 10 | exit          | exit code 0                            |
+/// | 11 | print char    | a0 = character                         |
+/// | 12 | read char     | a0 <- character                        |
+/// | 17 | exit2         | a0 = exit code                         |
+/// | 30 | time          | a0/a1 <- ms since epoch (low/high)     |
+/// | 34 | print hex     | a0 = value                             |
+/// | 35 | print binary  | a0 = value                             |
+/// | 36 | print unsigned| a0 = value                             |
+/// | 40 | set seed      | a0 = generator id, a1 = seed           |
+/// | 41 | random int    | a0 = generator id, a0 <- value         |
+/// | 42 | random range  | a0 = generator id, a1 = bound, a0 <- [0, bound) |
+pub struct RarsSyscalls {
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+    /// Explicit heap start, otherwise `default_heap_base` of the CPU's memory
+    heap_base: Option<Addr>,
+    brk: Option<Addr>,
+    seed: u64,
+    generators: HashMap<Word, u64>,
+}
+
+impl RarsSyscalls {
+    pub fn new(input: impl BufRead + Send + 'static, output: impl Write + Send + 'static) -> Self {
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+            heap_base: None,
+            brk: None,
+            seed: 0x2545_F491_4F6C_DD1D,
+            generators: HashMap::new(),
+        }
+    }
+
+    /// Host stdin/stdout
+    pub fn stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stdout())
+    }
+
+    /// Fixed input, output collected in the returned buffer
+    pub fn captured(input: &str) -> (Self, OutputCapture) {
+        let capture = OutputCapture::new();
+        let handler = Self::new(Cursor::new(input.as_bytes().to_vec()), capture.clone());
+        (handler, capture)
+    }
+
+    pub fn with_heap_base(mut self, base: Addr) -> Self {
+        self.heap_base = Some(base);
+        self.brk = None;
+        self
+    }
+
+    /// Initial seed for generators not seeded by the program (default is fixed)
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed.max(1);
+        self.generators.clear();
+        self
+    }
+
+    fn print(&mut self, text: &str) {
+        self.write_bytes(text.as_bytes());
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        // Program output is best effort - a closed pipe must not stop the CPU
+        let _ = self.output.write_all(bytes);
+        let _ = self.output.flush();
+    }
+
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        let _ = self.input.read_line(&mut line);
+        line
+    }
+
+    /// xorshift64* step for generator `id`
+    fn next_random(&mut self, id: Word) -> Word {
+        let state = self.generators.entry(id).or_insert(self.seed);
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as Word
+    }
+}
+
+impl SyscallHandler for RarsSyscalls {
+    fn ecall(&mut self, cpu: &mut Cpu) -> SyscallAction {
+        let a0 = cpu.registers.peek(A0);
+        let a1 = cpu.registers.peek(A1);
+
+        match cpu.registers.peek(A7) {
+            1 => self.print(&(a0 as i32).to_string()),
+            4 => {
+                let bytes = cpu.memory.read_cstring(a0, MAX_STRING);
+                self.print(&String::from_utf8_lossy(&bytes));
+            }
+            5 => {
+                let value = self.read_line().trim().parse::<i32>().unwrap_or(0);
+                cpu.registers.poke(A0, value as Word);
+            }
+            8 => {
+                // Up to size - 1 bytes of the line (newline kept if it fits), NUL-terminated
+                if a1 > 0 {
+                    let line = self.read_line();
+                    let len = line.len().min(a1 as usize - 1);
+                    cpu.memory.write_bytes(a0, &line.as_bytes()[..len]);
+                    cpu.memory.write_byte(a0.wrapping_add(len as Addr), 0);
+                }
+            }
+            9 => {
+                let heap_base = self.heap_base.unwrap_or_else(|| default_heap_base(&cpu.memory));
+                let old = self.brk.unwrap_or(heap_base);
+                let new = old.wrapping_add(a0);
+                if new >= heap_base && new as u64 <= cpu.memory.end() {
+                    self.brk = Some(new);
+                    cpu.registers.poke(A0, old);
+                } else {
+                    cpu.registers.poke(A0, Word::MAX);
+                }
+            }
+            10 => return SyscallAction::Exit(0),
+            11 => self.write_bytes(&[a0 as u8]),
+            12 => {
+                let mut byte = [0u8];
+                let value = match self.input.read(&mut byte) {
+                    Ok(1) => byte[0] as Word,
+                    _ => 0,
+                };
+                cpu.registers.poke(A0, value);
+            }
+            17 => return SyscallAction::Exit(a0 as i32),
+            30 => {
+                let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+                cpu.registers.poke(A0, millis as Word);
+                cpu.registers.poke(A1, (millis >> 32) as Word);
+            }
+            34 => self.print(&format!("0x{:08x}", a0)),
+            35 => self.print(&format!("{:032b}", a0)),
+            36 => self.print(&a0.to_string()),
+            40 => {
+                self.generators.insert(a0, (a1 as u64).max(1));
+            }
+            41 => {
+                let value = self.next_random(a0);
+                cpu.registers.poke(A0, value);
+            }
+            42 => {
+                let value = self.next_random(a0);
+                cpu.registers.poke(A0, if a1 == 0 { 0 } else { value % a1 });
+            }
+            _ => return SyscallAction::Unhandled,
+        }
+
+        SyscallAction::Continue
+    }
+
+    fn reset(&mut self) {
+        self.brk = None;
+        self.generators.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::{RunConfig, StopReason};
+    use crate::test_util::{addi, cpu_with, cpu_with_memory};
+
+    const ECALL: Word = 0x0000_0073;
+
+    /// CPU running `program` with RARS services reading `input`
+    fn rars_cpu(program: &[Word], input: &str) -> (Cpu, OutputCapture) {
+        let mut cpu = cpu_with(program);
+        let (handler, output) = RarsSyscalls::captured(input);
+        cpu.set_syscall_handler(Box::new(handler));
+        (cpu, output)
+    }
+
+    #[test]
+    fn test_print_services_and_exit2() {
+        let program = [
+            addi(A0, 0, 0x200), addi(A7, 0, 4), ECALL,      // print_string
+            addi(A0, 0, -42), addi(A7, 0, 1), ECALL,        // print_int
+            addi(A0, 0, '\n' as i16), addi(A7, 0, 11), ECALL,
+            addi(A0, 0, 255), addi(A7, 0, 34), ECALL,       // print_hex
+            addi(A0, 0, 3), addi(A7, 0, 17), ECALL,         // exit2(3)
+        ];
+        let (mut cpu, output) = rars_cpu(&program, "");
+        cpu.memory.write_bytes(0x200, b"Hello, RISC-V! \0");
+
+        let result = cpu.run(&RunConfig::new().with_max_cycles(100));
+
+        assert_eq!((result.reason, result.exit_code), (StopReason::Exit, Some(3)));
+        assert_eq!(output.contents(), "Hello, RISC-V! -42\n0x000000ff");
+    }
+
+    #[test]
+    fn test_read_int_sbrk_and_unhandled() {
+        let program = [
+            addi(A7, 0, 5), ECALL,                          // read_int
+            addi(5, A0, 0),
+            addi(A0, 0, 16), addi(A7, 0, 9), ECALL,         // sbrk(16)
+            addi(6, A0, 0),
+            addi(A0, 0, 16), addi(A7, 0, 9), ECALL,         // sbrk(16)
+            addi(A7, 0, 1234), ECALL,                       // unknown service
+        ];
+        let (mut cpu, _) = rars_cpu(&program, "  -17\n");
+
+        let result = cpu.run(&RunConfig::new().with_max_cycles(100));
+
+        assert_eq!(cpu.registers.peek(5) as i32, -17);
+        assert_eq!(cpu.registers.peek(6), 0x800);
+        assert_eq!(cpu.registers.peek(A0), 0x800 + 16);
+        // Unhandled ECALL falls back to the exception path (no mtvec: reported, then runs on)
+        assert_eq!(result.reason, StopReason::IllegalInstruction { pc: 48, word: 0 });
+    }
+
+    #[test]
+    fn test_heap_follows_memory_layout() {
+        let program = [addi(A0, 0, 16), addi(A7, 0, 9), ECALL, addi(A0, 0, 16), ECALL];   // sbrk(16) twice
+        let mut cpu = cpu_with_memory(Memory::with_base(0x8000_0000, 64 * 1024), &[]);
+        cpu.load_words(0x8000_0000, &program);
+        cpu.control.set_pc(0x8000_0000);
+        cpu.set_syscall_handler(Box::new(RarsSyscalls::captured("").0));
+
+        cpu.run_cycles(5);
+
+        assert_eq!(cpu.registers.peek(A0), 0x8000_8010);
+    }
+
+    #[test]
+    fn test_print_char_writes_raw_byte() {
+        let program = [addi(A0, 0, 0xE9), addi(A7, 0, 11), ECALL, addi(A7, 0, 10), ECALL];
+        let (mut cpu, output) = rars_cpu(&program, "");
+
+        cpu.run(&RunConfig::new().with_max_cycles(100));
+
+        assert_eq!(output.bytes(), [0xE9]);
+    }
+}
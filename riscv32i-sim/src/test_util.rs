@@ -0,0 +1,30 @@
+//! Fixtures shared by the unit tests
+
+use crate::types::*;
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+
+pub fn addi(rd: u8, rs1: u8, imm: i16) -> Word {
+    InstructionEncoder::addi(rd, rs1, imm)
+}
+
+pub fn lw(rd: u8, rs1: u8, imm: i16) -> Word {
+    InstructionEncoder::lw(rd, rs1, imm)
+}
+
+pub fn sw(rs1: u8, rs2: u8, imm: i16) -> Word {
+    InstructionEncoder::sw(rs1, rs2, imm)
+}
+
+/// CPU with `program` loaded at address 0
+pub fn cpu_with(program: &[Word]) -> Cpu {
+    cpu_with_memory(Memory::new(), program)
+}
+
+/// CPU using `memory`, with `program` loaded at address 0
+pub fn cpu_with_memory(memory: Memory, program: &[Word]) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.memory = memory;
+    cpu.load_words(0, program);
+    cpu
+}
@@ -0,0 +1,172 @@
+//! Scripted inputs and timestamped outputs for I/O peripherals
+//!
+//! A timeline file drives named device inputs at given cycles, one change
+//! per line (`#` starts a comment; values in decimal, 0x hex or 0b binary):
+//!
+//! ```text
+//! # cycle  input     value
+//! 100      btn0      1
+//! 120      btn0      0      # contact bounce
+//! 125      btn0      1
+//! 5000     switches  0b1010
+//! ```
+//!
+//! Input names are the device's: `pinN`/`pins` for `Gpio`, `swN`/`switches`
+//! and `btnN`/`buttons` for `Panel`.
+//!
+//! Output changes are logged the same way, so a run's log can be diffed
+//! against an expected one.
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::types::*;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TimelineError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Line {line}: {message}")]
+    Parse { line: usize, message: String },
+
+    #[error("Unknown input '{0}'")]
+    UnknownInput(String),
+}
+
+/// Input change at a cycle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedValue {
+    pub cycle: u64,
+    pub name: String,
+    pub value: Word,
+}
+
+/// Input changes in cycle order
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    events: Vec<TimedValue>,
+    next: usize,
+}
+
+impl Timeline {
+    pub fn new(mut events: Vec<TimedValue>) -> Self {
+        // Stable, so changes at the same cycle apply in file order
+        events.sort_by_key(|e| e.cycle);
+        Self { events, next: 0 }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, TimelineError> {
+        let mut events = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| TimelineError::Parse { line: index + 1, message: message.to_string() };
+            let fields: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+            match fields[..] {
+                [] => continue,
+                [cycle, name, value] => events.push(TimedValue {
+                    cycle: cycle.replace('_', "").parse().map_err(|_| error("bad cycle"))?,
+                    name: name.to_string(),
+                    value: parse_value(value).ok_or_else(|| error("bad value"))?,
+                }),
+                _ => return Err(error("expected CYCLE INPUT VALUE")),
+            }
+        }
+        Ok(Self::new(events))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, TimelineError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn events(&self) -> &[TimedValue] {
+        &self.events
+    }
+
+    /// Check every input name with `known`
+    pub fn validate(&self, known: impl Fn(&str) -> bool) -> Result<(), TimelineError> {
+        match self.events.iter().find(|e| !known(&e.name)) {
+            Some(event) => Err(TimelineError::UnknownInput(event.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Changes due at or before `cycle` not yet taken
+    pub fn take_due(&mut self, cycle: u64) -> &[TimedValue] {
+        let start = self.next;
+        while self.next < self.events.len() && self.events[self.next].cycle <= cycle {
+            self.next += 1;
+        }
+        &self.events[start..self.next]
+    }
+
+    pub fn rewind(&mut self) {
+        self.next = 0;
+    }
+}
+
+fn parse_value(text: &str) -> Option<Word> {
+    let text = text.replace('_', "");
+    if let Some(hex) = text.strip_prefix("0x") {
+        Word::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        Word::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Output changes with cycle timestamps
+#[derive(Default)]
+pub struct OutputLog {
+    entries: Vec<TimedValue>,
+    sink: Option<Box<dyn Write + Send>>,
+}
+
+impl OutputLog {
+    /// Also write each change as a `CYCLE NAME VALUE` line
+    pub fn with_sink(mut self, sink: impl Write + Send + 'static) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    pub fn record(&mut self, cycle: u64, name: &str, value: Word) {
+        if let Some(sink) = &mut self.sink {
+            let _ = writeln!(sink, "{} {} 0x{:x}", cycle, name, value);
+        }
+        self.entries.push(TimedValue { cycle, name: name.to_string(), value });
+    }
+
+    pub fn entries(&self) -> &[TimedValue] {
+        &self.entries
+    }
+
+    /// Changes to one output
+    pub fn changes<'a>(&'a self, name: &'a str) -> impl Iterator<Item = (u64, Word)> + 'a {
+        self.entries.iter().filter(move |e| e.name == name).map(|e| (e.cycle, e.value))
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_take_due() {
+        let mut timeline = Timeline::parse("# header\n200 sw 0b1010\n100 pin3 1  # press\n\n100 pin3 0x0\n").unwrap();
+        assert_eq!(timeline.events()[0], TimedValue { cycle: 100, name: "pin3".into(), value: 1 });
+        assert_eq!(timeline.events()[1].value, 0);
+        assert!(timeline.validate(|name| name.starts_with("pin")).is_err());
+
+        assert!(timeline.take_due(99).is_empty());
+        assert_eq!(timeline.take_due(150).len(), 2);
+        assert_eq!(timeline.take_due(1000)[0].value, 0b1010);
+        assert!(timeline.take_due(2000).is_empty());
+
+        assert!(matches!(Timeline::parse("10 pin1"), Err(TimelineError::Parse { line: 1, .. })));
+        assert!(matches!(Timeline::parse("\nx pin1 1"), Err(TimelineError::Parse { line: 2, .. })));
+    }
+}
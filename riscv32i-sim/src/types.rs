@@ -122,7 +122,8 @@ pub enum Opcode {
     Branch    = 0b1100011,  // B-type: BEQ, BNE, BLT, BGE, BLTU, BGEU
     Jalr      = 0b1100111,  // I-type: JALR
     Jal       = 0b1101111,  // J-type: JAL
-    System    = 0b1110011,  // I-type: ECALL, EBREAK
+    System    = 0b1110011,  // I-type: ECALL, EBREAK, MRET, WFI, CSRRW/S/C[I]
+    Amo       = 0b0101111,  // R-type: LR.W, SC.W, AMO*.W (A extension)
 }
 
 /// CPU Pipeline States
@@ -162,6 +163,59 @@ pub enum AluOp {
     PassB,
 }
 
+/// Zicsr read-modify-write operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsrOp {
+    Write,  // CSRRW, CSRRWI
+    Set,    // CSRRS, CSRRSI
+    Clear,  // CSRRC, CSRRCI
+}
+
+/// SYSTEM opcode operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemOp {
+    None,
+    Ecall,
+    Ebreak,
+    Mret,
+    Wfi,
+    Csr(CsrOp),
+}
+
+/// A extension atomic memory operations (word-sized)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmoOp {
+    Lr,     // Load-reserved
+    Sc,     // Store-conditional
+    Swap,
+    Add,
+    Xor,
+    And,
+    Or,
+    Min,
+    Max,
+    Minu,
+    Maxu,
+}
+
+impl AmoOp {
+    /// Memory value after a read-modify-write AMO (LR/SC don't compute one)
+    pub fn apply(self, old: Word, rs2: Word) -> Word {
+        match self {
+            AmoOp::Swap => rs2,
+            AmoOp::Add => old.wrapping_add(rs2),
+            AmoOp::Xor => old ^ rs2,
+            AmoOp::And => old & rs2,
+            AmoOp::Or => old | rs2,
+            AmoOp::Min => (old as i32).min(rs2 as i32) as Word,
+            AmoOp::Max => (old as i32).max(rs2 as i32) as Word,
+            AmoOp::Minu => old.min(rs2),
+            AmoOp::Maxu => old.max(rs2),
+            AmoOp::Lr | AmoOp::Sc => unreachable!("LR/SC are not read-modify-write"),
+        }
+    }
+}
+
 /// Control signals generated by decoder
 #[derive(Debug, Clone, Copy)]
 pub struct ControlSignals {
@@ -173,6 +227,9 @@ pub struct ControlSignals {
     pub mem_to_reg: bool,   // false = ALU result, true = memory
     pub branch: bool,       // Branch instruction
     pub jump: bool,         // Jump instruction
+    pub system: SystemOp,   // SYSTEM instruction (CSR access, traps)
+    pub amo: Option<AmoOp>, // Atomic memory operation
+    pub illegal: bool,      // Not a valid RV32I/Zicsr/A encoding
 }
 
 impl ControlSignals {
@@ -186,6 +243,9 @@ impl ControlSignals {
             mem_to_reg: false,
             branch: false,
             jump: false,
+            system: SystemOp::None,
+            amo: None,
+            illegal: false,
         }
     }
 }
@@ -241,6 +301,45 @@ impl InstructionEncoder {
             | ((rd as Word) << 7)
             | (opcode as Word)
     }
+
+    // B-type: imm[12|10:5] | rs2 | rs1 | funct3 | imm[4:1|11] | opcode
+    pub fn b_type(opcode: u8, funct3: u8, rs1: u8, rs2: u8, imm: i16) -> Word {
+        let imm_u = imm as u32 & 0x1FFE;
+        ((imm_u & 0x1000) << 19)
+            | ((imm_u & 0x7E0) << 20)
+            | ((rs2 as Word) << 20)
+            | ((rs1 as Word) << 15)
+            | ((funct3 as Word) << 12)
+            | ((imm_u & 0x1E) << 7)
+            | ((imm_u & 0x800) >> 4)
+            | (opcode as Word)
+    }
+
+    // J-type: imm[20|10:1|11|19:12] | rd | opcode
+    pub fn j_type(opcode: u8, rd: u8, imm: i32) -> Word {
+        let imm_u = imm as u32 & 0x1FFFFE;
+        ((imm_u & 0x100000) << 11)
+            | ((imm_u & 0x7FE) << 20)
+            | ((imm_u & 0x800) << 9)
+            | (imm_u & 0xFF000)
+            | ((rd as Word) << 7)
+            | (opcode as Word)
+    }
+
+    // addi rd, rs1, imm
+    pub fn addi(rd: u8, rs1: u8, imm: i16) -> Word {
+        Self::i_type(0b0010011, rd, 0b000, rs1, imm)
+    }
+
+    // lw rd, imm(rs1)
+    pub fn lw(rd: u8, rs1: u8, imm: i16) -> Word {
+        Self::i_type(0b0000011, rd, 0b010, rs1, imm)
+    }
+
+    // sw rs2, imm(rs1)
+    pub fn sw(rs1: u8, rs2: u8, imm: i16) -> Word {
+        Self::s_type(0b0100011, 0b010, rs1, rs2, imm)
+    }
 }
 
 
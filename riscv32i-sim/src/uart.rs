@@ -0,0 +1,496 @@
+//! 16550-compatible UART
+//!
+//! Byte-wide registers at consecutive offsets (reg-shift 0), as on QEMU's
+//! virt machine (UART0 at `VIRT_UART_BASE`), so console code for virt runs
+//! unchanged:
+//!
+//! | offset | read            | write           | DLAB = 1   |
+//! |--------|-----------------|-----------------|------------|
+//! | 0      | RBR receive     | THR transmit    | DLL        |
+//! | 1      | IER             | IER             | DLM        |
+//! | 2      | IIR             | FCR             |            |
+//! | 3      | LCR             | LCR             |            |
+//! | 4      | MCR             | MCR             |            |
+//! | 5      | LSR             | -               |            |
+//! | 6      | MSR             | -               |            |
+//! | 7      | SCR             | SCR             |            |
+//!
+//! Received bytes come from the host (stdin, a pseudo-terminal, or
+//! `push_input`) one per cycle into a 16-byte RX FIFO; transmitted bytes go
+//! to the output after `tx_delay` cycles each through a 16-byte TX FIFO.
+//! Received-data, character-timeout, THR-empty and line-status interrupts
+//! drive the device IRQ. MCR loopback routes TX back to RX.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+
+use crate::types::*;
+use crate::bus::Device;
+use crate::syscall::OutputCapture;
+
+/// UART0 on QEMU virt
+pub const VIRT_UART_BASE: Addr = 0x1000_0000;
+/// Size of the register window to map
+pub const UART_SIZE: Addr = 0x100;
+
+const FIFO_DEPTH: usize = 16;
+/// Idle cycles before a character-timeout interrupt (about 4 characters)
+const RX_TIMEOUT: u32 = 64;
+/// Cycles between pty polls
+const PTY_POLL_INTERVAL: u32 = 256;
+
+// IER
+const IER_RDA: u8 = 1 << 0;
+const IER_THRE: u8 = 1 << 1;
+const IER_RLS: u8 = 1 << 2;
+// IIR interrupt IDs (bit 0 clear = pending)
+const IIR_NONE: u8 = 0x01;
+const IIR_RLS: u8 = 0x06;
+const IIR_RDA: u8 = 0x04;
+const IIR_TIMEOUT: u8 = 0x0C;
+const IIR_THRE: u8 = 0x02;
+const IIR_FIFO: u8 = 0xC0;
+// FCR
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_CLEAR_TX: u8 = 1 << 2;
+// LCR / MCR
+const LCR_DLAB: u8 = 1 << 7;
+const MCR_LOOP: u8 = 1 << 4;
+// LSR
+const LSR_DR: u8 = 1 << 0;
+const LSR_OE: u8 = 1 << 1;
+const LSR_THRE: u8 = 1 << 5;
+const LSR_TEMT: u8 = 1 << 6;
+
+/// Where received bytes come from
+enum Input {
+    None,
+    /// Filled by a host reader thread (stdin)
+    Channel(Receiver<u8>),
+    /// Pseudo-terminal master, non-blocking
+    Pty(std::fs::File),
+}
+
+/// 16550 UART
+pub struct Uart16550 {
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    lsr_errors: u8,
+    scr: u8,
+    divisor: u16,
+
+    rx_fifo: VecDeque<u8>,
+    tx_fifo: VecDeque<u8>,
+    /// THR-empty interrupt latched (cleared by reading IIR or writing THR)
+    thre_pending: bool,
+    rx_idle: u32,
+    tx_delay: u32,
+    tx_countdown: u32,
+
+    host_input: VecDeque<u8>,
+    input: Input,
+    output: Box<dyn Write + Send>,
+    poll_countdown: u32,
+    /// Slave side of the pty, held open so the master never sees EIO
+    _pty_slave: Option<std::fs::File>,
+}
+
+impl Default for Uart16550 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Uart16550 {
+    /// No host input; output discarded
+    pub fn new() -> Self {
+        Self {
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            lsr_errors: 0,
+            scr: 0,
+            divisor: 1,
+            rx_fifo: VecDeque::new(),
+            tx_fifo: VecDeque::new(),
+            thre_pending: false,
+            rx_idle: 0,
+            tx_delay: 0,
+            tx_countdown: 0,
+            host_input: VecDeque::new(),
+            input: Input::None,
+            output: Box::new(io::sink()),
+            poll_countdown: 0,
+            _pty_slave: None,
+        }
+    }
+
+    /// Console on the host's stdin/stdout
+    pub fn stdio() -> Self {
+        Self::new().with_output(io::stdout()).with_input(io::stdin())
+    }
+
+    /// Transmitted bytes go to `output`
+    pub fn with_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Transmitted bytes collected in the returned buffer
+    pub fn captured(self) -> (Self, OutputCapture) {
+        let capture = OutputCapture::new();
+        (self.with_output(capture.clone()), capture)
+    }
+
+    /// Receive from `input`, read on a background thread
+    pub fn with_input(mut self, mut input: impl Read + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            while let Ok(n) = input.read(&mut buf) {
+                if n == 0 || buf[..n].iter().any(|&byte| sender.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+        self.input = Input::Channel(receiver);
+        self
+    }
+
+    /// Cycles to shift out each transmitted byte (0 = immediately)
+    pub fn with_tx_delay(mut self, cycles: u32) -> Self {
+        self.tx_delay = cycles;
+        self
+    }
+
+    /// Queue bytes as if typed on the host side
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.host_input.extend(bytes);
+    }
+
+    /// Divisor latch value programmed by the guest
+    pub fn divisor(&self) -> u16 {
+        self.divisor
+    }
+
+    fn fifo_enabled(&self) -> bool {
+        self.fcr & FCR_ENABLE != 0
+    }
+
+    fn fifo_depth(&self) -> usize {
+        if self.fifo_enabled() { FIFO_DEPTH } else { 1 }
+    }
+
+    /// RX FIFO level that raises the received-data interrupt
+    fn rx_trigger(&self) -> usize {
+        if !self.fifo_enabled() {
+            return 1;
+        }
+        [1, 4, 8, 14][(self.fcr >> 6) as usize]
+    }
+
+    fn lsr(&self) -> u8 {
+        let mut lsr = self.lsr_errors;
+        if !self.rx_fifo.is_empty() {
+            lsr |= LSR_DR;
+        }
+        if self.tx_fifo.is_empty() {
+            lsr |= LSR_THRE | LSR_TEMT;
+        }
+        lsr
+    }
+
+    /// Highest-priority pending interrupt (IIR identification)
+    fn interrupt_id(&self) -> u8 {
+        if self.ier & IER_RLS != 0 && self.lsr_errors & LSR_OE != 0 {
+            IIR_RLS
+        } else if self.ier & IER_RDA != 0 && self.rx_fifo.len() >= self.rx_trigger() {
+            IIR_RDA
+        } else if self.ier & IER_RDA != 0 && !self.rx_fifo.is_empty() && self.rx_idle >= RX_TIMEOUT {
+            IIR_TIMEOUT
+        } else if self.ier & IER_THRE != 0 && self.thre_pending {
+            IIR_THRE
+        } else {
+            IIR_NONE
+        }
+    }
+
+    fn receive(&mut self, byte: u8) {
+        if self.rx_fifo.len() >= self.fifo_depth() {
+            self.lsr_errors |= LSR_OE;
+            return;
+        }
+        self.rx_fifo.push_back(byte);
+        self.rx_idle = 0;
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        if self.mcr & MCR_LOOP != 0 {
+            self.receive(byte);
+        } else {
+            // Host output errors (closed pipe, pty nobody reads) drop the byte
+            let _ = self.output.write_all(&[byte]).and_then(|_| self.output.flush());
+        }
+    }
+
+    fn write_thr(&mut self, byte: u8) {
+        self.thre_pending = false;
+        if self.tx_fifo.len() < self.fifo_depth() {
+            self.tx_fifo.push_back(byte);
+        }
+        if self.tx_delay == 0 {
+            self.drain_tx();
+        } else if self.tx_fifo.len() == 1 {
+            self.tx_countdown = self.tx_delay;
+        }
+    }
+
+    fn drain_tx(&mut self) {
+        while let Some(byte) = self.tx_fifo.pop_front() {
+            self.transmit(byte);
+        }
+        self.thre_pending = true;
+    }
+
+    fn poll_host(&mut self) {
+        match &mut self.input {
+            Input::None => {}
+            Input::Channel(receiver) => self.host_input.extend(receiver.try_iter()),
+            Input::Pty(master) => {
+                if self.poll_countdown == 0 {
+                    self.poll_countdown = PTY_POLL_INTERVAL;
+                    let mut buf = [0u8; 64];
+                    if let Ok(n) = master.read(&mut buf) {
+                        self.host_input.extend(&buf[..n]);
+                    }
+                }
+                self.poll_countdown -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Uart16550 {
+    /// Console on a new pseudo-terminal - connect to the returned path
+    /// (e.g. `screen /dev/pts/3`)
+    pub fn pty() -> io::Result<(Self, std::path::PathBuf)> {
+        use std::ffi::CStr;
+        use std::os::fd::FromRawFd;
+
+        // SAFETY: plain libc calls; each result is checked and the master
+        // fd is owned by `master` from here on
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = std::fs::File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = std::path::PathBuf::from(CStr::from_ptr(name).to_string_lossy().into_owned());
+
+            // Raw mode on the slave so bytes pass through unchanged
+            let slave = std::fs::OpenOptions::new().read(true).write(true).open(&path)?;
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(std::os::fd::AsRawFd::as_raw_fd(&slave), &mut termios) == 0 {
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(std::os::fd::AsRawFd::as_raw_fd(&slave), libc::TCSANOW, &termios);
+            }
+
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+
+            let mut uart = Self::new().with_output(master.try_clone()?);
+            uart.input = Input::Pty(master);
+            uart._pty_slave = Some(slave);
+            Ok((uart, path))
+        }
+    }
+}
+
+impl Device for Uart16550 {
+    fn read(&mut self, offset: Addr, _size: usize) -> Word {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        let value = match offset {
+            0 if dlab => self.divisor as u8,
+            0 => {
+                let byte = self.rx_fifo.pop_front().unwrap_or(0);
+                self.rx_idle = 0;
+                byte
+            }
+            1 if dlab => (self.divisor >> 8) as u8,
+            1 => self.ier,
+            2 => {
+                let id = self.interrupt_id();
+                if id == IIR_THRE {
+                    self.thre_pending = false;
+                }
+                id | if self.fifo_enabled() { IIR_FIFO } else { 0 }
+            }
+            3 => self.lcr,
+            4 => self.mcr,
+            5 => {
+                let lsr = self.lsr();
+                self.lsr_errors = 0;
+                lsr
+            }
+            // CTS, DSR and DCD asserted; loopback mirrors MCR outputs
+            6 if self.mcr & MCR_LOOP != 0 => (self.mcr & 0x0F) << 4,
+            6 => 0xB0,
+            7 => self.scr,
+            _ => 0,
+        };
+        value as Word
+    }
+
+    fn write(&mut self, offset: Addr, value: Word, _size: usize) {
+        let byte = value as u8;
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            0 if dlab => self.divisor = (self.divisor & 0xFF00) | byte as u16,
+            0 => self.write_thr(byte),
+            1 if dlab => self.divisor = (self.divisor & 0x00FF) | (byte as u16) << 8,
+            1 => {
+                // Enabling THRE with an empty transmitter raises it straight away
+                if byte & IER_THRE != 0 && self.ier & IER_THRE == 0 && self.tx_fifo.is_empty() {
+                    self.thre_pending = true;
+                }
+                self.ier = byte & 0x0F;
+            }
+            2 => {
+                if byte & FCR_CLEAR_RX != 0 {
+                    self.rx_fifo.clear();
+                }
+                if byte & FCR_CLEAR_TX != 0 {
+                    self.tx_fifo.clear();
+                }
+                self.fcr = byte & 0xC9;
+            }
+            3 => self.lcr = byte,
+            4 => self.mcr = byte & 0x1F,
+            7 => self.scr = byte,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        self.poll_host();
+        if self.mcr & MCR_LOOP == 0 && self.rx_fifo.len() < self.fifo_depth() {
+            if let Some(byte) = self.host_input.pop_front() {
+                self.receive(byte);
+            }
+        }
+        self.rx_idle = self.rx_idle.saturating_add(1);
+
+        if self.tx_delay > 0 && !self.tx_fifo.is_empty() {
+            self.tx_countdown = self.tx_countdown.saturating_sub(1);
+            if self.tx_countdown == 0 {
+                let byte = self.tx_fifo.pop_front().unwrap_or(0);
+                self.transmit(byte);
+                if self.tx_fifo.is_empty() {
+                    self.thre_pending = true;
+                } else {
+                    self.tx_countdown = self.tx_delay;
+                }
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.interrupt_id() != IIR_NONE
+    }
+
+    fn reset(&mut self) {
+        let input = std::mem::replace(&mut self.input, Input::None);
+        let output = std::mem::replace(&mut self.output, Box::new(io::sink()));
+        let slave = self._pty_slave.take();
+        let host_input = std::mem::take(&mut self.host_input);
+        *self = Self { input, output, host_input, _pty_slave: slave, tx_delay: self.tx_delay, ..Self::new() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{addi, cpu_with};
+
+    fn write(uart: &mut Uart16550, offset: Addr, value: u8) {
+        Device::write(uart, offset, value as Word, 1);
+    }
+
+    fn read(uart: &mut Uart16550, offset: Addr) -> u8 {
+        Device::read(uart, offset, 1) as u8
+    }
+
+    #[test]
+    fn test_console_program_prints() {
+        // Poll LSR.THRE and write each byte of "Hi\n" to THR, as virt console code does
+        let (uart, output) = Uart16550::new().with_tx_delay(3).captured();
+        let mut program = vec![addi(10, 10, 0x400), addi(10, 10, 0x400)];          // a0 = 0x800
+        for byte in b"Hi\n" {
+            program.extend([
+                InstructionEncoder::i_type(0b0000011, 5, 0b100, 10, 5),            // wait: lbu t0, 5(a0)
+                InstructionEncoder::i_type(0b0010011, 5, 0b111, 5, 0x20),          // andi t0, t0, THRE
+                InstructionEncoder::b_type(0b1100011, 0b000, 5, 0, -8),            // beqz t0, wait
+                addi(6, 0, *byte as i16),
+                InstructionEncoder::s_type(0b0100011, 0b000, 10, 6, 0),            // sb t1, 0(a0)
+            ]);
+        }
+        program.push(InstructionEncoder::j_type(0b1101111, 0, 0));                 // done: j done
+        let mut cpu = cpu_with(&program);
+        cpu.memory.map_device("uart0", 0x800, 0x100, uart).unwrap();
+
+        cpu.run_cycles(200);
+        assert_eq!(output.contents(), "Hi\n");
+    }
+
+    #[test]
+    fn test_rx_fifo_interrupts_and_loopback() {
+        let mut uart = Uart16550::new();
+        write(&mut uart, 2, FCR_ENABLE | 0x40);     // FIFO, trigger level 4
+        write(&mut uart, 1, IER_RDA);
+        uart.push_input(b"abcde");
+
+        for _ in 0..3 {
+            uart.tick();
+        }
+        assert!(!uart.irq());                       // 3 bytes, below the trigger
+        uart.tick();
+        assert_eq!(read(&mut uart, 2), IIR_RDA | IIR_FIFO);
+        assert_eq!(read(&mut uart, 5) & LSR_DR, LSR_DR);
+        assert_eq!((read(&mut uart, 0), read(&mut uart, 0)), (b'a', b'b'));
+        assert!(!uart.irq());
+
+        // Remaining bytes below the trigger time out
+        for _ in 0..RX_TIMEOUT {
+            uart.tick();
+        }
+        assert_eq!(read(&mut uart, 2), IIR_TIMEOUT | IIR_FIFO);
+
+        // Divisor latch and loopback
+        write(&mut uart, 3, LCR_DLAB);
+        write(&mut uart, 0, 0x0C);
+        write(&mut uart, 3, 0x03);
+        assert_eq!(uart.divisor(), 0x0C);
+        write(&mut uart, 2, FCR_ENABLE | FCR_CLEAR_RX);
+        write(&mut uart, 4, MCR_LOOP);
+        write(&mut uart, 0, b'z');
+        assert_eq!(read(&mut uart, 0), b'z');
+
+        // THRE interrupt once enabled, cleared by reading IIR
+        write(&mut uart, 1, IER_THRE);
+        assert_eq!(read(&mut uart, 2), IIR_THRE | IIR_FIFO);
+        assert_eq!(read(&mut uart, 2), IIR_NONE | IIR_FIFO);
+    }
+}